@@ -0,0 +1,14 @@
+; Definitions and blocks
+
+(function_definition) @fold
+(compound_statement) @fold
+(struct_specifier) @fold
+(union_specifier) @fold
+(enum_specifier) @fold
+(initializer_list) @fold
+
+; Preprocessor conditionals and comments
+
+(preproc_if) @fold
+(preproc_ifdef) @fold
+(comment) @fold
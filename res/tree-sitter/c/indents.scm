@@ -0,0 +1,25 @@
+; Blocks and lists
+
+(compound_statement) @indent
+(field_declaration_list) @indent
+(enumerator_list) @indent
+(initializer_list) @indent
+(parameter_list) @indent
+(argument_list) @indent
+(case_statement) @indent
+
+; Statements without braces, and statements continued on the next line
+
+(if_statement) @indent
+(for_statement) @indent
+(while_statement) @indent
+(do_statement) @indent
+(declaration) @indent
+(expression_statement) @indent
+(return_statement) @indent
+
+; Closing brackets
+
+"}" @outdent
+")" @outdent
+"else" @outdent
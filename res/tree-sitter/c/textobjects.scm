@@ -0,0 +1,27 @@
+; Functions
+
+(function_definition
+  body: (compound_statement) @function.inner) @function.outer
+
+; Types
+
+(struct_specifier
+  body: (field_declaration_list) @class.inner) @class.outer
+
+(union_specifier
+  body: (field_declaration_list) @class.inner) @class.outer
+
+(enum_specifier
+  body: (enumerator_list) @class.inner) @class.outer
+
+; Blocks. Without an inner capture, the inner part is everything between the braces
+
+(compound_statement) @block.outer
+
+; Parameters and arguments
+
+(parameter_list (_) @parameter)
+
+(argument_list (_) @parameter)
+
+(initializer_list (_) @parameter)
@@ -0,0 +1,5 @@
+; Classes, namespaces and lambdas
+
+(class_specifier) @fold
+(namespace_definition) @fold
+(lambda_expression) @fold
@@ -0,0 +1,4 @@
+; Templates
+
+(template_parameter_list) @indent
+(template_argument_list) @indent
@@ -0,0 +1,17 @@
+; Functions
+
+(lambda_expression
+  body: (compound_statement) @function.inner) @function.outer
+
+; Types
+
+(class_specifier
+  body: (field_declaration_list) @class.inner) @class.outer
+
+; Blocks come from the C query, which is appended to this one
+
+; Parameters and arguments
+
+(template_parameter_list (_) @parameter)
+
+(template_argument_list (_) @parameter)
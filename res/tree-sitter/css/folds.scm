@@ -0,0 +1,8 @@
+; Rules and at-rules
+
+(rule_set) @fold
+(media_statement) @fold
+(supports_statement) @fold
+(keyframes_statement) @fold
+(keyframe_block) @fold
+(comment) @fold
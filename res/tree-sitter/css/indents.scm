@@ -0,0 +1,10 @@
+; Blocks
+
+(block) @indent
+(keyframe_block_list) @indent
+(arguments) @indent
+
+; Closing brackets
+
+"}" @outdent
+")" @outdent
@@ -0,0 +1,23 @@
+; Blocks
+
+(rule_set
+  (block) @block.inner) @block.outer
+
+(media_statement
+  (block) @block.inner) @block.outer
+
+(supports_statement
+  (block) @block.inner) @block.outer
+
+(at_rule
+  (block) @block.inner) @block.outer
+
+(keyframe_block
+  (block) @block.inner) @block.outer
+
+(keyframes_statement
+  (keyframe_block_list) @block.inner) @block.outer
+
+; Arguments
+
+(arguments (_) @parameter)
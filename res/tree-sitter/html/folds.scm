@@ -0,0 +1,6 @@
+; Elements
+
+(element) @fold
+(script_element) @fold
+(style_element) @fold
+(comment) @fold
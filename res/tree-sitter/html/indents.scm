@@ -0,0 +1,10 @@
+; Elements
+
+(element) @indent
+(script_element) @indent
+(style_element) @indent
+(start_tag) @indent
+
+; Closing tags
+
+(end_tag) @outdent
@@ -0,0 +1,14 @@
+; Blocks. Without an inner capture, the inner part is everything between the first and last
+; child, which is the text between the start and end tags
+
+(element) @block.outer
+
+(script_element) @block.outer
+
+(style_element) @block.outer
+
+; Attributes
+
+(start_tag (attribute) @parameter)
+
+(self_closing_tag (attribute) @parameter)
@@ -0,0 +1,17 @@
+; Functions, classes and blocks
+
+(function_declaration) @fold
+(generator_function_declaration) @fold
+(class_declaration) @fold
+(method_definition) @fold
+(statement_block) @fold
+(class_body) @fold
+(switch_body) @fold
+
+; Literals and JSX
+
+(object) @fold
+(array) @fold
+(template_string) @fold
+(jsx_element) @fold
+(comment) @fold
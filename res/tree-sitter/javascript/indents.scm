@@ -0,0 +1,41 @@
+; Blocks and lists
+
+(statement_block) @indent
+(class_body) @indent
+(switch_body) @indent
+(switch_case) @indent
+(switch_default) @indent
+(object) @indent
+(object_pattern) @indent
+(array) @indent
+(array_pattern) @indent
+(arguments) @indent
+(formal_parameters) @indent
+(named_imports) @indent
+(export_clause) @indent
+(template_substitution) @indent
+(parenthesized_expression) @indent
+
+; Statements without braces, and statements continued on the next line
+
+(if_statement) @indent
+(for_statement) @indent
+(for_in_statement) @indent
+(while_statement) @indent
+(do_statement) @indent
+(lexical_declaration) @indent
+(variable_declaration) @indent
+(expression_statement) @indent
+(return_statement) @indent
+(member_expression) @indent
+(jsx_element) @indent
+(jsx_opening_element) @indent
+(jsx_self_closing_element) @indent
+
+; Closing brackets
+
+"}" @outdent
+")" @outdent
+"]" @outdent
+"else" @outdent
+(jsx_closing_element) @outdent
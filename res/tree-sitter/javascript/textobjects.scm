@@ -0,0 +1,37 @@
+; Functions
+
+(function
+  body: (statement_block) @function.inner) @function.outer
+
+(function_declaration
+  body: (statement_block) @function.inner) @function.outer
+
+(generator_function
+  body: (statement_block) @function.inner) @function.outer
+
+(generator_function_declaration
+  body: (statement_block) @function.inner) @function.outer
+
+(method_definition
+  body: (statement_block) @function.inner) @function.outer
+
+(arrow_function
+  body: (_) @function.inner) @function.outer
+
+; Classes
+
+(class
+  body: (class_body) @class.inner) @class.outer
+
+(class_declaration
+  body: (class_body) @class.inner) @class.outer
+
+; Blocks. Without an inner capture, the inner part is everything between the braces
+
+(statement_block) @block.outer
+
+; Parameters and arguments
+
+(formal_parameters (_) @parameter)
+
+(arguments (_) @parameter)
@@ -0,0 +1,23 @@
+; Definitions and compound statements. A statement with clauses folds as a whole, and each of
+; its clauses folds by itself
+
+(function_definition) @fold
+(class_definition) @fold
+(decorated_definition) @fold
+(if_statement) @fold
+(elif_clause) @fold
+(else_clause) @fold
+(for_statement) @fold
+(while_statement) @fold
+(try_statement) @fold
+(except_clause) @fold
+(finally_clause) @fold
+(with_statement) @fold
+
+; Literals, including docstrings
+
+(list) @fold
+(dictionary) @fold
+(set) @fold
+(tuple) @fold
+(string) @fold
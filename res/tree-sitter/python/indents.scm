@@ -0,0 +1,50 @@
+; Compound statements. Their blocks have no brackets, so they also take in a new line after them
+; while the line above it is indented more than they are
+
+(function_definition) @indent
+(class_definition) @indent
+(if_statement) @indent
+(elif_clause) @indent
+(else_clause) @indent
+(for_statement) @indent
+(while_statement) @indent
+(try_statement) @indent
+(except_clause) @indent
+(finally_clause) @indent
+(with_statement) @indent
+
+(function_definition) @extend
+(class_definition) @extend
+(if_statement) @extend
+(elif_clause) @extend
+(else_clause) @extend
+(for_statement) @extend
+(while_statement) @extend
+(try_statement) @extend
+(except_clause) @extend
+(finally_clause) @extend
+(with_statement) @extend
+
+; Brackets
+
+(list) @indent
+(dictionary) @indent
+(set) @indent
+(tuple) @indent
+(parenthesized_expression) @indent
+(list_comprehension) @indent
+(dictionary_comprehension) @indent
+(set_comprehension) @indent
+(generator_expression) @indent
+(parameters) @align
+(argument_list) @align
+
+; Clauses which line up with the statement they belong to, and closing brackets
+
+(elif_clause) @outdent
+(else_clause) @outdent
+(except_clause) @outdent
+(finally_clause) @outdent
+")" @outdent
+"]" @outdent
+"}" @outdent
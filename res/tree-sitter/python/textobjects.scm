@@ -0,0 +1,50 @@
+; Functions
+
+(function_definition
+  body: (block) @function.inner) @function.outer
+
+(lambda
+  body: (_) @function.inner) @function.outer
+
+; Classes
+
+(class_definition
+  body: (block) @class.inner) @class.outer
+
+; Blocks. Blocks have no delimiters, so the outer part is the statement or clause which has the
+; block
+
+(if_statement
+  (block) @block.inner) @block.outer
+
+(elif_clause
+  (block) @block.inner) @block.outer
+
+(else_clause
+  (block) @block.inner) @block.outer
+
+(for_statement
+  (block) @block.inner) @block.outer
+
+(while_statement
+  (block) @block.inner) @block.outer
+
+(with_statement
+  (block) @block.inner) @block.outer
+
+(try_statement
+  (block) @block.inner) @block.outer
+
+(except_clause
+  (block) @block.inner) @block.outer
+
+(finally_clause
+  (block) @block.inner) @block.outer
+
+; Parameters and arguments
+
+(parameters (_) @parameter)
+
+(lambda_parameters (_) @parameter)
+
+(argument_list (_) @parameter)
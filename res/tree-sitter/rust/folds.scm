@@ -0,0 +1,21 @@
+; Items
+
+(function_item) @fold
+(impl_item) @fold
+(trait_item) @fold
+(struct_item) @fold
+(enum_item) @fold
+(union_item) @fold
+(mod_item) @fold
+(macro_definition) @fold
+(use_declaration) @fold
+
+; Blocks and expressions
+
+(block) @fold
+(match_block) @fold
+(match_arm) @fold
+(struct_expression) @fold
+(array_expression) @fold
+(macro_invocation) @fold
+(block_comment) @fold
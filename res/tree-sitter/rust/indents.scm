@@ -0,0 +1,32 @@
+; Blocks and lists
+
+(block) @indent
+(declaration_list) @indent
+(field_declaration_list) @indent
+(ordered_field_declaration_list) @indent
+(enum_variant_list) @indent
+(field_initializer_list) @indent
+(match_block) @indent
+(use_list) @indent
+(token_tree) @indent
+(parameters) @indent
+(closure_parameters) @indent
+(type_parameters) @indent
+(type_arguments) @indent
+(arguments) @indent
+(array_expression) @indent
+(tuple_expression) @indent
+(where_clause) @indent
+
+; Statements continued on the next line
+
+(let_declaration) @indent
+(match_arm) @indent
+(field_expression) @indent
+(binary_expression) @indent
+
+; Closing brackets
+
+"}" @outdent
+")" @outdent
+"]" @outdent
@@ -0,0 +1,40 @@
+; Functions
+
+(function_item
+  body: (block) @function.inner) @function.outer
+
+(closure_expression
+  body: (_) @function.inner) @function.outer
+
+; Types
+
+(struct_item
+  body: (_) @class.inner) @class.outer
+
+(enum_item
+  body: (enum_variant_list) @class.inner) @class.outer
+
+(union_item
+  body: (field_declaration_list) @class.inner) @class.outer
+
+(trait_item
+  body: (declaration_list) @class.inner) @class.outer
+
+(impl_item
+  body: (declaration_list) @class.inner) @class.outer
+
+; Blocks. Without an inner capture, the inner part is everything between the braces
+
+(block) @block.outer
+
+; Parameters and arguments
+
+(parameters (_) @parameter)
+
+(closure_parameters (_) @parameter)
+
+(arguments (_) @parameter)
+
+(type_parameters (_) @parameter)
+
+(type_arguments (_) @parameter)
@@ -0,0 +1,125 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use ropey::Rope;
+use tree_sitter::Tree;
+
+// What typing a character does, given the characters around the cursor
+#[derive(Debug, Eq, PartialEq)]
+pub(super) enum PairEdit {
+    // Insert the character followed by its closing character
+    Pair(char),
+    // Move over the same character after the cursor instead of inserting it
+    Skip,
+    // Insert the character on both sides of the cursor, like a space between brackets
+    Surround,
+    Insert,
+}
+
+pub(super) fn pair_edit(
+    pairs: &[(char, char)],
+    c: char,
+    prev: Option<char>,
+    next: Option<char>,
+    in_literal: bool,
+) -> PairEdit {
+    if next == Some(c) && pairs.iter().any(|(_, close)| *close == c) {
+        return PairEdit::Skip;
+    }
+    if in_literal {
+        return PairEdit::Insert;
+    }
+    if c == ' ' && is_pair(pairs, prev, next) && prev != next {
+        return PairEdit::Surround;
+    }
+    let close = match pairs.iter().find(|(open, _)| *open == c) {
+        Some((_, close)) => *close,
+        None => return PairEdit::Insert,
+    };
+    // Only pair before whitespace or closing characters, so that typing in front of a word
+    // doesn't leave a stray closing character
+    let next_ok = match next {
+        Some(next) => next.is_whitespace() || pairs.iter().any(|(_, close)| *close == next),
+        None => true,
+    };
+    // Quotes after a word are apostrophes or closing quotes
+    let prev_ok = c != close || !prev.map_or(false, |p| p.is_alphanumeric() || p == c);
+    if next_ok && prev_ok {
+        PairEdit::Pair(close)
+    } else {
+        PairEdit::Insert
+    }
+}
+
+// Whether the characters around the cursor are an empty pair
+pub(super) fn is_pair(pairs: &[(char, char)], prev: Option<char>, next: Option<char>) -> bool {
+    match (prev, next) {
+        (Some(prev), Some(next)) => pairs.contains(&(prev, next)),
+        _ => false,
+    }
+}
+
+// Whether the cursor is inside a string or comment, going by the names of syntax nodes
+pub(super) fn in_string_or_comment(data: &Rope, tree: &Tree, cidx: usize) -> bool {
+    let bidx = data.char_to_byte(cidx);
+    let mut node = tree.root_node().descendant_for_byte_range(bidx, bidx);
+    while let Some(n) = node {
+        let kind = n.kind();
+        if (kind.contains("string") || kind.contains("comment"))
+            && n.start_byte() < bidx
+            && bidx < n.end_byte()
+        {
+            return true;
+        }
+        node = n.parent();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAIRS: &[(char, char)] = &[('(', ')'), ('{', '}'), ('"', '"'), ('<', '>')];
+
+    #[test]
+    fn test_pair_edit() {
+        assert_eq!(
+            pair_edit(PAIRS, '(', None, None, false),
+            PairEdit::Pair(')')
+        );
+        assert_eq!(
+            pair_edit(PAIRS, '(', Some('f'), Some(')'), false),
+            PairEdit::Pair(')')
+        );
+        assert_eq!(
+            pair_edit(PAIRS, '(', None, Some('x'), false),
+            PairEdit::Insert
+        );
+        assert_eq!(pair_edit(PAIRS, '(', None, None, true), PairEdit::Insert);
+        assert_eq!(pair_edit(PAIRS, '[', None, None, false), PairEdit::Insert);
+        assert_eq!(
+            pair_edit(PAIRS, ')', None, Some(')'), false),
+            PairEdit::Skip
+        );
+        assert_eq!(pair_edit(PAIRS, '"', None, Some('"'), true), PairEdit::Skip);
+        assert_eq!(
+            pair_edit(PAIRS, '"', Some(' '), None, false),
+            PairEdit::Pair('"')
+        );
+        assert_eq!(
+            pair_edit(PAIRS, '"', Some('t'), None, false),
+            PairEdit::Insert
+        );
+        assert_eq!(
+            pair_edit(PAIRS, ' ', Some('{'), Some('}'), false),
+            PairEdit::Surround
+        );
+        assert_eq!(
+            pair_edit(PAIRS, ' ', Some('"'), Some('"'), false),
+            PairEdit::Insert
+        );
+        assert!(is_pair(PAIRS, Some('<'), Some('>')));
+        assert!(!is_pair(PAIRS, Some('('), Some('}')));
+        assert!(!is_pair(&[], Some('('), Some(')')));
+    }
+}
@@ -1,35 +1,58 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
-use std::cmp::min;
+use std::cell::RefCell;
+use std::cmp::{max, min, Ordering};
 use std::fs::File;
 use std::io::Result as IOResult;
 use std::io::Write;
 use std::path::Path;
 use std::rc::Rc;
 
-use euclid::{Point2D, Rect, Vector2D};
+use euclid::{Point2D, Rect, Size2D, Vector2D};
 use fnv::FnvHashMap;
 use ropey::{Rope, RopeSlice};
-use tree_sitter::{InputEdit, Parser, Point, Query, QueryCursor, Tree};
+use tree_sitter::{InputEdit, Parser, Point, QueryCursor, Tree};
 
-use crate::common::{rope_trim_newlines, PixelSize};
+use crate::common::{rope_next_grapheme_boundary, rope_trim_newlines, PixelSize};
 use crate::config::Config;
-use crate::input::{ComplAction, Motion, MotionOrObj, Object};
-use crate::language::Language;
+use crate::input::{
+    ComplAction, Fold, Motion, MotionOrObj, Object, Operator, ScreenPos, Scroll, Surround,
+    SurroundEdit, SurroundTarget, VisualMode,
+};
+use crate::language::{CommentTokens, Language};
 use crate::language_client::{
-    Hover as LspHover, Id as LspId, LanguageClient, LanguageClientManager, PublishDiagnosticParams,
-    Range as LspRange,
+    FoldingRange as LspFoldingRange, Hover as LspHover, Id as LspId, LanguageClient,
+    LanguageClientManager, PublishDiagnosticParams, Range as LspRange,
 };
 use crate::painter::Painter;
 use crate::project::Project;
+use crate::registers::{Register, RegisterKind, Registers, BLACK_HOLE};
 use crate::style::{Color, TextStyle};
 use crate::theme::Theme;
-use crate::ts::TsCore;
+use crate::ts::{TsCore, TsQueries};
 
+use super::autopair::{in_string_or_comment, is_pair, pair_edit, PairEdit};
+use super::comment::{language_at, toggle_comments};
 use super::completion::CompletionSource;
+use super::cursor::{cidx_gidx_from_cidx, cidx_gidx_from_gidx, cidx_range_from_gidx_range};
+use super::fold::syntax_fold_ranges;
+use super::history::{CursorSnapshot, EditOp, History, HistoryStep};
+use super::indent::{align_whitespace, first_non_blank, in_error, ts_indent, Indent};
+use super::marks::{shift_for_insert, shift_for_remove, Marks};
+use super::motion::{
+    find_char, matching_bracket, nth_back_word_end, nth_word_end, paragraph_boundary,
+    sentence_boundary,
+};
+use super::number::add_to_number;
+use super::regex::Regex;
+use super::search::{find_all, find_nth, line_end};
 use super::styled::StyledText;
-use super::types::{internal_cidx_to_lsp_position, internal_to_lsp_position, Diagnostics, Hover};
-use super::view::{BufferView, BufferViewCreateParams};
+use super::surround::{minimal_edit, surround_delimiters};
+use super::textobject::{delimiter_ranges, function_start, text_object_range};
+use super::types::{
+    internal_cidx_to_lsp_position, internal_to_lsp_position, Diagnostics, Hover, TextRange,
+};
+use super::view::{BufferView, BufferViewCreateParams, ExtraCursor, Selection};
 use super::{BufferID, BufferViewID, CursorStyle};
 
 fn default_hl_for_line(
@@ -44,6 +67,24 @@ fn default_hl_for_line(
     StyledText::new(lch, depth, TextStyle::default(), color, None)
 }
 
+// Indent of the rows a line is wrapped onto, if lines are soft-wrapped. The project's settings
+// take precedence over the language's
+fn soft_wrap_indent(
+    config: &Config,
+    language: Option<Language>,
+    project: Option<&Project>,
+) -> Option<usize> {
+    let (mut soft_wrap, mut wrap_indent) = language
+        .and_then(|ft| config.language.get(&ft))
+        .map(|ft| (ft.soft_wrap, ft.wrap_indent))
+        .unwrap_or((config.soft_wrap, config.wrap_indent));
+    if let Some(project) = project {
+        soft_wrap = project.soft_wrap.unwrap_or(soft_wrap);
+        wrap_indent = project.wrap_indent.unwrap_or(wrap_indent);
+    }
+    Some(wrap_indent).filter(|_| soft_wrap)
+}
+
 pub(crate) struct Buffer {
     buffer_id: BufferID,
     data: Rope,
@@ -52,17 +93,36 @@ pub(crate) struct Buffer {
     styled_lines: Vec<StyledText>,
     tab_width: usize,
     indent_tabs: bool,
+    soft_wrap: Option<usize>,
     path: Option<String>,
     language: Option<Language>,
     parser: Option<Parser>,
-    hl_query: Option<Rc<Query>>,
+    queries: Option<Rc<TsQueries>>,
     tree: Option<Tree>,
     project: Option<Rc<Project>>,
     theme: Rc<Theme>,
     config: Rc<Config>,
+    registers: Rc<RefCell<Registers>>,
     language_client: Option<LanguageClient>,
     diagnostics: Diagnostics,
     last_hover: Option<(LspId, BufferViewID)>,
+    // First and last lines of the regions which can be folded, from the language server if it
+    // has them, and from tree-sitter otherwise
+    fold_ranges: Vec<(usize, usize)>,
+    last_folding_range: Option<LspId>,
+    // Search pattern whose matches are highlighted
+    search_regex: Option<Regex>,
+    // Edit tracking
+    history: History,
+    recording_history: bool,
+    has_pending_edits: bool,
+    // Set while editing at each of several cursors, so that the edits are flushed together
+    batching_edits: bool,
+    // Set while at a cursor other than the main one. Only the text at the main cursor goes in to
+    // registers
+    at_extra_cursor: bool,
+    lsp_changes: Vec<(LspRange, String)>,
+    marks: Marks,
 }
 
 impl Buffer {
@@ -77,6 +137,7 @@ impl Buffer {
                 &self.data,
                 &self.styled_lines,
                 self.tab_width,
+                self.soft_wrap,
             ),
         );
     }
@@ -89,10 +150,13 @@ impl Buffer {
     }
 
     pub(crate) fn draw_view(&mut self, id: &BufferViewID, painter: &mut Painter) {
-        self.views
-            .get_mut(id)
-            .unwrap()
-            .draw(painter, &self.diagnostics);
+        self.views.get_mut(id).unwrap().draw(
+            painter,
+            &self.data,
+            &self.styled_lines,
+            &self.diagnostics,
+            &self.fold_ranges,
+        );
     }
 
     pub(crate) fn check_view_needs_redraw(&mut self, id: &BufferViewID) -> bool {
@@ -125,6 +189,78 @@ impl Buffer {
             .scroll(vec, &self.data, &self.styled_lines);
     }
 
+    // Scroll by half pages or pages. The cursor moves as many lines as the view for half pages,
+    // and only as far as it has to to stay in view for pages
+    pub(crate) fn view_cell_size(&self, id: &BufferViewID) -> Size2D<u32, PixelSize> {
+        self.views.get(id).unwrap().cell_size()
+    }
+
+    pub(crate) fn view_scroll_page(
+        &mut self,
+        id: &BufferViewID,
+        scroll: Scroll,
+        count: Option<usize>,
+    ) -> bool {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        let last_linum = self.data.len_lines() - 1;
+        let (first, _) = view.visible_lines();
+        let page = view.page_lines();
+        let linum = view.cursor.line_num;
+        let (start_line, cursor_line) = match scroll {
+            Scroll::HalfPageDown => {
+                if linum == last_linum {
+                    return false;
+                }
+                let n = count.unwrap_or(max(page / 2, 1));
+                let max_start = max(last_linum.saturating_sub(page - 1), first);
+                (min(first + n, max_start), min(linum + n, last_linum))
+            }
+            Scroll::HalfPageUp => {
+                if linum == 0 {
+                    return false;
+                }
+                let n = count.unwrap_or(max(page / 2, 1));
+                (first.saturating_sub(n), linum.saturating_sub(n))
+            }
+            Scroll::PageDown => {
+                if first >= last_linum {
+                    return false;
+                }
+                // Keep two lines of context
+                let n = count.unwrap_or(1) * max(page.saturating_sub(2), 1);
+                let start_line = min(first + n, last_linum);
+                (start_line, max(linum, start_line))
+            }
+            Scroll::PageUp => {
+                if first == 0 {
+                    return false;
+                }
+                let n = count.unwrap_or(1) * max(page.saturating_sub(2), 1);
+                let start_line = first.saturating_sub(n);
+                (start_line, min(linum, start_line + page - 1))
+            }
+        };
+        view.cursor.line_num = view.folds.visible_line(cursor_line);
+        view.cursor.sync_global_x(&self.data, self.tab_width);
+        view.scroll_to_line(start_line, &self.data, &self.styled_lines);
+        true
+    }
+
+    pub(crate) fn view_scroll_cursor(&mut self, id: &BufferViewID, pos: ScreenPos) {
+        self.views
+            .get_mut(id)
+            .unwrap()
+            .scroll_cursor(pos, &self.data, &self.styled_lines);
+    }
+
+    // Returns false if there was no fold to open or close at the cursor
+    pub(crate) fn view_fold(&mut self, id: &BufferViewID, fold: Fold) -> bool {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        view.fold(fold, &self.fold_ranges, &self.data, &self.styled_lines)
+    }
+
     pub(crate) fn set_view_hover(
         &mut self,
         viewid: &BufferViewID,
@@ -153,7 +289,13 @@ impl Buffer {
 
     // -------- View cursor manipulation ----------------
 
-    pub(crate) fn move_view_cursor(&mut self, id: &BufferViewID, mo: MotionOrObj) {
+    // Returns false if the motion failed, e.g. going down from the last line. Motions to a fixed
+    // position in a line or buffer don't fail
+    pub(crate) fn move_view_cursor(&mut self, id: &BufferViewID, mo: MotionOrObj) -> bool {
+        self.at_each_cursor(id, |buf| buf.move_view_main_cursor(id, mo))
+    }
+
+    fn move_view_main_cursor(&mut self, id: &BufferViewID, mo: MotionOrObj) -> bool {
         let view = self.views.get_mut(id).unwrap();
         view.stop_completion();
 
@@ -173,6 +315,7 @@ impl Buffer {
                 view.cursor
                     .sync_line_cidx_gidx_right(&self.data, self.tab_width);
             }
+            // Closed folds count as one line
             MotionOrObj::Motion(Motion::Up(n)) => {
                 if view.cursor.line_num == 0 {
                     view.cursor.char_idx = 0;
@@ -180,21 +323,20 @@ impl Buffer {
                     view.cursor.line_gidx = 0;
                     view.cursor.line_global_x = 0;
                 } else {
-                    if view.cursor.line_num < n {
-                        view.cursor.line_num = 0;
-                    } else {
-                        view.cursor.line_num -= n;
-                    }
+                    view.cursor.line_num = view.folds.lines_up(view.cursor.line_num, n);
                 }
                 view.cursor.sync_global_x(&self.data, self.tab_width);
             }
             MotionOrObj::Motion(Motion::Down(n)) => {
-                view.cursor.line_num += n;
-                if view.cursor.line_num >= self.data.len_lines() {
+                let len_lines = self.data.len_lines();
+                let linum = view.folds.lines_down(view.cursor.line_num, n, len_lines);
+                let last_shown = view.folds.visible_line(len_lines - 1);
+                if linum >= len_lines && last_shown == len_lines - 1 {
                     view.cursor.char_idx = self.data.len_chars();
                     view.cursor
                         .sync_and_update_char_idx_left(&self.data, self.tab_width);
                 } else {
+                    view.cursor.line_num = min(linum, last_shown);
                     view.cursor.sync_global_x(&self.data, self.tab_width);
                 }
             }
@@ -215,6 +357,53 @@ impl Buffer {
                 view.cursor
                     .sync_line_cidx_gidx_right(&self.data, self.tab_width);
             }
+            MotionOrObj::Motion(Motion::FirstNonBlank) => {
+                let linum = view.cursor.line_num;
+                self.move_view_cursor_to_first_non_blank(id, linum);
+            }
+            MotionOrObj::Motion(Motion::Percent(n)) => {
+                let linum = self.percent_line(n);
+                self.move_view_cursor_to_first_non_blank(id, linum);
+            }
+            MotionOrObj::Motion(Motion::ScreenLine(pos, n)) => {
+                let linum = view.screen_line(pos, n);
+                self.move_view_cursor_to_first_non_blank(id, linum);
+            }
+            MotionOrObj::Motion(Motion::RowUp(n)) | MotionOrObj::Motion(Motion::RowDown(n)) => {
+                let down = matches!(mo, MotionOrObj::Motion(Motion::RowDown(_)));
+                let (linum, gidx) = view.move_rows(&self.data, &self.styled_lines, n, down);
+                view.cursor.line_num = linum;
+                view.cursor.line_gidx = gidx;
+                view.cursor.sync_gidx(&self.data, self.tab_width);
+            }
+            MotionOrObj::Motion(Motion::FindChar(find, n, repeat)) => {
+                view.cursor.char_idx = match find_char(&self.data, cidx, find, n, repeat) {
+                    Some(new_cidx) => new_cidx,
+                    None => return false,
+                };
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+            MotionOrObj::Motion(Motion::MatchBracket) => {
+                view.cursor.char_idx = match matching_bracket(&self.data, self.tree.as_ref(), cidx)
+                {
+                    Some(new_cidx) => new_cidx,
+                    None => return false,
+                };
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+            MotionOrObj::Motion(Motion::Mark(name, linewise)) => {
+                let pos = match self.mark_position(id, name) {
+                    Some(pos) => pos,
+                    None => return false,
+                };
+                if linewise {
+                    self.move_view_cursor_to_first_non_blank(id, self.data.char_to_line(pos));
+                } else {
+                    self.move_view_cursor_to_cidx(id, pos);
+                }
+            }
             MotionOrObj::Object(Object::Words(n)) => {
                 let new_cidx = nth_word_start(&self.data, cidx, n, false);
                 view.cursor.char_idx = new_cidx;
@@ -239,10 +428,98 @@ impl Buffer {
                 view.cursor
                     .sync_and_update_char_idx_left(&self.data, self.tab_width);
             }
+            MotionOrObj::Object(Object::WordEnds(n))
+            | MotionOrObj::Object(Object::WordEndsExt(n)) => {
+                let extended = matches!(mo, MotionOrObj::Object(Object::WordEndsExt(_)));
+                view.cursor.char_idx = nth_word_end(&self.data, cidx, n, extended);
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+            MotionOrObj::Object(Object::BackWordEnds(n))
+            | MotionOrObj::Object(Object::BackWordEndsExt(n)) => {
+                let extended = matches!(mo, MotionOrObj::Object(Object::BackWordEndsExt(_)));
+                view.cursor.char_idx = nth_back_word_end(&self.data, cidx, n, extended);
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+            MotionOrObj::Object(Object::Paragraphs(n))
+            | MotionOrObj::Object(Object::BackParagraphs(n)) => {
+                let forward = matches!(mo, MotionOrObj::Object(Object::Paragraphs(_)));
+                view.cursor.char_idx = paragraph_boundary(&self.data, cidx, forward, n);
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+            MotionOrObj::Object(Object::Sentences(n))
+            | MotionOrObj::Object(Object::BackSentences(n)) => {
+                let forward = matches!(mo, MotionOrObj::Object(Object::Sentences(_)));
+                view.cursor.char_idx = sentence_boundary(&self.data, cidx, forward, n);
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+            MotionOrObj::Object(Object::Functions(n)) => {
+                if let Some(new_cidx) = self.function_start(cidx, true, n) {
+                    let view = self.views.get_mut(id).unwrap();
+                    view.cursor.char_idx = new_cidx;
+                    view.cursor
+                        .sync_and_update_char_idx_left(&self.data, self.tab_width);
+                }
+            }
+            MotionOrObj::Object(Object::BackFunctions(n)) => {
+                if let Some(new_cidx) = self.function_start(cidx, false, n) {
+                    let view = self.views.get_mut(id).unwrap();
+                    view.cursor.char_idx = new_cidx;
+                    view.cursor
+                        .sync_and_update_char_idx_left(&self.data, self.tab_width);
+                }
+            }
+            MotionOrObj::Object(Object::Inner(obj, n))
+            | MotionOrObj::Object(Object::Around(obj, n)) => {
+                // Text objects only move the cursor in visual mode, where they extend the selection
+                let inner = matches!(mo, MotionOrObj::Object(Object::Inner(..)));
+                let sel = match &mut view.selection {
+                    Some(sel) => sel,
+                    None => return false,
+                };
+                let query = self.queries.as_ref().map(|q| &q.textobjects);
+                let (start_cidx, end_cidx) = match text_object_range(
+                    &self.data,
+                    self.tree.as_ref(),
+                    query,
+                    cidx,
+                    obj,
+                    inner,
+                    n,
+                ) {
+                    Some(TextRange::Chars(start_cidx, end_cidx)) if start_cidx < end_cidx => {
+                        (start_cidx, end_cidx - 1)
+                    }
+                    Some(TextRange::Lines(first, last)) => (
+                        self.data.line_to_char(first),
+                        self.data.line_to_char(last)
+                            + rope_trim_newlines(self.data.line(last)).len_chars(),
+                    ),
+                    _ => return false,
+                };
+                sel.anchor = start_cidx;
+                view.cursor.char_idx = end_cidx;
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+                view.needs_redraw = true;
+            }
             MotionOrObj::Object(Object::Lines(_)) => unreachable!(),
         }
         let view = self.views.get_mut(id).unwrap();
         view.snap_to_cursor(&self.data, &self.styled_lines);
+        match mo {
+            MotionOrObj::Motion(Motion::LineStart)
+            | MotionOrObj::Motion(Motion::LineEnd)
+            | MotionOrObj::Motion(Motion::ToLine(_))
+            | MotionOrObj::Motion(Motion::FirstNonBlank)
+            | MotionOrObj::Motion(Motion::Percent(_))
+            | MotionOrObj::Motion(Motion::ScreenLine(..))
+            | MotionOrObj::Motion(Motion::Mark(..)) => true,
+            _ => view.cursor.char_idx != cidx,
+        }
     }
 
     pub(crate) fn move_view_cursor_to_point(
@@ -250,6 +527,7 @@ impl Buffer {
         id: &BufferViewID,
         point: Point2D<u32, PixelSize>,
     ) {
+        self.view_clear_cursors(id);
         let view = self.views.get_mut(id).unwrap();
         view.move_cursor_to_point(point, &self.data, &self.styled_lines, self.tab_width);
     }
@@ -262,9 +540,11 @@ impl Buffer {
 
     pub(crate) fn set_view_cursor_style(&mut self, id: &BufferViewID, style: CursorStyle) {
         let view = self.views.get_mut(id).unwrap();
-        view.cursor.style = style;
-        view.cursor
-            .sync_line_cidx_gidx_left(&self.data, self.tab_width);
+        let extra = view.extra_cursors.iter_mut().map(|extra| &mut extra.cursor);
+        for cursor in std::iter::once(&mut view.cursor).chain(extra) {
+            cursor.style = style;
+            cursor.sync_line_cidx_gidx_left(&self.data, self.tab_width);
+        }
         view.snap_to_cursor(&self.data, &self.styled_lines);
     }
 
@@ -282,415 +562,1893 @@ impl Buffer {
                 return self.view_insert_str(id, &s);
             }
         }
+        let is_completion_trigger = self.at_each_cursor(id, |buf| buf.insert_char_at_cursor(id, c));
+
+        // Completion only makes sense for a single cursor
+        let view = &self.views[id];
+        let completion = if is_completion_trigger && view.extra_cursors.is_empty() {
+            CompletionSource::Path.complete(
+                &self.data,
+                view.cursor.char_idx,
+                &self.config,
+                &self.theme,
+            )
+        } else {
+            None
+        };
+        if let Some((start, list)) = completion {
+            self.views
+                .get_mut(id)
+                .unwrap()
+                .start_completion(list, start);
+        }
+    }
+
+    // Insert a character at the main cursor. Returns true if it could trigger completion
+    fn insert_char_at_cursor(&mut self, id: &BufferViewID, c: char) -> bool {
+        let pairs = self.auto_pairs();
+        let view = self.views.get_mut(id).unwrap();
         view.stop_completion();
 
         let cidx = view.cursor.char_idx;
         let linum = view.cursor.line_num;
-        let mut end_linum = linum;
-        let mut end_cidx = cidx + 1;
+        let mut text = String::new();
+        text.push(c);
         let mut cursor_nchars = 1;
 
-        let mut is_completion_trigger = false;
+        let prev = if cidx > 0 {
+            Some(self.data.char(cidx - 1))
+        } else {
+            None
+        };
+        let next = if cidx < self.data.len_chars() {
+            Some(self.data.char(cidx))
+        } else {
+            None
+        };
+        let is_completion_trigger =
+            !c.is_whitespace() && !pairs.iter().any(|(open, close)| *open == c || *close == c);
 
         match c {
-            // Insert pair
-            '[' | '{' | '(' => {
-                self.data.insert_char(cidx, c);
-                if end_cidx >= self.data.len_chars() || self.data.char(end_cidx).is_whitespace() {
-                    match c {
-                        '[' => self.data.insert_char(end_cidx, ']'),
-                        '{' => self.data.insert_char(end_cidx, '}'),
-                        '(' => self.data.insert_char(end_cidx, ')'),
-                        _ => unreachable!(),
-                    }
-                    end_cidx += 1;
-                }
-            }
-            // Maybe insert pair, maybe skip
-            '"' | '\'' => {
-                if cidx >= self.data.len_chars() || self.data.char(cidx) != c {
-                    self.data.insert_char(cidx, c);
-                    self.data.insert_char(cidx + 1, c);
-                    end_cidx += 1;
-                } else {
-                    return self.move_view_cursor(id, MotionOrObj::Motion(Motion::Right(1)));
-                }
-            }
-            // Maybe skip insert
-            ']' | '}' | ')' => {
-                if cidx >= self.data.len_chars() || self.data.char(cidx) != c {
-                    self.data.insert_char(cidx, c);
-                } else {
-                    return self.move_view_cursor(id, MotionOrObj::Motion(Motion::Right(1)));
-                }
-            }
-            // Maybe insert twice?
-            ' ' => {
-                self.data.insert_char(cidx, ' ');
-                if cidx > 0 && end_cidx < self.data.len_chars() {
-                    let c0 = self.data.char(cidx - 1);
-                    let c1 = self.data.char(end_cidx);
-                    if (c0 == '(' && c1 == ')')
-                        || (c0 == '{' && c1 == '}')
-                        || (c0 == '[' && c1 == ']')
-                    {
-                        self.data.insert_char(end_cidx, ' ');
-                        end_cidx += 1;
-                    }
-                }
-            }
-            // Also handle indent
+            // Also open up an empty pair of brackets. The new lines are indented once they're
+            // in the syntax tree
             '\n' => {
-                let (ich, count) = get_indent(&self.data, linum, self.indent_tabs);
-                self.data.insert_char(cidx, '\n');
-                for _ in 0..count {
-                    self.data.insert_char(cidx + 1, ich);
+                let split_pair = is_pair(&pairs, prev, next) && prev != next;
+                if split_pair {
+                    text.push('\n');
                 }
-                end_cidx += count;
-                cursor_nchars += count;
-                end_linum += 1;
-                if cidx > 0 && end_cidx < self.data.len_chars() {
-                    let c0 = self.data.char(cidx - 1);
-                    let c1 = self.data.char(end_cidx);
-                    if (c0 == '(' && c1 == ')')
-                        || (c0 == '{' && c1 == '}')
-                        || (c0 == '[' && c1 == ']')
-                    {
-                        self.data.insert_char(end_cidx, '\n');
-                        for _ in 0..count {
-                            self.data.insert_char(end_cidx + 1, ich);
-                        }
-                        end_linum += 1;
-                        end_cidx += count + 1;
-                    }
+                self.insert_at(cidx, &text);
+                if split_pair {
+                    self.reindent_lines(linum + 2, linum + 2);
                 }
+                let indent = self.line_indent(linum + 1);
+                self.set_line_indent(linum + 1, &indent);
+                let view = self.views.get_mut(id).unwrap();
+                view.cursor.char_idx = self.data.line_to_char(linum + 1) + indent.chars().count();
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+                self.flush_edits();
+                return false;
             }
             '\t' => {
-                if self.indent_tabs {
-                    self.data.insert_char(cidx, '\t');
-                } else {
+                if !self.indent_tabs {
                     let start = view.cursor.line_cidx;
                     let end = (start / self.tab_width) * self.tab_width + self.tab_width;
                     cursor_nchars = end - start;
-                    end_cidx = cidx + cursor_nchars;
-                    for _ in cidx..end_cidx {
-                        self.data.insert_char(cidx, ' ');
+                    text.clear();
+                    for _ in 0..cursor_nchars {
+                        text.push(' ');
                     }
                 }
             }
-            c => {
-                self.data.insert_char(cidx, c);
-                is_completion_trigger = true;
+            _ => {
+                let in_literal = self
+                    .tree
+                    .as_ref()
+                    .map_or(false, |tree| in_string_or_comment(&self.data, tree, cidx));
+                match pair_edit(&pairs, c, prev, next, in_literal) {
+                    PairEdit::Pair(close) => text.push(close),
+                    PairEdit::Skip => {
+                        self.move_view_main_cursor(id, MotionOrObj::Motion(Motion::Right(1)));
+                        return false;
+                    }
+                    PairEdit::Surround => text.push(c),
+                    PairEdit::Insert => {}
+                }
             }
         }
 
-        self.version += 1;
-        self.last_hover = None;
-        if let Some(lc) = &mut self.language_client {
-            let pos = internal_cidx_to_lsp_position(&self.data, cidx);
-            let range = LspRange {
-                start: pos.clone(),
-                end: pos,
-            };
-            if lc.send_full_document_on_change() {
-                lc.change_full(
-                    self.path.as_ref().unwrap(),
-                    self.version,
-                    self.data.to_string(),
-                );
-            } else {
-                lc.change(
-                    self.path.as_ref().unwrap(),
-                    self.version,
-                    range,
-                    self.data.slice(cidx..end_cidx).to_string(),
-                );
-            }
+        self.insert_at(cidx, &text);
+        let view = self.views.get_mut(id).unwrap();
+        view.cursor.char_idx = cidx + cursor_nchars;
+        view.cursor
+            .sync_and_update_char_idx_left(&self.data, self.tab_width);
+        self.reindent_typed(linum, cidx, c);
+        self.flush_edits();
+        is_completion_trigger
+    }
+
+    pub(crate) fn view_insert_str(&mut self, id: &BufferViewID, s: &str) {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+
+        let cidx = view.cursor.char_idx;
+        let end_cidx = cidx + s.chars().count();
+        self.insert_at(cidx, s);
+        self.flush_edits();
+
+        let completion =
+            CompletionSource::Path.complete(&self.data, end_cidx, &self.config, &self.theme);
+        if let Some((start, list)) = completion {
+            self.views
+                .get_mut(id)
+                .unwrap()
+                .start_completion(list, start);
         }
+    }
 
-        let completion = if is_completion_trigger {
-            CompletionSource::Path.complete(&self.data, end_cidx, &self.config, &self.theme)
-        } else {
-            None
-        };
+    // Delete text without storing it in a register, as while typing. Deleting the opening
+    // character of an empty pair deletes the closing one too
+    pub(crate) fn view_delete(&mut self, id: &BufferViewID, mo: MotionOrObj) {
+        let pairs = self.auto_pairs();
+        self.at_each_cursor(id, |buf| {
+            if let MotionOrObj::Motion(Motion::Left(1)) = mo {
+                let cidx = buf.views[id].cursor.char_idx;
+                if cidx > 0 && cidx < buf.data.len_chars() {
+                    let (prev, next) = (buf.data.char(cidx - 1), buf.data.char(cidx));
+                    if is_pair(&pairs, Some(prev), Some(next)) {
+                        buf.remove_range(cidx, cidx + 1);
+                    }
+                }
+            }
+            buf.operate_at_cursor(id, Operator::Delete, mo, Some(BLACK_HOLE));
+        });
+    }
 
-        let fgcol = self.theme.textview.foreground;
-        self.styled_lines[linum] = default_hl_for_line(
-            self.data.line(linum),
-            fgcol,
-            self.tab_width,
-            self.indent_tabs,
+    pub(crate) fn view_operate(
+        &mut self,
+        id: &BufferViewID,
+        op: Operator,
+        mo: MotionOrObj,
+        register: Option<char>,
+    ) {
+        self.at_each_cursor(id, |buf| buf.operate_at_cursor(id, op, mo, register));
+    }
+
+    fn operate_at_cursor(
+        &mut self,
+        id: &BufferViewID,
+        op: Operator,
+        mo: MotionOrObj,
+        register: Option<char>,
+    ) {
+        self.views.get_mut(id).unwrap().stop_completion();
+        let mut range = match self.view_motion_range(id, mo) {
+            Some(range) => range,
+            None => return,
+        };
+        // Like vim, "cw" on a word changes up to the end of the word, leaving the whitespace after
+        // it alone
+        let is_word_motion = matches!(
+            mo,
+            MotionOrObj::Object(Object::Words(_)) | MotionOrObj::Object(Object::WordsExt(_))
         );
-        for i in linum..end_linum {
-            self.styled_lines.insert(
-                i + 1,
-                default_hl_for_line(
-                    self.data.line(i + 1),
-                    fgcol,
-                    self.tab_width,
-                    self.indent_tabs,
-                ),
-            );
+        if let (Operator::Change, true, TextRange::Chars(start_cidx, end_cidx)) =
+            (op, is_word_motion, range)
+        {
+            if start_cidx < end_cidx && !self.data.char(start_cidx).is_whitespace() {
+                let mut end_cidx = end_cidx;
+                while end_cidx > start_cidx + 1 && self.data.char(end_cidx - 1).is_whitespace() {
+                    end_cidx -= 1;
+                }
+                range = TextRange::Chars(start_cidx, end_cidx);
+            }
         }
+        self.apply_operator(id, op, range, register);
+    }
 
-        self.edit_tree(self.data.clone(), cidx, cidx, end_cidx);
-        let (end_byte, end_col) = {
-            let llen = self.data.line(end_linum).len_bytes();
-            let lb = self.data.line_to_byte(end_linum);
-            (lb + llen, llen)
+    pub(crate) fn view_put(
+        &mut self,
+        id: &BufferViewID,
+        register: Option<char>,
+        count: usize,
+        before: bool,
+    ) {
+        let reg = match register {
+            Some('%') => self
+                .path
+                .as_ref()
+                .map(|path| Register::new(RegisterKind::Chars, path.to_owned())),
+            _ => self.registers.borrow().get(register),
         };
-        self.rehighlight_range(tree_sitter::Range {
-            start_byte: self.data.line_to_byte(linum),
-            end_byte,
-            start_point: Point::new(linum, 0),
-            end_point: Point::new(end_linum, end_col),
-        });
+        let reg = match reg {
+            Some(reg) if !reg.text.is_empty() => reg,
+            _ => return,
+        };
+        self.at_each_cursor(id, |buf| buf.put_at_cursor(id, &reg, count, before));
+    }
 
-        for view in self.views.values_mut() {
-            if view.cursor.char_idx >= cidx {
-                view.cursor.char_idx += cursor_nchars;
+    fn put_at_cursor(&mut self, id: &BufferViewID, reg: &Register, count: usize, before: bool) {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+
+        let text = reg.text.repeat(count);
+        match reg.kind {
+            RegisterKind::Chars => {
+                let line_len = rope_trim_newlines(self.data.line(view.cursor.line_num)).len_chars();
+                let cidx = if before || view.cursor.line_cidx >= line_len {
+                    view.cursor.char_idx
+                } else {
+                    view.cursor.char_idx + 1
+                };
+                self.insert_at(cidx, &text);
+                // The cursor ends on the last inserted character, unless multiple lines were put
+                let view = self.views.get_mut(id).unwrap();
+                view.cursor.char_idx = if text.contains('\n') {
+                    cidx
+                } else {
+                    cidx + text.chars().count() - 1
+                };
                 view.cursor
                     .sync_and_update_char_idx_left(&self.data, self.tab_width);
             }
-            if view.is_active {
-                view.reshape(&self.data, &self.styled_lines);
-                view.snap_to_cursor(&self.data, &self.styled_lines);
+            RegisterKind::Lines => {
+                let linum = view.cursor.line_num;
+                let first_linum = if before { linum } else { linum + 1 };
+                if first_linum < self.data.len_lines() {
+                    let cidx = self.data.line_to_char(first_linum);
+                    self.insert_at(cidx, &text);
+                } else {
+                    // Putting after the last line, which has no trailing newline
+                    let mut text = text;
+                    text.pop();
+                    text.insert(0, '\n');
+                    self.insert_at(self.data.len_chars(), &text);
+                }
+                let line = self.data.line(first_linum);
+                let indent = line.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+                let view = self.views.get_mut(id).unwrap();
+                view.cursor.line_num = first_linum;
+                view.cursor.line_cidx = indent;
+                view.cursor
+                    .sync_line_cidx_gidx_left(&self.data, self.tab_width);
+            }
+            RegisterKind::Block => {
+                let (linum, line_cidx) = (view.cursor.line_num, view.cursor.line_cidx);
+                let trimmed = rope_trim_newlines(self.data.line(linum));
+                let col_cidx = if before || line_cidx >= trimmed.len_chars() {
+                    line_cidx
+                } else {
+                    rope_next_grapheme_boundary(&trimmed, line_cidx)
+                };
+                let (_, gidx) = cidx_gidx_from_cidx(&trimmed, col_cidx, self.tab_width);
+                self.put_block(&reg.text, count, linum, gidx);
+                let view = self.views.get_mut(id).unwrap();
+                view.cursor.line_num = linum;
+                view.cursor.line_gidx = gidx;
+                view.cursor.sync_gidx(&self.data, self.tab_width);
             }
         }
+        self.flush_edits();
+    }
 
-        if let Some((start, list)) = completion {
-            self.views
-                .get_mut(id)
-                .unwrap()
-                .start_completion(list, start);
+    // Insert each line of a block register at the given column of successive lines, starting at
+    // linum. Short lines are padded with spaces, and lines are added at the end of the buffer if
+    // required
+    fn put_block(&mut self, text: &str, count: usize, linum: usize, gidx: usize) {
+        let pieces = text.split('\n').collect::<Vec<_>>();
+        let width = pieces
+            .iter()
+            .map(|piece| piece.chars().count())
+            .max()
+            .unwrap_or(0);
+        for (i, piece) in pieces.iter().enumerate() {
+            let linum = linum + i;
+            if linum >= self.data.len_lines() {
+                self.insert_at(self.data.len_chars(), "\n");
+            }
+            let trimmed = rope_trim_newlines(self.data.line(linum));
+            let (cidx, line_gidx) = cidx_gidx_from_gidx(&trimmed, gidx, self.tab_width, true);
+            let at_end = cidx == trimmed.len_chars();
+            let mut s = " ".repeat(gidx.saturating_sub(line_gidx));
+            for _ in 0..count {
+                s.push_str(piece);
+                // Keep the block rectangular if there is text after it
+                if !at_end {
+                    s.push_str(&" ".repeat(width - piece.chars().count()));
+                }
+            }
+            let start_cidx = self.data.line_to_char(linum) + cidx;
+            self.insert_at(start_cidx, &s);
         }
     }
 
-    pub(crate) fn view_insert_str(&mut self, id: &BufferViewID, s: &str) {
-        let view = self.views.get_mut(id).unwrap();
-        view.stop_completion();
-
+    // Text covered by a motion or object from the cursor of the given view
+    fn view_motion_range(&self, id: &BufferViewID, mo: MotionOrObj) -> Option<TextRange> {
+        let view = self.views.get(id).unwrap();
         let cidx = view.cursor.char_idx;
+        let lc = self.data.len_chars();
         let linum = view.cursor.line_num;
-        self.data.insert(cidx, s);
-        let end_cidx = cidx + s.chars().count();
-        let end_linum = self.data.char_to_line(end_cidx);
-
-        self.version += 1;
-        self.last_hover = None;
-        if let Some(lc) = &mut self.language_client {
-            let pos = internal_cidx_to_lsp_position(&self.data, cidx);
-            let range = LspRange {
-                start: pos.clone(),
-                end: pos,
+        let last_linum = self.data.len_lines() - 1;
+        let range = match mo {
+            MotionOrObj::Motion(Motion::Left(n)) => {
+                if cidx == 0 {
+                    return None;
+                }
+                let start_cidx = if cidx <= n { 0 } else { cidx - n };
+                TextRange::Chars(start_cidx, cidx)
+            }
+            MotionOrObj::Motion(Motion::Right(n)) => {
+                if cidx == lc {
+                    return None;
+                }
+                let end_cidx = if cidx + n >= lc { lc } else { cidx + n };
+                TextRange::Chars(cidx, end_cidx)
+            }
+            // Lines of closed folds are all covered
+            MotionOrObj::Motion(Motion::Up(n)) => {
+                let start_linum = view.folds.lines_up(linum, n);
+                TextRange::Lines(start_linum, view.folds.next_line(linum) - 1)
+            }
+            MotionOrObj::Motion(Motion::Down(n)) => {
+                let end_linum = view.folds.lines_down(linum, n, last_linum + 1);
+                let end_linum = view.folds.next_line(min(end_linum, last_linum)) - 1;
+                TextRange::Lines(linum, min(end_linum, last_linum))
+            }
+            MotionOrObj::Motion(Motion::LineStart) => {
+                if view.cursor.line_cidx == 0 {
+                    return None;
+                }
+                TextRange::Chars(cidx - view.cursor.line_cidx, cidx)
+            }
+            MotionOrObj::Motion(Motion::LineEnd) => {
+                let lc = rope_trim_newlines(self.data.line(linum)).len_chars();
+                if view.cursor.line_cidx == lc {
+                    return None;
+                }
+                TextRange::Chars(cidx, cidx + (lc - view.cursor.line_cidx))
+            }
+            MotionOrObj::Motion(Motion::ToLine(n)) => {
+                let n = min(n, last_linum);
+                TextRange::Lines(min(n, linum), std::cmp::max(n, linum))
+            }
+            MotionOrObj::Motion(Motion::Mark(name, linewise)) => {
+                let pos = self.mark_position(id, name)?;
+                if linewise {
+                    let mark_linum = self.data.char_to_line(pos);
+                    TextRange::Lines(min(mark_linum, linum), max(mark_linum, linum))
+                } else {
+                    TextRange::Chars(min(pos, cidx), max(pos, cidx))
+                }
+            }
+            MotionOrObj::Motion(Motion::FirstNonBlank) => {
+                let indent = self
+                    .data
+                    .line(linum)
+                    .chars()
+                    .take_while(|c| *c == ' ' || *c == '\t')
+                    .count();
+                let pos = cidx - view.cursor.line_cidx + indent;
+                TextRange::Chars(min(pos, cidx), max(pos, cidx))
+            }
+            MotionOrObj::Motion(Motion::Percent(n)) => {
+                let target = self.percent_line(n);
+                TextRange::Lines(min(target, linum), max(target, linum))
+            }
+            MotionOrObj::Motion(Motion::ScreenLine(pos, n)) => {
+                let target = view.screen_line(pos, n);
+                TextRange::Lines(min(target, linum), max(target, linum))
+            }
+            MotionOrObj::Motion(Motion::RowUp(n)) | MotionOrObj::Motion(Motion::RowDown(n)) => {
+                let down = matches!(mo, MotionOrObj::Motion(Motion::RowDown(_)));
+                let (target, gidx) = view.move_rows(&self.data, &self.styled_lines, n, down);
+                let trimmed = rope_trim_newlines(self.data.line(target));
+                let (line_cidx, _) = cidx_gidx_from_gidx(&trimmed, gidx, self.tab_width, false);
+                let pos = self.data.line_to_char(target) + line_cidx;
+                TextRange::Chars(min(pos, cidx), max(pos, cidx))
+            }
+            // Forward finds and bracket matches include the character they stop at
+            MotionOrObj::Motion(Motion::FindChar(find, n, repeat)) => {
+                let pos = find_char(&self.data, cidx, find, n, repeat)?;
+                if find.forward {
+                    TextRange::Chars(cidx, pos + 1)
+                } else {
+                    TextRange::Chars(pos, cidx)
+                }
+            }
+            MotionOrObj::Motion(Motion::MatchBracket) => {
+                let pos = matching_bracket(&self.data, self.tree.as_ref(), cidx)?;
+                TextRange::Chars(min(pos, cidx), max(pos, cidx) + 1)
+            }
+            MotionOrObj::Object(Object::Words(n)) => {
+                TextRange::Chars(cidx, nth_word_start(&self.data, cidx, n, false))
+            }
+            MotionOrObj::Object(Object::WordsExt(n)) => {
+                TextRange::Chars(cidx, nth_word_start(&self.data, cidx, n, true))
+            }
+            MotionOrObj::Object(Object::BackWords(n)) => {
+                TextRange::Chars(nth_back_word_start(&self.data, cidx, n, false), cidx)
+            }
+            MotionOrObj::Object(Object::BackWordsExt(n)) => {
+                TextRange::Chars(nth_back_word_start(&self.data, cidx, n, true), cidx)
+            }
+            // Word ends are included
+            MotionOrObj::Object(Object::WordEnds(n)) => {
+                TextRange::Chars(cidx, min(nth_word_end(&self.data, cidx, n, false) + 1, lc))
+            }
+            MotionOrObj::Object(Object::WordEndsExt(n)) => {
+                TextRange::Chars(cidx, min(nth_word_end(&self.data, cidx, n, true) + 1, lc))
+            }
+            MotionOrObj::Object(Object::BackWordEnds(n)) => TextRange::Chars(
+                nth_back_word_end(&self.data, cidx, n, false),
+                min(cidx + 1, lc),
+            ),
+            MotionOrObj::Object(Object::BackWordEndsExt(n)) => TextRange::Chars(
+                nth_back_word_end(&self.data, cidx, n, true),
+                min(cidx + 1, lc),
+            ),
+            MotionOrObj::Object(Object::Paragraphs(n)) => {
+                TextRange::Chars(cidx, paragraph_boundary(&self.data, cidx, true, n))
+            }
+            MotionOrObj::Object(Object::BackParagraphs(n)) => {
+                TextRange::Chars(paragraph_boundary(&self.data, cidx, false, n), cidx)
+            }
+            MotionOrObj::Object(Object::Sentences(n)) => {
+                TextRange::Chars(cidx, sentence_boundary(&self.data, cidx, true, n))
+            }
+            MotionOrObj::Object(Object::BackSentences(n)) => {
+                TextRange::Chars(sentence_boundary(&self.data, cidx, false, n), cidx)
+            }
+            MotionOrObj::Object(Object::Lines(n)) => {
+                if n == 0 {
+                    return None;
+                }
+                TextRange::Lines(linum, min(linum + n - 1, last_linum))
+            }
+            MotionOrObj::Object(Object::Functions(n)) => {
+                TextRange::Chars(cidx, self.function_start(cidx, true, n)?)
+            }
+            MotionOrObj::Object(Object::BackFunctions(n)) => {
+                TextRange::Chars(self.function_start(cidx, false, n)?, cidx)
+            }
+            MotionOrObj::Object(Object::Inner(obj, n)) => {
+                let query = self.queries.as_ref().map(|q| &q.textobjects);
+                text_object_range(&self.data, self.tree.as_ref(), query, cidx, obj, true, n)?
+            }
+            MotionOrObj::Object(Object::Around(obj, n)) => {
+                let query = self.queries.as_ref().map(|q| &q.textobjects);
+                text_object_range(&self.data, self.tree.as_ref(), query, cidx, obj, false, n)?
+            }
+        };
+        Some(range)
+    }
+
+    // Line count percent of the way through the buffer. The count is at most 100
+    fn percent_line(&self, count: usize) -> usize {
+        let len_lines = self.data.len_lines();
+        min((count * len_lines + 99) / 100, len_lines).saturating_sub(1)
+    }
+
+    fn function_start(&self, cidx: usize, forward: bool, count: usize) -> Option<usize> {
+        let (tree, queries) = (self.tree.as_ref()?, self.queries.as_ref()?);
+        function_start(&self.data, tree, &queries.textobjects, cidx, forward, count)
+    }
+
+    // Contents of a text range, as they should be stored in a register. Line-wise contents
+    // always end with a newline
+    fn range_register(&self, range: TextRange) -> Register {
+        match range {
+            TextRange::Chars(start_cidx, end_cidx) => Register::new(
+                RegisterKind::Chars,
+                self.data.slice(start_cidx..end_cidx).to_string(),
+            ),
+            TextRange::Lines(first, last) => {
+                let start_cidx = self.data.line_to_char(first);
+                let end_cidx = if last + 1 < self.data.len_lines() {
+                    self.data.line_to_char(last + 1)
+                } else {
+                    self.data.len_chars()
+                };
+                let mut text = self.data.slice(start_cidx..end_cidx).to_string();
+                if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                Register::new(RegisterKind::Lines, text)
+            }
+            TextRange::Block(..) => {
+                let pieces = self
+                    .range_spans(range)
+                    .into_iter()
+                    .map(|(start_cidx, end_cidx)| self.data.slice(start_cidx..end_cidx).to_string())
+                    .collect::<Vec<_>>();
+                Register::new(RegisterKind::Block, pieces.join("\n"))
+            }
+        }
+    }
+
+    // Char ranges covered by a text range, excluding trailing newlines of line-wise ranges. Block
+    // ranges have one span per line
+    fn range_spans(&self, range: TextRange) -> Vec<(usize, usize)> {
+        match range {
+            TextRange::Chars(start_cidx, end_cidx) => vec![(start_cidx, end_cidx)],
+            TextRange::Lines(first, last) => {
+                let start_cidx = self.data.line_to_char(first);
+                let end_cidx = self.data.line_to_char(last)
+                    + rope_trim_newlines(self.data.line(last)).len_chars();
+                vec![(start_cidx, end_cidx)]
+            }
+            TextRange::Block(first, last, left, right) => (first..=last)
+                .map(|linum| {
+                    let line_cidx = self.data.line_to_char(linum);
+                    let trimmed = rope_trim_newlines(self.data.line(linum));
+                    let (start, end) =
+                        cidx_range_from_gidx_range(&trimmed, left, right, self.tab_width);
+                    (line_cidx + start, line_cidx + end)
+                })
+                .collect(),
+        }
+    }
+
+    // Char range to remove to delete lines first..=last. If there is no line after the range, the
+    // newline before the range is removed instead
+    fn lines_removal_range(&self, first: usize, last: usize) -> (usize, usize) {
+        if last + 1 < self.data.len_lines() {
+            (
+                self.data.line_to_char(first),
+                self.data.line_to_char(last + 1),
+            )
+        } else if first == 0 {
+            (0, self.data.len_chars())
+        } else {
+            (
+                self.data.line_to_char(first - 1)
+                    + rope_trim_newlines(self.data.line(first - 1)).len_chars(),
+                self.data.len_chars(),
+            )
+        }
+    }
+
+    // -------- Visual mode ----------------
+
+    // Start a selection at the cursor, or change the kind of the current selection
+    pub(crate) fn view_start_selection(&mut self, id: &BufferViewID, mode: VisualMode) {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        let anchor = match view.selection {
+            Some(sel) => sel.anchor,
+            None => view.cursor.char_idx,
+        };
+        view.selection = Some(Selection { mode, anchor });
+        for extra in &mut view.extra_cursors {
+            extra.anchor = extra.anchor.or(Some(extra.cursor.char_idx));
+        }
+        view.needs_redraw = true;
+    }
+
+    pub(crate) fn view_stop_selection(&mut self, id: &BufferViewID) {
+        self.stop_main_selection(id);
+        let view = self.views.get_mut(id).unwrap();
+        for extra in &mut view.extra_cursors {
+            extra.anchor = None;
+        }
+    }
+
+    fn stop_main_selection(&mut self, id: &BufferViewID) {
+        let lines = self.views[id]
+            .selection_range(&self.data)
+            .map(|range| self.range_lines(range));
+        let view = self.views.get_mut(id).unwrap();
+        if view.selection.take().is_some() {
+            view.last_selection = lines;
+            view.needs_redraw = true;
+        }
+    }
+
+    pub(crate) fn view_operate_selection(
+        &mut self,
+        id: &BufferViewID,
+        op: Operator,
+        register: Option<char>,
+    ) {
+        self.at_each_cursor(id, |buf| {
+            let view = buf.views.get_mut(id).unwrap();
+            view.stop_completion();
+            let range = match view.selection_range(&buf.data) {
+                Some(range) => range,
+                None => return,
             };
-            if lc.send_full_document_on_change() {
-                lc.change_full(
-                    self.path.as_ref().unwrap(),
-                    self.version,
-                    self.data.to_string(),
-                );
+            buf.stop_main_selection(id);
+            buf.apply_operator(id, op, range, register);
+        });
+    }
+
+    // Join the selected lines. A selection within one line joins it with the next line
+    pub(crate) fn view_join_selection(&mut self, id: &BufferViewID, spaces: bool) {
+        self.at_each_cursor(id, |buf| buf.join_selection(id, spaces));
+    }
+
+    fn join_selection(&mut self, id: &BufferViewID, spaces: bool) {
+        let view = self.views.get_mut(id).unwrap();
+        let (first, last) = match view.selection_range(&self.data) {
+            Some(range) => self.range_lines(range),
+            None => return,
+        };
+        self.stop_main_selection(id);
+        let last = max(last, min(first + 1, self.data.len_lines() - 1));
+        if let Some(cidx) = self.join_lines(first, last, spaces) {
+            let view = self.views.get_mut(id).unwrap();
+            view.cursor.char_idx = cidx;
+            view.cursor
+                .sync_and_update_char_idx_left(&self.data, self.tab_width);
+        }
+        self.flush_edits();
+    }
+
+    // Apply an operator to a range of text from the given view. This is the single entry point
+    // for operations on text ranges, whether they come from motions or selections
+    fn apply_operator(
+        &mut self,
+        id: &BufferViewID,
+        op: Operator,
+        range: TextRange,
+        register: Option<char>,
+    ) {
+        match op {
+            Operator::Yank => {
+                if !self.at_extra_cursor
+                    && !self
+                        .registers
+                        .borrow_mut()
+                        .yank(register, self.range_register(range))
+                {
+                    return;
+                }
+                self.move_view_cursor_to_range_start(id, range);
+                return;
+            }
+            Operator::Delete | Operator::Change => {
+                if !self.at_extra_cursor
+                    && !self
+                        .registers
+                        .borrow_mut()
+                        .delete(register, self.range_register(range))
+                {
+                    return;
+                }
+                self.remove_text_range(id, range, op == Operator::Change);
+            }
+            Operator::Indent | Operator::Outdent => {
+                let (first, last) = self.range_lines(range);
+                self.shift_lines(first, last, op == Operator::Indent);
+                self.move_view_cursor_to_first_non_blank(id, first);
+            }
+            Operator::Reindent => {
+                let (first, last) = self.range_lines(range);
+                self.reindent_lines(first, last);
+                self.move_view_cursor_to_first_non_blank(id, first);
+            }
+            Operator::Comment => {
+                let (first, last) = self.range_lines(range);
+                self.toggle_line_comments(first, last);
+                self.move_view_cursor_to_first_non_blank(id, first);
+            }
+            Operator::Fold => {
+                let (first, last) = self.range_lines(range);
+                self.move_view_cursor_to_range_start(id, range);
+                let view = self.views.get_mut(id).unwrap();
+                view.add_fold(first, last, &self.data, &self.styled_lines);
+                return;
+            }
+            // Surrounding needs a pair as well, and goes through view_surround()
+            Operator::Surround => unreachable!(),
+            Operator::Lowercase | Operator::Uppercase | Operator::ToggleCase => {
+                let spans = self.range_spans(range);
+                for (start_cidx, end_cidx) in spans.iter().cloned().rev() {
+                    let text = self.data.slice(start_cidx..end_cidx).to_string();
+                    let toggled = text
+                        .chars()
+                        .flat_map(|c| {
+                            if op == Operator::Uppercase
+                                || (op == Operator::ToggleCase && c.is_lowercase())
+                            {
+                                c.to_uppercase().collect::<Vec<_>>()
+                            } else {
+                                c.to_lowercase().collect::<Vec<_>>()
+                            }
+                        })
+                        .collect::<String>();
+                    if toggled != text {
+                        self.remove_range(start_cidx, end_cidx);
+                        self.insert_at(start_cidx, &toggled);
+                    }
+                }
+                let view = self.views.get_mut(id).unwrap();
+                view.cursor.char_idx = spans[0].0;
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+        }
+        self.flush_edits();
+    }
+
+    // Remove a text range. If the range is being changed, line-wise ranges leave an empty line
+    // behind (keeping the indentation of the first line) to insert into
+    fn remove_text_range(&mut self, id: &BufferViewID, range: TextRange, change: bool) {
+        match range {
+            TextRange::Chars(start_cidx, end_cidx) => self.remove_range(start_cidx, end_cidx),
+            TextRange::Lines(first, last) if change => {
+                let indent = self
+                    .data
+                    .line(first)
+                    .chars()
+                    .take_while(|c| *c == ' ' || *c == '\t')
+                    .count();
+                let start_cidx = self.data.line_to_char(first) + indent;
+                let end_cidx = self.data.line_to_char(last)
+                    + rope_trim_newlines(self.data.line(last)).len_chars();
+                self.remove_range(start_cidx, max(start_cidx, end_cidx));
+                let view = self.views.get_mut(id).unwrap();
+                view.cursor.char_idx = start_cidx;
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+            TextRange::Lines(first, last) => {
+                let (start_cidx, end_cidx) = self.lines_removal_range(first, last);
+                self.remove_range(start_cidx, end_cidx);
+                self.move_view_cursor_to_first_non_blank(id, self.data.char_to_line(start_cidx));
+            }
+            TextRange::Block(first, _, left, _) => {
+                for (start_cidx, end_cidx) in self.range_spans(range).into_iter().rev() {
+                    self.remove_range(start_cidx, end_cidx);
+                }
+                let view = self.views.get_mut(id).unwrap();
+                view.cursor.line_num = first;
+                view.cursor.line_gidx = left;
+                view.cursor.sync_gidx(&self.data, self.tab_width);
+            }
+        }
+    }
+
+    // Join lines first..=last into one line, replacing each line break and the indentation
+    // after it with at most one space. Without spaces, only the line breaks are removed. Returns
+    // the char index of the last join
+    fn join_lines(&mut self, first: usize, last: usize, spaces: bool) -> Option<usize> {
+        let mut ret = None;
+        for _ in first..last {
+            let trimmed = rope_trim_newlines(self.data.line(first));
+            let end_cidx = self.data.line_to_char(first) + trimmed.len_chars();
+            if !spaces {
+                self.remove_range(end_cidx, self.data.line_to_char(first + 1));
+                ret = Some(end_cidx);
+                continue;
+            }
+            let need_space = trimmed
+                .chars()
+                .last()
+                .map(|c| c != ' ' && c != '\t')
+                .unwrap_or(false);
+            let next = self.data.line(first + 1);
+            let indent = next.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+            let next_char = rope_trim_newlines(next).chars().nth(indent);
+            let next_cidx = self.data.line_to_char(first + 1) + indent;
+            self.remove_range(end_cidx, next_cidx);
+            if need_space && next_char.is_some() && next_char != Some(')') {
+                self.insert_at(end_cidx, " ");
+            }
+            ret = Some(end_cidx);
+        }
+        ret
+    }
+
+    // Lines touched by a text range
+    fn range_lines(&self, range: TextRange) -> (usize, usize) {
+        match range {
+            TextRange::Chars(start_cidx, end_cidx) => (
+                self.data.char_to_line(start_cidx),
+                self.data
+                    .char_to_line(max(start_cidx, end_cidx.saturating_sub(1))),
+            ),
+            TextRange::Lines(first, last) | TextRange::Block(first, last, _, _) => (first, last),
+        }
+    }
+
+    // Yanks leave the cursor at the start of the yanked text, if it was before the cursor
+    fn move_view_cursor_to_range_start(&mut self, id: &BufferViewID, range: TextRange) {
+        let view = self.views.get_mut(id).unwrap();
+        match range {
+            TextRange::Chars(start_cidx, _) if start_cidx < view.cursor.char_idx => {
+                view.cursor.char_idx = start_cidx;
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+            TextRange::Lines(first, _) if first < view.cursor.line_num => {
+                view.cursor.line_num = first;
+                view.cursor.sync_global_x(&self.data, self.tab_width);
+            }
+            TextRange::Block(first, _, left, _) => {
+                view.cursor.line_num = first;
+                view.cursor.line_gidx = left;
+                view.cursor.sync_gidx(&self.data, self.tab_width);
+            }
+            _ => return,
+        }
+        view.snap_to_cursor(&self.data, &self.styled_lines);
+    }
+
+    fn move_view_cursor_to_first_non_blank(&mut self, id: &BufferViewID, linum: usize) {
+        let indent = self
+            .data
+            .line(linum)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        let view = self.views.get_mut(id).unwrap();
+        view.cursor.line_num = linum;
+        view.cursor.line_cidx = indent;
+        view.cursor
+            .sync_line_cidx_gidx_left(&self.data, self.tab_width);
+    }
+
+    // Pairs to insert together for the buffer's language. Empty if auto-pairing is off
+    fn auto_pairs(&self) -> Vec<(char, char)> {
+        let config = self
+            .language
+            .as_ref()
+            .and_then(|language| self.config.language.get(language));
+        let (enabled, pairs) = match config {
+            Some(config) => (config.auto_pair, &config.auto_pairs),
+            None => (self.config.auto_pair, &self.config.auto_pairs),
+        };
+        if enabled {
+            pairs.clone()
+        } else {
+            Vec::new()
+        }
+    }
+
+    // -------- Indentation ----------------
+
+    // Indent or outdent every non-empty line in first..=last by one level
+    fn shift_lines(&mut self, first: usize, last: usize, indent: bool) {
+        let unit = self.indent_unit();
+        for linum in first..=last {
+            let line_cidx = self.data.line_to_char(linum);
+            let trimmed = rope_trim_newlines(self.data.line(linum));
+            if trimmed.len_chars() == 0 {
+                continue;
+            }
+            if indent {
+                self.insert_at(line_cidx, &unit);
+                continue;
+            }
+            let nremove = match trimmed.char(0) {
+                '\t' => 1,
+                _ => trimmed
+                    .chars()
+                    .take(self.tab_width)
+                    .take_while(|c| *c == ' ')
+                    .count(),
+            };
+            self.remove_range(line_cidx, line_cidx + nremove);
+        }
+    }
+
+    fn indent_unit(&self) -> String {
+        if self.indent_tabs {
+            "\t".to_owned()
+        } else {
+            " ".repeat(self.tab_width)
+        }
+    }
+
+    // Indentation for a line from the syntax tree, or from the brackets left open on the line
+    // above it if the tree has an error there
+    fn line_indent(&self, linum: usize) -> String {
+        self.indent_whitespace(linum, self.ts_line_indent(linum))
+    }
+
+    fn ts_line_indent(&self, linum: usize) -> Option<Indent> {
+        let (tree, queries) = (self.tree.as_ref()?, self.queries.as_ref()?);
+        ts_indent(&self.data, tree, &queries.indents, linum)
+    }
+
+    fn indent_whitespace(&self, linum: usize, indent: Option<Indent>) -> String {
+        match indent {
+            Some(Indent::Line(row, levels)) => {
+                leading_whitespace(self.data.line(row)) + &self.indent_unit().repeat(levels)
+            }
+            Some(Indent::Align(row, offset)) => align_whitespace(&self.data, row, offset),
+            Some(Indent::None) => String::new(),
+            None => self.bracket_indent(linum),
+        }
+    }
+
+    // Indentation of the line above, one level more if it leaves a bracket open, and one level
+    // less if this line starts by closing one
+    fn bracket_indent(&self, linum: usize) -> String {
+        let prev = match (0..linum)
+            .rev()
+            .find(|l| first_non_blank(&self.data, *l).is_some())
+        {
+            Some(prev) => prev,
+            None => return String::new(),
+        };
+        let prev_line = rope_trim_newlines(self.data.line(prev));
+        let mut indent = leading_whitespace(prev_line);
+        // A closing bracket at the start of the line above is already accounted for by its
+        // indentation
+        let mut balance = bracket_balance(&prev_line);
+        if starts_with_closing_bracket(prev_line) {
+            balance += 1;
+        }
+        if starts_with_closing_bracket(self.data.line(linum)) {
+            balance -= 1;
+        }
+        match balance.cmp(&0) {
+            Ordering::Greater => indent += &self.indent_unit(),
+            Ordering::Less if indent.ends_with('\t') => {
+                indent.pop();
+            }
+            Ordering::Less => {
+                let nspaces = indent.chars().rev().take_while(|c| *c == ' ').count();
+                indent.truncate(indent.len() - min(nspaces, self.tab_width));
+            }
+            Ordering::Equal => {}
+        }
+        indent
+    }
+
+    fn set_line_indent(&mut self, linum: usize, indent: &str) {
+        let line_cidx = self.data.line_to_char(linum);
+        let trimmed = rope_trim_newlines(self.data.line(linum));
+        let old = leading_whitespace(trimmed);
+        if old != indent {
+            self.remove_range(line_cidx, line_cidx + old.chars().count());
+            self.insert_at(line_cidx, indent);
+        }
+    }
+
+    // Open a line below the cursor's line, or above it, and move the cursor there
+    pub(crate) fn view_open_line(&mut self, id: &BufferViewID, above: bool) {
+        self.at_each_cursor(id, |buf| {
+            let view = buf.views.get_mut(id).unwrap();
+            view.stop_completion();
+            let linum = view.cursor.line_num;
+            let (cidx, linum) = if above {
+                (buf.data.line_to_char(linum), linum)
             } else {
-                lc.change(
-                    self.path.as_ref().unwrap(),
-                    self.version,
-                    range,
-                    self.data.slice(cidx..end_cidx).to_string(),
-                );
+                let trimmed = rope_trim_newlines(buf.data.line(linum));
+                (
+                    buf.data.line_to_char(linum) + trimmed.len_chars(),
+                    linum + 1,
+                )
+            };
+            buf.insert_at(cidx, "\n");
+            let indent = buf.line_indent(linum);
+            buf.set_line_indent(linum, &indent);
+            let view = buf.views.get_mut(id).unwrap();
+            view.cursor.char_idx = buf.data.line_to_char(linum) + indent.chars().count();
+            view.cursor
+                .sync_and_update_char_idx_left(&buf.data, buf.tab_width);
+            buf.flush_edits();
+        });
+    }
+
+    // Set the indentation of lines first..=last. Blank lines lose their indentation. The syntax
+    // tree is used as it is before any line moves, since in a language like Python, moving a
+    // line can move the lines after it to a different block
+    fn reindent_lines(&mut self, first: usize, last: usize) {
+        let indents = (first..=last)
+            .map(|linum| self.ts_line_indent(linum))
+            .collect::<Vec<_>>();
+        for (linum, indent) in (first..=last).zip(indents) {
+            let indent = if first_non_blank(&self.data, linum).is_some() {
+                self.indent_whitespace(linum, indent)
+            } else {
+                String::new()
+            };
+            self.set_line_indent(linum, &indent);
+        }
+    }
+
+    // Re-indent a line after typing a character at cidx, if it's one of the language's triggers
+    fn reindent_typed(&mut self, linum: usize, cidx: usize, c: char) {
+        let (at_start, anywhere) = match self.language {
+            Some(language) => language.indent_triggers(),
+            None => return,
+        };
+        let line_cidx = self.data.line_to_char(linum);
+        if at_start.contains(c) && first_non_blank(&self.data, linum) == Some(cidx - line_cidx) {
+            self.reindent_lines(linum, linum);
+        } else if anywhere.contains(c) {
+            if let Some(indent) = self.dedent_to_parse(linum) {
+                self.set_line_indent(linum, &indent);
             }
         }
+    }
 
-        let fgcol = self.theme.textview.foreground;
-        self.styled_lines[linum] = default_hl_for_line(
-            self.data.line(linum),
-            fgcol,
-            self.tab_width,
-            self.indent_tabs,
-        );
-        for i in linum..end_linum {
-            self.styled_lines.insert(
-                i + 1,
-                default_hl_for_line(
-                    self.data.line(i + 1),
-                    fgcol,
-                    self.tab_width,
-                    self.indent_tabs,
-                ),
-            );
+    // For a line inside a syntax error, the indentation of the nearest line above it which is
+    // indented less, and where it parses without the error
+    fn dedent_to_parse(&mut self, linum: usize) -> Option<String> {
+        let (tree, parser) = (self.tree.as_ref()?, self.parser.as_mut()?);
+        let line_cidx = self.data.line_to_char(linum);
+        let offset = first_non_blank(&self.data, linum)?;
+        if !in_error(&self.data, tree, line_cidx + offset) {
+            return None;
+        }
+        let line_bidx = self.data.line_to_byte(linum);
+        let old_len = self.data.line(linum).slice(..offset).len_bytes();
+        let mut width = offset;
+        for prev in (0..linum).rev() {
+            let prev_offset = match first_non_blank(&self.data, prev) {
+                Some(prev_offset) if prev_offset < width => prev_offset,
+                _ => continue,
+            };
+            width = prev_offset;
+            let indent = self.data.line(prev).slice(..prev_offset).to_string();
+            let mut data = self.data.clone();
+            data.remove(line_cidx..line_cidx + offset);
+            data.insert(line_cidx, &indent);
+            // Only the indentation changes, so the rest of the tree is reused
+            let mut old_tree = tree.clone();
+            old_tree.edit(&InputEdit {
+                start_byte: line_bidx,
+                old_end_byte: line_bidx + old_len,
+                new_end_byte: line_bidx + indent.len(),
+                start_position: Point::new(linum, 0),
+                old_end_position: Point::new(linum, old_len),
+                new_end_position: Point::new(linum, indent.len()),
+            });
+            let tree = parse_rope(parser, &data, Some(&old_tree));
+            if !in_error(&data, &tree, line_cidx + prev_offset) {
+                return Some(indent);
+            }
+            if width == 0 {
+                break;
+            }
+        }
+        None
+    }
+
+    // -------- Comments ----------------
+
+    fn toggle_line_comments(&mut self, first: usize, last: usize) {
+        let lines = (first..=last)
+            .map(|linum| rope_trim_newlines(self.data.line(linum)).to_string())
+            .collect::<Vec<_>>();
+        // Take the comment syntax from the first non-blank line, for embedded languages
+        let cidx = (first..=last)
+            .find_map(|linum| {
+                first_non_blank(&self.data, linum).map(|off| self.data.line_to_char(linum) + off)
+            })
+            .unwrap_or_else(|| self.data.line_to_char(first));
+        let tokens = match self.comment_tokens_at(cidx) {
+            Some(tokens) => tokens,
+            None => return,
+        };
+        for (i, off, len, text) in toggle_comments(&lines, &tokens).into_iter().rev() {
+            let cidx = self.data.line_to_char(first + i) + off;
+            if len > 0 {
+                self.remove_range(cidx, cidx + len);
+            }
+            if !text.is_empty() {
+                self.insert_at(cidx, &text);
+            }
+        }
+    }
+
+    // Comment tokens for the language at a char, from the config if it overrides them
+    fn comment_tokens_at(&self, cidx: usize) -> Option<CommentTokens> {
+        let mut language = self.language?;
+        if let Some(tree) = &self.tree {
+            language = language_at(&self.data, tree, language, cidx);
+        }
+        Some(match self.config.language.get(&language) {
+            Some(config) => config.comment_tokens.clone(),
+            None => language.comment_tokens(),
+        })
+    }
+
+    // -------- Surround ----------------
+
+    // Add a pair around the text covered by a motion, or replace the pair around the cursor.
+    // Returns false if there was nothing to surround
+    pub(crate) fn view_surround(
+        &mut self,
+        id: &BufferViewID,
+        edit: SurroundEdit,
+        surround: &Surround,
+    ) -> bool {
+        self.at_each_cursor(id, |buf| buf.surround_at_cursor(id, edit, surround))
+    }
+
+    fn surround_at_cursor(
+        &mut self,
+        id: &BufferViewID,
+        edit: SurroundEdit,
+        surround: &Surround,
+    ) -> bool {
+        self.views.get_mut(id).unwrap().stop_completion();
+        let (open, close) = surround_delimiters(surround);
+        let start_cidx = match edit {
+            SurroundEdit::Add(mo) => {
+                let (start_cidx, end_cidx) = match self.surround_range(id, mo) {
+                    Some(range) => range,
+                    None => return false,
+                };
+                self.insert_at(end_cidx, &close);
+                self.insert_at(start_cidx, &open);
+                start_cidx
+            }
+            SurroundEdit::Change(target) => {
+                let (open_range, close_range) = match self.surround_target_ranges(id, target) {
+                    Some(ranges) => ranges,
+                    None => return false,
+                };
+                self.replace_text(close_range, &close);
+                self.replace_text(open_range, &open);
+                open_range.0
+            }
+        };
+        self.move_view_cursor_to_cidx(id, start_cidx);
+        self.flush_edits();
+        true
+    }
+
+    pub(crate) fn view_delete_surround(
+        &mut self,
+        id: &BufferViewID,
+        target: SurroundTarget,
+    ) -> bool {
+        self.at_each_cursor(id, |buf| {
+            let (open_range, close_range) = match buf.surround_target_ranges(id, target) {
+                Some(ranges) => ranges,
+                None => return false,
+            };
+            buf.remove_range(close_range.0, close_range.1);
+            buf.remove_range(open_range.0, open_range.1);
+            buf.move_view_cursor_to_cidx(id, open_range.0);
+            buf.flush_edits();
+            true
+        })
+    }
+
+    // Chars to surround for a motion. Whitespace at the end of the text is left out, and lines
+    // are surrounded without their indentation
+    fn surround_range(&self, id: &BufferViewID, mo: MotionOrObj) -> Option<(usize, usize)> {
+        let (start_cidx, mut end_cidx) = match self.view_motion_range(id, mo)? {
+            TextRange::Chars(start_cidx, end_cidx) => (start_cidx, end_cidx),
+            TextRange::Lines(first, last) | TextRange::Block(first, last, _, _) => {
+                let start_cidx =
+                    self.data.line_to_char(first) + first_non_blank(&self.data, first)?;
+                let end_cidx = self.data.line_to_char(last)
+                    + rope_trim_newlines(self.data.line(last)).len_chars();
+                (start_cidx, end_cidx)
+            }
+        };
+        while end_cidx > start_cidx && self.data.char(end_cidx - 1).is_whitespace() {
+            end_cidx -= 1;
+        }
+        if start_cidx < end_cidx {
+            Some((start_cidx, end_cidx))
+        } else {
+            None
+        }
+    }
+
+    // Ranges of the opening and closing delimiters of the pair around the cursor. Trimming takes
+    // in the whitespace inside the pair on the same line
+    fn surround_target_ranges(
+        &self,
+        id: &BufferViewID,
+        target: SurroundTarget,
+    ) -> Option<((usize, usize), (usize, usize))> {
+        let cidx = self.views[id].cursor.char_idx;
+        let ((open_start, mut open_end), (mut close_start, close_end)) = delimiter_ranges(
+            &self.data,
+            self.tree.as_ref(),
+            cidx,
+            target.obj,
+            target.count,
+        )?;
+        if target.trim {
+            let is_blank = |c: char| c == ' ' || c == '\t';
+            while open_end < close_start && is_blank(self.data.char(open_end)) {
+                open_end += 1;
+            }
+            while close_start > open_end && is_blank(self.data.char(close_start - 1)) {
+                close_start -= 1;
+            }
+        }
+        Some(((open_start, open_end), (close_start, close_end)))
+    }
+
+    // Replace a range of text, only touching the chars which change
+    fn replace_text(&mut self, (start_cidx, end_cidx): (usize, usize), text: &str) {
+        let old = self.data.slice(start_cidx..end_cidx).to_string();
+        let (skip, len, text) = minimal_edit(&old, text);
+        self.remove_range(start_cidx + skip, start_cidx + skip + len);
+        self.insert_at(start_cidx + skip, &text);
+    }
+
+    // -------- Small edits ----------------
+
+    // Replace n characters from the cursor with c. A line break replaces all of them with one
+    // line break. Returns false if the line doesn't have enough characters
+    pub(crate) fn view_replace_char(&mut self, id: &BufferViewID, c: char, n: usize) -> bool {
+        self.at_each_cursor(id, |buf| buf.replace_char_at_cursor(id, c, n))
+    }
+
+    fn replace_char_at_cursor(&mut self, id: &BufferViewID, c: char, n: usize) -> bool {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        let cidx = view.cursor.char_idx;
+        let line_len = rope_trim_newlines(self.data.line(view.cursor.line_num)).len_chars();
+        if n == 0 || view.cursor.line_cidx + n > line_len {
+            return false;
+        }
+        self.remove_range(cidx, cidx + n);
+        let cursor_cidx = if c == '\n' {
+            self.insert_at(cidx, "\n");
+            cidx + 1
+        } else {
+            self.insert_at(cidx, &c.to_string().repeat(n));
+            cidx + n - 1
+        };
+        let view = self.views.get_mut(id).unwrap();
+        view.cursor.char_idx = cursor_cidx;
+        view.cursor
+            .sync_and_update_char_idx_left(&self.data, self.tab_width);
+        self.flush_edits();
+        true
+    }
+
+    // Join n lines from the cursor line, and at least two. Returns false on the last line
+    pub(crate) fn view_join(&mut self, id: &BufferViewID, spaces: bool, n: usize) -> bool {
+        self.at_each_cursor(id, |buf| {
+            let view = buf.views.get_mut(id).unwrap();
+            view.stop_completion();
+            let first = view.cursor.line_num;
+            let last = min(first + max(n, 2) - 1, buf.data.len_lines() - 1);
+            let cidx = match buf.join_lines(first, last, spaces) {
+                Some(cidx) => cidx,
+                None => return false,
+            };
+            let view = buf.views.get_mut(id).unwrap();
+            view.cursor.char_idx = cidx;
+            view.cursor
+                .sync_and_update_char_idx_left(&buf.data, buf.tab_width);
+            buf.flush_edits();
+            true
+        })
+    }
+
+    // Toggle the case of n characters from the cursor, and move past them
+    pub(crate) fn view_toggle_case(&mut self, id: &BufferViewID, n: usize) {
+        self.at_each_cursor(id, |buf| {
+            let mo = MotionOrObj::Motion(Motion::Right(n));
+            if let Some(range) = buf.view_motion_range(id, mo) {
+                buf.apply_operator(id, Operator::ToggleCase, range, None);
+                buf.move_view_main_cursor(id, mo);
+            }
+        });
+    }
+
+    // Add delta to the number under or after the cursor, and put the cursor on its last
+    // character. Returns false if there is no number on the rest of the line
+    pub(crate) fn view_add_to_number(&mut self, id: &BufferViewID, delta: i64) -> bool {
+        self.at_each_cursor(id, |buf| buf.add_to_number_at_cursor(id, delta))
+    }
+
+    fn add_to_number_at_cursor(&mut self, id: &BufferViewID, delta: i64) -> bool {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        let linum = view.cursor.line_num;
+        let line = rope_trim_newlines(self.data.line(linum));
+        let chars = line.chars().collect::<Vec<_>>();
+        let (start, end, text) = match add_to_number(&chars, view.cursor.line_cidx, delta) {
+            Some(change) => change,
+            None => return false,
+        };
+        let line_start = self.data.line_to_char(linum);
+        self.remove_range(line_start + start, line_start + end);
+        self.insert_at(line_start + start, &text);
+        let view = self.views.get_mut(id).unwrap();
+        view.cursor.char_idx = line_start + start + text.chars().count() - 1;
+        view.cursor
+            .sync_and_update_char_idx_left(&self.data, self.tab_width);
+        self.flush_edits();
+        true
+    }
+
+    // -------- Replace mode ----------------
+
+    // Replace mode only works with the main cursor
+    pub(crate) fn view_start_replace(&mut self, id: &BufferViewID) {
+        self.view_clear_cursors(id);
+        self.views.get_mut(id).unwrap().replaced.clear();
+    }
+
+    // Overwrite the character under the cursor. Past the end of the line, and for line breaks,
+    // the character is inserted instead
+    pub(crate) fn view_replace_mode_char(&mut self, id: &BufferViewID, c: char) {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        let cidx = view.cursor.char_idx;
+        let line_len = rope_trim_newlines(self.data.line(view.cursor.line_num)).len_chars();
+        let replaced = if c != '\n' && view.cursor.line_cidx < line_len {
+            Some(self.data.char(cidx))
+        } else {
+            None
+        };
+        if replaced.is_some() {
+            self.remove_range(cidx, cidx + 1);
+        }
+        self.insert_at(cidx, c.encode_utf8(&mut [0; 4]));
+        let view = self.views.get_mut(id).unwrap();
+        view.replaced.push((cidx, replaced));
+        view.cursor.char_idx = cidx + 1;
+        view.cursor
+            .sync_and_update_char_idx_left(&self.data, self.tab_width);
+        self.flush_edits();
+    }
+
+    // Restore the character overwritten just before the cursor. If the cursor was moved since,
+    // only move left
+    pub(crate) fn view_replace_mode_backspace(&mut self, id: &BufferViewID) {
+        let view = self.views.get_mut(id).unwrap();
+        let cidx = view.cursor.char_idx;
+        let (pos, replaced) = match view.replaced.last() {
+            Some((pos, replaced)) if pos + 1 == cidx => (*pos, *replaced),
+            _ => {
+                self.move_view_main_cursor(id, MotionOrObj::Motion(Motion::Left(1)));
+                return;
+            }
+        };
+        view.replaced.pop();
+        self.remove_range(pos, cidx);
+        if let Some(c) = replaced {
+            self.insert_at(pos, c.encode_utf8(&mut [0; 4]));
+        }
+        let view = self.views.get_mut(id).unwrap();
+        view.cursor.char_idx = pos;
+        view.cursor
+            .sync_and_update_char_idx_left(&self.data, self.tab_width);
+        self.flush_edits();
+    }
+
+    // -------- Multiple cursors ----------------
+
+    // Run f once for each cursor of the view, with that cursor swapped in as the main one. Edits
+    // made at all cursors are flushed together, so they are one undo step and one batch of
+    // changes for the language server. Yanked and deleted text from the main cursor is what goes
+    // in to registers. Returns what f returned for the main cursor
+    fn at_each_cursor<T, F>(&mut self, id: &BufferViewID, mut f: F) -> T
+    where
+        F: FnMut(&mut Buffer) -> T,
+    {
+        let view = &self.views[id];
+        if view.extra_cursors.is_empty() {
+            return f(self);
+        }
+        let mode = view.selection.map(|sel| sel.mode);
+        self.batching_edits = true;
+        let ret = f(self);
+        let last_selection = self.views[id].last_selection;
+        self.at_extra_cursor = true;
+        for i in 0..self.views[id].extra_cursors.len() {
+            self.views.get_mut(id).unwrap().swap_cursor(i, mode);
+            f(self);
+            self.views.get_mut(id).unwrap().swap_cursor(i, mode);
+        }
+        self.at_extra_cursor = false;
+        let view = self.views.get_mut(id).unwrap();
+        view.last_selection = last_selection;
+        view.merge_cursors();
+        self.batching_edits = false;
+        self.flush_edits();
+        let view = self.views.get_mut(id).unwrap();
+        view.snap_to_cursor(&self.data, &self.styled_lines);
+        ret
+    }
+
+    // Add cursors on the lines above the topmost cursor, or below the bottom-most one. Returns
+    // false if there was no line to add a cursor to
+    pub(crate) fn view_add_cursor_line(
+        &mut self,
+        id: &BufferViewID,
+        below: bool,
+        n: usize,
+    ) -> bool {
+        let last_linum = self.data.len_lines() - 1;
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        let mut added = false;
+        for _ in 0..n {
+            let extra = view.extra_cursors.iter().map(|extra| &extra.cursor);
+            let cursors = std::iter::once(&view.cursor).chain(extra);
+            let edge = if below {
+                cursors.max_by_key(|cursor| cursor.line_num)
+            } else {
+                cursors.min_by_key(|cursor| cursor.line_num)
+            };
+            let mut cursor = edge.unwrap().clone();
+            match (below, cursor.line_num) {
+                (true, linum) if linum < last_linum => cursor.line_num += 1,
+                (false, linum) if linum > 0 => cursor.line_num -= 1,
+                _ => break,
+            }
+            cursor.sync_global_x(&self.data, self.tab_width);
+            let anchor = view.selection.map(|_| cursor.char_idx);
+            view.extra_cursors.push(ExtraCursor { cursor, anchor });
+            added = true;
+        }
+        view.needs_redraw = true;
+        added
+    }
+
+    // Add cursors at the next occurrences of the word under the main cursor, at the same offset
+    // into the word. Returns false if there were no more occurrences
+    pub(crate) fn view_add_cursor_at_word(&mut self, id: &BufferViewID, n: usize) -> bool {
+        let (start, end) = match self.view_cursor_word(id) {
+            Some(range) => range,
+            None => return false,
+        };
+        let pattern = format!("\\<{}\\>", self.data.slice(start..end));
+        let regex = match Regex::new(&pattern, false) {
+            Ok(regex) => regex,
+            Err(_) => return false,
+        };
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        let offset = view.cursor.char_idx.saturating_sub(start);
+        let mut added = false;
+        for _ in 0..n {
+            let from = view
+                .extra_cursors
+                .last()
+                .map_or(start, |extra| extra.cursor.char_idx);
+            let cidx = match find_nth(&self.data, &regex, from, true, 1) {
+                Some((match_start, _)) => match_start + offset,
+                None => break,
+            };
+            // Searches wrap around, so this is where we run out of occurrences
+            let mut extra = view.extra_cursors.iter().map(|extra| &extra.cursor);
+            if view.cursor.char_idx == cidx || extra.any(|cursor| cursor.char_idx == cidx) {
+                break;
+            }
+            let mut cursor = view.cursor.clone();
+            cursor.char_idx = cidx;
+            cursor.sync_and_update_char_idx_left(&self.data, self.tab_width);
+            let anchor = view.selection.map(|_| cidx);
+            view.extra_cursors.push(ExtraCursor { cursor, anchor });
+            added = true;
+        }
+        view.needs_redraw = true;
+        added
+    }
+
+    // Replace the selections with a cursor at the start of each selected line
+    pub(crate) fn view_split_selection(&mut self, id: &BufferViewID) {
+        let view = &self.views[id];
+        let mut ranges = view.extra_selection_ranges(&self.data);
+        ranges.extend(view.selection_range(&self.data));
+        let mut starts = Vec::new();
+        for range in ranges {
+            match range {
+                TextRange::Chars(start_cidx, _) => {
+                    let (first, last) = self.range_lines(range);
+                    starts.push(start_cidx);
+                    starts.extend((first + 1..=last).map(|linum| self.data.line_to_char(linum)));
+                }
+                TextRange::Lines(first, last) => {
+                    starts.extend((first..=last).map(|linum| self.data.line_to_char(linum)))
+                }
+                TextRange::Block(..) => {
+                    starts.extend(self.range_spans(range).into_iter().map(|(start, _)| start))
+                }
+            }
+        }
+        self.view_stop_selection(id);
+        starts.sort_unstable();
+        starts.dedup();
+        let view = self.views.get_mut(id).unwrap();
+        let (data, tab_width) = (&self.data, self.tab_width);
+        let mut cursors = starts.into_iter().map(|cidx| {
+            let mut cursor = view.cursor.clone();
+            cursor.char_idx = cidx;
+            cursor.sync_and_update_char_idx_left(data, tab_width);
+            cursor
+        });
+        let main = match cursors.next() {
+            Some(cursor) => cursor,
+            None => return,
+        };
+        let extra_cursors = cursors
+            .map(|cursor| ExtraCursor {
+                cursor,
+                anchor: None,
+            })
+            .collect();
+        view.cursor = main;
+        view.extra_cursors = extra_cursors;
+        view.needs_redraw = true;
+        view.snap_to_cursor(&self.data, &self.styled_lines);
+    }
+
+    pub(crate) fn view_clear_cursors(&mut self, id: &BufferViewID) {
+        let view = self.views.get_mut(id).unwrap();
+        if !view.extra_cursors.is_empty() {
+            view.extra_cursors.clear();
+            view.needs_redraw = true;
+        }
+    }
+
+    // -------- Search ----------------
+
+    pub(crate) fn set_search_pattern(&mut self, pattern: Option<&str>) {
+        let pattern = pattern.filter(|p| !p.is_empty());
+        if self.search_regex.as_ref().map(|r| r.as_str()) == pattern {
+            return;
+        }
+        // Patterns which are still being typed in may not be valid yet
+        self.search_regex = pattern.and_then(|p| Regex::new(p, false).ok());
+        self.highlight_search_matches(0, self.styled_lines.len() - 1);
+        for view in self.views.values_mut() {
+            view.needs_redraw = true;
+        }
+    }
+
+    // Move the cursor to the count'th match of the pattern
+    pub(crate) fn view_search(
+        &mut self,
+        id: &BufferViewID,
+        pattern: &str,
+        forward: bool,
+        count: usize,
+    ) -> bool {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        let regex = match Regex::new(pattern, false) {
+            Ok(regex) => regex,
+            Err(_) => return false,
+        };
+        match find_nth(&self.data, &regex, view.cursor.char_idx, forward, count) {
+            Some((start_cidx, _)) => {
+                self.move_view_cursor_to_cidx(id, start_cidx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Search for the word under or after the cursor, and return the pattern used
+    pub(crate) fn view_search_word(
+        &mut self,
+        id: &BufferViewID,
+        forward: bool,
+        count: usize,
+    ) -> Option<String> {
+        let (start, end) = self.view_cursor_word(id)?;
+        let pattern = format!("\\<{}\\>", self.data.slice(start..end));
+        // Searching backwards from the middle of the word would find its own start
+        self.move_view_cursor_to_cidx(id, start);
+        self.view_search(id, &pattern, forward, count);
+        Some(pattern)
+    }
+
+    // Char range of the word under or after the cursor, on the cursor's line
+    fn view_cursor_word(&self, id: &BufferViewID) -> Option<(usize, usize)> {
+        let view = &self.views[id];
+        let linum = view.cursor.line_num;
+        let line = rope_trim_newlines(self.data.line(linum));
+        let is_word = |c: char| c == '_' || c.is_alphanumeric();
+        let mut start =
+            (view.cursor.line_cidx..line.len_chars()).find(|i| is_word(line.char(*i)))?;
+        while start > 0 && is_word(line.char(start - 1)) {
+            start -= 1;
+        }
+        let mut end = start;
+        while end < line.len_chars() && is_word(line.char(end)) {
+            end += 1;
+        }
+        let line_start = self.data.line_to_char(linum);
+        Some((line_start + start, line_start + end))
+    }
+
+    // Line of the next match after the cursor line, or of the previous match before it. This is
+    // for pattern addresses in command ranges, so the cursor stays where it is
+    pub(crate) fn view_search_line(
+        &self,
+        id: &BufferViewID,
+        pattern: &str,
+        forward: bool,
+    ) -> Result<usize, String> {
+        let regex = Regex::new(pattern, false)?;
+        let linum = self.views[id].cursor.line_num;
+        let cidx = if forward {
+            line_end(&self.data, linum) - 1
+        } else {
+            self.data.line_to_char(linum)
+        };
+        find_nth(&self.data, &regex, cidx, forward, 1)
+            .map(|(start_cidx, _)| self.data.char_to_line(start_cidx))
+            .ok_or_else(|| format!("pattern not found: {}", pattern))
+    }
+
+    // Incremental search. The cursor moves to the first match after the position the search
+    // started at, while the pattern is being typed
+    pub(crate) fn view_search_preview(&mut self, id: &BufferViewID, pattern: &str, forward: bool) {
+        let view = self.views.get_mut(id).unwrap();
+        let cidx = view.cursor.char_idx;
+        let origin = *view.search_origin.get_or_insert(cidx);
+        let cidx = Regex::new(pattern, false)
+            .ok()
+            .and_then(|regex| find_nth(&self.data, &regex, origin, forward, 1))
+            .map(|(start_cidx, _)| start_cidx)
+            .unwrap_or(origin);
+        self.move_view_cursor_to_cidx(id, cidx);
+        self.set_search_pattern(Some(pattern));
+    }
+
+    // Go back to where the incremental search started
+    pub(crate) fn view_stop_search_preview(&mut self, id: &BufferViewID) {
+        if let Some(origin) = self.views.get_mut(id).unwrap().search_origin.take() {
+            self.move_view_cursor_to_cidx(id, origin);
+        }
+    }
+
+    fn move_view_cursor_to_cidx(&mut self, id: &BufferViewID, cidx: usize) {
+        let view = self.views.get_mut(id).unwrap();
+        view.cursor.char_idx = cidx;
+        view.cursor
+            .sync_and_update_char_idx_left(&self.data, self.tab_width);
+        view.snap_to_cursor(&self.data, &self.styled_lines);
+    }
+
+    // Mark matches of the search pattern which start on lines first..=last
+    fn highlight_search_matches(&mut self, first: usize, last: usize) {
+        let last = min(last, self.styled_lines.len() - 1);
+        if first > last {
+            return;
+        }
+        for styled in &mut self.styled_lines[first..=last] {
+            styled.clear_backgrounds();
+        }
+        let regex = match &self.search_regex {
+            Some(regex) => regex,
+            None => return,
+        };
+        let color = Some(self.theme.textview.search_match);
+        let start_cidx = self.data.line_to_char(first);
+        let end_cidx = self.data.line_to_char(last) + self.data.line(last).len_chars();
+        for (mstart, mend) in find_all(&self.data, regex, start_cidx, end_cidx) {
+            let (first, last) = (self.data.char_to_line(mstart), self.data.char_to_line(mend));
+            for linum in first..=min(last, self.styled_lines.len() - 1) {
+                let line_start = self.data.line_to_char(linum);
+                let len_chars = rope_trim_newlines(self.data.line(linum)).len_chars();
+                let start = mstart.saturating_sub(line_start);
+                let end = min(mend - line_start, len_chars);
+                if start < end {
+                    self.styled_lines[linum].set_background(start..end, color);
+                }
+            }
+        }
+    }
+
+    // -------- Substitute ----------------
+
+    // Replace matches of the pattern which start on lines first..=last, as a single edit. Only
+    // the first match on each line is replaced unless global is set. The cursor moves to the
+    // line of the last substitution. Returns the number of substitutions
+    pub(crate) fn view_substitute(
+        &mut self,
+        id: &BufferViewID,
+        (first, last): (usize, usize),
+        pattern: &str,
+        replacement: &str,
+        global: bool,
+        ignore_case: bool,
+    ) -> Result<usize, String> {
+        self.views.get_mut(id).unwrap().stop_completion();
+        let regex = Regex::new(pattern, ignore_case)?;
+        let last = min(last, self.data.len_lines() - 1);
+        let end_cidx = line_end(&self.data, last);
+        // Matches are all found before any replacement, so replacements are never matched
+        let mut matches: Vec<(usize, usize, String)> = Vec::new();
+        let mut cidx = self.data.line_to_char(first);
+        while let Some(captures) = regex.find(&self.data, cidx, end_cidx) {
+            let (start, end) = captures.range();
+            // Like Vim, no empty match right after the previous match
+            if start == end && matches.last().map(|m| m.1) == Some(start) {
+                cidx = start + 1;
+                continue;
+            }
+            matches.push((start, end, captures.expand(&self.data, replacement)));
+            cidx = if !global {
+                max(end, line_end(&self.data, self.data.char_to_line(start)))
+            } else if end > start {
+                end
+            } else {
+                start + 1
+            };
+        }
+        let (last_start, _, _) = match matches.last() {
+            Some(m) => m,
+            None => return Err(format!("pattern not found: {}", pattern)),
+        };
+        // Earlier matches shift the start of the last one
+        let shift = matches[..matches.len() - 1]
+            .iter()
+            .map(|(start, end, text)| text.chars().count() as isize - (end - start) as isize)
+            .sum::<isize>();
+        let last_start = (*last_start as isize + shift) as usize;
+        // Replacing from the end keeps the positions of earlier matches valid
+        for (start, end, text) in matches.iter().rev() {
+            self.remove_range(*start, *end);
+            self.insert_at(*start, text);
+        }
+        self.move_view_cursor_to_first_non_blank(id, self.data.char_to_line(last_start));
+        self.flush_edits();
+        Ok(matches.len())
+    }
+
+    // -------- Undo/redo ----------------
+
+    pub(crate) fn view_undo(&mut self, id: &BufferViewID, n: usize) {
+        self.view_clear_cursors(id);
+        self.views.get_mut(id).unwrap().stop_completion();
+        self.commit_undo_group();
+        for _ in 0..n {
+            match self.history.undo() {
+                Some(step) => self.apply_history_step(step),
+                None => break,
+            }
+        }
+        self.flush_edits();
+    }
+
+    pub(crate) fn view_redo(&mut self, id: &BufferViewID, n: usize) {
+        self.view_clear_cursors(id);
+        self.views.get_mut(id).unwrap().stop_completion();
+        self.commit_undo_group();
+        for _ in 0..n {
+            match self.history.redo() {
+                Some(step) => self.apply_history_step(step),
+                None => break,
+            }
+        }
+        self.flush_edits();
+    }
+
+    // Close the current undo group, so that the next edit starts a new undo step
+    pub(crate) fn commit_undo_group(&mut self) {
+        if self.history.has_pending() {
+            let snapshot = self.cursor_snapshot();
+            self.history.commit(snapshot);
+        }
+    }
+
+    fn apply_history_step(&mut self, step: HistoryStep) {
+        self.recording_history = false;
+        for op in step.ops {
+            match op {
+                EditOp::Insert(cidx, s) => self.insert_at(cidx, &s),
+                EditOp::Remove(cidx, s) => self.remove_range(cidx, cidx + s.chars().count()),
+            }
+        }
+        self.recording_history = true;
+        for (vid, cidx) in step.cursors {
+            if let Some(view) = self.views.get_mut(&vid) {
+                view.cursor.char_idx = min(cidx, self.data.len_chars());
+                view.cursor
+                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
+            }
+        }
+    }
+
+    fn cursor_snapshot(&self) -> CursorSnapshot {
+        self.views
+            .iter()
+            .map(|(id, view)| (id.clone(), view.cursor.char_idx))
+            .collect()
+    }
+
+    // -------- Core edits ----------------
+
+    // All changes to the buffer contents go through insert_at and remove_range. They keep styled
+    // lines, the parse tree, cursors and undo history in sync, and queue changes for the language
+    // server. Callers must call flush_edits() once they are done editing.
+
+    fn insert_at(&mut self, cidx: usize, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        self.begin_edit();
+        if self.recording_history {
+            self.history.record(EditOp::Insert(cidx, s.to_owned()));
+        }
+        if self.language_client.is_some() {
+            let pos = internal_cidx_to_lsp_position(&self.data, cidx);
+            let range = LspRange {
+                start: pos.clone(),
+                end: pos,
+            };
+            self.lsp_changes.push((range, s.to_owned()));
         }
 
+        let linum = self.data.char_to_line(cidx);
+        self.data.insert(cidx, s);
+        let nchars = s.chars().count();
+        let end_cidx = cidx + nchars;
+        let end_linum = self.data.char_to_line(end_cidx);
+        self.update_styled_lines(linum, linum, end_linum);
+
         self.edit_tree(self.data.clone(), cidx, cidx, end_cidx);
-        let (end_byte, end_col) = {
-            let llen = self.data.line(end_linum).len_bytes();
-            let lb = self.data.line_to_byte(end_linum);
-            (lb + llen, llen)
-        };
-        self.rehighlight_range(tree_sitter::Range {
-            start_byte: self.data.line_to_byte(linum),
-            end_byte,
-            start_point: Point::new(linum, 0),
-            end_point: Point::new(end_linum, end_col),
-        });
+        self.rehighlight_lines(linum, end_linum);
 
+        self.marks.insert(cidx, nchars);
         for view in self.views.values_mut() {
-            if view.cursor.char_idx >= cidx {
-                view.cursor.char_idx += end_cidx - cidx;
-                view.cursor
-                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
-            }
-            if view.is_active {
-                view.reshape(&self.data, &self.styled_lines);
-                view.snap_to_cursor(&self.data, &self.styled_lines);
-            }
-        }
-
-        let completion =
-            CompletionSource::Path.complete(&self.data, end_cidx, &self.config, &self.theme);
-        if let Some((start, list)) = completion {
-            self.views
-                .get_mut(id)
-                .unwrap()
-                .start_completion(list, start);
+            view.shift_marks(|pos| shift_for_insert(pos, cidx, nchars));
+            view.shift_cursors(cidx, |pos| shift_for_insert(pos, cidx, nchars), &self.data);
         }
     }
 
-    pub(crate) fn view_delete(&mut self, id: &BufferViewID, mo: MotionOrObj) {
-        let view = self.views.get_mut(id).unwrap();
-        view.stop_completion();
-
-        let cidx = view.cursor.char_idx;
-        let lc = self.data.len_chars();
-        let mut linum = view.cursor.line_num;
-        let mut move_to_start = false;
-        let (start_cidx, end_cidx) = match mo {
-            MotionOrObj::Motion(Motion::Left(n)) => {
-                if cidx == 0 {
-                    return;
-                }
-                let start_cidx = if cidx <= n { 0 } else { cidx - n };
-                let start_line = self.data.char_to_line(start_cidx);
-                for _ in start_line..linum {
-                    self.styled_lines.remove(start_line + 1);
-                }
-                linum = start_line;
-                (start_cidx, cidx)
-            }
-            MotionOrObj::Motion(Motion::Right(n)) => {
-                if cidx == self.data.len_chars() {
-                    return;
-                }
-                let end_cidx = if cidx + n >= lc { lc } else { cidx + n };
-                let end_line = self.data.char_to_line(end_cidx);
-                for _ in linum..end_line {
-                    self.styled_lines.remove(linum + 1);
-                }
-                (cidx, end_cidx)
-            }
-            MotionOrObj::Motion(Motion::Up(n)) => {
-                let start_linum = if linum < n { 0 } else { linum - n };
-                let start_cidx = self.data.line_to_char(start_linum);
-                let end_cidx = self.data.line_to_char(linum + 1);
-                for _ in start_linum..linum {
-                    self.styled_lines.remove(start_linum + 1);
-                }
-                if self.data.char_to_line(end_cidx) > linum {
-                    self.styled_lines.remove(start_linum + 1);
-                }
-                linum = start_linum;
-                move_to_start = true;
-                (start_cidx, end_cidx)
-            }
-            MotionOrObj::Motion(Motion::Down(n)) => {
-                let start_linum = linum;
-                let (start_cidx, end_cidx) = if start_linum + n >= self.data.len_lines() {
-                    linum -= 1;
-                    self.styled_lines.truncate(start_linum);
-                    (
-                        self.data.line_to_char(linum)
-                            + rope_trim_newlines(self.data.line(linum)).len_chars(),
-                        self.data.len_chars(),
-                    )
-                } else {
-                    for _ in 0..n {
-                        self.styled_lines.remove(start_linum + 1);
-                    }
-                    if start_linum + n + 1 < self.data.len_lines() {
-                        self.styled_lines.remove(start_linum + 1);
-                    }
-                    (
-                        cidx - view.cursor.line_cidx,
-                        self.data.line_to_char(start_linum + n + 1),
-                    )
-                };
-                move_to_start = true;
-                (start_cidx, end_cidx)
-            }
-            MotionOrObj::Motion(Motion::LineStart) => {
-                if view.cursor.line_cidx == 0 {
-                    return;
-                }
-                (
-                    view.cursor.char_idx - view.cursor.line_cidx,
-                    view.cursor.char_idx,
-                )
-            }
-            MotionOrObj::Motion(Motion::LineEnd) => {
-                let lc = rope_trim_newlines(self.data.line(view.cursor.line_num)).len_chars();
-                if view.cursor.line_cidx == lc {
-                    return;
-                }
-                (
-                    view.cursor.char_idx,
-                    view.cursor.char_idx + (lc - view.cursor.line_cidx),
-                )
-            }
-            MotionOrObj::Motion(Motion::ToLine(_)) => unimplemented!(),
-            MotionOrObj::Object(Object::Words(n)) => {
-                let end_cidx = nth_word_start(&self.data, cidx, n, false);
-                let end_line = self.data.char_to_line(end_cidx);
-                for _ in linum..end_line {
-                    self.styled_lines.remove(linum + 1);
-                }
-                (cidx, end_cidx)
-            }
-            MotionOrObj::Object(Object::WordsExt(n)) => {
-                let end_cidx = nth_word_start(&self.data, cidx, n, true);
-                let end_line = self.data.char_to_line(end_cidx);
-                for _ in linum..end_line {
-                    self.styled_lines.remove(linum + 1);
-                }
-                (cidx, end_cidx)
-            }
-            MotionOrObj::Object(Object::BackWords(n)) => {
-                let start_cidx = nth_back_word_start(&self.data, cidx, n, false);
-                let start_line = self.data.char_to_line(start_cidx);
-                for _ in start_line..linum {
-                    self.styled_lines.remove(start_line + 1);
-                }
-                linum = start_line;
-                (start_cidx, cidx)
-            }
-            MotionOrObj::Object(Object::BackWordsExt(n)) => {
-                let start_cidx = nth_back_word_start(&self.data, cidx, n, true);
-                let start_line = self.data.char_to_line(start_cidx);
-                for _ in start_line..linum {
-                    self.styled_lines.remove(start_line + 1);
-                }
-                linum = start_line;
-                (start_cidx, cidx)
-            }
-            MotionOrObj::Object(Object::Lines(_)) => unimplemented!(),
-        };
+    fn remove_range(&mut self, start_cidx: usize, end_cidx: usize) {
+        if start_cidx >= end_cidx {
+            return;
+        }
+        self.begin_edit();
+        if self.recording_history {
+            let text = self.data.slice(start_cidx..end_cidx).to_string();
+            self.history.record(EditOp::Remove(start_cidx, text));
+        }
+        if self.language_client.is_some() {
+            let start = internal_cidx_to_lsp_position(&self.data, start_cidx);
+            let end = internal_cidx_to_lsp_position(&self.data, end_cidx);
+            self.lsp_changes
+                .push((LspRange { start, end }, "".to_owned()));
+        }
 
         let old_rope = self.data.clone();
+        let linum = self.data.char_to_line(start_cidx);
+        let old_end_linum = self.data.char_to_line(end_cidx);
         self.data.remove(start_cidx..end_cidx);
+        self.update_styled_lines(linum, old_end_linum, linum);
 
-        self.version += 1;
+        self.edit_tree(old_rope, start_cidx, end_cidx, start_cidx);
+        self.rehighlight_lines(linum, linum);
+
+        self.marks.remove(start_cidx, end_cidx);
+        for view in self.views.values_mut() {
+            view.shift_marks(|pos| shift_for_remove(pos, start_cidx, end_cidx));
+            view.shift_cursors(
+                start_cidx,
+                |pos| shift_for_remove(pos, start_cidx, end_cidx),
+                &self.data,
+            );
+        }
+    }
+
+    fn begin_edit(&mut self) {
+        self.has_pending_edits = true;
         self.last_hover = None;
+        if self.recording_history && !self.history.has_pending() {
+            let snapshot = self.cursor_snapshot();
+            self.history.begin(|| snapshot);
+        }
+    }
+
+    // Send queued changes to the language server as one batch, and update views
+    fn flush_edits(&mut self) {
+        if !self.has_pending_edits || self.batching_edits {
+            return;
+        }
+        self.has_pending_edits = false;
+        self.version += 1;
+        self.marks.finish_change(&self.data);
+        let changes = std::mem::take(&mut self.lsp_changes);
         if let Some(lc) = &mut self.language_client {
-            let start = internal_cidx_to_lsp_position(&old_rope, start_cidx);
-            let end = internal_cidx_to_lsp_position(&old_rope, end_cidx);
-            let range = LspRange { start, end };
             if lc.send_full_document_on_change() {
                 lc.change_full(
                     self.path.as_ref().unwrap(),
@@ -698,53 +2456,12 @@ impl Buffer {
                     self.data.to_string(),
                 );
             } else {
-                lc.change(
-                    self.path.as_ref().unwrap(),
-                    self.version,
-                    range,
-                    "".to_owned(),
-                );
+                lc.change(self.path.as_ref().unwrap(), self.version, changes);
             }
         }
-
-        self.styled_lines[linum] = default_hl_for_line(
-            self.data.line(linum),
-            self.theme.textview.foreground,
-            self.tab_width,
-            self.indent_tabs,
-        );
-
-        self.edit_tree(old_rope, start_cidx, end_cidx, start_cidx);
-
-        let (start_byte, end_byte) = {
-            let llen = self.data.line(linum).len_bytes();
-            let lb = self.data.line_to_byte(linum);
-            (lb, lb + llen)
-        };
-        self.rehighlight_range(tree_sitter::Range {
-            start_byte,
-            end_byte,
-            start_point: Point::new(linum, 0),
-            end_point: Point::new(linum, end_byte - start_byte),
-        });
-
-        for (vid, view) in self.views.iter_mut() {
-            if view.cursor.char_idx >= end_cidx {
-                view.cursor.char_idx -= end_cidx - start_cidx;
-                view.cursor
-                    .sync_and_update_char_idx_left(&self.data, self.tab_width);
-            } else if view.cursor.char_idx >= start_cidx {
-                if move_to_start && vid == id {
-                    view.cursor.line_num = linum;
-                    view.cursor.line_cidx = 0;
-                    view.cursor
-                        .sync_line_cidx_gidx_left(&self.data, self.tab_width);
-                } else {
-                    view.cursor.char_idx = start_cidx;
-                    view.cursor
-                        .sync_and_update_char_idx_left(&self.data, self.tab_width);
-                }
-            }
+        self.update_fold_ranges();
+        for view in self.views.values_mut() {
+            view.changelist_idx = None;
             if view.is_active {
                 view.reshape(&self.data, &self.styled_lines);
                 view.snap_to_cursor(&self.data, &self.styled_lines);
@@ -752,16 +2469,62 @@ impl Buffer {
         }
     }
 
+    // Replace styled lines start_linum..=old_end_linum with unhighlighted styled lines for
+    // start_linum..=new_end_linum
+    fn update_styled_lines(
+        &mut self,
+        start_linum: usize,
+        old_end_linum: usize,
+        new_end_linum: usize,
+    ) {
+        let fgcol = self.theme.textview.foreground;
+        let new_lines = (start_linum..=new_end_linum)
+            .map(|linum| {
+                default_hl_for_line(
+                    self.data.line(linum),
+                    fgcol,
+                    self.tab_width,
+                    self.indent_tabs,
+                )
+            })
+            .collect::<Vec<_>>();
+        self.styled_lines
+            .splice(start_linum..=old_end_linum, new_lines);
+    }
+
+    // Ask the language server for fold regions, or find them with tree-sitter if it doesn't have
+    // them. The old regions are kept until the server replies
+    fn update_fold_ranges(&mut self) {
+        if let Some(lc) = &mut self.language_client {
+            if let Some(id) = lc.folding_range(self.path.as_ref().unwrap()) {
+                let len_lines = self.data.len_lines();
+                self.fold_ranges.retain(|(_, last)| *last < len_lines);
+                self.last_folding_range = Some(id);
+                return;
+            }
+        }
+        self.fold_ranges = match (&self.tree, &self.queries) {
+            (Some(tree), Some(queries)) => syntax_fold_ranges(&self.data, tree, &queries.folds),
+            _ => Vec::new(),
+        };
+    }
+
     pub(crate) fn view_completion_action(&mut self, id: &BufferViewID, action: ComplAction) {
         self.views.get_mut(id).unwrap().completion_action(action);
     }
 
     // -------- Create buffer ----------------
 
-    pub(super) fn empty(buffer_id: BufferID, config: Rc<Config>, theme: Rc<Theme>) -> Buffer {
+    pub(super) fn empty(
+        buffer_id: BufferID,
+        config: Rc<Config>,
+        theme: Rc<Theme>,
+        registers: Rc<RefCell<Registers>>,
+    ) -> Buffer {
         let styled = StyledText::new(0, 0, TextStyle::default(), theme.textview.foreground, None);
         let tab_width = config.tab_width;
         let indent_tabs = config.indent_tabs;
+        let soft_wrap = soft_wrap_indent(&config, None, None);
         Buffer {
             buffer_id,
             data: Rope::new(),
@@ -769,18 +2532,30 @@ impl Buffer {
             styled_lines: vec![styled],
             language: None,
             parser: None,
-            hl_query: None,
+            queries: None,
             tree: None,
             theme,
             path: None,
             config,
+            registers,
             tab_width,
             indent_tabs,
+            soft_wrap,
             project: None,
             language_client: None,
             diagnostics: Diagnostics::empty(),
             version: 0,
             last_hover: None,
+            fold_ranges: Vec::new(),
+            last_folding_range: None,
+            search_regex: None,
+            history: History::new(),
+            recording_history: true,
+            has_pending_edits: false,
+            batching_edits: false,
+            at_extra_cursor: false,
+            lsp_changes: Vec::new(),
+            marks: Marks::default(),
         }
     }
 
@@ -791,6 +2566,7 @@ impl Buffer {
         ts_core: &TsCore,
         config: Rc<Config>,
         theme: Rc<Theme>,
+        registers: Rc<RefCell<Registers>>,
         lang_client_manager: &mut LanguageClientManager,
     ) -> IOResult<Buffer> {
         let rope = if let Ok(file) = File::open(path) {
@@ -798,7 +2574,7 @@ impl Buffer {
         } else {
             Rope::new()
         };
-        let (language, parser, hl_query) = Path::new(path)
+        let (language, parser, queries) = Path::new(path)
             .extension()
             .and_then(|s| s.to_str())
             .and_then(|s| ts_core.parser_from_extension(s))
@@ -814,6 +2590,7 @@ impl Buffer {
             tab_width = project.tab_width.unwrap_or(tab_width);
             indent_tabs = project.indent_tabs.unwrap_or(indent_tabs);
         }
+        let soft_wrap = soft_wrap_indent(&config, language, project.as_deref());
         let mut styled_lines = Vec::new();
         for line in rope.lines() {
             styled_lines.push(default_hl_for_line(
@@ -842,20 +2619,35 @@ impl Buffer {
             styled_lines,
             language,
             parser,
-            hl_query,
+            queries,
             tree: None,
             path: Some(path.to_owned()),
             theme,
             config,
+            registers,
             tab_width,
             indent_tabs,
+            soft_wrap,
             project,
             language_client,
             diagnostics: Diagnostics::empty(),
             version: 0,
             last_hover: None,
+            fold_ranges: Vec::new(),
+            last_folding_range: None,
+            search_regex: None,
+            history: History::new(),
+            recording_history: true,
+            has_pending_edits: false,
+            batching_edits: false,
+            at_extra_cursor: false,
+            lsp_changes: Vec::new(),
+            marks: Marks::default(),
         };
         ret.recreate_parse_tree();
+        ret.update_fold_ranges();
+        let search_pattern = ret.registers.borrow().last_search().map(|s| s.to_owned());
+        ret.set_search_pattern(search_pattern.as_deref());
         Ok(ret)
     }
 
@@ -878,7 +2670,7 @@ impl Buffer {
                 self.data = rope;
                 self.project = project;
                 self.styled_lines.clear();
-                let (language, parser, hl_query) = Path::new(path)
+                let (language, parser, queries) = Path::new(path)
                     .extension()
                     .and_then(|s| s.to_str())
                     .and_then(|s| ts_core.parser_from_extension(s))
@@ -902,16 +2694,22 @@ impl Buffer {
                 self.indent_tabs = indent_tabs;
                 self.language = language;
                 self.parser = parser;
-                self.hl_query = hl_query;
+                self.queries = queries;
                 self.diagnostics.clear();
                 self.recreate_parse_tree();
+                self.highlight_search_matches(0, self.styled_lines.len() - 1);
                 self.version = 0;
+                self.history.clear();
+                self.has_pending_edits = false;
+                self.lsp_changes.clear();
 
                 if let Some(project) = &self.project {
                     self.tab_width = project.tab_width.unwrap_or(self.tab_width);
                     self.indent_tabs = project.indent_tabs.unwrap_or(self.indent_tabs);
                 }
+                self.soft_wrap = soft_wrap_indent(&self.config, language, self.project.as_deref());
                 for view in self.views.values_mut() {
+                    view.soft_wrap = self.soft_wrap;
                     if view.cursor.char_idx > self.data.len_chars() {
                         view.cursor.char_idx = self.data.len_chars();
                     }
@@ -937,6 +2735,7 @@ impl Buffer {
                             None
                         }
                     });
+                self.update_fold_ranges();
             })
     }
 
@@ -961,6 +2760,8 @@ impl Buffer {
             }
             Err(e) => return Err(e),
         };
+        self.commit_undo_group();
+        self.history.mark_saved();
 
         if let Some(old_path) = self.path.as_ref() {
             if old_path == path {
@@ -976,7 +2777,7 @@ impl Buffer {
 
         self.diagnostics.clear();
 
-        let (language, parser, hl_query) = Path::new(path)
+        let (language, parser, queries) = Path::new(path)
             .extension()
             .and_then(|s| s.to_str())
             .and_then(|s| ts_core.parser_from_extension(s))
@@ -992,7 +2793,7 @@ impl Buffer {
             self.tab_width = tab_width;
             self.indent_tabs = indent_tabs;
             self.parser = parser;
-            self.hl_query = hl_query;
+            self.queries = queries;
         }
         if let Some(project) = &self.project {
             self.tab_width = project.tab_width.unwrap_or(self.tab_width);
@@ -1033,6 +2834,7 @@ impl Buffer {
                     None
                 }
             });
+        self.update_fold_ranges();
 
         Ok(len)
     }
@@ -1043,24 +2845,144 @@ impl Buffer {
         self.buffer_id
     }
 
+    // Path of the buffer, for messages and the window title
+    pub(crate) fn name(&self) -> &str {
+        self.path.as_deref().unwrap_or("[No Name]")
+    }
+
+    // Close the document on the language server, when the buffer is deleted
+    pub(crate) fn close(&mut self) {
+        if let (Some(path), Some(lc)) = (&self.path, &mut self.language_client) {
+            lc.close(path);
+        }
+        self.language_client = None;
+        self.last_hover = None;
+        self.last_folding_range = None;
+    }
+
+    // Whether there are changes which haven't been written. Undoing back to the state the buffer
+    // was last written in clears this
+    pub(crate) fn is_modified(&self) -> bool {
+        self.history.is_modified()
+    }
+
+    pub(crate) fn len_lines(&self) -> usize {
+        self.data.len_lines()
+    }
+
+    pub(crate) fn view_cursor_line(&self, id: &BufferViewID) -> usize {
+        self.views[id].cursor.line_num
+    }
+
+    // -------- Marks ----------------
+
+    // Set a mark at the cursor. Returns false for marks which can't be set
+    pub(crate) fn view_set_mark(&mut self, id: &BufferViewID, name: char) -> bool {
+        let view = self.views.get_mut(id).unwrap();
+        let cidx = view.cursor.char_idx;
+        match name {
+            '`' | '\'' => {
+                view.last_jump = Some(cidx);
+                true
+            }
+            name => self.marks.set(name, cidx),
+        }
+    }
+
+    // For global marks which have been set in another buffer
+    pub(crate) fn clear_mark(&mut self, name: char) {
+        self.marks.clear(name);
+    }
+
+    pub(crate) fn view_mark_line(&self, id: &BufferViewID, name: char) -> Option<usize> {
+        self.mark_position(id, name)
+            .map(|cidx| self.data.char_to_line(cidx))
+    }
+
+    pub(crate) fn view_mark_insert_stop(&mut self, id: &BufferViewID) {
+        self.marks.set_last_insert(self.views[id].cursor.char_idx);
+    }
+
+    // Marks of the buffer, and ` and ' for the latest jump in the view. '<' and '>' are the first
+    // and last line of the last selection
+    fn mark_position(&self, id: &BufferViewID, name: char) -> Option<usize> {
+        let view = &self.views[id];
+        let last_linum = self.data.len_lines() - 1;
+        let cidx = match name {
+            '`' | '\'' => view.last_jump?,
+            '<' => self
+                .data
+                .line_to_char(min(view.last_selection?.0, last_linum)),
+            '>' => self
+                .data
+                .line_to_char(min(view.last_selection?.1, last_linum)),
+            name => self.marks.get(name)?,
+        };
+        Some(min(cidx, self.data.len_chars()))
+    }
+
+    // Go through the changelist. Returns false if there is no change that far back or forward
+    pub(crate) fn view_changelist(&mut self, id: &BufferViewID, older: bool, n: usize) -> bool {
+        let changelist = self.marks.changelist();
+        let view = self.views.get_mut(id).unwrap();
+        let idx = view.changelist_idx.unwrap_or(changelist.len());
+        let idx = if older {
+            match idx.checked_sub(n) {
+                Some(idx) => idx,
+                None => return false,
+            }
+        } else if idx + n < changelist.len() {
+            idx + n
+        } else {
+            return false;
+        };
+        let cidx = min(changelist[idx], self.data.len_chars());
+        view.changelist_idx = Some(idx);
+        self.move_view_cursor_to_cidx(id, cidx);
+        true
+    }
+
+    // -------- Jumplist ----------------
+
+    pub(crate) fn view_cursor_cidx(&self, id: &BufferViewID) -> usize {
+        self.views[id].cursor.char_idx
+    }
+
+    pub(crate) fn view_add_jump(&mut self, id: &BufferViewID, jump_id: usize, cidx: usize) {
+        let view = self.views.get_mut(id).unwrap();
+        view.jumps.insert(jump_id, cidx);
+        view.last_jump = Some(cidx);
+    }
+
+    pub(crate) fn view_remove_jump(&mut self, id: &BufferViewID, jump_id: usize) {
+        self.views.get_mut(id).unwrap().jumps.remove(&jump_id);
+    }
+
+    pub(crate) fn view_jump_line(&self, id: &BufferViewID, jump_id: usize) -> Option<usize> {
+        self.views[id]
+            .jumps
+            .get(&jump_id)
+            .map(|cidx| self.data.char_to_line(min(*cidx, self.data.len_chars())))
+    }
+
+    pub(crate) fn view_go_to_jump(&mut self, id: &BufferViewID, jump_id: usize) -> bool {
+        let view = self.views.get_mut(id).unwrap();
+        view.stop_completion();
+        match view.jumps.get(&jump_id) {
+            Some(cidx) => {
+                let cidx = min(*cidx, self.data.len_chars());
+                self.move_view_cursor_to_cidx(id, cidx);
+                true
+            }
+            None => false,
+        }
+    }
+
     // -------- Parsing stuff ----------------
 
     fn recreate_parse_tree(&mut self) {
-        let rope = self.data.clone();
         if let Some(parser) = &mut self.parser {
-            let t = parser
-                .parse_with(
-                    &mut |boff, _| {
-                        if boff >= rope.len_bytes() {
-                            ""
-                        } else {
-                            let (ch, cb, _, _) = rope.chunk_at_byte(boff);
-                            &ch[boff - cb..]
-                        }
-                    },
-                    None,
-                )
-                .expect("failed to parse");
+            let t = parse_rope(parser, &self.data, None);
             self.tree = Some(t.clone());
             self.rehighlight_range(tree_sitter::Range {
                 start_byte: 0,
@@ -1104,21 +3026,8 @@ impl Buffer {
             new_end_position: Point::new(new_end_linum, new_end_linoff),
         });
 
-        let rope = self.data.clone();
         if let Some(parser) = &mut self.parser {
-            let t = parser
-                .parse_with(
-                    &mut |boff, _| {
-                        if boff >= rope.len_bytes() {
-                            ""
-                        } else {
-                            let (ch, cb, _, _) = rope.chunk_at_byte(boff);
-                            &ch[boff - cb..]
-                        }
-                    },
-                    Some(&mut tree),
-                )
-                .expect("failed to parse");
+            let t = parse_rope(parser, &self.data, Some(&tree));
             self.tree = Some(t.clone());
             for range in t.changed_ranges(&tree) {
                 self.rehighlight_range(range);
@@ -1128,6 +3037,8 @@ impl Buffer {
 
     fn rehighlight_range(&mut self, mut range: tree_sitter::Range) {
         self.expand_rehighlight_range(&mut range);
+        // Lines whose styles were reset, and need search matches marked again
+        let (mut first, mut last) = (range.start_point.row, range.end_point.row);
 
         let mut linum = range.start_point.row;
         for line in self.data.lines_at(range.start_point.row) {
@@ -1144,7 +3055,8 @@ impl Buffer {
         }
 
         if let Some(t) = &self.tree {
-            if let Some(hl_query) = &self.hl_query {
+            if let Some(queries) = &self.queries {
+                let hl_query = &queries.highlights;
                 let rope = self.data.clone();
                 let mut cursor = QueryCursor::new();
                 cursor.set_byte_range(range.start_byte, range.end_byte);
@@ -1200,6 +3112,8 @@ impl Buffer {
                                     None,
                                 );
                                 self.styled_lines[end.row].set(0..elc, style, fg, None);
+                                first = min(first, start.row);
+                                last = max(last, end.row);
                                 let mut linum = start.row + 1;
                                 for line in self.data.lines_at(linum) {
                                     if linum >= end.row {
@@ -1220,6 +3134,7 @@ impl Buffer {
                 }
             }
         }
+        self.highlight_search_matches(first, last);
     }
 
     fn expand_rehighlight_range(&self, range: &mut tree_sitter::Range) {
@@ -1233,6 +3148,20 @@ impl Buffer {
         range.end_byte = self.data.line_to_byte(range.end_point.row) + range.end_point.column;
     }
 
+    fn rehighlight_lines(&mut self, start_linum: usize, end_linum: usize) {
+        let (end_byte, end_col) = {
+            let llen = self.data.line(end_linum).len_bytes();
+            let lb = self.data.line_to_byte(end_linum);
+            (lb + llen, llen)
+        };
+        self.rehighlight_range(tree_sitter::Range {
+            start_byte: self.data.line_to_byte(start_linum),
+            end_byte,
+            start_point: Point::new(start_linum, 0),
+            end_point: Point::new(end_linum, end_col),
+        });
+    }
+
     // -------- Language server ----------------
 
     pub(crate) fn set_diagnostics(&mut self, diagnostics: &PublishDiagnosticParams) {
@@ -1251,6 +3180,25 @@ impl Buffer {
         }
     }
 
+    // Folding ranges from the language server replace those from tree-sitter
+    pub(crate) fn set_fold_ranges(&mut self, id: LspId, ranges: Vec<LspFoldingRange>) {
+        if self.last_folding_range.as_ref() != Some(&id) {
+            return;
+        }
+        self.last_folding_range = None;
+        let len_lines = self.data.len_lines();
+        self.fold_ranges = ranges
+            .iter()
+            .filter(|range| range.endLine > range.startLine && range.endLine < len_lines)
+            .map(|range| (range.startLine, range.endLine))
+            .collect();
+        self.fold_ranges.sort_unstable();
+        self.fold_ranges.dedup();
+        for view in self.views.values_mut() {
+            view.needs_redraw = true;
+        }
+    }
+
     pub(crate) fn update_hover(&mut self, id: LspId, lsphover: LspHover) {
         if let Some((lspid, viewid)) = self.last_hover.take() {
             if lspid != id {
@@ -1329,17 +3277,42 @@ fn nth_back_word_start(rope: &Rope, start_cidx: usize, n: usize, extended: bool)
     cidx
 }
 
-fn get_indent(data: &Rope, linum: usize, indent_tabs: bool) -> (char, usize) {
-    let mut count = 0;
-    let ich = if indent_tabs { '\t' } else { ' ' };
-    let line = rope_trim_newlines(data.line(linum));
-    for c in line.chars() {
-        if c != ich {
-            break;
-        }
-        count += 1;
-    }
-    (ich, count)
+fn parse_rope(parser: &mut Parser, rope: &Rope, old_tree: Option<&Tree>) -> Tree {
+    parser
+        .parse_with(
+            &mut |boff, _| {
+                if boff >= rope.len_bytes() {
+                    ""
+                } else {
+                    let (ch, cb, _, _) = rope.chunk_at_byte(boff);
+                    &ch[boff - cb..]
+                }
+            },
+            old_tree,
+        )
+        .expect("failed to parse")
+}
+
+fn leading_whitespace(line: RopeSlice) -> String {
+    line.chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect()
+}
+
+fn starts_with_closing_bracket(line: RopeSlice) -> bool {
+    matches!(
+        line.chars().find(|c| !c.is_whitespace()),
+        Some(')') | Some(']') | Some('}')
+    )
+}
+
+// Number of brackets opened on a line, minus the number closed
+fn bracket_balance(line: &RopeSlice) -> isize {
+    line.chars().fold(0, |acc, c| match c {
+        '(' | '[' | '{' => acc + 1,
+        ')' | ']' | '}' => acc - 1,
+        _ => acc,
+    })
 }
 
 fn indent_depth(line: &RopeSlice, indent_tabs: bool, tab_width: usize) -> usize {
@@ -1372,3 +3345,137 @@ fn indent_depth(line: &RopeSlice, indent_tabs: bool, tab_width: usize) -> usize
     }
     depth
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::FontCore;
+    use crate::text::TextShaper;
+    use euclid::size2;
+
+    // A buffer with some text, parsed as the language for an extension, and a view of it
+    fn buffer(ext: &str, text: &str) -> (Buffer, BufferViewID) {
+        let mut font_core = FontCore::new().unwrap();
+        let config = Rc::new(Config::load(&mut font_core));
+        let registers = Rc::new(RefCell::new(Registers::new()));
+        let theme = Rc::new(Theme::default());
+        let mut buf = Buffer::empty(BufferID(0), config.clone(), theme, registers);
+        if let Some((language, parser, queries)) = TsCore::new().parser_from_extension(ext) {
+            buf.language = Some(language);
+            buf.parser = Some(parser);
+            buf.queries = Some(queries);
+            buf.recreate_parse_tree();
+        }
+        buf.tab_width = 4;
+        buf.indent_tabs = false;
+        buf.insert_at(0, text);
+        buf.flush_edits();
+        let id = BufferViewID(0);
+        let params = BufferViewCreateParams {
+            config,
+            dpi: size2(96, 96),
+            text_shaper: Rc::new(RefCell::new(TextShaper::new(font_core))),
+            rect: Rect::new(Point2D::origin(), size2(800, 600)),
+        };
+        buf.new_view(&id, params);
+        buf.commit_undo_group();
+        (buf, id)
+    }
+
+    // Char indices of the main cursor and the extra ones
+    fn cursors(buf: &Buffer, id: &BufferViewID) -> Vec<usize> {
+        let view = &buf.views[id];
+        let extra = view.extra_cursors.iter().map(|extra| extra.cursor.char_idx);
+        std::iter::once(view.cursor.char_idx).chain(extra).collect()
+    }
+
+    #[test]
+    fn test_cursors_on_different_lines() {
+        let (mut buf, id) = buffer("txt", "abc\nabc\nabc\n");
+        assert!(buf.view_add_cursor_line(&id, true, 2));
+        buf.view_insert_char(&id, 'x');
+        buf.view_insert_char(&id, 'y');
+        assert_eq!(buf.data.to_string(), "xyabc\nxyabc\nxyabc\n");
+        assert_eq!(cursors(&buf, &id), vec![2, 8, 14]);
+        buf.view_delete(&id, MotionOrObj::Motion(Motion::Left(1)));
+        assert_eq!(buf.data.to_string(), "xabc\nxabc\nxabc\n");
+        assert_eq!(cursors(&buf, &id), vec![1, 6, 11]);
+        buf.commit_undo_group();
+        buf.view_operate(
+            &id,
+            Operator::Delete,
+            MotionOrObj::Motion(Motion::Right(2)),
+            None,
+        );
+        assert_eq!(buf.data.to_string(), "xc\nxc\nxc\n");
+        // The edits at all cursors are one undo step
+        buf.commit_undo_group();
+        buf.view_undo(&id, 1);
+        assert_eq!(buf.data.to_string(), "xabc\nxabc\nxabc\n");
+    }
+
+    #[test]
+    fn test_cursors_on_same_line() {
+        let (mut buf, id) = buffer("txt", "ab ab ab\n");
+        assert!(buf.view_add_cursor_at_word(&id, 2));
+        assert_eq!(cursors(&buf, &id), vec![0, 3, 6]);
+        buf.view_insert_char(&id, 'x');
+        buf.view_insert_char(&id, 'y');
+        assert_eq!(buf.data.to_string(), "xyab xyab xyab\n");
+        assert_eq!(cursors(&buf, &id), vec![2, 7, 12]);
+        buf.view_delete(&id, MotionOrObj::Motion(Motion::Left(2)));
+        assert_eq!(buf.data.to_string(), "ab ab ab\n");
+        assert_eq!(cursors(&buf, &id), vec![0, 3, 6]);
+        buf.view_operate(
+            &id,
+            Operator::Delete,
+            MotionOrObj::Motion(Motion::Right(1)),
+            None,
+        );
+        assert_eq!(buf.data.to_string(), "b b b\n");
+        assert_eq!(cursors(&buf, &id), vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn test_cursors_put() {
+        let (mut buf, id) = buffer("txt", "ab\ncd\nef\n");
+        assert!(buf.view_add_cursor_line(&id, true, 2));
+        // Only the text at the main cursor goes in to the register
+        buf.view_operate(
+            &id,
+            Operator::Yank,
+            MotionOrObj::Motion(Motion::Right(1)),
+            None,
+        );
+        assert_eq!(buf.registers.borrow().get(None).unwrap().text, "a");
+        buf.view_put(&id, None, 2, false);
+        assert_eq!(buf.data.to_string(), "aaab\ncaad\neaaf\n");
+        assert_eq!(cursors(&buf, &id), vec![2, 7, 12]);
+        buf.view_operate(
+            &id,
+            Operator::Delete,
+            MotionOrObj::Motion(Motion::Right(1)),
+            None,
+        );
+        assert_eq!(buf.registers.borrow().get(None).unwrap().text, "a");
+        // Lines go after each cursor's line
+        buf.registers
+            .borrow_mut()
+            .yank(None, Register::new(RegisterKind::Lines, "x\n".to_owned()));
+        buf.view_put(&id, None, 1, false);
+        assert_eq!(buf.data.to_string(), "aab\nx\ncad\nx\neaf\nx\n");
+    }
+
+    #[test]
+    fn test_dedent_to_parse() {
+        let (mut buf, _) = buffer("py", "if x:\n    y\n    else:\n");
+        assert_eq!(buf.dedent_to_parse(2), Some("".to_owned()));
+        // Lines which parse stay as they are
+        let (mut buf, _) = buffer("py", "if x:\n    y\nelse:\n");
+        assert_eq!(buf.dedent_to_parse(1), None);
+        assert_eq!(buf.dedent_to_parse(2), None);
+        let text = "def f():\n    if x:\n        y\n        else:\n";
+        let (mut buf, _) = buffer("py", text);
+        assert_eq!(buf.dedent_to_parse(3), Some("    ".to_owned()));
+    }
+}
@@ -0,0 +1,156 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use ropey::Rope;
+use tree_sitter::Tree;
+
+use crate::language::{CommentTokens, Language};
+
+// An edit to a line: char offset, number of chars to remove there, and text to insert
+pub(super) type LineEdit = (usize, usize, usize, String);
+
+// Edits which comment out lines, or uncomment them if every non-blank line is already commented.
+// Line comments go at the smallest indentation among the lines. Without a line comment token,
+// each line is wrapped in a block comment. Edits are in order, so they are to be applied in
+// reverse
+pub(super) fn toggle_comments(lines: &[String], tokens: &CommentTokens) -> Vec<LineEdit> {
+    let mut ret = Vec::new();
+    let indents = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, l)| {
+            l.chars()
+                .position(|c| !c.is_whitespace())
+                .map(|off| (i, off))
+        })
+        .collect::<Vec<_>>();
+    if let Some(token) = &tokens.line {
+        let commented = indents
+            .iter()
+            .all(|(i, off)| chars_from(&lines[*i], *off).starts_with(token.as_str()));
+        let min_indent = indents.iter().map(|(_, off)| *off).min().unwrap_or(0);
+        for (i, off) in indents {
+            if commented {
+                let len = token_len(&lines[i], off, token);
+                ret.push((i, off, len, String::new()));
+            } else {
+                ret.push((i, min_indent, 0, format!("{} ", token)));
+            }
+        }
+    } else if let Some((start, end)) = &tokens.block {
+        let commented = indents.iter().all(|(i, off)| {
+            let text = chars_from(&lines[*i], *off).trim_end();
+            text.len() >= start.len() + end.len()
+                && text.starts_with(start.as_str())
+                && text.ends_with(end.as_str())
+        });
+        for (i, off) in indents {
+            let line = &lines[i];
+            let text_end = line.trim_end().chars().count();
+            if commented {
+                let start_len = token_len(line, off, start);
+                let end_len = end.chars().count();
+                let end_off = text_end - end_len;
+                // Don't take the same space away twice, for an empty comment
+                let (end_off, end_len) =
+                    if end_off > off + start_len && line.chars().nth(end_off - 1) == Some(' ') {
+                        (end_off - 1, end_len + 1)
+                    } else {
+                        (end_off, end_len)
+                    };
+                ret.push((i, off, start_len, String::new()));
+                ret.push((i, end_off, end_len, String::new()));
+            } else {
+                ret.push((i, off, 0, format!("{} ", start)));
+                ret.push((i, text_end, 0, format!(" {}", end)));
+            }
+        }
+    }
+    ret
+}
+
+// Text of a line from a char offset
+fn chars_from(line: &str, off: usize) -> &str {
+    match line.char_indices().nth(off) {
+        Some((bidx, _)) => &line[bidx..],
+        None => "",
+    }
+}
+
+// Chars taken up by a comment token at an offset, and a space after it
+fn token_len(line: &str, off: usize, token: &str) -> usize {
+    let len = token.chars().count();
+    if chars_from(line, off + len).starts_with(' ') {
+        len + 1
+    } else {
+        len
+    }
+}
+
+// Language of the text at a char, which is the buffer's language unless the char is inside a
+// region of another language embedded in it, like a script in HTML
+pub(super) fn language_at(data: &Rope, tree: &Tree, language: Language, cidx: usize) -> Language {
+    let bidx = data.char_to_byte(cidx);
+    let mut node = tree.root_node().descendant_for_byte_range(bidx, bidx);
+    while let Some(n) = node {
+        if let Some(embedded) = language.embedded_language(n.kind()) {
+            // Only the text between the element's tags is in the embedded language
+            let count = n.child_count();
+            if count >= 2
+                && n.child(0).unwrap().end_byte() <= bidx
+                && bidx < n.child(count - 1).unwrap().start_byte()
+            {
+                return embedded;
+            }
+        }
+        node = n.parent();
+    }
+    language
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toggle(lines: &[&str], line: Option<&str>, block: Option<(&str, &str)>) -> Vec<String> {
+        let mut lines = lines.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let tokens = CommentTokens {
+            line: line.map(|s| s.to_owned()),
+            block: block.map(|(s, e)| (s.to_owned(), e.to_owned())),
+        };
+        for (i, off, len, text) in toggle_comments(&lines, &tokens).into_iter().rev() {
+            let mut chars = lines[i].chars().collect::<Vec<_>>();
+            chars.splice(off..off + len, text.chars());
+            lines[i] = chars.into_iter().collect();
+        }
+        lines
+    }
+
+    #[test]
+    fn test_toggle_comments() {
+        let block = Some(("/*", "*/"));
+        assert_eq!(
+            toggle(&["  a", "", "    b"], Some("//"), block),
+            vec!["  // a", "", "  //   b"]
+        );
+        assert_eq!(
+            toggle(&["  // a", "", "  //   b"], Some("//"), block),
+            vec!["  a", "", "    b"]
+        );
+        assert_eq!(toggle(&["#x", "y"], Some("#"), None), vec!["# #x", "# y"]);
+        assert_eq!(toggle(&["#x", "# y"], Some("#"), None), vec!["x", "y"]);
+        assert_eq!(
+            toggle(&["  a { }  ", "b"], None, block),
+            vec!["  /* a { } */  ", "/* b */"]
+        );
+        assert_eq!(
+            toggle(&["  /* a { } */  ", "/*b*/"], None, block),
+            vec!["  a { }  ", "b"]
+        );
+        assert_eq!(
+            toggle(&["<!-- a -->", "b"], None, Some(("<!--", "-->"))),
+            vec!["<!-- <!-- a --> -->", "<!-- b -->"]
+        );
+        assert_eq!(toggle(&["/* */", "/**/"], None, block), vec!["", ""]);
+        assert_eq!(toggle(&[""], None, None), vec![""]);
+    }
+}
@@ -13,6 +13,7 @@ pub(crate) enum CursorStyle {
     Block,
 }
 
+#[derive(Clone)]
 pub(super) struct Cursor {
     pub(super) visible: bool,
     pub(super) char_idx: usize,
@@ -127,7 +128,11 @@ fn gidx_from_cidx(line: &RopeSlice, cidx: usize, tab_width: usize) -> usize {
 }
 */
 
-fn cidx_gidx_from_cidx(slice: &RopeSlice, cidx: usize, tab_width: usize) -> (usize, usize) {
+pub(super) fn cidx_gidx_from_cidx(
+    slice: &RopeSlice,
+    cidx: usize,
+    tab_width: usize,
+) -> (usize, usize) {
     let (mut gidx, mut ccount) = (0, 0);
     for g in RopeGraphemes::new(slice) {
         let count_here = g.chars().count();
@@ -170,6 +175,38 @@ pub(super) fn cidx_gidx_from_gidx(
     (cidx, gcount)
 }
 
+// Char range of the graphemes which overlap the columns start_gidx..end_gidx
+pub(super) fn cidx_range_from_gidx_range(
+    slice: &RopeSlice,
+    start_gidx: usize,
+    end_gidx: usize,
+    tab_width: usize,
+) -> (usize, usize) {
+    let (mut gidx, mut ccount) = (0, 0);
+    let (mut start, mut end) = (None, 0);
+    for g in RopeGraphemes::new(slice) {
+        if gidx >= end_gidx {
+            break;
+        }
+        let next_gidx = if g == "\t" {
+            (gidx / tab_width) * tab_width + tab_width
+        } else {
+            gidx + 1
+        };
+        let count_here = g.chars().count();
+        if next_gidx > start_gidx {
+            start.get_or_insert(ccount);
+            end = ccount + count_here;
+        }
+        ccount += count_here;
+        gidx = next_gidx;
+    }
+    match start {
+        Some(start) => (start, end),
+        None => (ccount, ccount),
+    }
+}
+
 fn cidx_gidx_from_global_x(
     slice: &RopeSlice,
     global_x: usize,
@@ -195,3 +232,20 @@ fn cidx_gidx_from_global_x(
     }
     (ccount, gidx)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidx_range_from_gidx_range() {
+        let rope = Rope::from_str("a\tbc");
+        let slice = rope.slice(..);
+        // 'a' is column 0, the tab covers columns 1-3, 'b' is 4, 'c' is 5
+        assert_eq!(cidx_range_from_gidx_range(&slice, 0, 1, 4), (0, 1));
+        assert_eq!(cidx_range_from_gidx_range(&slice, 2, 3, 4), (1, 2));
+        assert_eq!(cidx_range_from_gidx_range(&slice, 2, 5, 4), (1, 3));
+        assert_eq!(cidx_range_from_gidx_range(&slice, 4, 10, 4), (2, 4));
+        assert_eq!(cidx_range_from_gidx_range(&slice, 6, 8, 4), (4, 4));
+    }
+}
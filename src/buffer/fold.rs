@@ -0,0 +1,312 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use std::cmp::{max, min};
+
+use ropey::Rope;
+use tree_sitter::{Query, QueryCursor, Tree};
+
+// First and last lines of the regions which can be folded, from the @fold captures of a folds
+// query. Regions span at least two lines, and are sorted by their first line
+pub(super) fn syntax_fold_ranges(data: &Rope, tree: &Tree, query: &Query) -> Vec<(usize, usize)> {
+    let b2c = |b| data.byte_to_char(b);
+    let mut cursor = QueryCursor::new();
+    let mut ret = Vec::new();
+    for query_match in cursor.matches(query, tree.root_node(), |node| {
+        let range = node.byte_range();
+        data.slice(b2c(range.start)..b2c(range.end)).to_string()
+    }) {
+        for capture in query_match.captures {
+            let (start, end) = (capture.node.start_position(), capture.node.end_position());
+            // Nodes which take in the line break at their end stop at the start of the next line
+            let last = if end.column == 0 && end.row > start.row {
+                end.row - 1
+            } else {
+                end.row
+            };
+            if last > start.row {
+                ret.push((start.row, last));
+            }
+        }
+    }
+    ret.sort_unstable();
+    ret.dedup();
+    ret
+}
+
+// Folds of a view. Closed folds, and folds made with zf, are kept as the char indices of the
+// starts of their first and last lines, so that they move with edits. Folds whose lines are
+// joined into one go away
+#[derive(Default)]
+pub(super) struct Folds {
+    // Made with zf. These can be closed again after being opened, like the fold regions of the
+    // buffer
+    manual: Vec<(usize, usize)>,
+    closed: Vec<(usize, usize)>,
+    // Lines of the above, as of the last sync
+    manual_lines: Vec<(usize, usize)>,
+    // Lines of the closed folds which aren't inside other closed folds, sorted. All but the
+    // first line of each are hidden
+    hidden: Vec<(usize, usize)>,
+}
+
+impl Folds {
+    pub(super) fn shift<F: Fn(usize) -> usize>(&mut self, f: F) {
+        for (first, last) in self.manual.iter_mut().chain(self.closed.iter_mut()) {
+            *first = f(*first);
+            *last = f(*last);
+        }
+    }
+
+    // Work out the lines of the folds again, after edits or changes to the folds
+    pub(super) fn sync(&mut self, data: &Rope) {
+        let len_chars = data.len_chars();
+        let lines = |(first, last): (usize, usize)| {
+            (
+                data.char_to_line(min(first, len_chars)),
+                data.char_to_line(min(last, len_chars)),
+            )
+        };
+        self.manual.retain(|fold| {
+            let (first, last) = lines(*fold);
+            first < last
+        });
+        self.closed.retain(|fold| {
+            let (first, last) = lines(*fold);
+            first < last
+        });
+        self.manual_lines = self.manual.iter().map(|fold| lines(*fold)).collect();
+        let mut closed = self
+            .closed
+            .iter()
+            .map(|fold| lines(*fold))
+            .collect::<Vec<_>>();
+        closed.sort_unstable();
+        self.hidden.clear();
+        for (first, last) in closed {
+            match self.hidden.last_mut() {
+                Some((_, hidden_last)) if first <= *hidden_last => {
+                    *hidden_last = max(*hidden_last, last)
+                }
+                _ => self.hidden.push((first, last)),
+            }
+        }
+    }
+
+    // Lines of the outermost closed fold the line is in
+    pub(super) fn closed_at(&self, linum: usize) -> Option<(usize, usize)> {
+        self.hidden
+            .iter()
+            .take_while(|(first, _)| *first <= linum)
+            .find(|(_, last)| *last >= linum)
+            .copied()
+    }
+
+    // The line itself, or the first line of the closed fold it is hidden in
+    pub(super) fn visible_line(&self, linum: usize) -> usize {
+        self.closed_at(linum).map_or(linum, |(first, _)| first)
+    }
+
+    // Line shown after the given one. This is past the last line after it
+    pub(super) fn next_line(&self, linum: usize) -> usize {
+        self.closed_at(linum).map_or(linum, |(_, last)| last) + 1
+    }
+
+    // Lines count lines down and up, with closed folds counting as one line. Going down stops
+    // once it gets to len_lines, past the last line
+    pub(super) fn lines_down(&self, mut linum: usize, count: usize, len_lines: usize) -> usize {
+        for _ in 0..count {
+            if linum >= len_lines {
+                break;
+            }
+            linum = self.next_line(linum);
+        }
+        linum
+    }
+
+    pub(super) fn lines_up(&self, mut linum: usize, count: usize) -> usize {
+        for _ in 0..count {
+            if linum == 0 {
+                break;
+            }
+            linum = self.visible_line(linum - 1);
+        }
+        linum
+    }
+
+    // Whether a fold region, or a fold made with zf, starts at the line
+    pub(super) fn starts_fold(&self, linum: usize, ranges: &[(usize, usize)]) -> bool {
+        ranges
+            .iter()
+            .chain(self.manual_lines.iter())
+            .any(|(first, _)| *first == linum)
+    }
+
+    // zf. Make a closed fold over the given lines
+    pub(super) fn add(&mut self, first: usize, last: usize, data: &Rope) -> bool {
+        if first >= last {
+            return false;
+        }
+        let fold = (data.line_to_char(first), data.line_to_char(last));
+        if !self.manual.contains(&fold) {
+            self.manual.push(fold);
+        }
+        if !self.closed.contains(&fold) {
+            self.closed.push(fold);
+        }
+        self.sync(data);
+        true
+    }
+
+    // zc. Close the smallest fold around the line which isn't closed yet, out of the buffer's
+    // fold regions and the folds made with zf. On a closed fold, this closes the one around it
+    pub(super) fn close(&mut self, linum: usize, ranges: &[(usize, usize)], data: &Rope) -> bool {
+        let (first, last) = self.closed_at(linum).unwrap_or((linum, linum));
+        let fold = ranges
+            .iter()
+            .chain(self.manual_lines.iter())
+            .filter(|(f, l)| *f <= first && *l >= last && (*f, *l) != (first, last))
+            .min_by_key(|(f, l)| l - f)
+            .copied();
+        match fold {
+            Some((first, last)) => {
+                self.closed
+                    .push((data.line_to_char(first), data.line_to_char(last)));
+                self.sync(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // zo. Open the outermost closed folds the line is in. Closed folds inside them stay closed
+    pub(super) fn open(&mut self, linum: usize, data: &Rope) -> bool {
+        let len_chars = data.len_chars();
+        let lines = |(first, last): (usize, usize)| {
+            (
+                data.char_to_line(min(first, len_chars)),
+                data.char_to_line(min(last, len_chars)),
+            )
+        };
+        let around = self
+            .closed
+            .iter()
+            .map(|fold| lines(*fold))
+            .filter(|(first, last)| *first <= linum && *last >= linum)
+            .collect::<Vec<_>>();
+        let is_outermost = |(first, last): (usize, usize)| {
+            !around
+                .iter()
+                .any(|(f, l)| *f <= first && *l >= last && (*f, *l) != (first, last))
+        };
+        let len = self.closed.len();
+        self.closed.retain(|fold| {
+            let (first, last) = lines(*fold);
+            !(first <= linum && last >= linum && is_outermost((first, last)))
+        });
+        self.sync(data);
+        self.closed.len() != len
+    }
+
+    // Open closed folds until the line isn't hidden in any. Returns true if any were opened
+    pub(super) fn reveal(&mut self, linum: usize, data: &Rope) -> bool {
+        let mut opened = false;
+        while self.visible_line(linum) != linum {
+            opened |= self.open(linum, data);
+        }
+        opened
+    }
+
+    // zR and zM
+    pub(super) fn open_all(&mut self) {
+        self.closed.clear();
+        self.hidden.clear();
+    }
+
+    pub(super) fn close_all(&mut self, ranges: &[(usize, usize)], data: &Rope) {
+        self.closed = ranges
+            .iter()
+            .chain(self.manual_lines.iter())
+            .map(|(first, last)| (data.line_to_char(*first), data.line_to_char(*last)))
+            .collect();
+        self.closed.sort_unstable();
+        self.closed.dedup();
+        self.sync(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::buffer::marks::{shift_for_insert, shift_for_remove};
+
+    fn rope(nlines: usize) -> Rope {
+        Rope::from_str(&"line\n".repeat(nlines))
+    }
+
+    #[test]
+    fn test_close_open() {
+        let data = rope(12);
+        let ranges = [(1, 8), (2, 4), (6, 7)];
+        let mut folds = Folds::default();
+        assert!(!folds.close(0, &ranges, &data));
+        assert!(folds.close(3, &ranges, &data));
+        assert_eq!(folds.closed_at(3), Some((2, 4)));
+        // Closing again closes the fold around it
+        assert!(folds.close(2, &ranges, &data));
+        assert_eq!(folds.closed_at(3), Some((1, 8)));
+        assert!(!folds.close(1, &ranges, &data));
+        assert_eq!(folds.visible_line(5), 1);
+        assert_eq!(folds.next_line(1), 9);
+        // Opening the outer fold leaves the inner one closed
+        assert!(folds.open(3, &data));
+        assert_eq!(folds.closed_at(3), Some((2, 4)));
+        assert_eq!(folds.closed_at(6), None);
+        assert!(folds.open(3, &data));
+        assert!(!folds.open(3, &data));
+    }
+
+    #[test]
+    fn test_lines_up_down() {
+        let data = rope(10);
+        let mut folds = Folds::default();
+        folds.close_all(&[(2, 4), (3, 4), (6, 8)], &data);
+        assert_eq!(folds.lines_down(0, 1, 11), 1);
+        assert_eq!(folds.lines_down(1, 1, 11), 2);
+        assert_eq!(folds.lines_down(2, 1, 11), 5);
+        assert_eq!(folds.lines_down(1, 3, 11), 6);
+        assert_eq!(folds.lines_down(6, 5, 11), 11);
+        assert_eq!(folds.lines_up(9, 1), 6);
+        assert_eq!(folds.lines_up(9, 3), 2);
+        assert_eq!(folds.lines_up(9, 10), 0);
+        assert!(folds.reveal(3, &data));
+        assert_eq!(folds.visible_line(3), 3);
+        folds.open_all();
+        assert_eq!(folds.closed_at(7), None);
+    }
+
+    #[test]
+    fn test_folds_follow_edits() {
+        let mut data = rope(6);
+        let mut folds = Folds::default();
+        assert!(!folds.add(2, 2, &data));
+        assert!(folds.add(2, 4, &data));
+        assert!(folds.starts_fold(2, &[]));
+        // A line inserted above moves the fold down
+        data.insert(0, "new\n");
+        folds.shift(|pos| shift_for_insert(pos, 0, 4));
+        folds.sync(&data);
+        assert_eq!(folds.closed_at(4), Some((3, 5)));
+        // Manual folds can be closed again once opened
+        folds.open_all();
+        assert!(folds.close(4, &[], &data));
+        assert_eq!(folds.closed_at(4), Some((3, 5)));
+        // Removing the lines in between leaves one line, and the fold goes away
+        let (start, end) = (data.line_to_char(3), data.line_to_char(5));
+        data.remove(start..end);
+        folds.shift(|pos| shift_for_remove(pos, start, end));
+        folds.sync(&data);
+        assert_eq!(folds.closed_at(3), None);
+        assert!(!folds.starts_fold(3, &[]));
+    }
+}
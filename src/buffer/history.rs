@@ -0,0 +1,301 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use super::BufferViewID;
+
+// All indices here are char indices into the buffer
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(super) enum EditOp {
+    Insert(usize, String),
+    Remove(usize, String),
+}
+
+impl EditOp {
+    fn inverse(&self) -> EditOp {
+        match self {
+            EditOp::Insert(cidx, s) => EditOp::Remove(*cidx, s.clone()),
+            EditOp::Remove(cidx, s) => EditOp::Insert(*cidx, s.clone()),
+        }
+    }
+
+    // Try to fold the next operation into this one, so that typing (or backspacing) over a
+    // run of characters is stored as one operation
+    fn merge(&mut self, next: &EditOp) -> bool {
+        match (self, next) {
+            (EditOp::Insert(cidx, s), EditOp::Insert(ncidx, ns))
+                if *ncidx == *cidx + s.chars().count() =>
+            {
+                s.push_str(ns);
+                true
+            }
+            (EditOp::Remove(cidx, s), EditOp::Remove(ncidx, ns)) if *ncidx == *cidx => {
+                s.push_str(ns);
+                true
+            }
+            (EditOp::Remove(cidx, s), EditOp::Remove(ncidx, ns))
+                if *ncidx + ns.chars().count() == *cidx =>
+            {
+                s.insert_str(0, ns);
+                *cidx = *ncidx;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Cursor positions of every view, as char indices
+pub(super) type CursorSnapshot = Vec<(BufferViewID, usize)>;
+
+// One undo step. All operations in a group are undone/redone together
+pub(super) struct UndoGroup {
+    ops: Vec<EditOp>,
+    cursors_before: CursorSnapshot,
+    cursors_after: CursorSnapshot,
+}
+
+// What the buffer needs to do to move through history
+pub(super) struct HistoryStep {
+    pub(super) ops: Vec<EditOp>,
+    pub(super) cursors: CursorSnapshot,
+}
+
+struct UndoNode {
+    parent: usize,
+    // The child which redo moves to. This is the most recently created or visited one
+    active_child: Option<usize>,
+    group: UndoGroup,
+}
+
+// Undo tree. Node 0 is the root and represents the buffer as it was loaded. Undoing and then
+// making a new edit creates a new branch instead of throwing away the old redo history
+pub(super) struct History {
+    nodes: Vec<UndoNode>,
+    current: usize,
+    pending: Option<UndoGroup>,
+    // Node the buffer was last written or loaded at. The buffer is modified anywhere else
+    saved: usize,
+}
+
+impl History {
+    pub(super) fn new() -> History {
+        History {
+            nodes: vec![UndoNode {
+                parent: 0,
+                active_child: None,
+                group: UndoGroup {
+                    ops: Vec::new(),
+                    cursors_before: Vec::new(),
+                    cursors_after: Vec::new(),
+                },
+            }],
+            current: 0,
+            pending: None,
+            saved: 0,
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        *self = History::new();
+    }
+
+    pub(super) fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // Whether the buffer differs from the file, going by where it was last saved in the tree
+    pub(super) fn is_modified(&self) -> bool {
+        self.current != self.saved || self.pending.as_ref().map_or(false, |g| !g.ops.is_empty())
+    }
+
+    pub(super) fn mark_saved(&mut self) {
+        self.saved = self.current;
+    }
+
+    // Start a new group if there isn't one already. The snapshot is only taken when required
+    pub(super) fn begin<F>(&mut self, snapshot: F)
+    where
+        F: FnOnce() -> CursorSnapshot,
+    {
+        if self.pending.is_none() {
+            self.pending = Some(UndoGroup {
+                ops: Vec::new(),
+                cursors_before: snapshot(),
+                cursors_after: Vec::new(),
+            });
+        }
+    }
+
+    pub(super) fn record(&mut self, op: EditOp) {
+        let group = self.pending.as_mut().expect("no undo group in progress");
+        if let Some(last) = group.ops.last_mut() {
+            if last.merge(&op) {
+                return;
+            }
+        }
+        group.ops.push(op);
+    }
+
+    pub(super) fn commit(&mut self, snapshot: CursorSnapshot) {
+        if let Some(mut group) = self.pending.take() {
+            if group.ops.is_empty() {
+                return;
+            }
+            group.cursors_after = snapshot;
+            let idx = self.nodes.len();
+            self.nodes.push(UndoNode {
+                parent: self.current,
+                active_child: None,
+                group,
+            });
+            self.nodes[self.current].active_child = Some(idx);
+            self.current = idx;
+        }
+    }
+
+    pub(super) fn undo(&mut self) -> Option<HistoryStep> {
+        if self.current == 0 {
+            return None;
+        }
+        let node = &self.nodes[self.current];
+        let ret = HistoryStep {
+            ops: node.group.ops.iter().rev().map(|op| op.inverse()).collect(),
+            cursors: clone_snapshot(&node.group.cursors_before),
+        };
+        let parent = node.parent;
+        self.nodes[parent].active_child = Some(self.current);
+        self.current = parent;
+        Some(ret)
+    }
+
+    pub(super) fn redo(&mut self) -> Option<HistoryStep> {
+        let child = self.nodes[self.current].active_child?;
+        let node = &self.nodes[child];
+        let ret = HistoryStep {
+            ops: node.group.ops.clone(),
+            cursors: clone_snapshot(&node.group.cursors_after),
+        };
+        self.current = child;
+        Some(ret)
+    }
+}
+
+fn clone_snapshot(snapshot: &CursorSnapshot) -> CursorSnapshot {
+    snapshot
+        .iter()
+        .map(|(id, cidx)| (id.clone(), *cidx))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit_ops(history: &mut History, ops: &[EditOp]) {
+        history.begin(|| vec![(BufferViewID(0), 0)]);
+        for op in ops {
+            history.record(op.clone());
+        }
+        history.commit(vec![(BufferViewID(0), 1)]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut history = History::new();
+        commit_ops(
+            &mut history,
+            &[
+                EditOp::Insert(0, "a".to_owned()),
+                EditOp::Insert(1, "bc".to_owned()),
+                EditOp::Remove(2, "c".to_owned()),
+                EditOp::Remove(1, "b".to_owned()),
+            ],
+        );
+        let step = history.undo().unwrap();
+        assert_eq!(
+            step.ops,
+            vec![
+                EditOp::Insert(1, "bc".to_owned()),
+                EditOp::Remove(0, "abc".to_owned())
+            ]
+        );
+        assert_eq!(step.cursors[0].1, 0);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::new();
+        assert!(history.undo().is_none());
+        commit_ops(&mut history, &[EditOp::Insert(0, "a".to_owned())]);
+        commit_ops(&mut history, &[EditOp::Insert(1, "b".to_owned())]);
+        assert_eq!(
+            history.undo().unwrap().ops,
+            vec![EditOp::Remove(1, "b".to_owned())]
+        );
+        assert_eq!(
+            history.undo().unwrap().ops,
+            vec![EditOp::Remove(0, "a".to_owned())]
+        );
+        assert!(history.undo().is_none());
+        let step = history.redo().unwrap();
+        assert_eq!(step.ops, vec![EditOp::Insert(0, "a".to_owned())]);
+        assert_eq!(step.cursors[0].1, 1);
+        assert_eq!(
+            history.redo().unwrap().ops,
+            vec![EditOp::Insert(1, "b".to_owned())]
+        );
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn test_modified() {
+        let mut history = History::new();
+        assert!(!history.is_modified());
+        history.begin(|| vec![(BufferViewID(0), 0)]);
+        assert!(!history.is_modified());
+        history.record(EditOp::Insert(0, "a".to_owned()));
+        assert!(history.is_modified());
+        history.commit(vec![(BufferViewID(0), 1)]);
+        history.mark_saved();
+        assert!(!history.is_modified());
+        commit_ops(&mut history, &[EditOp::Insert(1, "b".to_owned())]);
+        assert!(history.is_modified());
+        // Undoing back to the saved state clears the flag, and undoing past it sets it again
+        history.undo().unwrap();
+        assert!(!history.is_modified());
+        history.undo().unwrap();
+        assert!(history.is_modified());
+        // A new branch doesn't get back to the saved state
+        history.redo().unwrap();
+        history.undo().unwrap();
+        commit_ops(&mut history, &[EditOp::Insert(0, "c".to_owned())]);
+        assert!(history.is_modified());
+    }
+
+    #[test]
+    fn test_branch() {
+        let mut history = History::new();
+        commit_ops(&mut history, &[EditOp::Insert(0, "a".to_owned())]);
+        commit_ops(&mut history, &[EditOp::Insert(1, "b".to_owned())]);
+        history.undo().unwrap();
+        commit_ops(&mut history, &[EditOp::Insert(1, "c".to_owned())]);
+        assert!(history.redo().is_none());
+        assert_eq!(
+            history.undo().unwrap().ops,
+            vec![EditOp::Remove(1, "c".to_owned())]
+        );
+        // Redo follows the most recent branch
+        assert_eq!(
+            history.redo().unwrap().ops,
+            vec![EditOp::Insert(1, "c".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_empty_group() {
+        let mut history = History::new();
+        history.begin(Vec::new);
+        history.commit(Vec::new());
+        assert!(!history.has_pending());
+        assert!(history.undo().is_none());
+    }
+}
@@ -0,0 +1,286 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use fnv::FnvHashSet;
+use ropey::Rope;
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+use crate::common::rope_trim_newlines;
+
+// Indentation of a line, going by the captures of a language's indents query:
+//
+// @indent   lines in the node after its first line are indented one level more than that line
+// @align    like @indent, but lines up with the text after the opening bracket, if there is any
+// @outdent  a line starting with the node is indented like the first line of the node around it
+// @extend   the node also takes in a blank line after it, if the line above is indented more than
+//           the node's first line. Python's blocks end with their last statement
+#[derive(Debug, Eq, PartialEq)]
+pub(super) enum Indent {
+    // The indentation of a line, plus some levels
+    Line(usize, usize),
+    // Line up with a char in a line, given by its offset from the line's indentation
+    Align(usize, usize),
+    None,
+}
+
+// Nodes are told apart by kind and range
+type NodeKey = (u16, usize, usize);
+
+fn node_key(node: &Node) -> NodeKey {
+    (node.kind_id(), node.start_byte(), node.end_byte())
+}
+
+#[derive(Default)]
+struct Captures {
+    indent: FnvHashSet<NodeKey>,
+    align: FnvHashSet<NodeKey>,
+    outdent: FnvHashSet<NodeKey>,
+    extend: FnvHashSet<NodeKey>,
+}
+
+impl Captures {
+    // Captures of nodes around a byte
+    fn around(data: &Rope, tree: &Tree, query: &Query, bidx: usize) -> Captures {
+        let mut ret = Captures::default();
+        let capture_names = query.capture_names();
+        let b2c = |b| data.byte_to_char(b);
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(bidx, bidx + 1);
+        for query_match in cursor.matches(query, tree.root_node(), |node| {
+            let range = node.byte_range();
+            data.slice(b2c(range.start)..b2c(range.end)).to_string()
+        }) {
+            for capture in query_match.captures {
+                let set = match capture_names[capture.index as usize].as_str() {
+                    "indent" => &mut ret.indent,
+                    "align" => &mut ret.align,
+                    "outdent" => &mut ret.outdent,
+                    "extend" => &mut ret.extend,
+                    _ => continue,
+                };
+                set.insert(node_key(&capture.node));
+            }
+        }
+        ret
+    }
+
+    // Indentation for lines in the node, if it's captured
+    fn indent_in(&self, data: &Rope, node: &Node) -> Option<Indent> {
+        let key = node_key(node);
+        let row = node.start_position().row;
+        if self.align.contains(&key) {
+            Some(match align_offset(data, node) {
+                Some(offset) => Indent::Align(row, offset),
+                None => Indent::Line(row, 1),
+            })
+        } else if self.indent.contains(&key) {
+            Some(Indent::Line(row, 1))
+        } else {
+            None
+        }
+    }
+}
+
+// Indentation for a line, or None if there is a syntax error around it
+pub(super) fn ts_indent(data: &Rope, tree: &Tree, query: &Query, linum: usize) -> Option<Indent> {
+    let line_cidx = data.line_to_char(linum);
+    match first_non_blank(data, linum) {
+        Some(off) => line_indent(data, tree, query, linum, line_cidx + off),
+        None => blank_line_indent(data, tree, query, linum),
+    }
+}
+
+fn line_indent(
+    data: &Rope,
+    tree: &Tree,
+    query: &Query,
+    linum: usize,
+    cidx: usize,
+) -> Option<Indent> {
+    let bidx = data.char_to_byte(cidx);
+    let captures = Captures::around(data, tree, query, bidx);
+    let mut outdent = false;
+    let mut node = tree.root_node().descendant_for_byte_range(bidx, bidx + 1);
+    while let Some(n) = node {
+        if n.kind() == "ERROR" {
+            return None;
+        }
+        if n.start_byte() == bidx && captures.outdent.contains(&node_key(&n)) {
+            outdent = true;
+        }
+        if n.start_position().row < linum {
+            match captures.indent_in(data, &n) {
+                Some(Indent::Line(row, _)) | Some(Indent::Align(row, _)) if outdent => {
+                    return Some(Indent::Line(row, 0))
+                }
+                Some(indent) => return Some(indent),
+                None => {}
+            }
+        }
+        node = n.parent();
+    }
+    Some(Indent::None)
+}
+
+// A blank line goes in the innermost node around the end of the line above it which either
+// goes on past the blank line, is missing its end, or extends to it
+fn blank_line_indent(data: &Rope, tree: &Tree, query: &Query, linum: usize) -> Option<Indent> {
+    let prev = match (0..linum)
+        .rev()
+        .find(|l| first_non_blank(data, *l).is_some())
+    {
+        Some(prev) => prev,
+        None => return Some(Indent::None),
+    };
+    let prev_end = data.line_to_char(prev) + rope_trim_newlines(data.line(prev)).len_chars();
+    let last_cidx = (data.line_to_char(prev)..prev_end)
+        .rev()
+        .find(|cidx| !data.char(*cidx).is_whitespace())
+        .unwrap();
+    let bidx = data.char_to_byte(last_cidx);
+    let line_bidx = data.line_to_byte(linum);
+    let prev_indent = first_non_blank(data, prev).unwrap();
+    let captures = Captures::around(data, tree, query, bidx);
+    let mut node = tree.root_node().descendant_for_byte_range(bidx, bidx + 1);
+    while let Some(n) = node {
+        if n.kind() == "ERROR" {
+            return None;
+        }
+        let extends = captures.extend.contains(&node_key(&n))
+            && first_non_blank(data, n.start_position().row).map_or(false, |i| i < prev_indent);
+        if n.end_byte() > line_bidx || is_unfinished(&n) || extends {
+            if let Some(indent) = captures.indent_in(data, &n) {
+                return Some(indent);
+            }
+        }
+        node = n.parent();
+    }
+    Some(Indent::None)
+}
+
+// Whether a node ends with a missing token or an empty block
+fn is_unfinished(node: &Node) -> bool {
+    let mut last = *node;
+    while last.child_count() > 0 {
+        last = last.child(last.child_count() - 1).unwrap();
+    }
+    last.is_missing() || (last.is_named() && last.start_byte() == last.end_byte())
+}
+
+// Offset from the indentation of its line of the text after a node's opening bracket, if it's on
+// the same line
+fn align_offset(data: &Rope, node: &Node) -> Option<usize> {
+    if node.child_count() < 3 {
+        return None;
+    }
+    let next = node.child(1)?;
+    let row = node.start_position().row;
+    if next.start_position().row != row {
+        return None;
+    }
+    let offset = data.byte_to_char(next.start_byte()) - data.line_to_char(row);
+    Some(offset - first_non_blank(data, row)?)
+}
+
+// Char offset of the first non-whitespace character in a line
+pub(super) fn first_non_blank(data: &Rope, linum: usize) -> Option<usize> {
+    rope_trim_newlines(data.line(linum))
+        .chars()
+        .position(|c| !c.is_whitespace())
+}
+
+// Whitespace to put before a line to line it up with a char in another line, given by its offset
+// from that line's indentation. Tabs stay as they are, to line up the same way
+pub(super) fn align_whitespace(data: &Rope, linum: usize, offset: usize) -> String {
+    let indent = first_non_blank(data, linum).unwrap_or(0);
+    data.line(linum)
+        .chars()
+        .take(indent + offset)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect()
+}
+
+// Whether the node at a char is inside a syntax error
+pub(super) fn in_error(data: &Rope, tree: &Tree, cidx: usize) -> bool {
+    let bidx = data.char_to_byte(cidx);
+    let mut node = tree.root_node().descendant_for_byte_range(bidx, bidx + 1);
+    while let Some(n) = node {
+        if n.kind() == "ERROR" {
+            return true;
+        }
+        node = n.parent();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ts::TsCore;
+
+    // Indentation of a line, with the syntax tree and indents query of the language for an
+    // extension
+    fn indent(ext: &str, text: &str, linum: usize) -> Option<Indent> {
+        let (_, mut parser, queries) = TsCore::new().parser_from_extension(ext).unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        ts_indent(&Rope::from_str(text), &tree, &queries.indents, linum)
+    }
+
+    #[test]
+    fn test_rust_indent() {
+        assert_eq!(indent("rs", "fn f() {\n\n}\n", 1), Some(Indent::Line(0, 1)));
+        assert_eq!(
+            indent("rs", "fn f() {\n    x;\n}\n", 1),
+            Some(Indent::Line(0, 1))
+        );
+        assert_eq!(
+            indent("rs", "fn f() {\n    x;\n    }\n", 2),
+            Some(Indent::Line(0, 0))
+        );
+        let text = "fn f() {\n    let x = [\n        1,\n    ];\n}\n";
+        assert_eq!(indent("rs", text, 2), Some(Indent::Line(1, 1)));
+        assert_eq!(indent("rs", text, 3), Some(Indent::Line(1, 0)));
+        assert_eq!(indent("rs", "fn f() {}\n\n", 1), Some(Indent::None));
+    }
+
+    #[test]
+    fn test_python_indent() {
+        // The blank line after a block's last statement is still in the block
+        assert_eq!(
+            indent("py", "if x:\n    y\n\n", 2),
+            Some(Indent::Line(0, 1))
+        );
+        assert_eq!(indent("py", "if x:\n    y\n", 1), Some(Indent::Line(0, 1)));
+        // But not once the line above is back at the statement's indentation
+        let text = "if x:\n    y\nz\n\n";
+        assert_eq!(indent("py", text, 3), Some(Indent::None));
+        let text = "if x:\n    y\nelse:\n    z\n";
+        assert_eq!(indent("py", text, 2), Some(Indent::Line(0, 0)));
+        assert_eq!(indent("py", text, 3), Some(Indent::Line(2, 1)));
+        // An else which is still indented like the block doesn't parse
+        assert_eq!(indent("py", "if x:\n    y\n    else:\n", 2), None);
+        // Arguments line up with the first one
+        let text = "f(a,\n  b)\n";
+        assert_eq!(indent("py", text, 1), Some(Indent::Align(0, 2)));
+        let text = "f(\n  b)\n";
+        assert_eq!(indent("py", text, 1), Some(Indent::Line(0, 1)));
+    }
+
+    #[test]
+    fn test_c_indent() {
+        assert_eq!(indent("c", "int f() {\n\n}\n", 1), Some(Indent::Line(0, 1)));
+        assert_eq!(
+            indent("c", "int f() {\n    x;\n    }\n", 2),
+            Some(Indent::Line(0, 0))
+        );
+        let text = "int f() {\n    if (x)\n        y;\n    else\n        z;\n}\n";
+        assert_eq!(indent("c", text, 2), Some(Indent::Line(1, 1)));
+        assert_eq!(indent("c", text, 3), Some(Indent::Line(1, 0)));
+        assert_eq!(indent("c", text, 4), Some(Indent::Line(1, 1)));
+    }
+
+    #[test]
+    fn test_align_whitespace() {
+        let rope = Rope::from_str("\tf(a,\n");
+        assert_eq!(align_whitespace(&rope, 0, 2), "\t  ");
+    }
+}
@@ -0,0 +1,194 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use ropey::Rope;
+
+const MAX_CHANGES: usize = 100;
+
+// Where a position ends up after nchars are inserted at cidx
+pub(super) fn shift_for_insert(pos: usize, cidx: usize, nchars: usize) -> usize {
+    if pos >= cidx {
+        pos + nchars
+    } else {
+        pos
+    }
+}
+
+// Where a position ends up after start_cidx..end_cidx is removed. Positions within the range
+// move to its start
+pub(super) fn shift_for_remove(pos: usize, start_cidx: usize, end_cidx: usize) -> usize {
+    if pos >= end_cidx {
+        pos - (end_cidx - start_cidx)
+    } else if pos >= start_cidx {
+        start_cidx
+    } else {
+        pos
+    }
+}
+
+// Marks of a buffer, as char indices which move with edits.
+//
+// 'a'-'z'    set with m
+// 'A'-'Z'    also set with m. BufferMgr keeps track of which buffer has each of these
+// '.'        where the last change was made
+// '^'        where insert mode was last left
+// '[' ']'    first and last char of the last change
+#[derive(Default)]
+pub(super) struct Marks {
+    letters: Vec<(char, usize)>,
+    last_change: Option<usize>,
+    last_insert: Option<usize>,
+    change_range: Option<(usize, usize)>,
+    // Range covered by the edits since the last flush
+    pending: Option<(usize, usize)>,
+    // Positions of changes, oldest first, for g; and g,
+    changelist: Vec<usize>,
+}
+
+impl Marks {
+    pub(super) fn get(&self, name: char) -> Option<usize> {
+        match name {
+            '.' => self.last_change,
+            '^' => self.last_insert,
+            '[' => self.change_range.map(|(start, _)| start),
+            ']' => self
+                .change_range
+                .map(|(start, end)| if end > start { end - 1 } else { start }),
+            c => self
+                .letters
+                .iter()
+                .find(|(name, _)| *name == c)
+                .map(|(_, pos)| *pos),
+        }
+    }
+
+    // Returns false for names which can't be set
+    pub(super) fn set(&mut self, name: char, cidx: usize) -> bool {
+        if !name.is_ascii_alphabetic() {
+            return false;
+        }
+        match self.letters.iter_mut().find(|(c, _)| *c == name) {
+            Some((_, pos)) => *pos = cidx,
+            None => self.letters.push((name, cidx)),
+        }
+        true
+    }
+
+    pub(super) fn clear(&mut self, name: char) {
+        self.letters.retain(|(c, _)| *c != name);
+    }
+
+    pub(super) fn set_last_insert(&mut self, cidx: usize) {
+        self.last_insert = Some(cidx);
+    }
+
+    pub(super) fn changelist(&self) -> &[usize] {
+        &self.changelist
+    }
+
+    pub(super) fn insert(&mut self, cidx: usize, nchars: usize) {
+        self.shift(|pos| shift_for_insert(pos, cidx, nchars));
+        self.pending = Some(match self.pending {
+            Some((start, end)) => (start.min(cidx), end.max(cidx + nchars)),
+            None => (cidx, cidx + nchars),
+        });
+        self.last_change = Some(cidx);
+    }
+
+    pub(super) fn remove(&mut self, start_cidx: usize, end_cidx: usize) {
+        self.shift(|pos| shift_for_remove(pos, start_cidx, end_cidx));
+        self.pending = Some(match self.pending {
+            Some((start, end)) => (start.min(start_cidx), end.max(start_cidx)),
+            None => (start_cidx, start_cidx),
+        });
+        self.last_change = Some(start_cidx);
+    }
+
+    // Close the edits made since the last call as one change. A change on the same line as the
+    // last one replaces it in the changelist
+    pub(super) fn finish_change(&mut self, data: &Rope) {
+        let (start, end) = match self.pending.take() {
+            Some(range) => range,
+            None => return,
+        };
+        self.change_range = Some((start, end));
+        let linum = data.char_to_line(start.min(data.len_chars()));
+        match self.changelist.last_mut() {
+            Some(last) if data.char_to_line((*last).min(data.len_chars())) == linum => {
+                *last = start
+            }
+            _ => {
+                self.changelist.push(start);
+                if self.changelist.len() > MAX_CHANGES {
+                    self.changelist.remove(0);
+                }
+            }
+        }
+    }
+
+    fn shift<F: Fn(usize) -> usize>(&mut self, f: F) {
+        for (_, pos) in &mut self.letters {
+            *pos = f(*pos);
+        }
+        for pos in &mut self.changelist {
+            *pos = f(*pos);
+        }
+        for pos in self
+            .last_change
+            .iter_mut()
+            .chain(self.last_insert.iter_mut())
+        {
+            *pos = f(*pos);
+        }
+        if let Some((start, end)) = &mut self.change_range {
+            *start = f(*start);
+            *end = f(*end);
+        }
+        if let Some((start, end)) = &mut self.pending {
+            *start = f(*start);
+            *end = f(*end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shift() {
+        assert_eq!(shift_for_insert(5, 5, 3), 8);
+        assert_eq!(shift_for_insert(4, 5, 3), 4);
+        assert_eq!(shift_for_remove(10, 5, 8), 7);
+        assert_eq!(shift_for_remove(6, 5, 8), 5);
+        assert_eq!(shift_for_remove(4, 5, 8), 4);
+    }
+
+    #[test]
+    fn test_marks() {
+        let mut rope = Rope::from_str("foo\nbar\nbaz\n");
+        let mut marks = Marks::default();
+        assert!(marks.set('a', 5));
+        assert!(marks.set('B', 9));
+        assert!(!marks.set('.', 0));
+        rope.insert(0, "xx");
+        marks.insert(0, 2);
+        marks.finish_change(&rope);
+        assert_eq!(marks.get('a'), Some(7));
+        assert_eq!(marks.get('B'), Some(11));
+        assert_eq!(marks.get('['), Some(0));
+        assert_eq!(marks.get(']'), Some(1));
+        rope.remove(6..12);
+        marks.remove(6, 12);
+        rope.insert(6, "y");
+        marks.insert(6, 1);
+        marks.finish_change(&rope);
+        assert_eq!(marks.get('a'), Some(7));
+        assert_eq!(marks.get('B'), Some(7));
+        assert_eq!(marks.get('.'), Some(6));
+        assert_eq!(marks.changelist(), &[0, 6]);
+        // Same line as the last change
+        marks.insert(7, 1);
+        marks.finish_change(&rope);
+        assert_eq!(marks.changelist(), &[0, 7]);
+    }
+}
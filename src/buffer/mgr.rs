@@ -10,6 +10,7 @@ use fnv::FnvHashMap;
 use crate::config::Config;
 use crate::language_client::{LanguageClientManager, PublishDiagnosticParams};
 use crate::project::Projects;
+use crate::registers::Registers;
 use crate::theme::Theme;
 use crate::ts::TsCore;
 
@@ -26,18 +27,20 @@ pub(crate) struct BufferMgr {
     ts_core: TsCore,
     theme: Rc<Theme>,
     config: Rc<Config>,
+    registers: Rc<RefCell<Registers>>,
     lang_client_manager: LanguageClientManager,
     path_diagnostics_map: FnvHashMap<String, PublishDiagnosticParams>,
+    // Buffers which have the global marks 'A'-'Z'. The buffers keep the positions
+    global_marks: [Option<BufferID>; 26],
 }
 
-// TODO: Periodically clear out Weak buffers with a strong count of 0
-
 impl BufferMgr {
     pub(crate) fn new(
         ts_core: TsCore,
         projects: Projects,
         config: Rc<Config>,
         theme: Rc<Theme>,
+        registers: Rc<RefCell<Registers>>,
         lang_client_manager: LanguageClientManager,
     ) -> BufferMgr {
         BufferMgr {
@@ -50,8 +53,10 @@ impl BufferMgr {
             theme,
             projects,
             config,
+            registers,
             lang_client_manager,
             path_diagnostics_map: FnvHashMap::default(),
+            global_marks: [None; 26],
         }
     }
 
@@ -77,6 +82,43 @@ impl BufferMgr {
         self.path_diagnostics_map.insert(path, diagnostics);
     }
 
+    // Highlight matches of the search pattern in all buffers
+    pub(crate) fn set_search_pattern(&mut self, pattern: Option<&str>) {
+        for buf in self.id_buf_map.values().filter_map(|weak| weak.upgrade()) {
+            let buf = &mut *buf.borrow_mut();
+            buf.set_search_pattern(pattern);
+        }
+    }
+
+    // Note that a global mark was set in a buffer, and clear it from the buffer which had it
+    pub(crate) fn set_global_mark(&mut self, name: char, id: BufferID) {
+        let i = name as usize - 'A' as usize;
+        if let Some(old_id) = self.global_marks[i].replace(id) {
+            if old_id != id {
+                if let Some(buf) = self.id_buf_map.get(&old_id).and_then(|weak| weak.upgrade()) {
+                    buf.borrow_mut().clear_mark(name);
+                }
+            }
+        }
+    }
+
+    // Buffer with a global mark. A buffer which has been closed is opened again, but the mark
+    // is gone with it. A buffer which is open is never reloaded, so its changes are kept
+    pub(crate) fn global_mark_buffer(
+        &mut self,
+        name: char,
+    ) -> Option<IOResult<Rc<RefCell<Buffer>>>> {
+        let id = self.global_marks[name as usize - 'A' as usize]?;
+        if let Some(buf) = self.id_buf_map.get(&id).and_then(|weak| weak.upgrade()) {
+            return Some(Ok(buf));
+        }
+        let path = self.id_path_map.get(&id)?.clone();
+        if let Some(buf) = self.buffer_for_path(&path) {
+            return Some(Ok(buf));
+        }
+        Some(self.from_file(&path))
+    }
+
     pub(crate) fn empty(&mut self) -> Rc<RefCell<Buffer>> {
         let buf_id = BufferID(self.next_buf_id);
         self.next_buf_id += 1;
@@ -84,6 +126,7 @@ impl BufferMgr {
             buf_id,
             self.config.clone(),
             self.theme.clone(),
+            self.registers.clone(),
         )));
         self.id_buf_map.insert(buf_id, Rc::downgrade(&ret));
         ret
@@ -125,6 +168,7 @@ impl BufferMgr {
                     &self.ts_core,
                     self.config.clone(),
                     self.theme.clone(),
+                    self.registers.clone(),
                     &mut self.lang_client_manager,
                 )
                 .map(|mut buffer| {
@@ -183,6 +227,52 @@ impl BufferMgr {
         }
     }
 
+    // Open buffers, in the order they were opened
+    pub(crate) fn buffers(&self) -> Vec<Rc<RefCell<Buffer>>> {
+        let mut ret = self
+            .id_buf_map
+            .values()
+            .filter_map(|weak| weak.upgrade())
+            .collect::<Vec<_>>();
+        ret.sort_by_key(|buf| buf.borrow().buffer_id().0);
+        ret
+    }
+
+    // Open buffers with changes which haven't been written
+    pub(crate) fn modified_buffers(&self) -> Vec<Rc<RefCell<Buffer>>> {
+        let mut ret = self.buffers();
+        ret.retain(|buf| buf.borrow().is_modified());
+        ret
+    }
+
+    pub(crate) fn buffer_for_id(&self, id: BufferID) -> Option<Rc<RefCell<Buffer>>> {
+        self.id_buf_map.get(&id).and_then(|weak| weak.upgrade())
+    }
+
+    pub(crate) fn buffer_for_number(&self, number: usize) -> Option<Rc<RefCell<Buffer>>> {
+        self.buffer_for_id(BufferID(number.checked_sub(1)?))
+    }
+
+    // Forget buffers which have been dropped. Their paths and ids are kept, so that global marks
+    // in them can open them again, and so that a file which is opened again keeps its number
+    pub(crate) fn purge(&mut self) {
+        self.id_buf_map.retain(|_, weak| weak.strong_count() > 0);
+        let id_path_map = &self.id_path_map;
+        self.path_id_map
+            .retain(|_, id| id_path_map.contains_key(id));
+    }
+
+    // Forget everything about a deleted buffer, including its path and global marks
+    pub(crate) fn wipe(&mut self, id: BufferID) {
+        self.id_path_map.remove(&id);
+        for mark in self.global_marks.iter_mut() {
+            if *mark == Some(id) {
+                *mark = None;
+            }
+        }
+        self.purge();
+    }
+
     pub(crate) fn buffer_for_path(&self, path: &str) -> Option<Rc<RefCell<Buffer>>> {
         self.path_id_map
             .get(path)
@@ -211,3 +301,67 @@ impl BufferMgr {
         ret
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use euclid::{size2, Point2D, Rect};
+
+    use crate::font::FontCore;
+    use crate::text::TextShaper;
+
+    use super::super::BufferViewCreateParams;
+
+    fn buffer_mgr() -> (BufferMgr, BufferViewCreateParams) {
+        let mut font_core = FontCore::new().unwrap();
+        let config = Rc::new(Config::load(&mut font_core));
+        let (lsp_tx, _) = unbounded();
+        let buffer_mgr = BufferMgr::new(
+            TsCore::new(),
+            Projects::load(),
+            config.clone(),
+            Rc::new(Theme::default()),
+            Rc::new(RefCell::new(Registers::new())),
+            LanguageClientManager::new(lsp_tx),
+        );
+        let params = BufferViewCreateParams {
+            config,
+            dpi: size2(96, 96),
+            text_shaper: Rc::new(RefCell::new(TextShaper::new(font_core))),
+            rect: Rect::new(Point2D::origin(), size2(800, 600)),
+        };
+        (buffer_mgr, params)
+    }
+
+    #[test]
+    fn test_reopen_deleted_buffer() {
+        let path = std::env::temp_dir().join("bed_test_reopen_deleted_buffer.txt");
+        std::fs::write(&path, "abc\n").unwrap();
+        let path = path.to_str().unwrap().to_owned();
+        let (mut buffer_mgr, params) = buffer_mgr();
+        let _other = buffer_mgr.empty();
+        let buf = buffer_mgr.from_file(&path).unwrap();
+        let id = buf.borrow().buffer_id();
+        buffer_mgr.set_global_mark('A', id);
+        // :bd, and open the file again. It keeps its number
+        drop(buf);
+        buffer_mgr.purge();
+        assert!(buffer_mgr.buffer_for_number(2).is_none());
+        let buf = buffer_mgr.from_file(&path).unwrap();
+        assert!(buf.borrow().buffer_id() == id);
+        assert!(Rc::ptr_eq(&buffer_mgr.buffer_for_number(2).unwrap(), &buf));
+        // Jumping to the mark doesn't reload the buffer, which would lose the change
+        let view_id = buffer_mgr.next_view_id();
+        buf.borrow_mut().new_view(&view_id, params);
+        buf.borrow_mut().view_insert_char(&view_id, 'x');
+        let marked = buffer_mgr.global_mark_buffer('A').unwrap().unwrap();
+        assert!(Rc::ptr_eq(&marked, &buf));
+        assert!(buf.borrow().is_modified());
+        // Wiping it out forgets the mark
+        drop((buf, marked));
+        buffer_mgr.wipe(id);
+        assert!(buffer_mgr.global_mark_buffer('A').is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
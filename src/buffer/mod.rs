@@ -1,10 +1,22 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
+mod autopair;
 mod buffer;
+mod comment;
 mod completion;
 mod cursor;
+mod fold;
+mod history;
+mod indent;
+mod marks;
 mod mgr;
+mod motion;
+mod number;
+mod regex;
+mod search;
 mod styled;
+mod surround;
+mod textobject;
 mod types;
 mod view;
 
@@ -20,6 +32,13 @@ impl BufferViewID {
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 pub(crate) struct BufferID(usize);
 
+impl BufferID {
+    // Number of the buffer in the buffer list, counting from 1
+    pub(crate) fn number(self) -> usize {
+        self.0 + 1
+    }
+}
+
 pub(crate) use buffer::Buffer;
 pub(crate) use cursor::CursorStyle;
 pub(crate) use mgr::BufferMgr;
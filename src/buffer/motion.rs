@@ -0,0 +1,359 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use ropey::Rope;
+use tree_sitter::Tree;
+
+use crate::common::rope_trim_newlines;
+use crate::input::CharFind;
+
+use super::textobject::{char_class, is_blank_line, line_run, node_at, sentence_starts};
+
+// -------- Characters ----------------
+
+// Char index for the count'th occurrence of a character on the cursor's line. Repeating t or T
+// skips an occurrence right next to the cursor, which it would stop in front of again
+pub(super) fn find_char(
+    data: &Rope,
+    cidx: usize,
+    find: CharFind,
+    count: usize,
+    repeat: bool,
+) -> Option<usize> {
+    let linum = data.char_to_line(cidx);
+    let line_start = data.line_to_char(linum);
+    let line_end = line_start + rope_trim_newlines(data.line(linum)).len_chars();
+    let skip = if find.till && repeat { 1 } else { 0 };
+    let nth = count.saturating_sub(1);
+    if find.forward {
+        if cidx + 1 >= line_end {
+            return None;
+        }
+        let pos = data
+            .chars_at(cidx + 1)
+            .take(line_end - cidx - 1)
+            .enumerate()
+            .skip(skip)
+            .filter(|(_, c)| *c == find.c)
+            .nth(nth)
+            .map(|(i, _)| cidx + 1 + i)?;
+        Some(if find.till { pos - 1 } else { pos })
+    } else {
+        let mut chars = data.chars_at(cidx);
+        let pos = (line_start..cidx)
+            .rev()
+            .zip(std::iter::from_fn(|| chars.prev()))
+            .skip(skip)
+            .filter(|(_, c)| *c == find.c)
+            .nth(nth)
+            .map(|(i, _)| i)?;
+        Some(if find.till { pos + 1 } else { pos })
+    }
+}
+
+// -------- Words ----------------
+
+// Last char of the count'th word after the cursor
+pub(super) fn nth_word_end(data: &Rope, cidx: usize, count: usize, extended: bool) -> usize {
+    let len = data.len_chars();
+    let mut i = cidx;
+    for _ in 0..count {
+        let mut j = i + 1;
+        while j < len && data.char(j).is_whitespace() {
+            j += 1;
+        }
+        if j >= len {
+            break;
+        }
+        let class = char_class(data.char(j), extended);
+        while j + 1 < len && char_class(data.char(j + 1), extended) == class {
+            j += 1;
+        }
+        i = j;
+    }
+    i
+}
+
+// Last char of the count'th word before the cursor
+pub(super) fn nth_back_word_end(data: &Rope, cidx: usize, count: usize, extended: bool) -> usize {
+    let len = data.len_chars();
+    let mut i = cidx;
+    for _ in 0..count {
+        let mut j = i;
+        if j < len {
+            let class = char_class(data.char(j), extended);
+            if class != 0 {
+                while j > 0 && char_class(data.char(j - 1), extended) == class {
+                    j -= 1;
+                }
+            }
+        }
+        while j > 0 && data.char(j - 1).is_whitespace() {
+            j -= 1;
+        }
+        if j == 0 {
+            return 0;
+        }
+        i = j - 1;
+    }
+    i
+}
+
+// -------- Paragraphs and sentences ----------------
+
+// Start of the blank line after (or before) the count'th paragraph from the cursor. If there is
+// none, the end (or start) of the buffer
+pub(super) fn paragraph_boundary(data: &Rope, cidx: usize, forward: bool, count: usize) -> usize {
+    let last_linum = data.len_lines() - 1;
+    let mut linum = data.char_to_line(cidx);
+    for _ in 0..count {
+        if forward {
+            while linum < last_linum && is_blank_line(data, linum) {
+                linum += 1;
+            }
+            while linum < last_linum && !is_blank_line(data, linum) {
+                linum += 1;
+            }
+            if !is_blank_line(data, linum) {
+                return data.len_chars();
+            }
+        } else {
+            while linum > 0 && is_blank_line(data, linum) {
+                linum -= 1;
+            }
+            while linum > 0 && !is_blank_line(data, linum) {
+                linum -= 1;
+            }
+            if !is_blank_line(data, linum) {
+                return 0;
+            }
+        }
+    }
+    data.line_to_char(linum)
+}
+
+// Start of the count'th sentence after (or before) the cursor. The blank lines between
+// paragraphs count as a sentence too
+pub(super) fn sentence_boundary(data: &Rope, cidx: usize, forward: bool, count: usize) -> usize {
+    let mut pos = cidx;
+    for _ in 0..count {
+        pos = if forward {
+            next_sentence(data, pos)
+        } else {
+            prev_sentence(data, pos)
+        };
+    }
+    pos
+}
+
+fn paragraph_sentences(data: &Rope, first: usize, last: usize) -> Vec<usize> {
+    let start = data.line_to_char(first);
+    let end = data.line_to_char(last) + rope_trim_newlines(data.line(last)).len_chars();
+    let chars = data.slice(start..end).chars().collect::<Vec<_>>();
+    sentence_starts(&chars)
+        .into_iter()
+        .map(|i| start + i)
+        .collect()
+}
+
+fn next_sentence(data: &Rope, cidx: usize) -> usize {
+    let linum = data.char_to_line(cidx);
+    let (first, last) = line_run(data, linum);
+    if !is_blank_line(data, linum) {
+        let starts = paragraph_sentences(data, first, last);
+        if let Some(start) = starts.into_iter().find(|start| *start > cidx) {
+            return start;
+        }
+    }
+    if last + 1 < data.len_lines() {
+        data.line_to_char(last + 1)
+    } else {
+        data.len_chars()
+    }
+}
+
+fn prev_sentence(data: &Rope, cidx: usize) -> usize {
+    let linum = data.char_to_line(cidx);
+    let (first, last) = line_run(data, linum);
+    if !is_blank_line(data, linum) {
+        let starts = paragraph_sentences(data, first, last);
+        if let Some(start) = starts.into_iter().rev().find(|start| *start < cidx) {
+            return start;
+        }
+        return if first > 0 {
+            data.line_to_char(first - 1)
+        } else {
+            0
+        };
+    }
+    if first == 0 {
+        return 0;
+    }
+    let (prev_first, prev_last) = line_run(data, first - 1);
+    *paragraph_sentences(data, prev_first, prev_last)
+        .last()
+        .unwrap()
+}
+
+// -------- Brackets ----------------
+
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+// Bracket matching the first one at or after the cursor on its line. With a syntax tree, the
+// matching bracket is a sibling node, which skips brackets in strings and comments
+pub(super) fn matching_bracket(data: &Rope, tree: Option<&Tree>, cidx: usize) -> Option<usize> {
+    let linum = data.char_to_line(cidx);
+    let line_end = data.line_to_char(linum) + rope_trim_newlines(data.line(linum)).len_chars();
+    if cidx >= line_end {
+        return None;
+    }
+    let (pos, (open, close)) = data
+        .chars_at(cidx)
+        .take(line_end - cidx)
+        .enumerate()
+        .find_map(|(i, c)| {
+            BRACKETS
+                .iter()
+                .find(|(open, close)| c == *open || c == *close)
+                .map(|pair| (cidx + i, *pair))
+        })?;
+    tree.and_then(|tree| ts_matching_bracket(data, tree, pos, open, close))
+        .or_else(|| scan_matching_bracket(data, pos, open, close))
+}
+
+fn ts_matching_bracket(
+    data: &Rope,
+    tree: &Tree,
+    cidx: usize,
+    open: char,
+    close: char,
+) -> Option<usize> {
+    let node = node_at(data, tree, cidx)?;
+    let (mut open_buf, mut close_buf) = ([0; 4], [0; 4]);
+    let open = &*open.encode_utf8(&mut open_buf);
+    let close = &*close.encode_utf8(&mut close_buf);
+    let is_open = node.kind() == open;
+    if (!is_open && node.kind() != close) || node.start_byte() != data.char_to_byte(cidx) {
+        return None;
+    }
+    let parent = node.parent()?;
+    let siblings = (0..parent.child_count())
+        .filter_map(|i| parent.child(i))
+        .collect::<Vec<_>>();
+    let i = siblings
+        .iter()
+        .position(|s| s.start_byte() == node.start_byte())?;
+    let other = if is_open {
+        siblings[i + 1..].iter().find(|s| s.kind() == close)
+    } else {
+        siblings[..i].iter().rev().find(|s| s.kind() == open)
+    }?;
+    Some(data.byte_to_char(other.start_byte()))
+}
+
+fn scan_matching_bracket(data: &Rope, cidx: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    if data.char(cidx) == open {
+        for (i, c) in data.chars_at(cidx + 1).enumerate() {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                if depth == 0 {
+                    return Some(cidx + 1 + i);
+                }
+                depth -= 1;
+            }
+        }
+    } else {
+        let mut chars = data.chars_at(cidx);
+        let mut i = cidx;
+        while let Some(c) = chars.prev() {
+            i -= 1;
+            if c == close {
+                depth += 1;
+            } else if c == open {
+                if depth == 0 {
+                    return Some(i);
+                }
+                depth -= 1;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_char() {
+        let rope = Rope::from_str("a,b,c,d\nx,y");
+        let find = |c, forward, till| CharFind { c, forward, till };
+        assert_eq!(
+            find_char(&rope, 0, find(',', true, false), 2, false),
+            Some(3)
+        );
+        assert_eq!(
+            find_char(&rope, 0, find(',', true, true), 1, false),
+            Some(0)
+        );
+        assert_eq!(find_char(&rope, 0, find(',', true, true), 1, true), Some(2));
+        assert_eq!(
+            find_char(&rope, 6, find('b', false, false), 1, false),
+            Some(2)
+        );
+        assert_eq!(
+            find_char(&rope, 6, find(',', false, true), 1, false),
+            Some(6)
+        );
+        assert_eq!(
+            find_char(&rope, 6, find(',', false, true), 1, true),
+            Some(4)
+        );
+        // Only on the cursor's line
+        assert_eq!(find_char(&rope, 0, find('x', true, false), 1, false), None);
+        assert_eq!(find_char(&rope, 8, find(',', false, false), 1, false), None);
+    }
+
+    #[test]
+    fn test_word_ends() {
+        let rope = Rope::from_str("foo bar.baz  qux");
+        assert_eq!(nth_word_end(&rope, 0, 1, false), 2);
+        assert_eq!(nth_word_end(&rope, 2, 1, false), 6);
+        assert_eq!(nth_word_end(&rope, 2, 2, false), 7);
+        assert_eq!(nth_word_end(&rope, 2, 1, true), 10);
+        assert_eq!(nth_word_end(&rope, 15, 1, false), 15);
+        assert_eq!(nth_back_word_end(&rope, 14, 1, false), 10);
+        assert_eq!(nth_back_word_end(&rope, 9, 1, false), 7);
+        assert_eq!(nth_back_word_end(&rope, 9, 1, true), 2);
+        assert_eq!(nth_back_word_end(&rope, 1, 1, false), 0);
+    }
+
+    #[test]
+    fn test_paragraphs_and_sentences() {
+        let rope = Rope::from_str("One. Two!\nThree.\n\n\nFour. Five.\n");
+        assert_eq!(paragraph_boundary(&rope, 0, true, 1), 17);
+        assert_eq!(paragraph_boundary(&rope, 17, true, 1), 31);
+        assert_eq!(paragraph_boundary(&rope, 0, true, 3), 31);
+        assert_eq!(paragraph_boundary(&rope, 25, false, 1), 18);
+        assert_eq!(paragraph_boundary(&rope, 18, false, 1), 0);
+        assert_eq!(sentence_boundary(&rope, 0, true, 1), 5);
+        assert_eq!(sentence_boundary(&rope, 5, true, 2), 17);
+        assert_eq!(sentence_boundary(&rope, 17, true, 1), 19);
+        assert_eq!(sentence_boundary(&rope, 19, false, 1), 18);
+        assert_eq!(sentence_boundary(&rope, 17, false, 1), 10);
+        assert_eq!(sentence_boundary(&rope, 7, false, 2), 0);
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        let rope = Rope::from_str("f(a[0], (b))\n{\n}");
+        assert_eq!(matching_bracket(&rope, None, 0), Some(11));
+        assert_eq!(matching_bracket(&rope, None, 11), Some(1));
+        assert_eq!(matching_bracket(&rope, None, 3), Some(5));
+        assert_eq!(matching_bracket(&rope, None, 4), Some(3));
+        assert_eq!(matching_bracket(&rope, None, 8), Some(10));
+        assert_eq!(matching_bracket(&rope, None, 13), Some(15));
+        assert_eq!(matching_bracket(&rope, None, 15), Some(13));
+    }
+}
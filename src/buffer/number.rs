@@ -0,0 +1,154 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+// Numbers in text, for Ctrl-A and Ctrl-X
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Radix {
+    Binary,
+    Octal,
+    Decimal,
+    Hex,
+}
+
+// A number in a line, with char offsets. The digits don't include a prefix like "0x", but the
+// range does
+struct Number {
+    start: usize,
+    digits_start: usize,
+    end: usize,
+    radix: Radix,
+}
+
+// Add delta to the first number which ends after the cursor offset in the line. Returns the range
+// of chars covered by the number, and the text to replace it with. Decimal numbers are signed,
+// and others wrap around like unsigned 64-bit integers, keeping their prefix, case and width
+pub(super) fn add_to_number(
+    line: &[char],
+    cursor: usize,
+    delta: i64,
+) -> Option<(usize, usize, String)> {
+    let mut i = 0;
+    let num = loop {
+        if i >= line.len() {
+            return None;
+        }
+        match number_at(line, i) {
+            Some(num) if num.end > cursor => break num,
+            Some(num) => i = num.end,
+            None => i += 1,
+        }
+    };
+    let digits = line[num.digits_start..num.end].iter().collect::<String>();
+    let prefix = line[num.start..num.digits_start].iter().collect::<String>();
+    let width = num.end - num.digits_start;
+    let text = match num.radix {
+        Radix::Decimal => {
+            let value = digits.parse::<i64>().ok()?;
+            let value = if prefix == "-" { -value } else { value };
+            value.saturating_add(delta).to_string()
+        }
+        Radix::Octal => {
+            let value = u64::from_str_radix(&digits, 8).ok()?;
+            format!("0{:0w$o}", value.wrapping_add(delta as u64), w = width - 1)
+        }
+        Radix::Hex => {
+            let value = u64::from_str_radix(&digits, 16).ok()?;
+            let value = value.wrapping_add(delta as u64);
+            // Follow the case of the last letter, or of the prefix if there are none
+            let upper = match digits.chars().rev().find(char::is_ascii_alphabetic) {
+                Some(c) => c.is_ascii_uppercase(),
+                None => prefix == "0X",
+            };
+            if upper {
+                format!("{}{:0w$X}", prefix, value, w = width)
+            } else {
+                format!("{}{:0w$x}", prefix, value, w = width)
+            }
+        }
+        Radix::Binary => {
+            let value = u64::from_str_radix(&digits, 2).ok()?;
+            format!(
+                "{}{:0w$b}",
+                prefix,
+                value.wrapping_add(delta as u64),
+                w = width
+            )
+        }
+    };
+    Some((num.start, num.end, text))
+}
+
+// The number starting at i, if there is one
+fn number_at(line: &[char], i: usize) -> Option<Number> {
+    let run = |start: usize, f: fn(&char) -> bool| {
+        start + line[start..].iter().take_while(|c| f(c)).count()
+    };
+    if !line[i].is_ascii_digit() {
+        return None;
+    }
+    if line[i] == '0' && i + 1 < line.len() {
+        let (radix, f): (_, fn(&char) -> bool) = match line.get(i + 1) {
+            Some('x') | Some('X') => (Radix::Hex, char::is_ascii_hexdigit),
+            Some('b') | Some('B') => (Radix::Binary, |c| *c == '0' || *c == '1'),
+            _ => (Radix::Decimal, char::is_ascii_digit),
+        };
+        let end = run(i + 2, f);
+        if radix != Radix::Decimal && end > i + 2 {
+            return Some(Number {
+                start: i,
+                digits_start: i + 2,
+                end,
+                radix,
+            });
+        }
+    }
+    let end = run(i, char::is_ascii_digit);
+    let is_octal = line[i] == '0' && end > i + 1 && line[i..end].iter().all(|c| *c < '8');
+    if is_octal {
+        return Some(Number {
+            start: i,
+            digits_start: i,
+            end,
+            radix: Radix::Octal,
+        });
+    }
+    let start = if i > 0 && line[i - 1] == '-' {
+        i - 1
+    } else {
+        i
+    };
+    Some(Number {
+        start,
+        digits_start: i,
+        end,
+        radix: Radix::Decimal,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(line: &str, cursor: usize, delta: i64) -> Option<(usize, usize, String)> {
+        add_to_number(&line.chars().collect::<Vec<_>>(), cursor, delta)
+    }
+
+    #[test]
+    fn test_add_to_number() {
+        assert_eq!(add("x = 41;", 0, 1), Some((4, 6, "42".to_owned())));
+        assert_eq!(add("x = 41;", 5, -42), Some((4, 6, "-1".to_owned())));
+        assert_eq!(add("a -5 b", 2, 10), Some((2, 4, "5".to_owned())));
+        assert_eq!(add("1 2 3", 1, 1), Some((2, 3, "3".to_owned())));
+        assert_eq!(add("0x0fF", 0, 1), Some((0, 5, "0x100".to_owned())));
+        assert_eq!(add("0x0fe", 3, 1), Some((0, 5, "0x0ff".to_owned())));
+        assert_eq!(
+            add("0X00", 0, -1),
+            Some((0, 4, "0XFFFFFFFFFFFFFFFF".to_owned()))
+        );
+        assert_eq!(add("0b0111", 0, 1), Some((0, 6, "0b1000".to_owned())));
+        assert_eq!(add("007", 0, 1), Some((0, 3, "010".to_owned())));
+        assert_eq!(add("089", 0, 1), Some((0, 3, "90".to_owned())));
+        assert_eq!(add("foo", 0, 1), None);
+        assert_eq!(add("12 foo", 3, 1), None);
+    }
+}
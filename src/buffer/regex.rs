@@ -0,0 +1,807 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+// Regular expressions with Vim's pattern syntax. Patterns are compiled to a program for a Pike
+// VM, which steps over the chars of the rope one at a time. Matches can span chunks and lines
+// without the text ever being copied out of the rope.
+
+use std::mem::swap;
+
+use ropey::Rope;
+
+// Refuse to expand counted repeats into huge programs
+const MAX_REPEAT: usize = 1000;
+const MAX_PROGRAM: usize = 100_000;
+
+// -------- Tokens ----------------
+
+// How many chars are special without a backslash, set by \v, \m, \M and \V
+#[derive(Clone, Copy, PartialEq)]
+enum MagicLevel {
+    VeryMagic,
+    Magic,
+    NoMagic,
+    VeryNoMagic,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+    // Classes only match line breaks when prefixed with \_
+    newline: bool,
+}
+
+impl Class {
+    fn any(newline: bool) -> Class {
+        Class {
+            ranges: Vec::new(),
+            negated: true,
+            newline,
+        }
+    }
+
+    // \s, \d, \w and friends. Upper-case letters are the negated classes
+    fn named(c: char, newline: bool) -> Option<Class> {
+        let ranges = match c.to_ascii_lowercase() {
+            's' => vec![(' ', ' '), ('\t', '\t')],
+            'd' => vec![('0', '9')],
+            'w' => vec![('0', '9'), ('A', 'Z'), ('a', 'z'), ('_', '_')],
+            'a' => vec![('A', 'Z'), ('a', 'z')],
+            'l' => vec![('a', 'z')],
+            'u' => vec![('A', 'Z')],
+            'x' => vec![('0', '9'), ('A', 'F'), ('a', 'f')],
+            'h' => vec![('A', 'Z'), ('a', 'z'), ('_', '_')],
+            _ => return None,
+        };
+        Some(Class {
+            ranges,
+            negated: c.is_ascii_uppercase(),
+            newline,
+        })
+    }
+
+    // Parse a [] collection, starting after the [. Returns None if there is no closing ]
+    fn parse(chars: &[char], mut i: usize, newline: bool) -> Option<(Class, usize)> {
+        let mut class = Class {
+            ranges: Vec::new(),
+            negated: false,
+            newline,
+        };
+        if chars.get(i) == Some(&'^') {
+            class.negated = true;
+            i += 1;
+        }
+        if chars.get(i) == Some(&']') {
+            class.ranges.push((']', ']'));
+            i += 1;
+        }
+        loop {
+            let lo = match *chars.get(i)? {
+                ']' => return Some((class, i + 1)),
+                '\\' if chars.get(i + 1) == Some(&'n') => {
+                    class.newline = true;
+                    i += 2;
+                    continue;
+                }
+                _ => class_char(chars, &mut i),
+            };
+            if chars.get(i) == Some(&'-') && matches!(chars.get(i + 1), Some(c) if *c != ']') {
+                i += 1;
+                let hi = class_char(chars, &mut i);
+                class.ranges.push(if hi < lo { (hi, lo) } else { (lo, hi) });
+            } else {
+                class.ranges.push((lo, lo));
+            }
+        }
+    }
+
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        if c == '\n' {
+            return self.newline;
+        }
+        let contains = |c: char| self.ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi);
+        let found =
+            contains(c) || (ignore_case && (contains(to_lower(c)) || contains(to_upper(c))));
+        found != self.negated
+    }
+}
+
+// A char within a collection, which may be escaped
+fn class_char(chars: &[char], i: &mut usize) -> char {
+    let c = chars[*i];
+    *i += 1;
+    if c != '\\' {
+        return c;
+    }
+    let escaped = match chars.get(*i) {
+        Some('t') => '\t',
+        Some('e') => '\x1b',
+        Some('r') => '\r',
+        Some(c) if "\\]^-".contains(*c) => *c,
+        _ => return '\\',
+    };
+    *i += 1;
+    escaped
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Lit(char),
+    Special(char),
+    Class(Class),
+}
+
+// Split the pattern into tokens, after working out which chars are special. Returns the tokens,
+// and Some(ignore_case) if the pattern contains \c or \C
+fn tokenize(pattern: &str) -> Result<(Vec<Token>, Option<bool>), String> {
+    let chars = pattern.chars().collect::<Vec<_>>();
+    let mut magic = MagicLevel::Magic;
+    let mut ignore_case = None;
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        if c != '\\' {
+            let special = match magic {
+                MagicLevel::VeryMagic => "^$.*+?={()|<>[~%".contains(c),
+                MagicLevel::Magic => "^$.*[~".contains(c),
+                _ => "^$".contains(c),
+            };
+            push_token(&mut tokens, &chars, &mut i, c, special);
+            continue;
+        }
+        let c = *chars.get(i).ok_or("trailing backslash")?;
+        i += 1;
+        match c {
+            'v' => magic = MagicLevel::VeryMagic,
+            'm' => magic = MagicLevel::Magic,
+            'M' => magic = MagicLevel::NoMagic,
+            'V' => magic = MagicLevel::VeryNoMagic,
+            'c' => ignore_case = Some(true),
+            'C' => ignore_case = ignore_case.or(Some(false)),
+            'n' => tokens.push(Token::Lit('\n')),
+            't' => tokens.push(Token::Lit('\t')),
+            'e' => tokens.push(Token::Lit('\x1b')),
+            'r' => tokens.push(Token::Lit('\r')),
+            '_' => {
+                let c = *chars.get(i).ok_or("trailing \\_")?;
+                i += 1;
+                let class = match c {
+                    '.' => Class::any(true),
+                    '[' => match Class::parse(&chars, i, true) {
+                        Some((class, next)) => {
+                            i = next;
+                            class
+                        }
+                        None => return Err("missing ] after \\_[".to_owned()),
+                    },
+                    c => Class::named(c, true).ok_or_else(|| format!("invalid use of \\_{}", c))?,
+                };
+                tokens.push(Token::Class(class));
+            }
+            c => {
+                if let Some(class) = Class::named(c, false) {
+                    tokens.push(Token::Class(class));
+                    continue;
+                }
+                let special = match magic {
+                    MagicLevel::VeryMagic => false,
+                    MagicLevel::Magic => "+?={()|<>%".contains(c),
+                    _ => "+?={()|<>%.*[~".contains(c),
+                };
+                push_token(&mut tokens, &chars, &mut i, c, special);
+            }
+        }
+    }
+    Ok((tokens, ignore_case))
+}
+
+// A special [ starts a collection, unless there is no closing ]
+fn push_token(tokens: &mut Vec<Token>, chars: &[char], i: &mut usize, c: char, special: bool) {
+    if !special {
+        tokens.push(Token::Lit(c));
+    } else if c != '[' {
+        tokens.push(Token::Special(c));
+    } else if let Some((class, next)) = Class::parse(chars, *i, false) {
+        tokens.push(Token::Class(class));
+        *i = next;
+    } else {
+        tokens.push(Token::Lit(c));
+    }
+}
+
+// -------- Parser ----------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Assertion {
+    LineStart,
+    LineEnd,
+    WordStart,
+    WordEnd,
+}
+
+impl Assertion {
+    fn holds(&self, prev: Option<char>, cur: Option<char>) -> bool {
+        match self {
+            Assertion::LineStart => matches!(prev, None | Some('\n')),
+            Assertion::LineEnd => matches!(cur, None | Some('\n')),
+            Assertion::WordStart => !is_word(prev) && is_word(cur),
+            Assertion::WordEnd => is_word(prev) && !is_word(cur),
+        }
+    }
+}
+
+enum Node {
+    Empty,
+    Char(char),
+    Class(Class),
+    Assert(Assertion),
+    // Capture groups have an index, starting from 1
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    // Minimum and maximum count, and whether the repeat is greedy
+    Repeat(Box<Node>, usize, Option<usize>, bool),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    num_groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn at_branch_end(&self) -> bool {
+        matches!(
+            self.peek(),
+            None | Some(Token::Special('|')) | Some(Token::Special(')'))
+        )
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek() == Some(&Token::Special('|')) {
+            self.pos += 1;
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alt(branches))
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        let start = self.pos;
+        while !self.at_branch_end() {
+            let token = self.tokens[self.pos].clone();
+            self.pos += 1;
+            let c = match token {
+                Token::Lit(c) => {
+                    nodes.push(Node::Char(c));
+                    continue;
+                }
+                Token::Class(class) => {
+                    nodes.push(Node::Class(class));
+                    continue;
+                }
+                Token::Special(c) => c,
+            };
+            // A multi at the start of a branch matches itself
+            if "*+?={".contains(c) && !nodes.is_empty() {
+                let (min, max, greedy) = match c {
+                    '*' => (0, None, true),
+                    '+' => (1, None, true),
+                    '?' | '=' => (0, Some(1), true),
+                    _ => self.parse_brace()?,
+                };
+                let node = nodes.pop().unwrap();
+                nodes.push(Node::Repeat(Box::new(node), min, max, greedy));
+                continue;
+            }
+            let node = match c {
+                '.' => Node::Class(Class::any(false)),
+                '^' if self.pos - 1 == start => Node::Assert(Assertion::LineStart),
+                '$' if self.at_branch_end() => Node::Assert(Assertion::LineEnd),
+                '<' => Node::Assert(Assertion::WordStart),
+                '>' => Node::Assert(Assertion::WordEnd),
+                '(' => self.parse_group(true)?,
+                // \%( is only special after the %
+                '%' if matches!(
+                    self.peek(),
+                    Some(Token::Lit('(')) | Some(Token::Special('('))
+                ) =>
+                {
+                    self.pos += 1;
+                    self.parse_group(false)?
+                }
+                c => Node::Char(c),
+            };
+            nodes.push(node);
+        }
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn parse_group(&mut self, capture: bool) -> Result<Node, String> {
+        let index = if capture {
+            self.num_groups += 1;
+            Some(self.num_groups)
+        } else {
+            None
+        };
+        let inner = self.parse_alt()?;
+        if self.peek() != Some(&Token::Special(')')) {
+            return Err("unmatched \\(".to_owned());
+        }
+        self.pos += 1;
+        Ok(Node::Group(Box::new(inner), index))
+    }
+
+    // The bounds of \{n,m}, starting after the {. A leading - makes the repeat non-greedy
+    fn parse_brace(&mut self) -> Result<(usize, Option<usize>, bool), String> {
+        let greedy = if self.peek() == Some(&Token::Lit('-')) {
+            self.pos += 1;
+            false
+        } else {
+            true
+        };
+        let min = self.parse_number()?;
+        let (min, max) = if self.peek() == Some(&Token::Lit(',')) {
+            self.pos += 1;
+            (min.unwrap_or(0), self.parse_number()?)
+        } else {
+            (min.unwrap_or(0), min)
+        };
+        if self.peek() != Some(&Token::Lit('}')) {
+            return Err("invalid \\{".to_owned());
+        }
+        self.pos += 1;
+        if min > MAX_REPEAT || max.unwrap_or(0) > MAX_REPEAT {
+            return Err("repeat count too large".to_owned());
+        }
+        Ok(match max {
+            Some(max) if max < min => (max, Some(min), greedy),
+            max => (min, max, greedy),
+        })
+    }
+
+    fn parse_number(&mut self) -> Result<Option<usize>, String> {
+        let mut ret = None;
+        while let Some(Token::Lit(c)) = self.peek() {
+            let digit = match c.to_digit(10) {
+                Some(digit) => digit as usize,
+                None => break,
+            };
+            let n = ret.unwrap_or(0usize).checked_mul(10);
+            let n = n.and_then(|n| n.checked_add(digit));
+            ret = Some(n.ok_or_else(|| "repeat count too large".to_owned())?);
+            self.pos += 1;
+        }
+        Ok(ret)
+    }
+}
+
+// -------- Compiler ----------------
+
+enum Inst {
+    Char(char),
+    Class(Class),
+    Assert(Assertion),
+    Save(usize),
+    // Threads try the first branch before the second
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+// At least the number of instructions compile() makes for a node. None if it doesn't fit in a
+// usize
+fn program_len(node: &Node) -> Option<usize> {
+    match node {
+        Node::Empty => Some(0),
+        Node::Char(_) | Node::Class(_) | Node::Assert(_) => Some(1),
+        Node::Group(inner, None) => program_len(inner),
+        Node::Group(inner, Some(_)) => program_len(inner)?.checked_add(2),
+        Node::Concat(nodes) => nodes
+            .iter()
+            .try_fold(0usize, |len, node| len.checked_add(program_len(node)?)),
+        Node::Alt(branches) => branches.iter().try_fold(0usize, |len, branch| {
+            len.checked_add(program_len(branch)?)?.checked_add(2)
+        }),
+        Node::Repeat(inner, min, max, _) => {
+            let inner = program_len(inner)?;
+            let optional = match max {
+                None => inner.checked_add(2)?,
+                Some(max) => (max - min).checked_mul(inner.checked_add(1)?)?,
+            };
+            min.checked_mul(inner)?.checked_add(optional)
+        }
+    }
+}
+
+fn compile(node: &Node, prog: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Char(c) => prog.push(Inst::Char(*c)),
+        Node::Class(class) => prog.push(Inst::Class(class.clone())),
+        Node::Assert(assertion) => prog.push(Inst::Assert(*assertion)),
+        Node::Group(inner, None) => compile(inner, prog),
+        Node::Group(inner, Some(index)) => {
+            prog.push(Inst::Save(index * 2));
+            compile(inner, prog);
+            prog.push(Inst::Save(index * 2 + 1));
+        }
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, prog);
+            }
+        }
+        Node::Alt(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    compile(branch, prog);
+                    break;
+                }
+                let split = prog.len();
+                prog.push(Inst::Split(split + 1, 0));
+                compile(branch, prog);
+                jumps.push(prog.len());
+                prog.push(Inst::Jmp(0));
+                let next = prog.len();
+                prog[split] = Inst::Split(split + 1, next);
+            }
+            let end = prog.len();
+            for jump in jumps {
+                prog[jump] = Inst::Jmp(end);
+            }
+        }
+        Node::Repeat(inner, min, max, greedy) => {
+            for _ in 0..*min {
+                compile(inner, prog);
+            }
+            let split = |from: usize, to: usize| {
+                if *greedy {
+                    Inst::Split(from + 1, to)
+                } else {
+                    Inst::Split(to, from + 1)
+                }
+            };
+            match max {
+                None => {
+                    let start = prog.len();
+                    prog.push(Inst::Jmp(0));
+                    compile(inner, prog);
+                    prog.push(Inst::Jmp(start));
+                    let end = prog.len();
+                    prog[start] = split(start, end);
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(prog.len());
+                        prog.push(Inst::Jmp(0));
+                        compile(inner, prog);
+                    }
+                    let end = prog.len();
+                    for start in splits {
+                        prog[start] = split(start, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// -------- Matching ----------------
+
+// Positions of the match and its groups. Slots 0 and 1 are the whole match
+pub(super) struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    pub(super) fn range(&self) -> (usize, usize) {
+        (self.slots[0].unwrap(), self.slots[1].unwrap())
+    }
+
+    pub(super) fn group(&self, i: usize) -> Option<(usize, usize)> {
+        match (self.slots.get(i * 2)?, self.slots.get(i * 2 + 1)?) {
+            (Some(start), Some(end)) => Some((*start, *end)),
+            _ => None,
+        }
+    }
+
+    // Expand a substitute replacement for this match. & and \0 are the whole match, \1 to \9 the
+    // groups, and \r or \n break the line
+    pub(super) fn expand(&self, data: &Rope, replacement: &str) -> String {
+        let mut ret = String::new();
+        let mut chars = replacement.chars();
+        while let Some(c) = chars.next() {
+            let group = match c {
+                '&' => 0,
+                '\\' => match chars.next() {
+                    Some(c) if c.is_ascii_digit() => c.to_digit(10).unwrap() as usize,
+                    Some('r') | Some('n') => {
+                        ret.push('\n');
+                        continue;
+                    }
+                    Some('t') => {
+                        ret.push('\t');
+                        continue;
+                    }
+                    Some(c) => {
+                        ret.push(c);
+                        continue;
+                    }
+                    None => {
+                        ret.push('\\');
+                        break;
+                    }
+                },
+                c => {
+                    ret.push(c);
+                    continue;
+                }
+            };
+            if let Some((start, end)) = self.group(group) {
+                ret.extend(data.slice(start..end).chars());
+            }
+        }
+        ret
+    }
+}
+
+// Threads waiting on the same char, in order of priority
+struct Threads {
+    threads: Vec<(usize, Vec<Option<usize>>)>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            threads: Vec::new(),
+            seen: vec![false; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.threads.clear();
+        for seen in &mut self.seen {
+            *seen = false;
+        }
+    }
+}
+
+pub(super) struct Regex {
+    pattern: String,
+    prog: Vec<Inst>,
+    num_slots: usize,
+    ignore_case: bool,
+}
+
+impl Regex {
+    // \c and \C in the pattern take precedence over ignore_case
+    pub(super) fn new(pattern: &str, ignore_case: bool) -> Result<Regex, String> {
+        let (tokens, case) = tokenize(pattern)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            num_groups: 0,
+        };
+        let node = parser.parse_alt()?;
+        if parser.pos < parser.tokens.len() {
+            return Err("unmatched \\)".to_owned());
+        }
+        // Nested repeats multiply the size of the program
+        if program_len(&node).map_or(true, |len| len > MAX_PROGRAM) {
+            return Err("pattern too large".to_owned());
+        }
+        let mut prog = vec![Inst::Save(0)];
+        compile(&node, &mut prog);
+        prog.push(Inst::Save(1));
+        prog.push(Inst::Match);
+        Ok(Regex {
+            pattern: pattern.to_owned(),
+            prog,
+            num_slots: (parser.num_groups + 1) * 2,
+            ignore_case: case.unwrap_or(ignore_case),
+        })
+    }
+
+    pub(super) fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // The leftmost match which starts within start_cidx..end_cidx. The match itself may run past
+    // end_cidx. Alternatives are preferred in order, and repeats are greedy, as with Vim
+    pub(super) fn find(&self, data: &Rope, start_cidx: usize, end_cidx: usize) -> Option<Captures> {
+        if start_cidx > data.len_chars() {
+            return None;
+        }
+        let mut clist = Threads::new(self.prog.len());
+        let mut nlist = Threads::new(self.prog.len());
+        let mut chars = data.chars_at(start_cidx);
+        let mut prev = if start_cidx > 0 {
+            Some(data.char(start_cidx - 1))
+        } else {
+            None
+        };
+        let mut cur = chars.next();
+        let mut pos = start_cidx;
+        let mut matched = None;
+        loop {
+            if matched.is_none() && pos < end_cidx {
+                let slots = vec![None; self.num_slots];
+                self.add_thread(&mut clist, 0, slots, pos, prev, cur);
+            }
+            if clist.threads.is_empty() && (matched.is_some() || pos >= end_cidx) {
+                break;
+            }
+            let next = chars.next();
+            for (pc, slots) in clist.threads.drain(..) {
+                let step = match &self.prog[pc] {
+                    Inst::Char(c) => matches!(cur, Some(cur) if self.char_eq(*c, cur)),
+                    Inst::Class(class) => {
+                        matches!(cur, Some(cur) if class.matches(cur, self.ignore_case))
+                    }
+                    Inst::Match => {
+                        // Lower priority threads are dropped
+                        matched = Some(slots);
+                        break;
+                    }
+                    _ => unreachable!(),
+                };
+                if step {
+                    self.add_thread(&mut nlist, pc + 1, slots, pos + 1, cur, next);
+                }
+            }
+            if cur.is_none() {
+                break;
+            }
+            swap(&mut clist, &mut nlist);
+            nlist.clear();
+            pos += 1;
+            prev = cur;
+            cur = next;
+        }
+        matched.map(|slots| Captures { slots })
+    }
+
+    // Follow jumps, splits and zero-width instructions from pc, and add threads for the
+    // instructions which consume a char
+    fn add_thread(
+        &self,
+        list: &mut Threads,
+        pc: usize,
+        mut slots: Vec<Option<usize>>,
+        pos: usize,
+        prev: Option<char>,
+        cur: Option<char>,
+    ) {
+        if list.seen[pc] {
+            return;
+        }
+        list.seen[pc] = true;
+        match &self.prog[pc] {
+            Inst::Jmp(to) => self.add_thread(list, *to, slots, pos, prev, cur),
+            Inst::Split(first, second) => {
+                self.add_thread(list, *first, slots.clone(), pos, prev, cur);
+                self.add_thread(list, *second, slots, pos, prev, cur);
+            }
+            Inst::Save(slot) => {
+                slots[*slot] = Some(pos);
+                self.add_thread(list, pc + 1, slots, pos, prev, cur);
+            }
+            Inst::Assert(assertion) => {
+                if assertion.holds(prev, cur) {
+                    self.add_thread(list, pc + 1, slots, pos, prev, cur);
+                }
+            }
+            _ => list.threads.push((pc, slots)),
+        }
+    }
+
+    fn char_eq(&self, a: char, b: char) -> bool {
+        a == b || (self.ignore_case && to_lower(a) == to_lower(b))
+    }
+}
+
+fn is_word(c: Option<char>) -> bool {
+    matches!(c, Some(c) if c == '_' || c.is_alphanumeric())
+}
+
+fn to_lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn to_upper(c: char) -> char {
+    c.to_uppercase().next().unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        let rope = Rope::from_str(text);
+        let regex = Regex::new(pattern, false).unwrap();
+        regex
+            .find(&rope, 0, rope.len_chars() + 1)
+            .map(|caps| caps.range())
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find("bar", "foo bar"), Some((4, 7)));
+        assert_eq!(find("b.r", "foo bar"), Some((4, 7)));
+        assert_eq!(find("a*", "baa"), Some((0, 0)));
+        assert_eq!(find("ba*", "baa"), Some((0, 3)));
+        assert_eq!(find("ba\\{-1,}", "baa"), Some((0, 2)));
+        assert_eq!(find("a\\{2}", "abaab"), Some((2, 4)));
+        assert_eq!(find("[0-9]\\+", "ab 123 c"), Some((3, 6)));
+        assert_eq!(find("[^a-z ]", "ab 1"), Some((3, 4)));
+        assert_eq!(find("\\d\\+", "ab 123 c"), Some((3, 6)));
+        assert_eq!(find("foo\\|bar", "a bar foo"), Some((2, 5)));
+        assert_eq!(find("\\(ab\\)\\+", "xababa"), Some((1, 5)));
+        assert_eq!(find("^b", "ab\nba"), Some((3, 4)));
+        assert_eq!(find("a$", "ab\nba"), Some((4, 5)));
+        assert_eq!(find("b\\nb", "ab\nba"), Some((1, 4)));
+        assert_eq!(find("b.a", "ab\nba"), None);
+        assert_eq!(find("b\\_.b", "ab\nba"), Some((1, 4)));
+        assert_eq!(find("a*b", "*ab"), Some((1, 3)));
+        assert_eq!(find("*b", "a*b"), Some((1, 3)));
+    }
+
+    #[test]
+    fn test_find_vim_syntax() {
+        assert_eq!(find("\\<foo\\>", "foobar foo"), Some((7, 10)));
+        assert_eq!(find("\\<bar", "foobar bar"), Some((7, 10)));
+        assert_eq!(find("\\v<(foo|bar)+>", "xfoo foobar"), Some((5, 11)));
+        assert_eq!(find("\\vx{2,3}", "xxxx"), Some((0, 3)));
+        assert_eq!(find("\\v\\(", "a(b"), Some((1, 2)));
+        assert_eq!(find("a+", "aa a+"), Some((3, 5)));
+        assert_eq!(find("\\Va.b", "axb a.b"), Some((4, 7)));
+        assert_eq!(find("\\cFOO", "a Foo"), Some((2, 5)));
+        assert_eq!(find("FOO", "a Foo"), None);
+        assert!(Regex::new("\\(a", false).is_err());
+        assert!(Regex::new("a\\)", false).is_err());
+        assert!(Regex::new("a\\{x}", false).is_err());
+        assert!(Regex::new("a\\{99999999999999999999}", false).is_err());
+        assert!(Regex::new("a\\{1,99999999999999999999}", false).is_err());
+        assert!(Regex::new("a\\{1001}", false).is_err());
+        assert!(Regex::new("\\(\\(a\\{1000}\\)\\{1000}\\)\\{1000}", false).is_err());
+        assert!(Regex::new("\\(a\\{100}\\)\\{100}", false).is_ok());
+    }
+
+    #[test]
+    fn test_find_range() {
+        let rope = Rope::from_str("abc abc");
+        let regex = Regex::new("abc", false).unwrap();
+        assert_eq!(regex.find(&rope, 1, 8).map(|c| c.range()), Some((4, 7)));
+        assert_eq!(regex.find(&rope, 1, 4).map(|c| c.range()), None);
+        assert_eq!(regex.find(&rope, 1, 5).map(|c| c.range()), Some((4, 7)));
+    }
+
+    #[test]
+    fn test_expand() {
+        let rope = Rope::from_str("key = value");
+        let regex = Regex::new("\\(\\w\\+\\) = \\(\\w\\+\\)", false).unwrap();
+        let caps = regex.find(&rope, 0, 12).unwrap();
+        assert_eq!(caps.group(1), Some((0, 3)));
+        assert_eq!(caps.group(2), Some((6, 11)));
+        assert_eq!(caps.expand(&rope, "\\2: \\1"), "value: key");
+        assert_eq!(caps.expand(&rope, "[&]\\r\\&"), "[key = value]\n&");
+    }
+}
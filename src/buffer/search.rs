@@ -0,0 +1,131 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use std::cmp::min;
+
+use ropey::Rope;
+
+use super::regex::Regex;
+
+// Matches which start within start_cidx..end_cidx. Matches don't overlap, and the search moves
+// on by one char after an empty match
+pub(super) fn find_all(
+    data: &Rope,
+    regex: &Regex,
+    start_cidx: usize,
+    end_cidx: usize,
+) -> Vec<(usize, usize)> {
+    let mut ret = Vec::new();
+    let mut cidx = start_cidx;
+    while let Some(captures) = regex.find(data, cidx, end_cidx) {
+        let (start, end) = captures.range();
+        ret.push((start, end));
+        cidx = if end > start { end } else { start + 1 };
+    }
+    ret
+}
+
+// The count'th match after (or before) cidx. Searches wrap around the end of the buffer
+pub(super) fn find_nth(
+    data: &Rope,
+    regex: &Regex,
+    cidx: usize,
+    forward: bool,
+    count: usize,
+) -> Option<(usize, usize)> {
+    if count == 0 {
+        return None;
+    }
+    let len_chars = data.len_chars();
+    let cidx = min(cidx, len_chars);
+    let mut ahead = Vec::new();
+    let behind;
+    if forward {
+        let mut from = cidx + 1;
+        while ahead.len() < count {
+            match regex.find(data, from, len_chars + 1) {
+                Some(captures) => {
+                    let (start, end) = captures.range();
+                    ahead.push((start, end));
+                    from = if end > start { end } else { start + 1 };
+                }
+                None => break,
+            }
+        }
+        if ahead.len() == count {
+            return ahead.pop();
+        }
+        behind = find_all(data, regex, 0, cidx + 1);
+    } else {
+        // Matches can only be found forwards, so go back a line at a time
+        for linum in (0..=data.char_to_line(cidx)).rev() {
+            let end_cidx = min(cidx, line_end(data, linum));
+            let matches = find_all(data, regex, data.line_to_char(linum), end_cidx);
+            ahead.extend(matches.into_iter().rev());
+            if ahead.len() >= count {
+                return Some(ahead[count - 1]);
+            }
+        }
+        let mut matches = find_all(data, regex, cidx, len_chars + 1);
+        matches.reverse();
+        behind = matches;
+    }
+    // Wrap around, as many times as needed
+    let total = ahead.len() + behind.len();
+    if total == 0 {
+        return None;
+    }
+    let i = (count - ahead.len() - 1) % total;
+    if i < behind.len() {
+        Some(behind[i])
+    } else {
+        Some(ahead[i - behind.len()])
+    }
+}
+
+// End of the range of match starts on a line, including its line break
+pub(super) fn line_end(data: &Rope, linum: usize) -> usize {
+    if linum + 1 < data.len_lines() {
+        data.line_to_char(linum + 1)
+    } else {
+        data.len_chars() + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regex(pattern: &str) -> Regex {
+        Regex::new(pattern, false).unwrap()
+    }
+
+    #[test]
+    fn test_find_all() {
+        let rope = Rope::from_str("abab\nxabab");
+        assert_eq!(
+            find_all(&rope, &regex("ab"), 0, 11),
+            vec![(0, 2), (2, 4), (6, 8), (8, 10)]
+        );
+        assert_eq!(find_all(&rope, &regex("b\nx"), 0, 11), vec![(3, 6)]);
+        assert_eq!(find_all(&rope, &regex("aba"), 1, 7), vec![(6, 9)]);
+        assert_eq!(find_all(&rope, &regex("^"), 0, 11), vec![(0, 0), (5, 5)]);
+        assert_eq!(find_all(&rope, &regex("b*"), 3, 5), vec![(3, 4), (4, 4)]);
+    }
+
+    #[test]
+    fn test_find_nth() {
+        let rope = Rope::from_str("foo bar foo\nbaz foo");
+        let foo = regex("foo");
+        assert_eq!(find_nth(&rope, &foo, 0, true, 1), Some((8, 11)));
+        assert_eq!(find_nth(&rope, &foo, 0, true, 2), Some((16, 19)));
+        assert_eq!(find_nth(&rope, &foo, 0, true, 3), Some((0, 3)));
+        assert_eq!(find_nth(&rope, &foo, 0, true, 4), Some((8, 11)));
+        assert_eq!(find_nth(&rope, &foo, 8, false, 1), Some((0, 3)));
+        assert_eq!(find_nth(&rope, &foo, 9, false, 1), Some((8, 11)));
+        assert_eq!(find_nth(&rope, &foo, 8, false, 2), Some((16, 19)));
+        assert_eq!(find_nth(&rope, &foo, 17, false, 1), Some((16, 19)));
+        assert_eq!(find_nth(&rope, &foo, 17, false, 2), Some((8, 11)));
+        assert_eq!(find_nth(&rope, &regex("bar"), 4, true, 1), Some((4, 7)));
+        assert_eq!(find_nth(&rope, &regex("qux"), 0, true, 1), None);
+    }
+}
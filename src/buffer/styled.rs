@@ -12,6 +12,8 @@ pub(super) struct StyledText {
     pub(super) styles: Vec<(usize, TextStyle)>,
     pub(super) colors: Vec<(usize, Color)>,
     pub(super) unders: Vec<(usize, Option<Color>)>,
+    // Drawn behind the text, for search matches
+    pub(super) backgrounds: Vec<(usize, Option<Color>)>,
 }
 
 impl StyledText {
@@ -27,6 +29,7 @@ impl StyledText {
             styles: vec![(len, style)],
             colors: vec![(len, color)],
             unders: vec![(len, under)],
+            backgrounds: vec![(len, None)],
         }
     }
 
@@ -45,6 +48,15 @@ impl StyledText {
     pub(super) fn set_under(&mut self, range: Range<usize>, under: Option<Color>) {
         set(&mut self.unders, range, under);
     }
+
+    pub(super) fn set_background(&mut self, range: Range<usize>, background: Option<Color>) {
+        set(&mut self.backgrounds, range, background);
+    }
+
+    pub(super) fn clear_backgrounds(&mut self) {
+        let len = self.styles.last().unwrap().0;
+        self.backgrounds = vec![(len, None)];
+    }
 }
 
 fn set<T>(vec: &mut Vec<(usize, T)>, range: Range<usize>, val: T)
@@ -98,6 +110,7 @@ mod tests {
         assert_eq!(styled.colors, vec![(5, Color::new(0, 0, 0, 0))]);
         assert_eq!(styled.unders, vec![(5, None)]);
         assert_eq!(styled.styles, vec![(5, TextStyle::default())]);
+        assert_eq!(styled.backgrounds, vec![(5, None)]);
     }
 
     #[test]
@@ -0,0 +1,60 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use crate::input::Surround;
+
+// Opening and closing text of a pair
+pub(super) fn surround_delimiters(surround: &Surround) -> (String, String) {
+    match surround {
+        Surround::Pair(open, close, true) => (format!("{} ", open), format!(" {}", close)),
+        Surround::Pair(open, close, false) => (open.to_string(), close.to_string()),
+        Surround::Tag(tag) => {
+            let tag = tag.trim().trim_end_matches('>');
+            let name = tag.split_whitespace().next().unwrap_or("");
+            (format!("<{}>", tag), format!("</{}>", name))
+        }
+    }
+}
+
+// Smallest edit turning old into new: chars to skip, chars to remove after that, and text to
+// insert in their place
+pub(super) fn minimal_edit(old: &str, new: &str) -> (usize, usize, String) {
+    let old = old.chars().collect::<Vec<_>>();
+    let new = new.chars().collect::<Vec<_>>();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let text = new[prefix..new.len() - suffix].iter().collect();
+    (prefix, old.len() - prefix - suffix, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_surround_delimiters() {
+        let delims = |s: &Surround| {
+            let (open, close) = surround_delimiters(s);
+            format!("{}x{}", open, close)
+        };
+        assert_eq!(delims(&Surround::Pair('(', ')', true)), "( x )");
+        assert_eq!(delims(&Surround::Pair('"', '"', false)), "\"x\"");
+        assert_eq!(
+            delims(&Surround::Tag("a href=\"#\">".to_owned())),
+            "<a href=\"#\">x</a>"
+        );
+    }
+
+    #[test]
+    fn test_minimal_edit() {
+        assert_eq!(minimal_edit("\"", "'"), (0, 1, "'".to_owned()));
+        assert_eq!(minimal_edit("( ", "("), (1, 1, "".to_owned()));
+        assert_eq!(minimal_edit("<div id=x>", "<p>"), (1, 8, "p".to_owned()));
+        assert_eq!(minimal_edit("</em>", "</b>"), (2, 2, "b".to_owned()));
+        assert_eq!(minimal_edit("aa", "aaa"), (2, 0, "a".to_owned()));
+    }
+}
@@ -0,0 +1,843 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use std::cmp::min;
+
+use ropey::Rope;
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+use crate::common::rope_trim_newlines;
+use crate::input::TextObject;
+
+use super::types::TextRange;
+
+// Text covered by a text object at the given char index. Inner objects leave out surrounding
+// whitespace and delimiters, "around" objects include them. Syntax objects need both the tree
+// and the language's textobjects query
+pub(super) fn text_object_range(
+    data: &Rope,
+    tree: Option<&Tree>,
+    query: Option<&Query>,
+    cidx: usize,
+    obj: TextObject,
+    inner: bool,
+    count: usize,
+) -> Option<TextRange> {
+    if data.len_chars() == 0 {
+        return None;
+    }
+    let cidx = min(cidx, data.len_chars() - 1);
+    match obj {
+        TextObject::Word => word_range(data, cidx, inner, count, false),
+        TextObject::WordExt => word_range(data, cidx, inner, count, true),
+        TextObject::Sentence => sentence_range(data, cidx, inner, count),
+        TextObject::Paragraph => Some(paragraph_range(data, cidx, inner, count)),
+        TextObject::Quote(quote) => quote_range(data, cidx, quote, inner),
+        TextObject::Bracket(open, close) => {
+            // Brackets in strings and comments are not part of the syntax tree
+            let (start, end) = match tree {
+                Some(tree) => ts_bracket_pair(data, tree, cidx, open, close, count)?,
+                None => scan_bracket_pair(data, cidx, open, close, count)?,
+            };
+            Some(bracket_range(data, start, end, inner))
+        }
+        TextObject::Tag => {
+            let (open, close) = tree
+                .and_then(|tree| ts_tag_pair(data, tree, cidx, count))
+                .or_else(|| scan_tag_pair(data, cidx, count))?;
+            if inner {
+                Some(TextRange::Chars(open.1, close.0))
+            } else {
+                Some(TextRange::Chars(open.0, close.1))
+            }
+        }
+        TextObject::Function => {
+            let (tree, query) = (tree?, query?);
+            let name = function_capture(query);
+            ts_object_range(data, tree, query, cidx, name, inner, count)
+        }
+        TextObject::Class => ts_object_range(data, tree?, query?, cidx, "class", inner, count),
+        TextObject::Parameter => {
+            ts_object_range(data, tree?, query?, cidx, "parameter", inner, count)
+        }
+        TextObject::Block => ts_object_range(data, tree?, query?, cidx, "block", inner, count),
+    }
+}
+
+// Char ranges of the opening and closing delimiters of the count'th pair of brackets, quotes or
+// tags around the given char index
+pub(super) fn delimiter_ranges(
+    data: &Rope,
+    tree: Option<&Tree>,
+    cidx: usize,
+    obj: TextObject,
+    count: usize,
+) -> Option<((usize, usize), (usize, usize))> {
+    if data.len_chars() == 0 {
+        return None;
+    }
+    let cidx = min(cidx, data.len_chars() - 1);
+    match obj {
+        TextObject::Bracket(open, close) => {
+            let (start, end) = match tree {
+                Some(tree) => ts_bracket_pair(data, tree, cidx, open, close, count)?,
+                None => scan_bracket_pair(data, cidx, open, close, count)?,
+            };
+            Some(((start, start + 1), (end, end + 1)))
+        }
+        TextObject::Quote(quote) => match quote_range(data, cidx, quote, true)? {
+            TextRange::Chars(start, end) => Some(((start - 1, start), (end, end + 1))),
+            _ => None,
+        },
+        TextObject::Tag => tree
+            .and_then(|tree| ts_tag_pair(data, tree, cidx, count))
+            .or_else(|| scan_tag_pair(data, cidx, count)),
+        _ => None,
+    }
+}
+
+// Start of the count'th function after (or before) the given char index
+pub(super) fn function_start(
+    data: &Rope,
+    tree: &Tree,
+    query: &Query,
+    cidx: usize,
+    forward: bool,
+    count: usize,
+) -> Option<usize> {
+    let mut starts = ts_objects(data, tree, query, function_capture(query), None)
+        .into_iter()
+        .map(|obj| obj.outer.0)
+        .collect::<Vec<_>>();
+    starts.sort();
+    starts.dedup();
+    if forward {
+        starts.into_iter().filter(|s| *s > cidx).nth(count - 1)
+    } else {
+        starts
+            .into_iter()
+            .rev()
+            .filter(|s| *s < cidx)
+            .nth(count - 1)
+    }
+}
+
+// -------- Words ----------------
+
+pub(super) fn char_class(c: char, extended: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if extended || c == '_' || c.is_alphanumeric() {
+        1
+    } else {
+        2
+    }
+}
+
+fn word_range(
+    data: &Rope,
+    cidx: usize,
+    inner: bool,
+    count: usize,
+    extended: bool,
+) -> Option<TextRange> {
+    let linum = data.char_to_line(cidx);
+    let line_start = data.line_to_char(linum);
+    let line_end = line_start + rope_trim_newlines(data.line(linum)).len_chars();
+    if line_start == line_end {
+        return None;
+    }
+    let cidx = min(cidx, line_end - 1);
+    let class_at = |i: usize| char_class(data.char(i), extended);
+    // End of the run of characters of the same class starting at i
+    let run_end = |mut i: usize| {
+        if i < line_end {
+            let class = class_at(i);
+            while i < line_end && class_at(i) == class {
+                i += 1;
+            }
+        }
+        i
+    };
+
+    let mut start = cidx;
+    while start > line_start && class_at(start - 1) == class_at(cidx) {
+        start -= 1;
+    }
+    let mut end = start;
+    if inner {
+        // Whitespace between words counts too, so "3iw" is a word, a space, and a word
+        for _ in 0..count {
+            end = run_end(end);
+        }
+    } else if class_at(cidx) == 0 {
+        // Whitespace, followed by a word
+        for _ in 0..count {
+            end = run_end(run_end(end));
+        }
+    } else {
+        // A word, followed by whitespace. If there is no whitespace after the last word, the
+        // whitespace before the first word is included instead
+        for _ in 0..count {
+            end = run_end(end);
+            if end < line_end && class_at(end) == 0 {
+                end = run_end(end);
+            }
+        }
+        if class_at(end - 1) != 0 {
+            while start > line_start && class_at(start - 1) == 0 {
+                start -= 1;
+            }
+        }
+    }
+    Some(TextRange::Chars(start, end))
+}
+
+// -------- Sentences and paragraphs ----------------
+
+pub(super) fn is_blank_line(data: &Rope, linum: usize) -> bool {
+    rope_trim_newlines(data.line(linum))
+        .chars()
+        .all(|c| c.is_whitespace())
+}
+
+// Lines of the run of blank or non-blank lines around linum
+pub(super) fn line_run(data: &Rope, linum: usize) -> (usize, usize) {
+    let blank = is_blank_line(data, linum);
+    let (mut first, mut last) = (linum, linum);
+    while first > 0 && is_blank_line(data, first - 1) == blank {
+        first -= 1;
+    }
+    while last + 1 < data.len_lines() && is_blank_line(data, last + 1) == blank {
+        last += 1;
+    }
+    (first, last)
+}
+
+fn paragraph_range(data: &Rope, cidx: usize, inner: bool, count: usize) -> TextRange {
+    let (mut first, mut last) = line_run(data, data.char_to_line(cidx));
+    // Like words, the blank lines between paragraphs count towards the count of "ip"
+    for _ in 1..count {
+        if last + 1 < data.len_lines() {
+            last = line_run(data, last + 1).1;
+        }
+    }
+    if !inner {
+        if last + 1 < data.len_lines() {
+            last = line_run(data, last + 1).1;
+        } else if first > 0 {
+            first = line_run(data, first - 1).0;
+        }
+    }
+    TextRange::Lines(first, last)
+}
+
+// Starts of the sentences in a paragraph. A sentence ends at '.', '!' or '?', followed by any
+// closing brackets or quotes, and then whitespace. The next one starts after the whitespace
+pub(super) fn sentence_starts(chars: &[char]) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '.' || chars[i] == '!' || chars[i] == '?' {
+            let mut j = i + 1;
+            while j < chars.len() && ")]\"'".contains(chars[j]) {
+                j += 1;
+            }
+            if j < chars.len() && chars[j].is_whitespace() {
+                while j < chars.len() && chars[j].is_whitespace() {
+                    j += 1;
+                }
+                if j < chars.len() {
+                    starts.push(j);
+                }
+                i = j;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    starts
+}
+
+fn sentence_range(data: &Rope, cidx: usize, inner: bool, count: usize) -> Option<TextRange> {
+    let linum = data.char_to_line(cidx);
+    if is_blank_line(data, linum) {
+        return None;
+    }
+    // Sentences don't cross paragraph boundaries
+    let (first, last) = line_run(data, linum);
+    let para_start = data.line_to_char(first);
+    let para_end = data.line_to_char(last) + rope_trim_newlines(data.line(last)).len_chars();
+    let chars = data.slice(para_start..para_end).chars().collect::<Vec<_>>();
+    let mut starts = sentence_starts(&chars);
+    starts.push(chars.len());
+    // End of sentence n, without the whitespace after it
+    let text_end = |n: usize| {
+        let mut end = starts[min(n + 1, starts.len() - 1)];
+        while end > starts[n] && chars[end - 1].is_whitespace() {
+            end -= 1;
+        }
+        end
+    };
+    let last_sentence = starts.len() - 2;
+
+    let col = min(cidx - para_start, chars.len() - 1);
+    let n = starts.iter().rposition(|start| *start <= col).unwrap();
+    let n_end = min(n + count - 1, last_sentence);
+    let (start, end) = if col >= text_end(n) {
+        // On the whitespace between two sentences
+        if inner {
+            (text_end(n), starts[n + 1])
+        } else {
+            (text_end(n), text_end(min(n + count, last_sentence)))
+        }
+    } else if inner {
+        (starts[n], text_end(n_end))
+    } else if n_end < last_sentence {
+        (starts[n], starts[n_end + 1])
+    } else if n > 0 {
+        // No whitespace after the last sentence, so take the whitespace before the first
+        (text_end(n - 1), text_end(n_end))
+    } else {
+        (starts[n], text_end(n_end))
+    };
+    Some(TextRange::Chars(para_start + start, para_start + end))
+}
+
+// -------- Quotes ----------------
+
+// Quotes are paired up from the start of the line, skipping escaped quotes
+fn quote_range(data: &Rope, cidx: usize, quote: char, inner: bool) -> Option<TextRange> {
+    let linum = data.char_to_line(cidx);
+    let line_start = data.line_to_char(linum);
+    let chars = rope_trim_newlines(data.line(linum))
+        .chars()
+        .collect::<Vec<_>>();
+    let mut positions = Vec::new();
+    let mut escaped = false;
+    for (i, c) in chars.iter().enumerate() {
+        if *c == quote && !escaped {
+            positions.push(i);
+        }
+        escaped = *c == '\\' && !escaped;
+    }
+    let col = cidx - line_start;
+    // The pair around the cursor, or the first one after it
+    let (open, close) = positions
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|(_, close)| col <= *close)?;
+    if inner {
+        return Some(TextRange::Chars(line_start + open + 1, line_start + close));
+    }
+    let (mut start, mut end) = (open, close + 1);
+    while end < chars.len() && chars[end].is_whitespace() {
+        end += 1;
+    }
+    if end == close + 1 {
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+    }
+    Some(TextRange::Chars(line_start + start, line_start + end))
+}
+
+// -------- Brackets ----------------
+
+// Inner bracket ranges spanning multiple lines leave out the line break after the opening
+// bracket, and the indentation before the closing bracket
+fn bracket_range(data: &Rope, open: usize, close: usize, inner: bool) -> TextRange {
+    if !inner {
+        return TextRange::Chars(open, close + 1);
+    }
+    let (mut start, mut end) = (open + 1, close);
+    let open_linum = data.char_to_line(open);
+    let close_linum = data.char_to_line(close);
+    if close_linum > open_linum {
+        let open_line_end =
+            data.line_to_char(open_linum) + rope_trim_newlines(data.line(open_linum)).len_chars();
+        if open + 1 == open_line_end {
+            start = data.line_to_char(open_linum + 1);
+        }
+        let close_line_start = data.line_to_char(close_linum);
+        if data
+            .slice(close_line_start..close)
+            .chars()
+            .all(|c| c.is_whitespace())
+        {
+            end = close_line_start;
+        }
+    }
+    TextRange::Chars(min(start, end), end)
+}
+
+// Char indices of the count'th pair of brackets around the cursor
+fn scan_bracket_pair(
+    data: &Rope,
+    cidx: usize,
+    open: char,
+    close: char,
+    count: usize,
+) -> Option<(usize, usize)> {
+    let mut start = None;
+    let (mut depth, mut remaining) = (0, count);
+    let mut chars = data.chars_at(cidx + 1);
+    let mut i = cidx + 1;
+    while let Some(c) = chars.prev() {
+        i -= 1;
+        // A closing bracket under the cursor belongs to the pair it closes
+        if c == close && i != cidx {
+            depth += 1;
+        } else if c == open {
+            if depth > 0 {
+                depth -= 1;
+                continue;
+            }
+            remaining -= 1;
+            if remaining == 0 {
+                start = Some(i);
+                break;
+            }
+        }
+    }
+    let start = start?;
+    let mut depth = 0;
+    for (i, c) in data.chars_at(start + 1).enumerate() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                return Some((start, start + 1 + i));
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+// Smallest node containing the given char index
+pub(super) fn node_at<'a>(data: &Rope, tree: &'a Tree, cidx: usize) -> Option<Node<'a>> {
+    let bidx = data.char_to_byte(cidx);
+    tree.root_node().descendant_for_byte_range(bidx, bidx)
+}
+
+fn ts_bracket_pair(
+    data: &Rope,
+    tree: &Tree,
+    cidx: usize,
+    open: char,
+    close: char,
+    count: usize,
+) -> Option<(usize, usize)> {
+    let bidx = data.char_to_byte(cidx);
+    let (mut open_buf, mut close_buf) = ([0; 4], [0; 4]);
+    let open = &*open.encode_utf8(&mut open_buf);
+    let close = &*close.encode_utf8(&mut close_buf);
+    let mut remaining = count;
+    let mut node = node_at(data, tree, cidx);
+    while let Some(n) = node {
+        // The bracket tokens are children of the node they delimit, but need not be the first
+        // and last children
+        let children = (0..n.child_count())
+            .filter_map(|i| n.child(i))
+            .collect::<Vec<_>>();
+        let open_node = children.iter().find(|c| c.kind() == open);
+        let close_node = children.iter().rev().find(|c| c.kind() == close);
+        if let (Some(o), Some(c)) = (open_node, close_node) {
+            if o.start_byte() <= bidx && bidx < c.end_byte() && o.end_byte() <= c.start_byte() {
+                remaining -= 1;
+                if remaining == 0 {
+                    return Some((
+                        data.byte_to_char(o.start_byte()),
+                        data.byte_to_char(c.start_byte()),
+                    ));
+                }
+            }
+        }
+        node = n.parent();
+    }
+    None
+}
+
+// -------- Syntax nodes ----------------
+
+// Char ranges of a node matched by the textobjects query
+struct SyntaxObject {
+    outer: (usize, usize),
+    inner: Option<(usize, usize)>,
+}
+
+// Languages without functions, like CSS and HTML, use blocks instead
+fn function_capture(query: &Query) -> &'static str {
+    if query
+        .capture_names()
+        .iter()
+        .any(|name| name == "function.outer")
+    {
+        "function"
+    } else {
+        "block"
+    }
+}
+
+// Nodes captured as "name" or "name.outer", with their "name.inner" captures. Blocks without an
+// inner capture use the text between their first and last children
+fn ts_objects(
+    data: &Rope,
+    tree: &Tree,
+    query: &Query,
+    name: &str,
+    byte_range: Option<(usize, usize)>,
+) -> Vec<SyntaxObject> {
+    let outer_name = format!("{}.outer", name);
+    let inner_name = format!("{}.inner", name);
+    let capture_names = query.capture_names();
+    let b2c = |b| data.byte_to_char(b);
+    let mut cursor = QueryCursor::new();
+    if let Some((start, end)) = byte_range {
+        cursor.set_byte_range(start, end);
+    }
+    let mut ret = Vec::new();
+    for query_match in cursor.matches(query, tree.root_node(), |node| {
+        let range = node.byte_range();
+        data.slice(b2c(range.start)..b2c(range.end)).to_string()
+    }) {
+        let (mut outer, mut inner) = (None, None);
+        for capture in query_match.captures {
+            let node = capture.node;
+            let capture_name = &capture_names[capture.index as usize];
+            if node.kind() == "comment" {
+                continue;
+            } else if capture_name == name || *capture_name == outer_name {
+                outer = Some(node);
+            } else if *capture_name == inner_name {
+                inner = Some((b2c(node.start_byte()), b2c(node.end_byte())));
+            }
+        }
+        let outer = match outer {
+            Some(outer) => outer,
+            None => continue,
+        };
+        if inner.is_none() && name == "block" && outer.child_count() >= 2 {
+            let first = outer.child(0).unwrap();
+            let last = outer.child(outer.child_count() - 1).unwrap();
+            inner = Some((b2c(first.end_byte()), b2c(last.start_byte())));
+        }
+        ret.push(SyntaxObject {
+            outer: (b2c(outer.start_byte()), b2c(outer.end_byte())),
+            inner,
+        });
+    }
+    ret
+}
+
+fn ts_object_range(
+    data: &Rope,
+    tree: &Tree,
+    query: &Query,
+    cidx: usize,
+    name: &str,
+    inner: bool,
+    count: usize,
+) -> Option<TextRange> {
+    let bidx = data.char_to_byte(cidx);
+    // Query cursors stop at the first node after the byte range, which could be an inner capture
+    let mut objects = ts_objects(data, tree, query, name, Some((bidx, data.len_bytes())));
+    objects.retain(|obj| obj.outer.0 <= cidx && cidx < obj.outer.1);
+    // Innermost first
+    objects.sort_by_key(|obj| obj.outer.1 - obj.outer.0);
+    objects.dedup_by_key(|obj| obj.outer);
+    let obj = objects.get(count - 1)?;
+    let (start, end) = obj.outer;
+    if !inner {
+        if name == "parameter" {
+            return Some(parameter_around(data, start, end));
+        }
+        return Some(TextRange::Chars(start, end));
+    }
+    let (start, end) = obj.inner.unwrap_or(obj.outer);
+    // Bodies delimited by brackets are treated like the bracket text object
+    if end > start + 1 {
+        let (open, close) = (data.char(start), data.char(end - 1));
+        if (open, close) == ('{', '}') || (open, close) == ('(', ')') || (open, close) == ('[', ']')
+        {
+            return Some(bracket_range(data, start, end - 1, true));
+        }
+    }
+    Some(TextRange::Chars(start, end))
+}
+
+// A parameter with the separator after it, or the one before it if it is the last parameter
+fn parameter_around(data: &Rope, start: usize, end: usize) -> TextRange {
+    let len_chars = data.len_chars();
+    let mut i = end;
+    while i < len_chars && (data.char(i) == ' ' || data.char(i) == '\t') {
+        i += 1;
+    }
+    if i < len_chars && data.char(i) == ',' {
+        i += 1;
+        while i < len_chars && data.char(i).is_whitespace() {
+            i += 1;
+        }
+        return TextRange::Chars(start, i);
+    }
+    let mut i = start;
+    while i > 0 && data.char(i - 1).is_whitespace() {
+        i -= 1;
+    }
+    if i > 0 && data.char(i - 1) == ',' {
+        return TextRange::Chars(i - 1, end);
+    }
+    TextRange::Chars(start, end)
+}
+
+// -------- Tags ----------------
+
+// Char ranges of the opening and closing tags of an element
+type TagPair = ((usize, usize), (usize, usize));
+
+fn ts_tag_pair(data: &Rope, tree: &Tree, cidx: usize, count: usize) -> Option<TagPair> {
+    let mut remaining = count;
+    let mut node = node_at(data, tree, cidx);
+    while let Some(n) = node {
+        if n.child_count() >= 2 {
+            let first = n.child(0).unwrap();
+            let last = n.child(n.child_count() - 1).unwrap();
+            let is_open =
+                first.kind().ends_with("start_tag") || first.kind().ends_with("opening_element");
+            let is_close =
+                last.kind().ends_with("end_tag") || last.kind().ends_with("closing_element");
+            if is_open && is_close {
+                remaining -= 1;
+                if remaining == 0 {
+                    let b2c = |b| data.byte_to_char(b);
+                    return Some((
+                        (b2c(first.start_byte()), b2c(first.end_byte())),
+                        (b2c(last.start_byte()), b2c(last.end_byte())),
+                    ));
+                }
+            }
+        }
+        node = n.parent();
+    }
+    None
+}
+
+struct Tag {
+    start: usize,
+    end: usize,
+    name: String,
+    closing: bool,
+}
+
+// Opening and closing tags in the buffer. Self-closing tags, comments and declarations are
+// skipped
+fn scan_tags(data: &Rope) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    let mut chars = data.chars().enumerate().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '<' {
+            continue;
+        }
+        let closing = chars.peek().map(|(_, c)| *c == '/').unwrap_or(false);
+        if closing {
+            chars.next();
+        }
+        let mut name = String::new();
+        while let Some((_, c)) = chars.peek() {
+            if !c.is_alphanumeric() && !"-_:.".contains(*c) {
+                break;
+            }
+            name.push(*c);
+            chars.next();
+        }
+        if name.is_empty() {
+            continue;
+        }
+        let (mut quote, mut prev) = (None, c);
+        for (i, c) in &mut chars {
+            match quote {
+                Some(q) if c == q => quote = None,
+                Some(_) => {}
+                None if c == '"' || c == '\'' => quote = Some(c),
+                None if c == '>' => {
+                    if prev != '/' {
+                        let end = i + 1;
+                        tags.push(Tag {
+                            start,
+                            end,
+                            name,
+                            closing,
+                        });
+                    }
+                    break;
+                }
+                None => {}
+            }
+            prev = c;
+        }
+    }
+    tags
+}
+
+fn scan_tag_pair(data: &Rope, cidx: usize, count: usize) -> Option<TagPair> {
+    let mut stack: Vec<Tag> = Vec::new();
+    let mut pairs = Vec::new();
+    for tag in scan_tags(data) {
+        if !tag.closing {
+            stack.push(tag);
+            continue;
+        }
+        // Unclosed tags inside this element, like <br> in HTML, are dropped
+        if let Some(pos) = stack.iter().rposition(|open| open.name == tag.name) {
+            let open = stack.drain(pos..).next().unwrap();
+            if open.start <= cidx && cidx < tag.end {
+                pairs.push(((open.start, open.end), (tag.start, tag.end)));
+            }
+        }
+    }
+    // Inner elements are closed first
+    pairs.into_iter().nth(count - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ts::TsCore;
+
+    fn range(text: &str, cidx: usize, obj: TextObject, inner: bool, count: usize) -> String {
+        let rope = Rope::from_str(text);
+        match text_object_range(&rope, None, None, cidx, obj, inner, count) {
+            Some(TextRange::Chars(start, end)) => rope.slice(start..end).to_string(),
+            Some(TextRange::Lines(first, last)) => format!("lines {}-{}", first, last),
+            _ => "none".to_owned(),
+        }
+    }
+
+    // Like range(), with the syntax tree and textobjects query of the language for an extension
+    fn ts_range(ext: &str, text: &str, cidx: usize, obj: TextObject, inner: bool) -> String {
+        let (_, mut parser, queries) = TsCore::new().parser_from_extension(ext).unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let rope = Rope::from_str(text);
+        let query = Some(&queries.textobjects);
+        match text_object_range(&rope, Some(&tree), query, cidx, obj, inner, 1) {
+            Some(TextRange::Chars(start, end)) => rope.slice(start..end).to_string(),
+            _ => "none".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_words() {
+        let text = "foo bar.baz  qux";
+        assert_eq!(range(text, 5, TextObject::Word, true, 1), "bar");
+        assert_eq!(range(text, 5, TextObject::Word, false, 1), " bar");
+        assert_eq!(range(text, 5, TextObject::WordExt, true, 1), "bar.baz");
+        assert_eq!(range(text, 5, TextObject::WordExt, false, 1), "bar.baz  ");
+        assert_eq!(range(text, 1, TextObject::Word, true, 3), "foo bar");
+        assert_eq!(range(text, 1, TextObject::Word, false, 2), "foo bar");
+        assert_eq!(range(text, 14, TextObject::Word, false, 1), "  qux");
+        assert_eq!(range(text, 11, TextObject::Word, false, 1), "  qux");
+    }
+
+    #[test]
+    fn test_sentences() {
+        let text = "One. Two (three)! Four\nfive.\n\nSix.";
+        assert_eq!(
+            range(text, 6, TextObject::Sentence, true, 1),
+            "Two (three)!"
+        );
+        assert_eq!(
+            range(text, 6, TextObject::Sentence, false, 1),
+            "Two (three)! "
+        );
+        assert_eq!(
+            range(text, 20, TextObject::Sentence, true, 1),
+            "Four\nfive."
+        );
+        assert_eq!(
+            range(text, 20, TextObject::Sentence, false, 1),
+            " Four\nfive."
+        );
+        assert_eq!(
+            range(text, 1, TextObject::Sentence, true, 2),
+            "One. Two (three)!"
+        );
+    }
+
+    #[test]
+    fn test_paragraphs() {
+        let text = "a\nb\n\n\nc\n";
+        assert_eq!(range(text, 0, TextObject::Paragraph, true, 1), "lines 0-1");
+        assert_eq!(range(text, 0, TextObject::Paragraph, false, 1), "lines 0-3");
+        assert_eq!(range(text, 4, TextObject::Paragraph, false, 1), "lines 2-4");
+        assert_eq!(range(text, 0, TextObject::Paragraph, true, 3), "lines 0-4");
+    }
+
+    #[test]
+    fn test_quotes() {
+        let text = r#"a "b\"c" 'd' "e""#;
+        let dq = TextObject::Quote('"');
+        assert_eq!(range(text, 4, dq, true, 1), r#"b\"c"#);
+        assert_eq!(range(text, 4, dq, false, 1), r#""b\"c" "#);
+        assert_eq!(range(text, 0, dq, true, 1), r#"b\"c"#);
+        assert_eq!(range(text, 14, dq, false, 1), r#" "e""#);
+        assert_eq!(range(text, 10, TextObject::Quote('\''), true, 1), "d");
+    }
+
+    #[test]
+    fn test_brackets() {
+        let paren = TextObject::Bracket('(', ')');
+        let text = "f(a, (b), c)";
+        assert_eq!(range(text, 3, paren, true, 1), "a, (b), c");
+        assert_eq!(range(text, 6, paren, true, 1), "b");
+        assert_eq!(range(text, 6, paren, false, 2), "(a, (b), c)");
+        assert_eq!(range(text, 7, paren, false, 1), "(b)");
+        assert_eq!(range(text, 0, paren, true, 1), "none");
+        let text = "fn f() {\n    x;\n}";
+        let brace = TextObject::Bracket('{', '}');
+        assert_eq!(range(text, 13, brace, true, 1), "    x;\n");
+    }
+
+    #[test]
+    fn test_parameter_around() {
+        let rope = Rope::from_str("f(a, b,\n  c)");
+        let text = |range| match range {
+            TextRange::Chars(start, end) => rope.slice(start..end).to_string(),
+            _ => unreachable!(),
+        };
+        assert_eq!(text(parameter_around(&rope, 2, 3)), "a, ");
+        assert_eq!(text(parameter_around(&rope, 5, 6)), "b,\n  ");
+        assert_eq!(text(parameter_around(&rope, 10, 11)), ",\n  c");
+        assert_eq!(text(parameter_around(&rope, 0, 1)), "f");
+    }
+
+    #[test]
+    fn test_tags() {
+        let text = "<div a=\">\"><p>x<br>y</p></div>";
+        assert_eq!(range(text, 15, TextObject::Tag, true, 1), "x<br>y");
+        assert_eq!(range(text, 15, TextObject::Tag, false, 1), "<p>x<br>y</p>");
+        assert_eq!(range(text, 15, TextObject::Tag, true, 2), "<p>x<br>y</p>");
+        assert_eq!(range(text, 2, TextObject::Tag, true, 1), "<p>x<br>y</p>");
+    }
+
+    #[test]
+    fn test_blocks() {
+        let block = TextObject::Block;
+        let text = "fn f() {\n    if x {\n        y;\n    }\n}\n";
+        assert_eq!(ts_range("rs", text, 30, block, true), "\n        y;\n    ");
+        assert_eq!(
+            ts_range("rs", text, 30, block, false),
+            "{\n        y;\n    }"
+        );
+        let text = "int f() {\n    return 0;\n}\n";
+        assert_eq!(ts_range("c", text, 16, block, false), "{\n    return 0;\n}");
+        let text = "function f() {\n    return 0;\n}\n";
+        assert_eq!(
+            ts_range("js", text, 21, block, false),
+            "{\n    return 0;\n}"
+        );
+        let text = "def f():\n    if x:\n        y\n    z\n";
+        assert_eq!(ts_range("py", text, 27, block, true), "y");
+        assert_eq!(ts_range("py", text, 27, block, false), "if x:\n        y");
+        assert_eq!(ts_range("py", text, 2, block, false), "none");
+    }
+}
@@ -25,6 +25,15 @@ pub(super) fn internal_cidx_to_lsp_position(data: &Rope, cidx: usize) -> LspPosi
     LspPosition { line, character }
 }
 
+// Text selected by a motion, object or visual selection. Line-wise ranges hold the first and last
+// line numbers. Block ranges hold the first and last line numbers, and a range of columns (gidx)
+#[derive(Clone, Copy, Debug)]
+pub(super) enum TextRange {
+    Chars(usize, usize),
+    Lines(usize, usize),
+    Block(usize, usize, usize, usize),
+}
+
 #[derive(Debug)]
 pub(super) struct Hover {
     pub(super) range: Option<Range>,
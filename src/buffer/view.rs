@@ -1,28 +1,30 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
 use std::cell::RefCell;
+use std::cmp::{max, min};
 use std::collections::VecDeque;
-use std::fmt::Write;
 use std::rc::Rc;
 
 use euclid::{point2, size2, Point2D, Rect, Size2D, Vector2D};
+use fnv::FnvHashMap;
 use ropey::Rope;
 
-use crate::common::{rope_trim_newlines, PixelSize, DPI};
+use crate::common::{rope_next_grapheme_boundary, rope_trim_newlines, PixelSize, DPI};
 use crate::completion_popup::{CompletionOption, CompletionPopup};
 use crate::config::Config;
 use crate::hover_popup::HoverPopup;
-use crate::input::ComplAction;
+use crate::input::{ComplAction, Fold, ScreenPos, VisualMode};
 use crate::language_client::HoverContents;
-use crate::painter::Painter;
+use crate::painter::{Painter, TextWrap};
 use crate::style::TextStyle;
 use crate::text::{RopeOrStr, ShapedText, TextAlignment, TextShaper};
 use crate::theme::Theme;
 use crate::{CURSOR_BLOCK_WIDTH, CURSOR_LINE_WIDTH};
 
-use super::cursor::{cidx_gidx_from_gidx, Cursor, CursorStyle};
+use super::cursor::{cidx_gidx_from_cidx, cidx_gidx_from_gidx, Cursor, CursorStyle};
+use super::fold::Folds;
 use super::styled::StyledText;
-use super::types::Diagnostics;
+use super::types::{Diagnostics, TextRange};
 
 const INDENT_GUIDE_WIDTH: i32 = 1;
 
@@ -34,23 +36,57 @@ pub(crate) struct BufferViewCreateParams {
     pub(crate) rect: Rect<u32, PixelSize>,
 }
 
+// Visual mode selection. The anchor is a char index, and the other end is the cursor
+#[derive(Clone, Copy)]
+pub(super) struct Selection {
+    pub(super) mode: VisualMode,
+    pub(super) anchor: usize,
+}
+
+// A cursor besides the main one, with its own selection anchor in visual mode
+pub(super) struct ExtraCursor {
+    pub(super) cursor: Cursor,
+    pub(super) anchor: Option<usize>,
+}
+
 pub(super) struct BufferView {
     pub(super) cursor: Cursor,
+    pub(super) selection: Option<Selection>,
+    pub(super) extra_cursors: Vec<ExtraCursor>,
+    // Characters overwritten in replace mode, with where they were. None for characters which
+    // were inserted rather than replacing anything
+    pub(super) replaced: Vec<(usize, Option<char>)>,
+    // Cursor position when an incremental search started, to go back to if it is cancelled
+    pub(super) search_origin: Option<usize>,
+    // Lines of the last selection, for '< and '> in command ranges
+    pub(super) last_selection: Option<(usize, usize)>,
+    // Starts of this view's entries in its pane's jumplist, by jump ID, and of the latest jump,
+    // for the ` mark
+    pub(super) jumps: FnvHashMap<usize, usize>,
+    pub(super) last_jump: Option<usize>,
+    // Position in the buffer's changelist while going through it with g; and g,
+    pub(super) changelist_idx: Option<usize>,
     pub(super) rect: Rect<u32, PixelSize>,
     pub(super) needs_redraw: bool,
     pub(super) is_active: bool,
     tab_width: usize,
+    // Indent of the rows which lines wider than the view are wrapped onto, in columns, if
+    // soft-wrapping
+    pub(super) soft_wrap: Option<usize>,
+    pub(super) folds: Folds,
     // Text shaping
     dpi: Size2D<u32, DPI>,
     text_shaper: Rc<RefCell<TextShaper>>,
-    // Shaped lines and gutter
-    shaped_lines: VecDeque<(ShapedText, usize)>,
-    shaped_gutter: VecDeque<ShapedText>,
+    // Shaped lines, starting at the view's start line. Lines hidden in closed folds are left out
+    shaped_lines: VecDeque<ViewLine>,
     ascender: i32,
     descender: i32,
+    // Height of a row of text
     height: u32,
+    space_width: i32,
     prev_depth: usize,
-    // View start line, and offsets
+    // View start line, and offsets. The y offset is from the top of the start line, which can
+    // be more than a row down when it is wrapped
     start_line: usize,
     yoff: u32,
     xoff: u32,
@@ -72,30 +108,52 @@ impl BufferView {
         data: &Rope,
         styled_lines: &[StyledText],
         tab_width: usize,
+        soft_wrap: Option<usize>,
     ) -> BufferView {
         let config = params.config;
-        let (ascender, descender) = {
+        let (ascender, descender, space_width) = {
             let shaper = &mut *params.text_shaper.borrow_mut();
             let raster = shaper
                 .get_raster(config.textview_face, TextStyle::default())
                 .unwrap();
             let metrics = raster.get_metrics(config.textview_font_size, params.dpi);
-            (metrics.ascender, metrics.descender)
+            let space = shaper.shape_line(
+                RopeOrStr::from(" "),
+                params.dpi,
+                tab_width,
+                &[(1, config.textview_face)],
+                &[(1, TextStyle::default())],
+                &[(1, config.textview_font_size)],
+                &[(1, theme.textview.foreground)],
+                &[(1, None)],
+                &[(1, TextAlignment::Left)],
+            );
+            (metrics.ascender, metrics.descender, space.width())
         };
         let mut view = BufferView {
             cursor: Cursor::default(),
+            selection: None,
+            extra_cursors: Vec::new(),
+            replaced: Vec::new(),
+            search_origin: None,
+            last_selection: None,
+            jumps: FnvHashMap::default(),
+            last_jump: None,
+            changelist_idx: None,
             rect: params.rect,
             needs_redraw: true,
             is_active: true,
             tab_width,
+            soft_wrap,
+            folds: Folds::default(),
             dpi: params.dpi,
             text_shaper: params.text_shaper,
             ascender,
             descender,
             height: (ascender - descender) as u32 + 2 * config.textview_line_padding,
+            space_width,
             prev_depth: 0,
             shaped_lines: VecDeque::new(),
-            shaped_gutter: VecDeque::new(),
             start_line: 0,
             yoff: 0,
             xoff: 0,
@@ -105,20 +163,20 @@ impl BufferView {
             config,
             theme,
         };
-        view.fill_or_truncate_view(data, styled_lines);
         view.update_gutter_width(data);
+        view.fill_or_truncate_view(data, styled_lines);
         view
     }
 
     pub(crate) fn deactivate(&mut self) {
         self.shaped_lines.clear();
-        self.shaped_gutter.clear();
         self.is_active = false;
     }
 
     pub(crate) fn activate(&mut self, data: &Rope, styled_lines: &[StyledText]) {
-        self.fill_or_truncate_view(data, styled_lines);
+        self.folds.sync(data);
         self.update_gutter_width(data);
+        self.fill_or_truncate_view(data, styled_lines);
         self.is_active = true;
         self.prev_depth = if self.start_line > 0 {
             styled_lines[self.start_line - 1].indent_depth
@@ -170,6 +228,71 @@ impl BufferView {
         }
     }
 
+    // Move positions kept by the view along with an edit
+    pub(super) fn shift_marks<F: Fn(usize) -> usize>(&mut self, f: F) {
+        for pos in self.jumps.values_mut() {
+            *pos = f(*pos);
+        }
+        if let Some(pos) = &mut self.last_jump {
+            *pos = f(*pos);
+        }
+        self.folds.shift(f);
+    }
+
+    // Move the cursors and selection anchors along with an edit starting at edit_start
+    pub(super) fn shift_cursors<F: Fn(usize) -> usize>(
+        &mut self,
+        edit_start: usize,
+        f: F,
+        data: &Rope,
+    ) {
+        let extra = self.extra_cursors.iter_mut().map(|extra| &mut extra.cursor);
+        for cursor in std::iter::once(&mut self.cursor).chain(extra) {
+            if cursor.char_idx >= edit_start {
+                cursor.char_idx = f(cursor.char_idx);
+                cursor.sync_and_update_char_idx_left(data, self.tab_width);
+            }
+        }
+        let extra = self
+            .extra_cursors
+            .iter_mut()
+            .filter_map(|extra| extra.anchor.as_mut());
+        for anchor in self
+            .selection
+            .iter_mut()
+            .map(|sel| &mut sel.anchor)
+            .chain(extra)
+        {
+            *anchor = f(*anchor);
+        }
+    }
+
+    // Swap the main cursor and its selection anchor with those of extra cursor i, so that it can
+    // be moved or edited at like the main one
+    pub(super) fn swap_cursor(&mut self, i: usize, mode: Option<VisualMode>) {
+        let extra = &mut self.extra_cursors[i];
+        std::mem::swap(&mut self.cursor, &mut extra.cursor);
+        let anchor = self.selection.map(|sel| sel.anchor);
+        self.selection = mode
+            .zip(extra.anchor)
+            .map(|(mode, anchor)| Selection { mode, anchor });
+        extra.anchor = anchor;
+    }
+
+    // Drop extra cursors which ended up where another cursor is
+    pub(super) fn merge_cursors(&mut self) {
+        let mut seen = vec![self.cursor.char_idx];
+        self.extra_cursors.retain(|extra| {
+            if seen.contains(&extra.cursor.char_idx) {
+                false
+            } else {
+                seen.push(extra.cursor.char_idx);
+                true
+            }
+        });
+        self.needs_redraw = true;
+    }
+
     pub(crate) fn stop_completion(&mut self) {
         self.completion = None;
         self.needs_redraw = true;
@@ -183,32 +306,17 @@ impl BufferView {
     ) {
         // Scroll y
         if vec.y < 0 {
-            let ysub = (-vec.y) as usize;
-            let mut ycur = self.start_line * self.height as usize + self.yoff as usize;
-            if ycur <= ysub {
-                self.start_line = 0;
-                self.yoff = 0;
-            } else {
-                ycur -= ysub;
-                self.start_line = ycur / self.height as usize;
-                self.yoff = (ycur % self.height as usize) as u32;
-            }
+            self.scroll_up_by((-vec.y) as u32, data, styled_lines);
         } else {
-            let ycur = self.start_line * self.height as usize + self.yoff as usize + vec.y as usize;
-            self.start_line = ycur / self.height as usize;
-            self.yoff = (ycur % self.height as usize) as u32;
-            if self.start_line >= data.len_lines() - 1 {
-                self.start_line = data.len_lines() - 1;
-                self.yoff = 0;
-            }
+            self.scroll_down_by(vec.y as u32, data, styled_lines);
         }
-        self.shaped_lines.clear();
-        self.shaped_gutter.clear();
-        self.fill_or_truncate_view(data, styled_lines);
         self.update_gutter_width(data);
+        self.fill_or_truncate_view(data, styled_lines);
 
-        // Scroll x
-        if vec.x < 0 {
+        // Scroll x. Soft-wrapped lines always fit
+        if self.soft_wrap.is_some() {
+            self.xoff = 0;
+        } else if vec.x < 0 {
             self.xoff += (-vec.x) as u32;
         } else {
             if self.xoff < vec.x as u32 {
@@ -226,6 +334,131 @@ impl BufferView {
         self.needs_redraw = true;
     }
 
+    // Width of a space and height of a row, which panes are resized in steps of
+    pub(super) fn cell_size(&self) -> Size2D<u32, PixelSize> {
+        size2(max(self.space_width, 1) as u32, self.height)
+    }
+
+    // Number of rows which fit in the view
+    pub(super) fn page_lines(&self) -> usize {
+        max(self.rect.size.height / self.height, 1) as usize
+    }
+
+    // Lines which are fully in view. Closed folds are there as their first line. This has at
+    // least the start line, even if that doesn't fit
+    fn shown_lines(&self) -> Vec<usize> {
+        let (mut top, mut ret) = (0, Vec::new());
+        for line in &self.shaped_lines {
+            let bottom = top + self.line_height(line);
+            if bottom > self.rect.size.height + self.yoff {
+                break;
+            }
+            if top >= self.yoff {
+                ret.push(line.linum);
+            }
+            top = bottom;
+        }
+        if ret.is_empty() {
+            ret.push(self.start_line);
+        }
+        ret
+    }
+
+    // First and last lines which are fully in view
+    pub(super) fn visible_lines(&self) -> (usize, usize) {
+        let lines = self.shown_lines();
+        (lines[0], lines[lines.len() - 1])
+    }
+
+    // Line at the top, middle or bottom of the view. The count is in lines from the top or bottom
+    pub(super) fn screen_line(&self, pos: ScreenPos, count: usize) -> usize {
+        let lines = self.shown_lines();
+        let (offset, last) = (count.saturating_sub(1), lines.len() - 1);
+        match pos {
+            ScreenPos::Top => lines[min(offset, last)],
+            ScreenPos::Middle => lines[last / 2],
+            ScreenPos::Bottom => lines[last.saturating_sub(offset)],
+        }
+    }
+
+    // Scroll to put the given line at the top. The cursor should already be where it will be in
+    // view
+    pub(super) fn scroll_to_line(
+        &mut self,
+        linum: usize,
+        data: &Rope,
+        styled_lines: &[StyledText],
+    ) {
+        self.start_line = self.folds.visible_line(min(linum, data.len_lines() - 1));
+        self.yoff = 0;
+        self.shaped_lines.clear();
+        self.fill_or_truncate_view(data, styled_lines);
+        self.snap_to_cursor(data, styled_lines);
+    }
+
+    // Scroll to put the cursor row at the top, middle or bottom, for zt, zz and zb
+    pub(super) fn scroll_cursor(
+        &mut self,
+        pos: ScreenPos,
+        data: &Rope,
+        styled_lines: &[StyledText],
+    ) {
+        let (linum, gidx) = (self.cursor.line_num, self.cursor.line_gidx);
+        let row = self.with_line(data, styled_lines, linum, |line| line.row_of(gidx));
+        self.start_line = linum;
+        self.yoff = row as u32 * self.height;
+        self.shaped_lines.clear();
+        let space = self.rect.size.height.saturating_sub(self.height);
+        let up = match pos {
+            ScreenPos::Top => 0,
+            ScreenPos::Middle => space / 2,
+            ScreenPos::Bottom => space,
+        };
+        self.scroll_up_by(up, data, styled_lines);
+        self.fill_or_truncate_view(data, styled_lines);
+        self.snap_to_cursor(data, styled_lines);
+    }
+
+    // Move the top of the view down by some pixels, no further than the last row
+    fn scroll_down_by(&mut self, mut dy: u32, data: &Rope, styled_lines: &[StyledText]) {
+        loop {
+            if self.shaped_lines.is_empty() {
+                let line = self.shape_line(data, styled_lines, self.start_line);
+                self.shaped_lines.push_back(line);
+            }
+            let height = self.line_height(&self.shaped_lines[0]);
+            if self.yoff + dy < height {
+                self.yoff += dy;
+                return;
+            }
+            let next = self.folds.next_line(self.start_line);
+            if next >= data.len_lines() {
+                self.yoff = height - self.height;
+                return;
+            }
+            dy -= height - self.yoff;
+            self.yoff = 0;
+            self.shaped_lines.pop_front();
+            self.start_line = next;
+        }
+    }
+
+    // Move the top of the view up by some pixels, no further than the first row
+    fn scroll_up_by(&mut self, mut dy: u32, data: &Rope, styled_lines: &[StyledText]) {
+        while dy > self.yoff {
+            if self.start_line == 0 {
+                self.yoff = 0;
+                return;
+            }
+            dy -= self.yoff;
+            self.start_line = self.folds.visible_line(self.start_line - 1);
+            let line = self.shape_line(data, styled_lines, self.start_line);
+            self.yoff = self.line_height(&line);
+            self.shaped_lines.push_front(line);
+        }
+        self.yoff -= dy;
+    }
+
     pub(super) fn update_hover(&mut self, hover_contents: HoverContents) {
         if let Some(hover) = self.hover.as_mut() {
             hover.update_contents(hover_contents);
@@ -247,37 +480,14 @@ impl BufferView {
             }
             point.y -= self.rect.origin.y;
             point.x -= self.rect.origin.x;
-            point.y += self.yoff;
-            point.x += self.xoff;
             if point.x <= self.gutter_width {
                 self.hover = None;
                 return None;
             }
             point.x -= self.gutter_width;
-            let linum = (point.y / self.height) as usize;
-            if linum >= self.shaped_lines.len() {
-                return None;
-            }
-            let line = &self.shaped_lines[linum].0;
-            let (mut gidx, mut x, mut found) = (0, 0, false);
-            'outer: for (clusters, _, _, _, _, _, _) in line.styled_iter() {
-                for clus in clusters {
-                    let width = clus.glyph_infos.iter().fold(0, |a, x| a + x.advance.width);
-                    if x + width < point.x as i32 {
-                        x += width;
-                        gidx += clus.num_graphemes;
-                        continue;
-                    }
-                    let rem_width = point.x as i32 - x;
-                    gidx += ((rem_width * clus.num_graphemes as i32) / width) as usize;
-                    found = true;
-                    break 'outer;
-                }
-            }
-            if !found {
-                return None;
-            }
-            let linum = self.start_line + linum;
+            let (i, row, x) = self.point_to_row(point)?;
+            let gidx = self.shaped_lines[i].point_column(row, x, self.wrap_indent())?;
+            let linum = self.shaped_lines[i].linum;
             let trimmed_line = rope_trim_newlines(data.line(linum));
             let past_end = self.cursor.past_end();
             let (cidx, _) = cidx_gidx_from_gidx(&trimmed_line, gidx, tab_width, past_end);
@@ -338,34 +548,19 @@ impl BufferView {
         assert!(self.rect.contains(point));
         point.y -= self.rect.origin.y;
         point.x -= self.rect.origin.x;
-        point.y += self.yoff;
-        point.x += self.xoff;
         if point.x <= self.gutter_width {
             point.x = 0;
         } else {
             point.x -= self.gutter_width;
         }
-        let linum = (point.y / self.height) as usize;
-        self.cursor.line_num = self.start_line + linum;
-        if self.cursor.line_num >= data.len_lines() {
-            self.cursor.line_num = data.len_lines() - 1;
-        }
-        let line = &self.shaped_lines[self.cursor.line_num - self.start_line].0;
-        let (mut gidx, mut x) = (0, 0);
-        'outer: for (clusters, _, _, _, _, _, _) in line.styled_iter() {
-            for clus in clusters {
-                let width = clus.glyph_infos.iter().fold(0, |a, x| a + x.advance.width);
-                if x + width < point.x as i32 {
-                    x += width;
-                    gidx += clus.num_graphemes;
-                    continue;
-                }
-                let rem_width = point.x as i32 - x;
-                gidx += ((rem_width * clus.num_graphemes as i32) / width) as usize;
-                break 'outer;
-            }
-        }
-        self.cursor.line_gidx = gidx;
+        // Below the last line is the last row
+        let (i, row, x) = self.point_to_row(point).unwrap_or_else(|| {
+            let i = self.shaped_lines.len() - 1;
+            let row = self.shaped_lines[i].rows() - 1;
+            (i, row, point.x as i32 + self.xoff as i32)
+        });
+        self.cursor.line_num = self.shaped_lines[i].linum;
+        self.cursor.line_gidx = self.shaped_lines[i].row_column(row, x, self.wrap_indent());
         self.cursor.sync_gidx(data, tab_width);
         self.snap_to_cursor(data, styled_lines);
 
@@ -377,12 +572,58 @@ impl BufferView {
         self.needs_redraw = true;
     }
 
+    // Line and column of the cursor moved by some rows. Rows are those of soft-wrapped lines,
+    // so this moves by lines otherwise, and closed folds are one row. The cursor keeps its
+    // horizontal offset in its row
+    pub(super) fn move_rows(
+        &self,
+        data: &Rope,
+        styled_lines: &[StyledText],
+        n: usize,
+        down: bool,
+    ) -> (usize, usize) {
+        let indent = self.wrap_indent();
+        let (mut linum, gidx) = (self.cursor.line_num, self.cursor.line_gidx);
+        let (mut row, x) = self.with_line(data, styled_lines, linum, |line| {
+            line.column_point(gidx, indent)
+        });
+        for _ in 0..n {
+            if down {
+                let rows = self.with_line(data, styled_lines, linum, |line| line.rows());
+                let next = self.folds.next_line(linum);
+                if row + 1 < rows {
+                    row += 1;
+                } else if next < data.len_lines() {
+                    linum = next;
+                    row = 0;
+                } else {
+                    break;
+                }
+            } else if row > 0 {
+                row -= 1;
+            } else if linum > 0 {
+                linum = self.folds.visible_line(linum - 1);
+                row = self.with_line(data, styled_lines, linum, |line| line.rows()) - 1;
+            } else {
+                break;
+            }
+        }
+        let gidx = self.with_line(data, styled_lines, linum, |line| {
+            line.row_column(row, x, indent)
+        });
+        (linum, gidx)
+    }
+
     pub(super) fn set_rect(
         &mut self,
         rect: Rect<u32, PixelSize>,
         data: &Rope,
         styled_lines: &[StyledText],
     ) {
+        // Lines are wrapped to fit the width
+        if self.soft_wrap.is_some() && rect.size.width != self.rect.size.width {
+            self.shaped_lines.clear();
+        }
         self.rect = rect;
         self.fill_or_truncate_view(data, styled_lines);
         self.snap_to_cursor(data, styled_lines);
@@ -395,10 +636,10 @@ impl BufferView {
     }
 
     pub(super) fn reshape(&mut self, data: &Rope, styled_lines: &[StyledText]) {
+        self.folds.sync(data);
         self.shaped_lines.clear();
-        self.shaped_gutter.clear();
-        self.fill_or_truncate_view(data, styled_lines);
         self.update_gutter_width(data);
+        self.fill_or_truncate_view(data, styled_lines);
         self.prev_depth = if self.start_line > 0 {
             styled_lines[self.start_line - 1].indent_depth
         } else {
@@ -407,47 +648,158 @@ impl BufferView {
         self.needs_redraw = true;
     }
 
+    // zo, zc, za, zR and zM, at the cursor line. The ranges are the buffer's fold regions.
+    // Returns false if there was no fold to open or close
+    pub(super) fn fold(
+        &mut self,
+        fold: Fold,
+        ranges: &[(usize, usize)],
+        data: &Rope,
+        styled_lines: &[StyledText],
+    ) -> bool {
+        let linum = self.cursor.line_num;
+        let ret = match fold {
+            Fold::Open => self.folds.open(linum, data),
+            Fold::Close => self.folds.close(linum, ranges, data),
+            Fold::Toggle if self.folds.closed_at(linum).is_some() => self.folds.open(linum, data),
+            Fold::Toggle => self.folds.close(linum, ranges, data),
+            Fold::OpenAll => {
+                self.folds.open_all();
+                true
+            }
+            Fold::CloseAll => {
+                self.folds.close_all(ranges, data);
+                true
+            }
+        };
+        self.update_folds(data, styled_lines);
+        ret
+    }
+
+    // zf. Make a closed fold over the given lines
+    pub(super) fn add_fold(
+        &mut self,
+        first: usize,
+        last: usize,
+        data: &Rope,
+        styled_lines: &[StyledText],
+    ) -> bool {
+        let ret = self.folds.add(first, last, data);
+        self.update_folds(data, styled_lines);
+        ret
+    }
+
+    // Lay out the view again after folds were opened or closed. A cursor in a fold which got
+    // closed goes to its first line
+    fn update_folds(&mut self, data: &Rope, styled_lines: &[StyledText]) {
+        let linum = self.folds.visible_line(self.cursor.line_num);
+        if linum != self.cursor.line_num {
+            self.cursor.line_num = linum;
+            self.cursor.sync_global_x(data, self.tab_width);
+        }
+        self.reshape(data, styled_lines);
+        self.snap_to_cursor(data, styled_lines);
+    }
+
     pub(super) fn snap_to_cursor(&mut self, data: &Rope, styled_lines: &[StyledText]) {
-        // Sync Y
-        if self.cursor.line_num <= self.start_line {
-            self.move_view_up_to_cursor(data, styled_lines);
-        } else {
-            self.move_view_down_to_cursor(data, styled_lines);
+        self.update_gutter_width(data);
+        // The cursor can't be on a hidden line. Motions which take it into a closed fold, other
+        // than to its first line, open the fold
+        let (linum, gidx) = (self.cursor.line_num, self.cursor.line_gidx);
+        if self.folds.reveal(linum, data) {
+            self.shaped_lines.clear();
+        }
+        // Sync Y. Jumps far away from the view start over at the cursor line, and put the
+        // cursor row at the top when moving up or the bottom when moving down
+        let page_lines = self.page_lines();
+        let shaped_end = match self.shaped_lines.back() {
+            Some(line) => self.folds.next_line(line.linum),
+            None => self.start_line,
+        };
+        let jump_down = linum >= shaped_end + page_lines;
+        let jump = linum < self.start_line || jump_down;
+        if linum + page_lines < self.start_line || jump_down {
+            self.shaped_lines.clear();
+            self.start_line = linum;
         }
+        if self.shaped_lines.is_empty() {
+            let line = self.shape_line(data, styled_lines, self.start_line);
+            self.shaped_lines.push_back(line);
+        }
+        while self.start_line > linum {
+            self.start_line = self.folds.visible_line(self.start_line - 1);
+            let line = self.shape_line(data, styled_lines, self.start_line);
+            self.shaped_lines.push_front(line);
+        }
+        while self.shaped_lines[self.shaped_lines.len() - 1].linum < linum {
+            let next = self
+                .folds
+                .next_line(self.shaped_lines[self.shaped_lines.len() - 1].linum);
+            let line = self.shape_line(data, styled_lines, next);
+            self.shaped_lines.push_back(line);
+        }
+        let idx = self.line_index(linum).unwrap();
+        let mut top = self
+            .shaped_lines
+            .iter()
+            .take(idx)
+            .map(|line| self.line_height(line))
+            .sum::<u32>();
+        top += self.shaped_lines[idx].row_of(gidx) as u32 * self.height;
+        let bottom = top + self.height;
+        if jump {
+            self.yoff = top;
+            if jump_down {
+                let up = self.rect.size.height.saturating_sub(self.height);
+                self.scroll_up_by(up, data, styled_lines);
+            }
+        } else if top < self.yoff {
+            self.scroll_up_by(self.yoff - top, data, styled_lines);
+        } else if bottom > self.yoff + self.rect.size.height {
+            let down = bottom - self.yoff - self.rect.size.height;
+            self.scroll_down_by(down, data, styled_lines);
+        }
+        self.fill_or_truncate_view(data, styled_lines);
         // Sync X
-        let line = &self.shaped_lines[self.cursor.line_num - self.start_line].0;
-        let mut gidx = 0;
-        let mut cursor_x = 0;
-        let mut cursor_width = 0;
-        let cgidx = self.cursor.line_gidx;
-        for (clusters, _, _, _, _, _, _) in line.styled_iter() {
-            for clus in clusters {
-                let width = clus.glyph_infos.iter().fold(0, |a, x| a + x.advance.width);
-                if gidx + clus.num_graphemes <= cgidx {
-                    gidx += clus.num_graphemes;
-                    cursor_x += width;
-                } else {
-                    cursor_x += width * ((cgidx - gidx) as i32) / clus.num_graphemes as i32;
-                    cursor_width = width * clus.num_graphemes as i32;
-                    break;
+        if self.soft_wrap.is_some() {
+            self.xoff = 0;
+        } else {
+            let idx = self.line_index(self.cursor.line_num).unwrap();
+            let line = &self.shaped_lines[idx].text;
+            let mut gidx = 0;
+            let mut cursor_x = 0;
+            let mut cursor_width = 0;
+            let cgidx = self.cursor.line_gidx;
+            for (clusters, _, _, _, _, _, _) in line.styled_iter() {
+                for clus in clusters {
+                    let width = clus.glyph_infos.iter().fold(0, |a, x| a + x.advance.width);
+                    if gidx + clus.num_graphemes <= cgidx {
+                        gidx += clus.num_graphemes;
+                        cursor_x += width;
+                    } else {
+                        cursor_x += width * ((cgidx - gidx) as i32) / clus.num_graphemes as i32;
+                        cursor_width = width * clus.num_graphemes as i32;
+                        break;
+                    }
                 }
             }
-        }
-        let cursor_x = if cursor_x < 0 { 0u32 } else { cursor_x as u32 };
-        let cursor_width = if cursor_width <= 0 {
-            match self.cursor.style {
-                CursorStyle::Line => CURSOR_LINE_WIDTH as u32,
-                _ => CURSOR_BLOCK_WIDTH as u32,
+            let cursor_x = if cursor_x < 0 { 0u32 } else { cursor_x as u32 };
+            let cursor_width = if cursor_width <= 0 {
+                match self.cursor.style {
+                    CursorStyle::Line => CURSOR_LINE_WIDTH as u32,
+                    _ => CURSOR_BLOCK_WIDTH as u32,
+                }
+            } else {
+                cursor_width as u32
+            };
+            if cursor_x < self.xoff {
+                self.xoff = cursor_x;
+            } else if cursor_x + cursor_width + self.gutter_width
+                >= self.xoff + self.rect.size.width
+            {
+                self.xoff = cursor_x + cursor_width + self.gutter_width - self.rect.size.width;
             }
-        } else {
-            cursor_width as u32
-        };
-        if cursor_x < self.xoff {
-            self.xoff = cursor_x;
-        } else if cursor_x + cursor_width + self.gutter_width >= self.xoff + self.rect.size.width {
-            self.xoff = cursor_x + cursor_width + self.gutter_width - self.rect.size.width;
         }
-        self.update_gutter_width(data);
         self.prev_depth = if self.start_line > 0 {
             styled_lines[self.start_line - 1].indent_depth
         } else {
@@ -456,7 +808,125 @@ impl BufferView {
         self.needs_redraw = true;
     }
 
-    pub(super) fn draw(&mut self, painter: &mut Painter, diagnostics: &Diagnostics) {
+    // Text covered by the selection. Char-wise selections include the character under the cursor
+    pub(super) fn selection_range(&self, data: &Rope) -> Option<TextRange> {
+        self.selection
+            .map(|sel| self.range_to_cursor(data, sel, &self.cursor))
+    }
+
+    // Text covered by the selections of the extra cursors
+    pub(super) fn extra_selection_ranges(&self, data: &Rope) -> Vec<TextRange> {
+        let mode = match self.selection {
+            Some(sel) => sel.mode,
+            None => return Vec::new(),
+        };
+        self.extra_cursors
+            .iter()
+            .filter_map(|extra| {
+                let anchor = extra.anchor?;
+                let sel = Selection { mode, anchor };
+                Some(self.range_to_cursor(data, sel, &extra.cursor))
+            })
+            .collect()
+    }
+
+    fn range_to_cursor(&self, data: &Rope, sel: Selection, cursor: &Cursor) -> TextRange {
+        let cursor_linum = cursor.line_num;
+        let anchor_linum = data.char_to_line(sel.anchor);
+        let (first, last) = if anchor_linum < cursor_linum {
+            (anchor_linum, cursor_linum)
+        } else {
+            (cursor_linum, anchor_linum)
+        };
+        let (cursor_cidx, cursor) = (cursor.char_idx, cursor_linum);
+        match sel.mode {
+            VisualMode::Char => {
+                let (start, end) = if sel.anchor < cursor_cidx {
+                    (sel.anchor, cursor_cidx)
+                } else {
+                    (cursor_cidx, sel.anchor)
+                };
+                let end = if end < data.len_chars() {
+                    rope_next_grapheme_boundary(&data.slice(..), end)
+                } else {
+                    end
+                };
+                TextRange::Chars(start, end)
+            }
+            VisualMode::Line => TextRange::Lines(first, last),
+            VisualMode::Block => {
+                let (astart, aend) = self.grapheme_columns(data, anchor_linum, sel.anchor);
+                let (cstart, cend) = self.grapheme_columns(data, cursor, cursor_cidx);
+                TextRange::Block(
+                    first,
+                    last,
+                    std::cmp::min(astart, cstart),
+                    std::cmp::max(aend, cend),
+                )
+            }
+        }
+    }
+
+    // Columns (gidx) covered by the grapheme at the given char index. An empty line still takes
+    // up one column
+    fn grapheme_columns(&self, data: &Rope, linum: usize, cidx: usize) -> (usize, usize) {
+        let trimmed = rope_trim_newlines(data.line(linum));
+        let line_cidx = cidx - data.line_to_char(linum);
+        let (_, start) = cidx_gidx_from_cidx(&trimmed, line_cidx, self.tab_width);
+        if line_cidx >= trimmed.len_chars() {
+            return (start, start + 1);
+        }
+        let next = rope_next_grapheme_boundary(&trimmed, line_cidx);
+        let (_, end) = cidx_gidx_from_cidx(&trimmed, next, self.tab_width);
+        (start, end)
+    }
+
+    // Columns of the given line which are covered by the selection range. A column past the end
+    // of the line stands for the newline
+    fn selected_columns(
+        &self,
+        data: &Rope,
+        range: TextRange,
+        linum: usize,
+    ) -> Option<(usize, usize)> {
+        let trimmed = rope_trim_newlines(data.line(linum));
+        let (_, width) = cidx_gidx_from_cidx(&trimmed, trimmed.len_chars(), self.tab_width);
+        match range {
+            TextRange::Chars(start, end) => {
+                let line_start = data.line_to_char(linum);
+                let line_end = line_start + data.line(linum).len_chars();
+                if start >= line_end || end <= line_start || start == end {
+                    return None;
+                }
+                let scidx = start.saturating_sub(line_start);
+                let (_, sgidx) = cidx_gidx_from_cidx(&trimmed, scidx, self.tab_width);
+                let egidx = if end > line_start + trimmed.len_chars() {
+                    width + 1
+                } else {
+                    cidx_gidx_from_cidx(&trimmed, end - line_start, self.tab_width).1
+                };
+                Some((sgidx, egidx))
+            }
+            TextRange::Lines(first, last) if linum >= first && linum <= last => {
+                Some((0, width + 1))
+            }
+            TextRange::Block(first, last, left, right)
+                if linum >= first && linum <= last && left < width =>
+            {
+                Some((left, std::cmp::min(right, width)))
+            }
+            _ => None,
+        }
+    }
+
+    pub(super) fn draw(
+        &mut self,
+        painter: &mut Painter,
+        data: &Rope,
+        styled_lines: &[StyledText],
+        diagnostics: &Diagnostics,
+        fold_ranges: &[(usize, usize)],
+    ) {
         self.needs_redraw = false;
         let line_pad = self.config.textview_line_padding as i32;
 
@@ -477,6 +947,11 @@ impl BufferView {
             let diag_size = (self.height / 3) as i32;
             let diag_pad = (self.height / 12) as i32;
             let diag_height = (self.height / 2) as i32;
+            // Fold markers go in the padding right of the line numbers
+            let fold_size = min(diag_size, self.config.gutter_padding as i32);
+            let fold_x = self.gutter_width as i32 - self.config.gutter_padding as i32
+                + (self.config.gutter_padding as i32 - fold_size) / 2;
+            let fold_y = (self.height as i32 - fold_size) / 2;
 
             let get_points = |basey: i32| {
                 [
@@ -493,46 +968,63 @@ impl BufferView {
             let mut painter =
                 painter.widget_ctx(gutter_rect.cast(), self.theme.gutter.background, false);
             let basex = self.config.gutter_padding as i32;
-            let mut pos = point2(basex, -(self.yoff as i32));
-
-            let mut linum = self.start_line;
-            for line in &self.shaped_gutter {
-                if let Some((diag_linum, diag_opts)) = &opt_diag_line {
-                    if linum == *diag_linum {
-                        if diag_opts.warning {
-                            if let Some(color) = self.theme.textview.lint_warnings {
-                                let points = get_points(pos.y);
-                                painter.color_triangle(&points, color);
-                            }
-                        }
-                        if diag_opts.error {
-                            if let Some(color) = self.theme.textview.lint_errors {
-                                let points = get_points(pos.y + diag_height);
-                                painter.color_triangle(&points, color);
-                            }
-                        }
-                    }
-                }
-                while let Some((diag_linum, _)) = &opt_diag_line {
-                    if linum < *diag_linum {
+            let mut y = -(self.yoff as i32);
+
+            // Wrapped lines have their number on the first row. Closed folds are marked with
+            // the diagnostics of all their lines
+            for line in &self.shaped_lines {
+                let (mut warning, mut error) = (false, false);
+                while let Some((diag_linum, diag_opts)) = &opt_diag_line {
+                    if *diag_linum > line.fold.unwrap_or(line.linum) {
                         break;
                     }
+                    if *diag_linum >= line.linum {
+                        warning |= diag_opts.warning;
+                        error |= diag_opts.error;
+                    }
                     opt_diag_line = diag_lines.next();
                 }
+                if warning {
+                    if let Some(color) = self.theme.textview.lint_warnings {
+                        let points = get_points(y);
+                        painter.color_triangle(&points, color);
+                    }
+                }
+                if error {
+                    if let Some(color) = self.theme.textview.lint_errors {
+                        let points = get_points(y + diag_height);
+                        painter.color_triangle(&points, color);
+                    }
+                }
+
+                // Closed folds point right, and the first lines of open ones point down
+                let (x, top) = (fold_x, y + fold_y);
+                if line.fold.is_some() {
+                    let points = [
+                        point2(x, top),
+                        point2(x, top + fold_size),
+                        point2(x + fold_size, top + fold_size / 2),
+                    ];
+                    painter.color_triangle(&points, self.theme.gutter.foreground);
+                } else if self.folds.starts_fold(line.linum, fold_ranges) {
+                    let points = [
+                        point2(x, top),
+                        point2(x + fold_size, top),
+                        point2(x + fold_size / 2, top + fold_size),
+                    ];
+                    painter.color_triangle(&points, self.theme.gutter.foreground);
+                }
 
-                pos.y += self.ascender + line_pad;
                 painter.draw_shaped_text(
                     shaper,
-                    pos,
-                    line,
+                    point2(basex, y + self.ascender + line_pad),
+                    &line.gutter,
                     None,
                     gutter_rect.size.width - self.config.gutter_padding,
                     self.height,
-                    false,
+                    TextWrap::None,
                 );
-                pos.y -= self.descender - line_pad;
-                pos.x = basex;
-                linum += 1;
+                y += self.line_height(line) as i32;
             }
         }
 
@@ -552,19 +1044,84 @@ impl BufferView {
                 Some((self.cursor.line_gidx, ccolor, self.cursor.style))
             };
 
-            let mut pos = point2(-(self.xoff as i32), -(self.yoff as i32));
-            let mut linum = self.start_line;
+            let mut pos: Point2D<i32, PixelSize> = point2(-(self.xoff as i32), -(self.yoff as i32));
+            let mut selections = self.extra_selection_ranges(data);
+            selections.extend(self.selection_range(data));
+            let height = self.height as i32;
+            let indent = self.wrap_indent();
 
             let mut prev_depth = self.prev_depth;
-            for (line, depth) in &self.shaped_lines {
+            for line in &self.shaped_lines {
+                let linum = line.linum;
                 let cursor = if linum == self.cursor.line_num {
                     cursor
                 } else {
                     None
                 };
-                if *depth > 1 {
+                let row_rect = |row: usize, x0: i32, x1: i32| {
+                    Rect::new(
+                        point2(pos.x + x0, pos.y + row as i32 * height),
+                        size2(x1 - x0, height),
+                    )
+                };
+                if line.fold.is_some() {
+                    painter.color_quad(
+                        Rect::new(point2(0, pos.y), size2(text_rect.size.width as i32, height)),
+                        self.theme.textview.fold,
+                        false,
+                    );
+                }
+                let mut start_cidx = 0;
+                for (end_cidx, background) in &styled_lines[linum].backgrounds {
+                    if let Some(color) = background {
+                        let trimmed = rope_trim_newlines(data.line(linum));
+                        let (_, start) = cidx_gidx_from_cidx(&trimmed, start_cidx, self.tab_width);
+                        let (_, end) = cidx_gidx_from_cidx(&trimmed, *end_cidx, self.tab_width);
+                        for (row, x0, x1) in line.row_spans(start, end, indent) {
+                            painter.color_quad(row_rect(row, x0, x1), *color, false);
+                        }
+                    }
+                    start_cidx = *end_cidx;
+                }
+                for (start, end) in selections
+                    .iter()
+                    .filter_map(|range| self.selected_columns(data, *range, linum))
+                {
+                    for (row, x0, x1) in line.row_spans(start, end, indent) {
+                        painter.color_quad(
+                            row_rect(row, x0, x1),
+                            self.theme.textview.selection,
+                            false,
+                        );
+                    }
+                }
+                // The painter only draws one cursor per line, so extra cursors are plain quads
+                if self.cursor.visible {
+                    for extra in &self.extra_cursors {
+                        if extra.cursor.line_num != linum {
+                            continue;
+                        }
+                        let gidx = extra.cursor.line_gidx;
+                        let (row, x0, x1) = line.row_spans(gidx, gidx + 1, indent)[0];
+                        let rect = row_rect(row, x0, x1);
+                        let rect = match self.cursor.style {
+                            CursorStyle::Block => rect,
+                            CursorStyle::Line => {
+                                Rect::new(rect.origin, size2(CURSOR_LINE_WIDTH, height))
+                            }
+                            CursorStyle::Underline => Rect::new(
+                                point2(rect.origin.x, rect.origin.y + height - CURSOR_LINE_WIDTH),
+                                size2(x1 - x0, CURSOR_LINE_WIDTH),
+                            ),
+                        };
+                        painter.color_quad(rect, ccolor, false);
+                    }
+                }
+                // Indent guides are drawn on the first row of wrapped lines
+                let depth = line.indent_depth;
+                if depth > 1 {
                     let (mut x, mut count, mut i) = (pos.x, 0, 0);
-                    'outer: for (clusters, _, _, _, _, _, _) in line.styled_iter() {
+                    'outer: for (clusters, _, _, _, _, _, _) in line.text.styled_iter() {
                         for clus in clusters {
                             for gi in clus.glyph_infos {
                                 x += gi.advance.width;
@@ -572,13 +1129,13 @@ impl BufferView {
                                 if count == self.tab_width {
                                     count = 0;
                                     i += 1;
-                                    if i >= *depth {
+                                    if i >= depth {
                                         break 'outer;
                                     }
                                     let (y, height) = if i < prev_depth {
-                                        (pos.y - line_pad, self.height as i32)
+                                        (pos.y - line_pad, height)
                                     } else {
-                                        (pos.y + line_pad, self.height as i32 - 2 * line_pad)
+                                        (pos.y + line_pad, height - 2 * line_pad)
                                     };
                                     painter.color_quad(
                                         Rect::new(point2(x, y), size2(INDENT_GUIDE_WIDTH, height)),
@@ -590,19 +1147,22 @@ impl BufferView {
                         }
                     }
                 }
-                pos.y += self.ascender + line_pad;
+                let wrap = if line.wraps.is_empty() {
+                    TextWrap::None
+                } else {
+                    TextWrap::At(&line.wraps, indent)
+                };
                 painter.draw_shaped_text(
                     shaper,
-                    pos,
-                    line,
+                    point2(pos.x, pos.y + self.ascender + line_pad),
+                    &line.text,
                     cursor,
                     text_rect.size.width,
                     self.height,
-                    false,
+                    wrap,
                 );
-                pos.y -= self.descender - line_pad;
-                linum += 1;
-                prev_depth = *depth;
+                pos.y += self.line_height(line) as i32;
+                prev_depth = depth;
             }
         }
 
@@ -617,84 +1177,31 @@ impl BufferView {
         }
     }
 
-    fn fill_or_truncate_view(&mut self, data: &Rope, styled_lines: &[StyledText]) {
-        let mut buf = String::new();
+    // Shape a line of the buffer, and its line number. Soft-wrapped lines get broken into rows
+    // as wide as the view, less the space for a cursor at the end. A closed fold is one row, with
+    // its first line and how many lines it has
+    fn shape_line(&self, data: &Rope, styled_lines: &[StyledText], linum: usize) -> ViewLine {
         let shaper = &mut *self.text_shaper.borrow_mut();
-        let mut height = self.shaped_lines.len() as u32 * self.height;
-        if height >= self.rect.size.height {
-            let mut num_lines = self.rect.size.height / self.height;
-            if self.rect.size.height % self.height > 0 {
-                num_lines += 1;
-            }
-            self.shaped_lines.truncate(num_lines as usize);
-            self.shaped_gutter.truncate(num_lines as usize);
-            return;
-        }
-        let mut linum = self.start_line + self.shaped_lines.len();
-        for (line, styled) in data.lines_at(linum).zip(&styled_lines[linum..]) {
-            // Shape textview
-            let trimmed = rope_trim_newlines(line);
-            let len_chars = trimmed.len_chars();
-            let shaped = shaper.shape_line(
-                trimmed.into(),
+        let styled = &styled_lines[linum];
+        let trimmed = rope_trim_newlines(data.line(linum));
+        let fold = self.folds.closed_at(linum).map(|(_, last)| last);
+        let text = if let Some(last) = fold {
+            let buf = format!("{}  \u{b7}\u{b7}\u{b7} {} lines", trimmed, last + 1 - linum);
+            let len_chars = buf.chars().count();
+            shaper.shape_line(
+                RopeOrStr::from(buf.as_ref()),
                 self.dpi,
                 self.tab_width,
                 &[(len_chars, self.config.textview_face)],
-                &styled.styles,
+                &extend_runs(&styled.styles, len_chars, TextStyle::default()),
                 &[(len_chars, self.config.textview_font_size)],
-                &styled.colors,
-                &styled.unders,
+                &extend_runs(&styled.colors, len_chars, self.theme.gutter.foreground),
+                &extend_runs(&styled.unders, len_chars, None),
                 &[(len_chars, TextAlignment::Left)],
-            );
-            height += self.height;
-            self.shaped_lines.push_back((shaped, styled.indent_depth));
-
-            // Shape gutter
-            linum += 1;
-            buf.clear();
-            write!(&mut buf, "{}", linum).unwrap();
-            let rs = RopeOrStr::from(buf.as_ref());
-            let lc = rs.len_chars();
-            let shaped = shaper.shape_line(
-                rs,
-                self.dpi,
-                self.tab_width,
-                &[(lc, self.config.gutter_face)],
-                &[(lc, TextStyle::default())],
-                &[(lc, self.config.gutter_font_size)],
-                &[(lc, self.theme.gutter.foreground)],
-                &[(lc, None)],
-                &[(lc, TextAlignment::Right)],
-            );
-            self.shaped_gutter.push_back(shaped);
-
-            if height >= self.rect.size.height + self.yoff {
-                break;
-            }
-        }
-    }
-
-    fn move_view_up_to_cursor(&mut self, data: &Rope, styled_lines: &[StyledText]) {
-        let mut buf = String::new();
-        self.yoff = 0;
-        let shaper = &mut *self.text_shaper.borrow_mut();
-        let mut cur_height = self.shaped_lines.len() as u32 * self.height;
-        if self.start_line == self.cursor.line_num {
-            if cur_height > self.rect.size.height + self.height {
-                self.shaped_lines.pop_back();
-                self.shaped_gutter.pop_back();
-            }
-            return;
-        }
-        let mut new_height = 0;
-        let (mut new_shaped_lines, mut new_shaped_gutter) = (Vec::new(), Vec::new());
-        let mut linum = self.cursor.line_num;
-
-        for (line, styled) in data.lines_at(linum).zip(&styled_lines[linum..]) {
-            // Shape textview
-            let trimmed = rope_trim_newlines(line);
+            )
+        } else {
             let len_chars = trimmed.len_chars();
-            let shaped = shaper.shape_line(
+            shaper.shape_line(
                 trimmed.into(),
                 self.dpi,
                 self.tab_width,
@@ -704,156 +1211,135 @@ impl BufferView {
                 &styled.colors,
                 &styled.unders,
                 &[(len_chars, TextAlignment::Left)],
-            );
-            new_height += self.height;
-            new_shaped_lines.push((shaped, styled.indent_depth));
-
-            // Shape gutter
-            linum += 1;
-            buf.clear();
-            write!(&mut buf, "{}", linum).unwrap();
-            let rs = RopeOrStr::from(buf.as_ref());
-            let lc = rs.len_chars();
-            let shaped = shaper.shape_line(
-                rs,
-                self.dpi,
-                self.tab_width,
-                &[(lc, self.config.gutter_face)],
-                &[(lc, TextStyle::default())],
-                &[(lc, self.config.gutter_font_size)],
-                &[(lc, self.theme.gutter.foreground)],
-                &[(lc, None)],
-                &[(lc, TextAlignment::Right)],
-            );
-            new_shaped_gutter.push(shaped);
-
-            if new_height >= self.rect.size.height
-                || self.cursor.line_num + new_shaped_lines.len() == self.start_line
-            {
-                break;
-            }
-        }
-        self.start_line = self.cursor.line_num;
-        if new_height >= self.rect.size.height {
-            self.shaped_lines.clear();
-            self.shaped_gutter.clear();
-            for line in new_shaped_lines {
-                self.shaped_lines.push_back(line);
-            }
-            for line in new_shaped_gutter {
-                self.shaped_gutter.push_back(line);
+            )
+        };
+        let buf = format!("{}", linum + 1);
+        let rs = RopeOrStr::from(buf.as_ref());
+        let lc = rs.len_chars();
+        let gutter = shaper.shape_line(
+            rs,
+            self.dpi,
+            self.tab_width,
+            &[(lc, self.config.gutter_face)],
+            &[(lc, TextStyle::default())],
+            &[(lc, self.config.gutter_font_size)],
+            &[(lc, self.theme.gutter.foreground)],
+            &[(lc, None)],
+            &[(lc, TextAlignment::Right)],
+        );
+        let wraps = match self.soft_wrap {
+            Some(_) if fold.is_none() => {
+                let width = self.text_width() - CURSOR_BLOCK_WIDTH;
+                text.wrap_points(width, self.wrap_indent())
             }
-            return;
-        }
-        while let Some(line) = new_shaped_lines.pop() {
-            self.shaped_lines.push_front(line);
+            _ => Vec::new(),
+        };
+        ViewLine {
+            linum,
+            fold,
+            text,
+            gutter,
+            indent_depth: styled.indent_depth,
+            wraps,
         }
-        while let Some(line) = new_shaped_gutter.pop() {
-            self.shaped_gutter.push_front(line);
+    }
+
+    // Call f with a line as shaped for this view, shaping it if it isn't in view
+    fn with_line<T, F: FnOnce(&ViewLine) -> T>(
+        &self,
+        data: &Rope,
+        styled_lines: &[StyledText],
+        linum: usize,
+        f: F,
+    ) -> T {
+        match self.line_index(linum) {
+            Some(idx) => f(&self.shaped_lines[idx]),
+            None => f(&self.shape_line(data, styled_lines, linum)),
         }
-        cur_height += new_height;
-        if cur_height > self.rect.size.height {
-            let num_lines = (self.rect.size.height + self.height - 1) / self.height;
-            self.shaped_lines.truncate(num_lines as usize);
-            self.shaped_gutter.truncate(num_lines as usize);
+    }
+
+    // Index of a line in the shaped lines, if it is in view
+    fn line_index(&self, linum: usize) -> Option<usize> {
+        if linum < self.start_line {
+            return None;
         }
+        self.shaped_lines
+            .iter()
+            .position(|line| line.linum == linum)
     }
 
-    fn move_view_down_to_cursor(&mut self, data: &Rope, styled_lines: &[StyledText]) {
-        let mut buf = String::new();
-        if self.cursor.line_num < self.start_line + self.shaped_lines.len() {
-            let height = (self.cursor.line_num as u32 - self.start_line as u32 + 1) * self.height;
-            if height > self.rect.size.height {
-                self.yoff = height - self.rect.size.height;
+    fn line_height(&self, line: &ViewLine) -> u32 {
+        line.rows() as u32 * self.height
+    }
+
+    fn text_width(&self) -> i32 {
+        self.rect.size.width as i32 - self.gutter_width as i32
+    }
+
+    // Indent of rows after the first in wrapped lines, in pixels. Rows keep at least half of
+    // the width
+    fn wrap_indent(&self) -> i32 {
+        let indent = self.soft_wrap.unwrap_or(0) as i32 * self.space_width;
+        max(min(indent, self.text_width() / 2), 0)
+    }
+
+    // Index in the shaped lines, row and horizontal offset in that row of a point in the text
+    // area
+    fn point_to_row(&self, point: Point2D<u32, PixelSize>) -> Option<(usize, usize, i32)> {
+        let mut y = point.y + self.yoff;
+        for (i, line) in self.shaped_lines.iter().enumerate() {
+            let height = self.line_height(line);
+            if y < height {
+                let row = (y / self.height) as usize;
+                return Some((i, row, point.x as i32 + self.xoff as i32));
             }
-            return;
+            y -= height;
         }
-        let shaped_end = self.start_line + self.shaped_lines.len();
-        let shaper = &mut *self.text_shaper.borrow_mut();
-        let mut height = 0;
-        let (mut new_shaped_lines, mut new_shaped_gutter) = (Vec::new(), Vec::new());
-        let mut lines = data.lines_at(self.cursor.line_num + 1);
-        let mut linum = self.cursor.line_num + 1;
-
-        while let Some(line) = lines.prev() {
-            // Shape textview
-            linum -= 1;
-            let styled = &styled_lines[linum];
-            let trimmed = rope_trim_newlines(line);
-            let len_chars = trimmed.len_chars();
-            let shaped = shaper.shape_line(
-                trimmed.into(),
-                self.dpi,
-                self.tab_width,
-                &[(len_chars, self.config.textview_face)],
-                &styled.styles,
-                &[(len_chars, self.config.textview_font_size)],
-                &styled.colors,
-                &styled.unders,
-                &[(len_chars, TextAlignment::Left)],
-            );
-            height += self.height;
-            new_shaped_lines.push((shaped, styled.indent_depth));
-
-            // Shape gutter
-            buf.clear();
-            write!(&mut buf, "{}", linum + 1).unwrap();
-            let rs = RopeOrStr::from(buf.as_ref());
-            let lc = rs.len_chars();
-            let shaped = shaper.shape_line(
-                rs,
-                self.dpi,
-                self.tab_width,
-                &[(lc, self.config.gutter_face)],
-                &[(lc, TextStyle::default())],
-                &[(lc, self.config.gutter_font_size)],
-                &[(lc, self.theme.gutter.foreground)],
-                &[(lc, None)],
-                &[(lc, TextAlignment::Right)],
-            );
-            new_shaped_gutter.push(shaped);
+        None
+    }
 
-            if height >= self.rect.size.height
-                || self.cursor.line_num < shaped_end + new_shaped_lines.len()
-            {
-                break;
-            }
+    fn fill_or_truncate_view(&mut self, data: &Rope, styled_lines: &[StyledText]) {
+        if self.start_line >= data.len_lines() {
+            self.start_line = data.len_lines() - 1;
+            self.yoff = 0;
+            self.shaped_lines.clear();
         }
-        if height >= self.rect.size.height {
+        // The start line can have been hidden in a fold which was closed since
+        let start_line = self.folds.visible_line(self.start_line);
+        if start_line != self.start_line {
+            self.start_line = start_line;
+            self.yoff = 0;
             self.shaped_lines.clear();
-            self.shaped_gutter.clear();
-            let mut i = self.cursor.line_num + 1;
-            while let Some(line) = new_shaped_lines.pop() {
-                self.shaped_lines.push_back(line);
-                i -= 1;
-            }
-            while let Some(line) = new_shaped_gutter.pop() {
-                self.shaped_gutter.push_back(line);
-            }
-            self.start_line = i;
-            self.yoff = height - self.rect.size.height;
-            return;
         }
-        while let Some(line) = new_shaped_lines.pop() {
+        if self.shaped_lines.is_empty() {
+            let line = self.shape_line(data, styled_lines, self.start_line);
             self.shaped_lines.push_back(line);
         }
-        while let Some(line) = new_shaped_gutter.pop() {
-            self.shaped_gutter.push_back(line);
-        }
-        height = self.shaped_lines.len() as u32 * self.height;
-        if height > self.rect.size.height {
-            while let Some(line) = self.shaped_lines.pop_front() {
-                if height < self.rect.size.height + self.height {
-                    self.shaped_lines.push_front(line);
-                    break;
-                }
-                self.shaped_gutter.pop_front();
-                self.start_line += 1;
-                height -= self.height;
+        // The start line might have been wrapped into fewer rows since
+        let max_yoff = self.line_height(&self.shaped_lines[0]) - self.height;
+        self.yoff = min(self.yoff, max_yoff);
+
+        let view_height = self.rect.size.height + self.yoff;
+        let (mut height, mut count) = (0, 0);
+        for line in &self.shaped_lines {
+            if height >= view_height {
+                break;
             }
+            height += self.line_height(line);
+            count += 1;
+        }
+        self.shaped_lines.truncate(count);
+        let mut linum = self.folds.next_line(self.shaped_lines[count - 1].linum);
+        while height < view_height && linum < data.len_lines() {
+            let line = self.shape_line(data, styled_lines, linum);
+            height += self.line_height(&line);
+            self.shaped_lines.push_back(line);
+            linum = self.folds.next_line(linum);
         }
     }
 
+    // Rows are as wide as the space the gutter leaves, so lines get wrapped again when it
+    // changes width
     fn update_gutter_width(&mut self, data: &Rope) {
         let shaper = &mut *self.text_shaper.borrow_mut();
         let buf = format!("{}", data.len_lines());
@@ -872,7 +1358,11 @@ impl BufferView {
         );
         let width = shaped.width();
         let width = if width < 0 { 0u32 } else { width as u32 };
-        self.gutter_width = self.config.gutter_padding * 2 + width;
+        let gutter_width = self.config.gutter_padding * 2 + width;
+        if gutter_width != self.gutter_width && self.soft_wrap.is_some() {
+            self.shaped_lines.clear();
+        }
+        self.gutter_width = gutter_width;
     }
 
     fn loc_to_relative_point(
@@ -880,30 +1370,21 @@ impl BufferView {
         line_num: usize,
         line_gidx: usize,
     ) -> Option<Point2D<u32, PixelSize>> {
-        if line_num < self.start_line || line_num >= self.start_line + self.shaped_lines.len() {
-            return None;
-        }
-        let y = (line_num - self.start_line) as u32 * self.height
+        let idx = self.line_index(line_num)?;
+        let line = &self.shaped_lines[idx];
+        let (row, x) = line.column_point(line_gidx, self.wrap_indent());
+        let y = self
+            .shaped_lines
+            .iter()
+            .take(idx)
+            .map(|line| self.line_height(line))
+            .sum::<u32>()
+            + row as u32 * self.height
             + self.config.textview_line_padding
             + self.ascender as u32;
         if y < self.yoff {
             return None;
         }
-        let (line, _) = &self.shaped_lines[line_num - self.start_line];
-        let (mut gidx, mut x) = (0, 0);
-        'outer: for (clusters, _, _, _, _, _, _) in line.styled_iter() {
-            for clus in clusters {
-                let width = clus.glyph_infos.iter().fold(0, |a, x| a + x.advance.width);
-                if gidx + clus.num_graphemes < line_gidx {
-                    x += width;
-                    gidx += clus.num_graphemes;
-                    continue;
-                }
-                let rem_graphemes = line_gidx - gidx;
-                x += ((rem_graphemes * width as usize) / clus.num_graphemes) as i32;
-                break 'outer;
-            }
-        }
         if x >= self.xoff as i32 {
             Some(point2(x as u32 - self.xoff, y - self.yoff))
         } else {
@@ -911,3 +1392,135 @@ impl BufferView {
         }
     }
 }
+
+// A shaped line and its line number for the gutter. Soft-wrapped lines are broken into rows,
+// each starting at one of the columns in wraps. A closed fold is drawn as its first line, and has
+// its last line in fold
+struct ViewLine {
+    linum: usize,
+    fold: Option<usize>,
+    text: ShapedText,
+    gutter: ShapedText,
+    indent_depth: usize,
+    wraps: Vec<usize>,
+}
+
+impl ViewLine {
+    fn rows(&self) -> usize {
+        self.wraps.len() + 1
+    }
+
+    fn row_of(&self, gidx: usize) -> usize {
+        self.wraps.iter().take_while(|w| **w <= gidx).count()
+    }
+
+    // First column of a row, and that of the next row, if there is one
+    fn row_range(&self, row: usize) -> (usize, Option<usize>) {
+        let start = if row == 0 { 0 } else { self.wraps[row - 1] };
+        (start, self.wraps.get(row).copied())
+    }
+
+    // How far left of where it would be unwrapped a row is drawn
+    fn row_shift(&self, row: usize, indent: i32) -> i32 {
+        if row == 0 {
+            0
+        } else {
+            gidx_to_x(&self.text, self.row_range(row).0) - indent
+        }
+    }
+
+    // Row a column is on, and its horizontal offset in the row
+    fn column_point(&self, gidx: usize, indent: i32) -> (usize, i32) {
+        let row = self.row_of(gidx);
+        (
+            row,
+            gidx_to_x(&self.text, gidx) - self.row_shift(row, indent),
+        )
+    }
+
+    // Column at a horizontal offset in a row, or None if that is past the end of the row
+    fn point_column(&self, row: usize, x: i32, indent: i32) -> Option<usize> {
+        let (start, end) = self.row_range(row);
+        let x = max(
+            x + self.row_shift(row, indent),
+            gidx_to_x(&self.text, start),
+        );
+        let gidx = max(x_to_gidx(&self.text, x)?, start);
+        match end {
+            Some(end) if gidx >= end => None,
+            _ => Some(gidx),
+        }
+    }
+
+    // Like point_column, but past the end of a row is its last column
+    fn row_column(&self, row: usize, x: i32, indent: i32) -> usize {
+        self.point_column(row, x, indent)
+            .unwrap_or_else(|| match self.row_range(row).1 {
+                Some(end) => end - 1,
+                None => usize::MAX,
+            })
+    }
+
+    // Rows with the columns start..end in them, and the horizontal offsets in each row where
+    // those columns start and end
+    fn row_spans(&self, start: usize, end: usize, indent: i32) -> Vec<(usize, i32, i32)> {
+        let first = self.row_of(start);
+        let mut ret = Vec::new();
+        for row in first..self.rows() {
+            let (row_start, row_end) = self.row_range(row);
+            if row > first && row_start >= end {
+                break;
+            }
+            let (start, end) = (max(start, row_start), row_end.map_or(end, |e| min(e, end)));
+            let shift = self.row_shift(row, indent);
+            ret.push((
+                row,
+                gidx_to_x(&self.text, start) - shift,
+                gidx_to_x(&self.text, end) - shift,
+            ));
+        }
+        ret
+    }
+}
+
+// Style runs of a line, with another run up to len
+fn extend_runs<T: Copy>(runs: &[(usize, T)], len: usize, value: T) -> Vec<(usize, T)> {
+    let mut ret = runs.to_vec();
+    ret.push((len, value));
+    ret
+}
+
+// Column at a horizontal offset in a shaped line, or None if that is past the end of the line
+fn x_to_gidx(line: &ShapedText, x: i32) -> Option<usize> {
+    let (mut gidx, mut clus_x) = (0, 0);
+    for (clusters, _, _, _, _, _, _) in line.styled_iter() {
+        for clus in clusters {
+            let width = clus.glyph_infos.iter().fold(0, |a, x| a + x.advance.width);
+            if clus_x + width < x {
+                clus_x += width;
+                gidx += clus.num_graphemes;
+                continue;
+            }
+            let rem_width = x - clus_x;
+            return Some(gidx + ((rem_width * clus.num_graphemes as i32) / width) as usize);
+        }
+    }
+    None
+}
+
+// Horizontal offset of the given column in a shaped line. Columns past the end of the line are
+// as wide as a block cursor
+fn gidx_to_x(line: &ShapedText, gidx: usize) -> i32 {
+    let (mut g, mut x) = (0, 0);
+    for (clusters, _, _, _, _, _, _) in line.styled_iter() {
+        for clus in clusters {
+            let width = clus.glyph_infos.iter().fold(0, |a, x| a + x.advance.width);
+            if g + clus.num_graphemes > gidx {
+                return x + (width * (gidx - g) as i32) / clus.num_graphemes as i32;
+            }
+            g += clus.num_graphemes;
+            x += width;
+        }
+    }
+    x + (gidx - g) as i32 * CURSOR_BLOCK_WIDTH
+}
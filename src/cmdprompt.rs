@@ -10,8 +10,8 @@ use crate::buffer::CursorStyle;
 use crate::common::{PixelSize, DPI};
 use crate::config::Config;
 use crate::input::{Action, Motion, MotionOrObj};
-use crate::painter::Painter;
-use crate::style::TextStyle;
+use crate::painter::{Painter, TextWrap};
+use crate::style::{Color, TextStyle};
 use crate::text::{RopeOrStr, ShapedText, TextAlignment, TextShaper};
 use crate::theme::Theme;
 
@@ -33,6 +33,9 @@ pub(crate) struct CmdPrompt {
     prompt_len: usize,
     cursor_bidx: usize,
     cursor_gidx: usize,
+    // Errors and command output, with their color. Shown while the prompt is empty, until the
+    // next prompt starts
+    message: Option<(String, Color)>,
 }
 
 impl CmdPrompt {
@@ -85,6 +88,7 @@ impl CmdPrompt {
             prompt_len: 0,
             cursor_bidx: 0,
             cursor_gidx: 0,
+            message: None,
         }
     }
 
@@ -111,7 +115,7 @@ impl CmdPrompt {
             cursor,
             self.rect.size.width - self.config.prompt_padding_horizontal,
             (self.ascender - self.descender) as u32,
-            false,
+            TextWrap::None,
         );
     }
 
@@ -129,6 +133,7 @@ impl CmdPrompt {
     }
 
     pub(crate) fn set_prompt(&mut self, s: &str) {
+        self.message = None;
         self.command.clear();
         self.command.push_str(s);
         self.prompt_len = s.len();
@@ -146,6 +151,20 @@ impl CmdPrompt {
         self.reshape();
     }
 
+    pub(crate) fn set_error(&mut self, message: &str) {
+        self.message = Some((message.to_owned(), self.theme.prompt.error));
+        self.reshape();
+    }
+
+    pub(crate) fn set_message(&mut self, message: &str) {
+        self.message = Some((message.to_owned(), self.theme.prompt.foreground));
+        self.reshape();
+    }
+
+    pub(crate) fn prompt(&self) -> &str {
+        &self.command[..self.prompt_len]
+    }
+
     pub(crate) fn get_command(&mut self) -> String {
         self.command[self.prompt_len..].to_owned()
     }
@@ -276,16 +295,20 @@ impl CmdPrompt {
     }
 
     fn reshape(&mut self) {
-        let lc = self.command.chars().count();
+        let (text, color) = match &self.message {
+            Some((message, color)) if self.command.is_empty() => (message, *color),
+            _ => (&self.command, self.theme.prompt.foreground),
+        };
+        let lc = text.chars().count();
         let shaper = &mut *self.text_shaper.borrow_mut();
         self.shaped = shaper.shape_line(
-            RopeOrStr::from(self.command.as_ref()),
+            RopeOrStr::from(text.as_ref()),
             self.dpi,
             TAB_WIDTH,
             &[(lc, self.config.prompt_face)],
             &[(lc, TextStyle::default())],
             &[(lc, self.config.prompt_font_size)],
-            &[(lc, self.theme.prompt.foreground)],
+            &[(lc, color)],
             &[(lc, None)],
             &[(lc, TextAlignment::Left)],
         );
@@ -1,74 +1,854 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
+use std::cell::RefCell;
+use std::env;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::buffer::Buffer;
+use crate::input::{Motion, MotionOrObj};
+
 use super::Bed;
 
+// First and last lines of a command range, counting from 0
+type LineRange = (usize, usize);
+
+// -------- Command table ----------------
+
+// Flags for commands in the table
+const RANGE: u8 = 1;
+const BANG: u8 = 1 << 1;
+const COUNT: u8 = 1 << 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ArgKind {
+    Nothing,
+    // At most one argument, which may be quoted
+    Single,
+    // The rest of the command, up to an unescaped |
+    Raw,
+}
+
+// Shortest abbreviation, full name, flags and arguments of each command. An abbreviation which
+// could mean more than one command goes to the first one listed
+const COMMANDS: &[(&str, &str, u8, ArgKind)] = &[
+    ("b", "buffer", 0, ArgKind::Single),
+    ("bd", "bdelete", BANG, ArgKind::Single),
+    ("bn", "bnext", COUNT, ArgKind::Nothing),
+    ("bp", "bprevious", COUNT, ArgKind::Nothing),
+    ("bw", "bwipeout", BANG, ArgKind::Single),
+    ("cd", "cd", 0, ArgKind::Single),
+    ("clo", "close", BANG, ArgKind::Nothing),
+    ("e", "edit", BANG, ArgKind::Single),
+    ("ls", "ls", 0, ArgKind::Nothing),
+    ("on", "only", BANG, ArgKind::Nothing),
+    ("q", "quit", BANG, ArgKind::Nothing),
+    ("qa", "qall", BANG, ArgKind::Nothing),
+    ("s", "substitute", RANGE, ArgKind::Raw),
+    ("sp", "split", 0, ArgKind::Single),
+    ("tabc", "tabclose", BANG, ArgKind::Single),
+    ("tabm", "tabmove", 0, ArgKind::Single),
+    ("tabnew", "tabnew", 0, ArgKind::Single),
+    ("vs", "vsplit", 0, ArgKind::Single),
+    ("w", "write", BANG, ArgKind::Single),
+    ("wa", "wall", BANG, ArgKind::Nothing),
+    ("wq", "wq", BANG, ArgKind::Single),
+    ("x", "xit", BANG, ArgKind::Single),
+];
+
+fn lookup_command(name: &str) -> Option<(&'static str, u8, ArgKind)> {
+    COMMANDS
+        .iter()
+        .find(|(abbrev, full, _, _)| name.starts_with(abbrev) && full.starts_with(name))
+        .map(|(_, full, flags, args)| (*full, *flags, *args))
+}
+
+// -------- Parser ----------------
+
+// A line address, before it is resolved against the active buffer
+#[derive(Debug, PartialEq)]
+enum Address {
+    // Counting from 1
+    Line(usize),
+    Current,
+    Last,
+    Mark(char),
+    // Next line with a match for the pattern, or previous line if false
+    Search(String, bool),
+}
+
+// An address with a line offset
+type LineAddress = (Address, isize);
+
+#[derive(Debug, PartialEq)]
+enum Range {
+    Whole,
+    Lines(LineAddress, Option<LineAddress>),
+}
+
+#[derive(Debug, PartialEq)]
+struct Command {
+    range: Option<Range>,
+    // Full name from the table. Empty for a range on its own
+    name: &'static str,
+    bang: bool,
+    count: Option<usize>,
+    args: Vec<String>,
+}
+
+// Parse a command line, which may chain commands with |
+fn parse_command_line(mut line: &str) -> Result<Vec<Command>, String> {
+    let mut ret = Vec::new();
+    loop {
+        let (cmd, rest) = parse_command(line)?;
+        ret.extend(cmd);
+        match rest.strip_prefix('|') {
+            Some(rest) => line = rest,
+            None => return Ok(ret),
+        }
+    }
+}
+
+// Parse one command, and return it with the rest of the line
+fn parse_command(s: &str) -> Result<(Option<Command>, &str), String> {
+    let s = s.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let (range, s) = parse_range(s)?;
+    let s = s.trim_start();
+    let name_len = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    let (name, s) = s.split_at(name_len);
+    if name.is_empty() {
+        let (rest, _) = split_raw(s);
+        if !rest.trim().is_empty() {
+            return Err(format!("not an editor command: {}", rest.trim()));
+        }
+        let cmd = range.map(|range| Command {
+            range: Some(range),
+            name: "",
+            bang: false,
+            count: None,
+            args: Vec::new(),
+        });
+        return Ok((cmd, s));
+    }
+    let (name, flags, kind) =
+        lookup_command(name).ok_or_else(|| format!("not an editor command: {}", name))?;
+    let (bang, s) = match s.strip_prefix('!') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    if bang && flags & BANG == 0 {
+        return Err(format!("no ! allowed: {}", name));
+    }
+    if range.is_some() && flags & RANGE == 0 {
+        return Err(format!("no range allowed: {}", name));
+    }
+    let (mut args, rest) = match kind {
+        ArgKind::Raw => {
+            let (raw, rest) = split_raw(s);
+            let raw = raw.trim_start();
+            if raw.is_empty() {
+                (Vec::new(), rest)
+            } else {
+                (vec![raw.to_owned()], rest)
+            }
+        }
+        _ => split_args(s)?,
+    };
+    let count = match args.first().map(|arg| arg.parse::<usize>()) {
+        Some(Ok(count)) if flags & COUNT != 0 => {
+            args.remove(0);
+            Some(count)
+        }
+        _ => None,
+    };
+    match kind {
+        ArgKind::Nothing if !args.is_empty() => {
+            return Err(format!("trailing characters: {}", args.join(" ")))
+        }
+        ArgKind::Single if args.len() > 1 => return Err(format!("too many arguments: {}", name)),
+        _ => {}
+    }
+    let cmd = Command {
+        range,
+        name,
+        bang,
+        count,
+        args,
+    };
+    Ok((Some(cmd), rest))
+}
+
+// One or two addresses, or % for the whole buffer. A missing address is the cursor line
+fn parse_range(s: &str) -> Result<(Option<Range>, &str), String> {
+    if let Some(rest) = s.strip_prefix('%') {
+        return Ok((Some(Range::Whole), rest));
+    }
+    let (first, rest) = match parse_address(s)? {
+        Some(ret) => ret,
+        None if s.starts_with(&[',', ';'][..]) => ((Address::Current, 0), s),
+        None => return Ok((None, s)),
+    };
+    let (last, rest) = match rest.strip_prefix(&[',', ';'][..]) {
+        Some(rest) => match parse_address(rest)? {
+            Some((last, rest)) => (Some(last), rest),
+            None => (Some((Address::Current, 0)), rest),
+        },
+        None => (None, rest),
+    };
+    Ok((Some(Range::Lines(first, last)), rest))
+}
+
+// A line number, ".", "$", a mark, or a pattern between slashes (or question marks, to search
+// backwards), followed by any number of +n or -n offsets. Offsets on their own are relative to
+// the cursor line
+fn parse_address(s: &str) -> Result<Option<(LineAddress, &str)>, String> {
+    let (address, mut rest) = if let Some(rest) = s.strip_prefix('.') {
+        (Address::Current, rest)
+    } else if let Some(rest) = s.strip_prefix('$') {
+        (Address::Last, rest)
+    } else if let Some(rest) = s.strip_prefix('\'') {
+        let mark = rest.chars().next().ok_or("missing mark")?;
+        (Address::Mark(mark), &rest[mark.len_utf8()..])
+    } else if s.starts_with(&['/', '?'][..]) {
+        let delim = s.chars().next().unwrap();
+        let (pattern, rest) = split_pattern(&s[1..], delim);
+        (Address::Search(pattern.to_owned(), delim == '/'), rest)
+    } else if let Some((n, rest)) = split_number(s) {
+        (Address::Line(n), rest)
+    } else if s.starts_with(&['+', '-'][..]) {
+        (Address::Current, s)
+    } else {
+        return Ok(None);
+    };
+    let mut offset = 0;
+    loop {
+        let (sign, after) = if let Some(after) = rest.strip_prefix('+') {
+            (1, after)
+        } else if let Some(after) = rest.strip_prefix('-') {
+            (-1, after)
+        } else {
+            break;
+        };
+        let (n, after) = split_number(after).unwrap_or((1, after));
+        offset += sign * n as isize;
+        rest = after;
+    }
+    Ok(Some(((address, offset), rest)))
+}
+
+// A pattern up to an unescaped delimiter, which may be left out at the end of the line
+fn split_pattern(s: &str, delim: char) -> (&str, &str) {
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == delim {
+            return (&s[..i], &s[i + c.len_utf8()..]);
+        }
+    }
+    (s, "")
+}
+
+// Text up to an unescaped |. Escapes are kept as they are
+fn split_raw(s: &str) -> (&str, &str) {
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == '|' {
+            return s.split_at(i);
+        }
+    }
+    (s, "")
+}
+
+// Whitespace-separated arguments, up to an unescaped | outside quotes. Arguments can be quoted
+// with " or ', and a backslash escapes the next char outside single quotes
+fn split_args(s: &str) -> Result<(Vec<String>, &str), String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut quote = None;
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => arg.get_or_insert_with(String::new).push(c),
+            (_, '\\') => {
+                let arg = arg.get_or_insert_with(String::new);
+                match chars.next() {
+                    Some((_, c)) => arg.push(c),
+                    None => arg.push('\\'),
+                }
+            }
+            (Some(_), c) => arg.get_or_insert_with(String::new).push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            }
+            (None, '|') => {
+                args.extend(arg);
+                return Ok((args, &s[i..]));
+            }
+            (None, c) if c.is_whitespace() => args.extend(arg.take()),
+            (None, c) => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated quote".to_owned());
+    }
+    args.extend(arg);
+    Ok((args, ""))
+}
+
+// A decimal number at the start of the string, and the rest of it
+fn split_number(s: &str) -> Option<(usize, &str)> {
+    let len = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if len == 0 {
+        return None;
+    }
+    Some((s[..len].parse().ok()?, &s[len..]))
+}
+
+// -------- Execution ----------------
+
 impl Bed {
-    pub(crate) fn handle_command(&mut self, cmd: &str) {
-        let mut bytes = cmd.bytes();
-        match bytes.next() {
-            Some(b'b') => self.handle_b(cmd),
-            Some(b'c') => self.handle_c(cmd),
-            Some(b'e') => self.handle_e(cmd),
-            Some(b'q') => self.handle_q(cmd),
-            Some(b's') => self.handle_s(cmd),
-            Some(b'v') => self.handle_v(cmd),
-            Some(b'w') => self.handle_w(cmd),
-            _ => {}
+    pub(crate) fn handle_command(&mut self, line: &str) {
+        if let Err(e) = self.run_command_line(line) {
+            self.cmd_prompt.set_error(&e);
         }
     }
 
-    fn handle_b(&mut self, s: &str) {
-        match &s[1..] {
-            "n" | "next" => self.textview_tree.active_mut().next_buffer(),
-            "p" | "previous" => self.textview_tree.active_mut().prev_buffer(),
-            _ => {}
+    // Commands are all parsed before any of them run
+    fn run_command_line(&mut self, line: &str) -> Result<(), String> {
+        for cmd in parse_command_line(line)? {
+            self.run_command(cmd)?;
         }
+        Ok(())
     }
 
-    fn handle_c(&mut self, s: &str) {
-        let mut sp = s.split_whitespace();
-        match sp.next() {
-            Some("cd") => self.change_directory(sp.next()),
-            _ => {}
+    fn run_command(&mut self, cmd: Command) -> Result<(), String> {
+        let range = match &cmd.range {
+            Some(range) => Some(self.resolve_range(range)?),
+            None => None,
+        };
+        let arg = cmd.args.first().map(|s| s.as_str());
+        let count = cmd.count.unwrap_or(1);
+        match cmd.name {
+            "" => {
+                // A range on its own goes to its last line
+                let (_, last) = range.unwrap();
+                self.jump(|bed| {
+                    let pane = bed.tabs.tree_mut().active_mut();
+                    pane.move_cursor(MotionOrObj::Motion(Motion::ToLine(last)))
+                });
+                Ok(())
+            }
+            "buffer" => {
+                if arg.is_some() {
+                    let buf = self.find_buffer(arg)?;
+                    self.switch_buffer(buf);
+                }
+                Ok(())
+            }
+            "bdelete" => self.delete_buffer(arg, cmd.bang, false),
+            "bwipeout" => self.delete_buffer(arg, cmd.bang, true),
+            "bnext" => {
+                for _ in 0..count {
+                    self.tabs.tree_mut().active_mut().next_buffer();
+                }
+                Ok(())
+            }
+            "bprevious" => {
+                for _ in 0..count {
+                    self.tabs.tree_mut().active_mut().prev_buffer();
+                }
+                Ok(())
+            }
+            "cd" => self.change_directory(arg),
+            "close" => self.tabs.tree_mut().close(cmd.bang),
+            "edit" => self.load_buffer(arg, cmd.bang),
+            "ls" => {
+                self.list_buffers();
+                Ok(())
+            }
+            "only" => self.tabs.tree_mut().only(cmd.bang),
+            "quit" | "qall" => self.quit(cmd.bang),
+            "split" => self.horizontal_split(arg),
+            "substitute" => self.substitute(range, arg.unwrap_or("")),
+            "tabclose" => {
+                let number = arg
+                    .map(|arg| {
+                        arg.parse::<usize>()
+                            .map_err(|_| format!("invalid tab page: {}", arg))
+                    })
+                    .transpose()?;
+                self.tabs.close_tab(number, cmd.bang)
+            }
+            "tabmove" => self.tabs.move_tab(arg),
+            "tabnew" => self.new_tab(arg),
+            "vsplit" => self.vertical_split(arg),
+            "wall" => self.write_all(),
+            "write" => self.write_buffer(arg, cmd.bang),
+            "wq" => {
+                self.write_buffer(arg, cmd.bang)?;
+                self.quit(cmd.bang)
+            }
+            // Like :wq, but only writes if there are changes
+            "xit" => {
+                if arg.is_some() || self.tabs.tree().active().is_modified() {
+                    self.write_buffer(arg, cmd.bang)?;
+                }
+                self.quit(cmd.bang)
+            }
+            _ => unreachable!(),
         }
     }
 
-    fn handle_e(&mut self, s: &str) {
-        let mut sp = s.split_whitespace();
-        match sp.next() {
-            Some("e") | Some("edit") => self.load_buffer(sp.next()),
-            _ => {}
+    // -------- Quitting ----------------
+
+    // Close the editor. This is refused while buffers have changes which haven't been written,
+    // unless forced
+    fn quit(&mut self, force: bool) -> Result<(), String> {
+        if !force {
+            if self.tabs.tree().active().is_modified() {
+                return Err("no write since last change (add ! to override)".to_owned());
+            }
+            if let Some(buf) = self.buffer_mgr.modified_buffers().first() {
+                return Err(format!(
+                    "no write since last change for buffer \"{}\" (add ! to override)",
+                    buf.borrow().name()
+                ));
+            }
         }
+        self.window.set_should_close();
+        Ok(())
     }
 
-    fn handle_s(&mut self, s: &str) {
-        let mut sp = s.split_whitespace();
-        match sp.next() {
-            Some("sp") | Some("split") => self.horizontal_split(sp.next()),
-            _ => {}
+    // -------- Buffer list ----------------
+
+    // Buffer for an argument to :b, :bd or :bw. This is a buffer number, or part of the name of
+    // one buffer. No argument is the active buffer
+    fn find_buffer(&self, arg: Option<&str>) -> Result<Rc<RefCell<Buffer>>, String> {
+        let arg = match arg {
+            Some(arg) => arg,
+            None => {
+                let id = self.tabs.tree().active().buffer_id();
+                return Ok(self.buffer_mgr.buffer_for_id(id).unwrap());
+            }
+        };
+        if let Ok(number) = arg.parse::<usize>() {
+            return self
+                .buffer_mgr
+                .buffer_for_number(number)
+                .ok_or_else(|| format!("buffer {} does not exist", number));
         }
+        let buffers = self.buffer_mgr.buffers();
+        let names = buffers
+            .iter()
+            .map(|buf| relative_name(buf.borrow().name()))
+            .collect::<Vec<_>>();
+        let i = match_buffer_name(&names, arg)?;
+        Ok(buffers[i].clone())
     }
 
-    fn handle_q(&mut self, s: &str) {
-        match &s[1..] {
-            "" | "uit" => self.window.set_should_close(),
-            _ => {}
+    fn switch_buffer(&mut self, buf: Rc<RefCell<Buffer>>) {
+        let bufmgr = &mut self.buffer_mgr;
+        self.tabs
+            .tree_mut()
+            .active_mut()
+            .new_buffer(buf, || bufmgr.next_view_id());
+    }
+
+    // Ctrl-^. Go to the alternate buffer, or the buffer with the given number
+    pub(crate) fn alternate_buffer(&mut self, number: Option<usize>) -> bool {
+        let buf = match number {
+            Some(number) => self
+                .buffer_mgr
+                .buffer_for_number(number)
+                .ok_or_else(|| format!("buffer {} does not exist", number)),
+            None => self
+                .tabs
+                .tree()
+                .active()
+                .alternate_buffer()
+                .and_then(|id| self.buffer_mgr.buffer_for_id(id))
+                .ok_or_else(|| "no alternate file".to_owned()),
+        };
+        match buf {
+            Ok(buf) => {
+                self.switch_buffer(buf);
+                true
+            }
+            Err(e) => {
+                self.cmd_prompt.set_error(&e);
+                false
+            }
         }
     }
 
-    fn handle_v(&mut self, s: &str) {
-        let mut sp = s.split_whitespace();
-        match sp.next() {
-            Some("vsp") | Some("vsplit") => self.vertical_split(sp.next()),
-            _ => {}
+    // :bd and :bw. The buffer is taken out of every pane, and its document is closed on the
+    // language server. Wiping it out also forgets its path and global marks
+    fn delete_buffer(&mut self, arg: Option<&str>, force: bool, wipe: bool) -> Result<(), String> {
+        let buf = self.find_buffer(arg)?;
+        let id = {
+            let buf = &mut *buf.borrow_mut();
+            if !force && buf.is_modified() {
+                return Err(format!(
+                    "no write since last change for buffer \"{}\" (add ! to override)",
+                    buf.name()
+                ));
+            }
+            buf.close();
+            buf.buffer_id()
+        };
+        drop(buf);
+        let other = self
+            .buffer_mgr
+            .buffers()
+            .into_iter()
+            .find(|buf| buf.borrow().buffer_id() != id);
+        let replacement = other.unwrap_or_else(|| self.buffer_mgr.empty());
+        let bufmgr = &mut self.buffer_mgr;
+        self.tabs
+            .remove_buffer(id, &replacement, &mut || bufmgr.next_view_id());
+        drop(replacement);
+        if wipe {
+            self.buffer_mgr.wipe(id);
+        } else {
+            self.buffer_mgr.purge();
         }
+        Ok(())
+    }
+
+    // :ls. Buffers are shown with their number, % for the active buffer, # for the alternate
+    // buffer and + if they are modified
+    fn list_buffers(&mut self) {
+        let pane = self.tabs.tree().active();
+        let (active, alternate) = (pane.buffer_id(), pane.alternate_buffer());
+        let list = self
+            .buffer_mgr
+            .buffers()
+            .iter()
+            .map(|buf| {
+                let buf = buf.borrow();
+                let id = buf.buffer_id();
+                let flag = if id == active {
+                    '%'
+                } else if Some(id) == alternate {
+                    '#'
+                } else {
+                    ' '
+                };
+                let modified = if buf.is_modified() { '+' } else { ' ' };
+                let name = relative_name(buf.name());
+                format!("{} {}{} \"{}\"", id.number(), flag, modified, name)
+            })
+            .collect::<Vec<_>>();
+        self.cmd_prompt.set_message(&list.join("   "));
     }
 
-    fn handle_w(&mut self, s: &str) {
-        let mut sp = s.split_whitespace();
-        match sp.next() {
-            Some("w") | Some("write") => self.write_buffer(sp.next()),
-            _ => {}
+    // -------- Ranges ----------------
+
+    fn resolve_range(&self, range: &Range) -> Result<LineRange, String> {
+        let last_line = self.tabs.tree().active().len_lines() - 1;
+        let (first, last) = match range {
+            Range::Whole => return Ok((0, last_line)),
+            Range::Lines(first, None) => {
+                let first = self.resolve_address(first)?;
+                (first, first)
+            }
+            Range::Lines(first, Some(last)) => {
+                (self.resolve_address(first)?, self.resolve_address(last)?)
+            }
+        };
+        let clamp = |linum: isize| linum.max(0).min(last_line as isize) as usize;
+        let (first, last) = (clamp(first), clamp(last));
+        if first > last {
+            Ok((last, first))
+        } else {
+            Ok((first, last))
+        }
+    }
+
+    fn resolve_address(&self, (address, offset): &LineAddress) -> Result<isize, String> {
+        let pane = self.tabs.tree().active();
+        let linum = match address {
+            Address::Line(n) => *n as isize - 1,
+            Address::Current => pane.cursor_line() as isize,
+            Address::Last => pane.len_lines() as isize - 1,
+            Address::Mark(c) => match pane.mark_line(*c) {
+                Some(linum) => linum as isize,
+                None => return Err(format!("mark not set: {}", c)),
+            },
+            Address::Search(pattern, forward) => {
+                let registers = self.registers.borrow();
+                let pattern = match (pattern.as_str(), registers.last_search()) {
+                    ("", Some(last)) => last,
+                    ("", None) => return Err("no previous search pattern".to_owned()),
+                    (pattern, _) => pattern,
+                };
+                pane.search_line(pattern, *forward)? as isize
+            }
+        };
+        Ok(linum + offset)
+    }
+
+    // -------- Substitute ----------------
+
+    // :s/pattern/replacement/flags count. The pattern and replacement may contain escaped
+    // delimiters. An empty pattern uses the last search pattern. With a count, the substitute
+    // covers count lines from the last line of the range
+    fn substitute(&mut self, range: Option<LineRange>, args: &str) -> Result<(), String> {
+        let (first, last) = match range {
+            Some(range) => range,
+            None => {
+                let linum = self.tabs.tree().active().cursor_line();
+                (linum, linum)
+            }
+        };
+        let delim = match args.chars().next() {
+            Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' && c != '"' => c,
+            _ => return Err(format!("invalid substitute: {}", args)),
+        };
+        let mut parts = split_delimited(&args[delim.len_utf8()..], delim);
+        let flags = if parts.len() > 2 {
+            parts.remove(2)
+        } else {
+            String::new()
+        };
+        let replacement = if parts.len() > 1 {
+            parts.remove(1)
+        } else {
+            String::new()
+        };
+        let pattern = if parts[0].is_empty() {
+            let registers = self.registers.borrow();
+            let last = registers
+                .last_search()
+                .ok_or("no previous search pattern")?;
+            last.to_owned()
+        } else {
+            parts.remove(0)
+        };
+        let (mut global, mut ignore_case) = (false, false);
+        let flags = flags.trim();
+        let count_start = flags
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(flags.len());
+        for c in flags[..count_start].chars().filter(|c| !c.is_whitespace()) {
+            match c {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                'I' => ignore_case = false,
+                _ => return Err(format!("unsupported substitute flag: {}", c)),
+            }
         }
+        let range = match &flags[count_start..] {
+            "" => (first, last),
+            count => match count.parse::<usize>() {
+                Ok(count) if count > 0 => (last, last + count - 1),
+                _ => return Err(format!("invalid count: {}", count)),
+            },
+        };
+        let pane = self.tabs.tree_mut().active_mut();
+        pane.substitute(range, &pattern, &replacement, global, ignore_case)?;
+        self.registers.borrow_mut().set_last_search(&pattern);
+        self.buffer_mgr.set_search_pattern(Some(&pattern));
+        Ok(())
+    }
+}
+
+// Buffer name relative to the current directory, for buffers with files under it
+fn relative_name(name: &str) -> String {
+    env::current_dir()
+        .ok()
+        .and_then(|dir| {
+            let path = Path::new(name).strip_prefix(dir).ok()?;
+            Some(path.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| name.to_owned())
+}
+
+// Index of the buffer name which matches a pattern. A name or file name which is the same as the
+// pattern is taken over names which only contain it
+fn match_buffer_name(names: &[String], pattern: &str) -> Result<usize, String> {
+    let exact = |name: &String| {
+        name == pattern || Path::new(name).file_name().map_or(false, |f| f == pattern)
+    };
+    let mut matches = (0..names.len())
+        .filter(|i| exact(&names[*i]))
+        .collect::<Vec<_>>();
+    if matches.is_empty() {
+        matches = (0..names.len())
+            .filter(|i| names[*i].contains(pattern))
+            .collect();
+    }
+    match matches[..] {
+        [] => Err(format!("no matching buffer for {}", pattern)),
+        [i] => Ok(i),
+        _ => Err(format!("more than one match for {}", pattern)),
+    }
+}
+
+// Split at delimiters which aren't escaped, into at most 3 parts. The backslash before an escaped
+// delimiter is dropped, while other escapes are kept
+fn split_delimited(s: &str, delim: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == delim && parts.len() < 3 {
+            parts.push(String::new());
+            continue;
+        }
+        let part = parts.last_mut().unwrap();
+        if c == '\\' {
+            match chars.next() {
+                Some(c) if c == delim => part.push(c),
+                Some(c) => {
+                    part.push('\\');
+                    part.push(c);
+                }
+                None => part.push('\\'),
+            }
+        } else {
+            part.push(c);
+        }
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(name: &'static str, bang: bool, args: &[&str]) -> Command {
+        Command {
+            range: None,
+            name,
+            bang,
+            count: None,
+            args: args.iter().map(|s| (*s).to_owned()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_match_buffer_name() {
+        let names = [
+            "src/lib.rs",
+            "src/buffer/mod.rs",
+            "src/textview/mod.rs",
+            "lib.rs",
+        ]
+        .iter()
+        .map(|s| (*s).to_owned())
+        .collect::<Vec<_>>();
+        assert_eq!(match_buffer_name(&names, "buffer"), Ok(1));
+        assert_eq!(
+            match_buffer_name(&names, "lib.rs"),
+            Err("more than one match for lib.rs".to_owned())
+        );
+        assert_eq!(match_buffer_name(&names, "src/lib.rs"), Ok(0));
+        assert_eq!(match_buffer_name(&names, "view/mod"), Ok(2));
+        assert!(match_buffer_name(&names, "mod.rs").is_err());
+        assert!(match_buffer_name(&names, "main").is_err());
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("%s").unwrap(), (Some(Range::Whole), "s"));
+        assert_eq!(
+            parse_range("10,$-2d").unwrap(),
+            (
+                Some(Range::Lines(
+                    (Address::Line(10), 0),
+                    Some((Address::Last, -2))
+                )),
+                "d"
+            )
+        );
+        assert_eq!(
+            parse_range("'<,'>s").unwrap(),
+            (
+                Some(Range::Lines(
+                    (Address::Mark('<'), 0),
+                    Some((Address::Mark('>'), 0))
+                )),
+                "s"
+            )
+        );
+        assert_eq!(
+            parse_range("/fo\\/o/+,?bar").unwrap(),
+            (
+                Some(Range::Lines(
+                    (Address::Search("fo\\/o".to_owned(), true), 1),
+                    Some((Address::Search("bar".to_owned(), false), 0))
+                )),
+                ""
+            )
+        );
+        assert_eq!(
+            parse_range("-3,").unwrap(),
+            (
+                Some(Range::Lines(
+                    (Address::Current, -3),
+                    Some((Address::Current, 0))
+                )),
+                ""
+            )
+        );
+        assert_eq!(parse_range("w").unwrap(), (None, "w"));
+    }
+
+    #[test]
+    fn test_parse_command_line() {
+        assert_eq!(
+            parse_command_line("w").unwrap(),
+            vec![command("write", false, &[])]
+        );
+        assert_eq!(
+            parse_command_line(":vs foo | e! \"a b\\\"c\"").unwrap(),
+            vec![
+                command("vsplit", false, &["foo"]),
+                command("edit", true, &["a b\"c"])
+            ]
+        );
+        assert_eq!(
+            parse_command_line("s/a\\|b/c/g|w 'x|y'").unwrap(),
+            vec![
+                command("substitute", false, &["/a\\|b/c/g"]),
+                command("write", false, &["x|y"])
+            ]
+        );
+        assert_eq!(
+            parse_command_line("tabm -1 | tabc! 2 | tabnew").unwrap(),
+            vec![
+                command("tabmove", false, &["-1"]),
+                command("tabclose", true, &["2"]),
+                command("tabnew", false, &[]),
+            ]
+        );
+        let mut bn = command("bnext", false, &[]);
+        bn.count = Some(3);
+        assert_eq!(parse_command_line("bn 3").unwrap(), vec![bn]);
+        let mut goto = command("", false, &[]);
+        goto.range = Some(Range::Lines((Address::Line(5), 0), None));
+        assert_eq!(parse_command_line("5").unwrap(), vec![goto]);
+        assert!(parse_command_line("").unwrap().is_empty());
+        assert!(parse_command_line("foo").is_err());
+        assert!(parse_command_line("sp!").is_err());
+        assert!(parse_command_line("1,2w").is_err());
+        assert!(parse_command_line("q now").is_err());
+        assert_eq!(
+            parse_command_line("wq!|qa!").unwrap(),
+            vec![command("wq", true, &[]), command("qall", true, &[])]
+        );
+        assert_eq!(
+            parse_command_line("wa|x foo").unwrap(),
+            vec![command("wall", false, &[]), command("xit", false, &["foo"])]
+        );
+        assert!(parse_command_line("e a b").is_err());
+        assert!(parse_command_line("e \"a").is_err());
     }
 }
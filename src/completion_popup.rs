@@ -9,7 +9,7 @@ use euclid::{point2, size2, Point2D, Rect, Size2D};
 
 use crate::common::{PixelSize, DPI};
 use crate::config::Config;
-use crate::painter::Painter;
+use crate::painter::{Painter, TextWrap};
 use crate::style::{Color, TextStyle};
 use crate::text::{RopeOrStr, ShapedText, TextAlignment, TextShaper};
 use crate::theme::Theme;
@@ -203,7 +203,7 @@ impl CompletionPopup {
                 None,
                 self.rect.size.width - basex as u32,
                 self.height,
-                false,
+                TextWrap::None,
             );
             pos.y -= self.descender - self.config.completion_line_padding as i32;
             pos.x = basex;
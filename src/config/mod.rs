@@ -7,7 +7,7 @@ use fnv::FnvHashMap;
 use serde::Deserialize;
 
 use crate::font::{FaceKey, FontCore};
-use crate::language::Language;
+use crate::language::{CommentTokens, Language};
 use crate::style::TextSize;
 
 use super::DEFAULT_THEME;
@@ -20,6 +20,9 @@ static DEFAULT_FONT: &'static str = "Consolas";
 static DEFAULT_FONT_SIZE: f32 = 8.0;
 static DEFAULT_TAB_WIDTH: usize = 8;
 static DEFAULT_INDENT_TABS: bool = true;
+static DEFAULT_SOFT_WRAP: bool = false;
+static DEFAULT_AUTO_PAIRS: &[(char, char)] =
+    &[('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
 static DEFAULT_GUTTER_PADDING: u32 = 8;
 static DEFAULT_PROMPT_PADDING_HORIZONTAL: u32 = 4;
 static DEFAULT_PROMPT_PADDING_VERTICAL: u32 = 2;
@@ -31,6 +34,11 @@ static DEFAULT_HOVER_PADDING_VERTICAL: u32 = 2;
 pub(crate) struct ConfigLanguage {
     pub(crate) tab_width: usize,
     pub(crate) indent_tabs: bool,
+    pub(crate) soft_wrap: bool,
+    pub(crate) wrap_indent: usize,
+    pub(crate) auto_pair: bool,
+    pub(crate) auto_pairs: Vec<(char, char)>,
+    pub(crate) comment_tokens: CommentTokens,
     pub(crate) language_server: Option<ConfigLanguageServer>,
 }
 
@@ -52,20 +60,68 @@ struct ConfigLanguageInner {
     tab_width: Option<usize>,
     #[serde(rename(deserialize = "editor.indent_tabs"))]
     indent_tabs: Option<bool>,
+    #[serde(rename(deserialize = "editor.soft_wrap"))]
+    soft_wrap: Option<bool>,
+    #[serde(rename(deserialize = "editor.soft_wrap.indent"))]
+    wrap_indent: Option<usize>,
+    #[serde(rename(deserialize = "editor.auto_pair"))]
+    auto_pair: Option<bool>,
+    #[serde(rename(deserialize = "editor.auto_pair.pairs"))]
+    auto_pairs: Option<Vec<String>>,
+    #[serde(rename(deserialize = "editor.comment.line"))]
+    comment_line: Option<String>,
+    #[serde(rename(deserialize = "editor.comment.block"))]
+    comment_block: Option<Vec<String>>,
     #[serde(rename(deserialize = "completion.language_server"))]
     language_server: Option<ConfigLanguageServer>,
 }
 
 impl ConfigLanguageInner {
-    fn finalize(self, tab_width: usize, indent_tabs: bool) -> ConfigLanguage {
+    fn finalize(self, language: Language, config: &Config) -> ConfigLanguage {
+        let mut comment_tokens = language.comment_tokens();
+        // An empty line comment token turns line comments off
+        if let Some(line) = self.comment_line {
+            comment_tokens.line = Some(line).filter(|s| !s.is_empty());
+        }
+        if let Some(block) = self.comment_block {
+            match block.as_slice() {
+                [start, end] => comment_tokens.block = Some((start.clone(), end.clone())),
+                _ => error!("invalid block comment tokens: {:?}", block),
+            }
+        }
         ConfigLanguage {
-            tab_width: self.tab_width.unwrap_or(tab_width),
-            indent_tabs: self.indent_tabs.unwrap_or(indent_tabs),
+            tab_width: self.tab_width.unwrap_or(config.tab_width),
+            indent_tabs: self.indent_tabs.unwrap_or(config.indent_tabs),
+            soft_wrap: self.soft_wrap.unwrap_or(config.soft_wrap),
+            wrap_indent: self.wrap_indent.unwrap_or(config.wrap_indent),
+            auto_pair: self.auto_pair.unwrap_or(config.auto_pair),
+            auto_pairs: self
+                .auto_pairs
+                .map(|pairs| parse_pairs(&pairs))
+                .unwrap_or_else(|| config.auto_pairs.clone()),
+            comment_tokens,
             language_server: self.language_server,
         }
     }
 }
 
+// Pairs are given as two-character strings, like "()"
+fn parse_pairs(pairs: &[String]) -> Vec<(char, char)> {
+    pairs
+        .iter()
+        .filter_map(|s| {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next(), chars.next()) {
+                (Some(open), Some(close), None) => Some((open, close)),
+                _ => {
+                    error!("invalid auto-pair: {:?}", s);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
 #[derive(Default, Deserialize)]
 pub(crate) struct ConfigCompletionAnnotation {
     #[serde(rename(deserialize = "path.directory"), default)]
@@ -78,6 +134,12 @@ pub(crate) struct Config {
     pub(crate) theme: String,
     pub(crate) tab_width: usize,
     pub(crate) indent_tabs: bool,
+    // Break lines wider than the view into rows, indenting rows after the first by some columns
+    pub(crate) soft_wrap: bool,
+    pub(crate) wrap_indent: usize,
+    // Characters which get their closing character inserted along with them
+    pub(crate) auto_pair: bool,
+    pub(crate) auto_pairs: Vec<(char, char)>,
     pub(crate) language: FnvHashMap<Language, ConfigLanguage>,
     // Textview
     pub(crate) textview_face: FaceKey,
@@ -144,6 +206,14 @@ struct ConfigInner {
     tab_width: Option<usize>,
     #[serde(rename(deserialize = "editor.indent_tabs"))]
     indent_tabs: Option<bool>,
+    #[serde(rename(deserialize = "editor.soft_wrap"))]
+    soft_wrap: Option<bool>,
+    #[serde(rename(deserialize = "editor.soft_wrap.indent"))]
+    wrap_indent: Option<usize>,
+    #[serde(rename(deserialize = "editor.auto_pair"))]
+    auto_pair: Option<bool>,
+    #[serde(rename(deserialize = "editor.auto_pair.pairs"))]
+    auto_pairs: Option<Vec<String>>,
     // Gutter
     #[serde(rename(deserialize = "gutter.font_family"))]
     gutter_font_family: Option<String>,
@@ -198,6 +268,13 @@ impl ConfigInner {
         let theme = self.theme.unwrap_or(DEFAULT_THEME.to_owned());
         let tab_width = self.tab_width.unwrap_or(DEFAULT_TAB_WIDTH);
         let indent_tabs = self.indent_tabs.unwrap_or(DEFAULT_INDENT_TABS);
+        let soft_wrap = self.soft_wrap.unwrap_or(DEFAULT_SOFT_WRAP);
+        let wrap_indent = self.wrap_indent.unwrap_or(0);
+        let auto_pair = self.auto_pair.unwrap_or(true);
+        let auto_pairs = self
+            .auto_pairs
+            .map(|pairs| parse_pairs(&pairs))
+            .unwrap_or_else(|| DEFAULT_AUTO_PAIRS.to_vec());
         // Textview
         let textview_face = self
             .textview_font_family
@@ -256,17 +333,16 @@ impl ConfigInner {
         let hover_padding_vertical = self
             .hover_padding_vertical
             .unwrap_or(DEFAULT_HOVER_PADDING_HORIZONTAL);
-        // Language config
-        let mut language = FnvHashMap::default();
-        for (k, v) in self.language {
-            language.insert(k, v.finalize(tab_width, indent_tabs));
-        }
         // Return
-        Config {
+        let mut config = Config {
             theme,
             tab_width,
             indent_tabs,
-            language,
+            soft_wrap,
+            wrap_indent,
+            auto_pair,
+            auto_pairs,
+            language: FnvHashMap::default(),
             textview_face,
             textview_font_size,
             textview_line_padding: self.textview_line_padding,
@@ -289,6 +365,12 @@ impl ConfigInner {
             hover_padding_vertical,
             hover_padding_horizontal,
             hover_line_padding: self.hover_line_padding,
+        };
+        // Language config
+        for (k, v) in self.language {
+            let v = v.finalize(k, &config);
+            config.language.insert(k, v);
         }
+        config
     }
 }
@@ -12,7 +12,7 @@ use crate::config::Config;
 use crate::language_client::{
     DiagnosticCode, DiagnosticSeverity, HoverContents, MarkedString, MarkupKind,
 };
-use crate::painter::Painter;
+use crate::painter::{Painter, TextWrap};
 use crate::style::TextStyle;
 use crate::text::{RopeOrStr, ShapedText, TextAlignment, TextShaper};
 use crate::theme::Theme;
@@ -344,7 +344,7 @@ impl HoverPopup {
             for (num_lines, line) in &self.contents {
                 let height = line.metrics.height() as u32;
                 pos.y += line.metrics.ascender + self.config.hover_line_padding as i32;
-                painter.draw_shaped_text(shaper, pos, line, None, width, height, true);
+                painter.draw_shaped_text(shaper, pos, line, None, width, height, TextWrap::Fold);
                 pos.y -= line.metrics.descender - self.config.hover_line_padding as i32;
                 pos.y += (num_lines - 1) as i32 * height as i32;
                 pos.x = basex;
@@ -354,7 +354,7 @@ impl HoverPopup {
 
         for (num_lines, line) in &self.diagnostic {
             pos.y += self.ascender + self.config.hover_line_padding as i32;
-            painter.draw_shaped_text(shaper, pos, line, None, width, self.height, true);
+            painter.draw_shaped_text(shaper, pos, line, None, width, self.height, TextWrap::Fold);
             pos.y -= self.descender - self.config.hover_line_padding as i32;
             pos.y += (num_lines - 1) as i32 * self.height as i32;
             pos.x = basex;
@@ -3,17 +3,153 @@
 use glfw::{Key, Modifiers};
 
 use crate::buffer::CursorStyle;
+use crate::registers::Registers;
 
 #[derive(Eq, PartialEq)]
 pub enum Mode {
     Normal,
     Input,
     Command,
-    GPressed(usize),
-    DPressed(usize),
+    // 'g' typed, with the count typed before it
+    GPressed(Option<usize>),
+    // Waiting for a motion or object, with the count typed before the operator, if any
+    OperatorPending(Operator, Option<usize>),
+    OperatorGPressed(Operator, usize),
+    // 'i' or 'a' typed, waiting for a text object. The operator is None in visual mode
+    ObjectPending(Option<Operator>, usize, bool),
+    // ']' or '[' typed. The operator is None outside of operator-pending mode
+    BracketPressed(Option<Operator>, usize, bool),
+    QuotePressed,
+    // 'm' typed, waiting for the mark to set
+    MarkPending,
+    // ''' or '`' typed, waiting for the mark to go to. True for ''', which goes to the mark's line.
+    // The operator is None outside of operator-pending mode
+    MarkJumpPending(Option<Operator>, bool),
+    // 'q' typed, waiting for the register to record a macro in
+    RecordPending,
+    // '@' typed, waiting for the register of the macro to run
+    MacroPending(usize),
+    // 'f', 't', 'F' or 'T' typed, waiting for the character to find. The operator is None
+    // outside of operator-pending mode. The flags are for forward, and for stopping before it
+    FindPending(Option<Operator>, usize, bool, bool),
+    // 'z' typed, waiting for where to scroll the cursor line to, or a fold command
+    ZPressed,
+    // 'r' typed, waiting for the character to replace count characters with
+    ReplacePending(usize),
+    // Typed characters overwrite the text under the cursor
+    Replace,
+    // "cs" (true) or "ds" typed, waiting for the pair to change or delete, with a count
+    SurroundTargetPending(usize, bool),
+    // Waiting for the character of the pair to surround text with
+    SurroundPending(SurroundEdit),
+    // Ctrl-W typed, waiting for a command on panes, with the count typed before it
+    WindowPending(Option<usize>),
+    Visual,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum VisualMode {
+    Char,
+    Line,
+    Block,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Outdent,
+    Reindent,
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+    Comment,
+    // Make a closed fold over the lines
+    Fold,
+    // Waits for the pair to add around the text. Never reaches the buffer as an operator
+    Surround,
+}
+
+impl Operator {
+    // Key which, when typed after the operator, makes it apply to whole lines (dd, yy, g~~ etc.)
+    fn line_key(self) -> char {
+        match self {
+            Operator::Delete => 'd',
+            Operator::Change => 'c',
+            Operator::Yank => 'y',
+            Operator::Indent => '>',
+            Operator::Outdent => '<',
+            Operator::Reindent => '=',
+            Operator::Lowercase => 'u',
+            Operator::Uppercase => 'U',
+            Operator::ToggleCase => '~',
+            Operator::Comment => 'c',
+            Operator::Fold => 'f',
+            Operator::Surround => 's',
+        }
+    }
+}
+
+// Character to look for on the cursor's line
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(crate) struct CharFind {
+    pub(crate) c: char,
+    pub(crate) forward: bool,
+    // Stop next to the character
+    pub(crate) till: bool,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ScreenPos {
+    Top,
+    Middle,
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+// Ctrl-W commands on panes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Window {
+    // Go count panes in a direction
+    Focus(Direction, usize),
+    // Go to the next (true) or previous pane, or the pane with the given number
+    Cycle(bool, Option<usize>),
+    // Go back to the pane which was active before
+    Previous,
+    // Swap with the next pane in the row or column, or the one with the given number
+    Exchange(Option<usize>),
+    // Rotate the panes in the row or column count times. True to rotate downwards or rightwards
+    Rotate(bool, usize),
+    // Make all panes the same size
+    Equalize,
+    // Grow or shrink the pane by a number of rows (true) or columns
+    Resize(bool, i32),
+    // Move the pane to an edge
+    MoveToEdge(Direction),
+    // Split the pane. True for a vertical split
+    Split(bool),
+    Close,
+    Only,
 }
 
 #[derive(Clone, Copy)]
+pub(crate) enum Scroll {
+    HalfPageDown,
+    HalfPageUp,
+    PageDown,
+    PageUp,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub(crate) enum Motion {
     Up(usize),
     Down(usize),
@@ -22,38 +158,424 @@ pub(crate) enum Motion {
     ToLine(usize),
     LineStart,
     LineEnd,
+    FirstNonBlank,
+    // Go to a mark. True to go to the first non-blank of its line
+    Mark(char, bool),
+    // Find a character count times. True if repeated with ';' or ','
+    FindChar(CharFind, usize, bool),
+    // Bracket matching the one under or after the cursor
+    MatchBracket,
+    // Line at a percentage of the buffer
+    Percent(usize),
+    // Line of the view, count lines from the top or bottom
+    ScreenLine(ScreenPos, usize),
+    // Rows of soft-wrapped lines, which are lines otherwise
+    RowUp(usize),
+    RowDown(usize),
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum TextObject {
+    Word,
+    WordExt,
+    Sentence,
+    Paragraph,
+    // Opening and closing bracket
+    Bracket(char, char),
+    Quote(char),
+    Tag,
+    // Syntax nodes, from the language's textobjects query
+    Function,
+    Class,
+    Parameter,
+    Block,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub(crate) enum Object {
     Lines(usize),
     Words(usize),
     WordsExt(usize),
     BackWords(usize),
     BackWordsExt(usize),
+    WordEnds(usize),
+    WordEndsExt(usize),
+    BackWordEnds(usize),
+    BackWordEndsExt(usize),
+    Paragraphs(usize),
+    BackParagraphs(usize),
+    Sentences(usize),
+    BackSentences(usize),
+    // Starts of functions, or of blocks in languages without functions
+    Functions(usize),
+    BackFunctions(usize),
+    // Text objects, with a count. Inner objects exclude delimiters and surrounding whitespace
+    Inner(TextObject, usize),
+    Around(TextObject, usize),
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub(crate) enum MotionOrObj {
     Motion(Motion),
     Object(Object),
 }
 
+impl MotionOrObj {
+    // Replace the count, for motions and objects which have one
+    fn set_count(&mut self, count: usize) -> bool {
+        match self {
+            MotionOrObj::Motion(Motion::Up(n))
+            | MotionOrObj::Motion(Motion::Down(n))
+            | MotionOrObj::Motion(Motion::Left(n))
+            | MotionOrObj::Motion(Motion::Right(n))
+            | MotionOrObj::Object(Object::Lines(n))
+            | MotionOrObj::Object(Object::Words(n))
+            | MotionOrObj::Object(Object::WordsExt(n))
+            | MotionOrObj::Object(Object::BackWords(n))
+            | MotionOrObj::Motion(Motion::FindChar(_, n, _))
+            | MotionOrObj::Motion(Motion::ScreenLine(_, n))
+            | MotionOrObj::Motion(Motion::RowUp(n))
+            | MotionOrObj::Motion(Motion::RowDown(n))
+            | MotionOrObj::Object(Object::BackWordsExt(n))
+            | MotionOrObj::Object(Object::WordEnds(n))
+            | MotionOrObj::Object(Object::WordEndsExt(n))
+            | MotionOrObj::Object(Object::BackWordEnds(n))
+            | MotionOrObj::Object(Object::BackWordEndsExt(n))
+            | MotionOrObj::Object(Object::Paragraphs(n))
+            | MotionOrObj::Object(Object::BackParagraphs(n))
+            | MotionOrObj::Object(Object::Sentences(n))
+            | MotionOrObj::Object(Object::BackSentences(n))
+            | MotionOrObj::Object(Object::Functions(n))
+            | MotionOrObj::Object(Object::BackFunctions(n))
+            | MotionOrObj::Object(Object::Inner(_, n))
+            | MotionOrObj::Object(Object::Around(_, n)) => {
+                *n = count;
+                true
+            }
+            MotionOrObj::Motion(_) => false,
+        }
+    }
+
+    // Whether moving adds to the jumplist
+    pub(crate) fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            MotionOrObj::Motion(Motion::ToLine(_))
+                | MotionOrObj::Motion(Motion::Mark(..))
+                | MotionOrObj::Motion(Motion::MatchBracket)
+                | MotionOrObj::Motion(Motion::Percent(_))
+                | MotionOrObj::Motion(Motion::ScreenLine(..))
+                | MotionOrObj::Object(Object::Paragraphs(_))
+                | MotionOrObj::Object(Object::BackParagraphs(_))
+                | MotionOrObj::Object(Object::Sentences(_))
+                | MotionOrObj::Object(Object::BackSentences(_))
+        )
+    }
+}
+
+// Pair of brackets, quotes or tags around the cursor, for "cs" and "ds". Opening brackets also
+// take the whitespace inside the pair
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct SurroundTarget {
+    pub(crate) obj: TextObject,
+    pub(crate) count: usize,
+    pub(crate) trim: bool,
+}
+
+// Text to add a pair around, or the pair to replace
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(crate) enum SurroundEdit {
+    Add(MotionOrObj),
+    Change(SurroundTarget),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Surround {
+    // Opening and closing characters. True to put spaces inside them
+    Pair(char, char, bool),
+    // Tag name, with any attributes
+    Tag(String),
+}
+
 #[derive(Clone, Copy)]
 pub(crate) enum ComplAction {
     Next,
     Prev,
 }
 
+// Open or close folds at the cursor, or all folds
+#[derive(Clone, Copy)]
+pub(crate) enum Fold {
+    Open,
+    Close,
+    Toggle,
+    OpenAll,
+    CloseAll,
+}
+
+// A key press or typed character. Macros are kept in registers as text, with keys written as
+// <Esc>, <C-r> etc., and '<' as <lt>
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Input {
+    Key(Key, Modifiers),
+    Char(char),
+}
+
+const KEY_NAMES: &[(Key, &str)] = &[
+    (Key::Escape, "Esc"),
+    (Key::Enter, "CR"),
+    (Key::Tab, "Tab"),
+    (Key::Backspace, "BS"),
+    (Key::Delete, "Del"),
+    (Key::Insert, "Insert"),
+    (Key::Up, "Up"),
+    (Key::Down, "Down"),
+    (Key::Left, "Left"),
+    (Key::Right, "Right"),
+    (Key::Home, "Home"),
+    (Key::End, "End"),
+    (Key::PageUp, "PageUp"),
+    (Key::PageDown, "PageDown"),
+];
+
+// Letter keys are only written out with a modifier. Typing them also gives a character
+const LETTER_KEYS: [Key; 26] = [
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+];
+
+const MODIFIER_NAMES: &[(Modifiers, &str)] = &[
+    (Modifiers::Control, "C-"),
+    (Modifiers::Alt, "A-"),
+    (Modifiers::Shift, "S-"),
+    (Modifiers::Super, "D-"),
+];
+
+impl Input {
+    // Append the input to a macro. Key presses which only give a character are skipped
+    pub(crate) fn write(&self, text: &mut String) {
+        match self {
+            Input::Char('<') => text.push_str("<lt>"),
+            Input::Char(c) => text.push(*c),
+            Input::Key(key, md) => {
+                let name = match KEY_NAMES.iter().find(|(k, _)| k == key) {
+                    Some((_, name)) => (*name).to_owned(),
+                    None if md
+                        .intersects(Modifiers::Control | Modifiers::Alt | Modifiers::Super) =>
+                    {
+                        match LETTER_KEYS.iter().position(|k| k == key) {
+                            Some(i) => ((b'a' + i as u8) as char).to_string(),
+                            None => return,
+                        }
+                    }
+                    None => return,
+                };
+                text.push('<');
+                for (m, prefix) in MODIFIER_NAMES {
+                    if md.contains(*m) {
+                        text.push_str(prefix);
+                    }
+                }
+                text.push_str(&name);
+                text.push('>');
+            }
+        }
+    }
+
+    // Read back a macro. Line breaks and escapes in text from elsewhere act as keys
+    pub(crate) fn parse_all(text: &str) -> Vec<Input> {
+        let mut ret = Vec::new();
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            if c == '<' {
+                if let Some((input, len)) = rest
+                    .find('>')
+                    .and_then(|end| Input::parse_key(&rest[1..end]).map(|input| (input, end + 1)))
+                {
+                    ret.push(input);
+                    rest = &rest[len..];
+                    continue;
+                }
+            }
+            ret.push(match c {
+                '\n' | '\r' => Input::Key(Key::Enter, Modifiers::empty()),
+                '\t' => Input::Key(Key::Tab, Modifiers::empty()),
+                '\x1b' => Input::Key(Key::Escape, Modifiers::empty()),
+                c => Input::Char(c),
+            });
+            rest = &rest[c.len_utf8()..];
+        }
+        ret
+    }
+
+    fn parse_key(mut name: &str) -> Option<Input> {
+        if name == "lt" {
+            return Some(Input::Char('<'));
+        }
+        let mut md = Modifiers::empty();
+        'outer: loop {
+            for (m, prefix) in MODIFIER_NAMES {
+                match name.strip_prefix(prefix) {
+                    Some(rest) if !rest.is_empty() => {
+                        md |= *m;
+                        name = rest;
+                        continue 'outer;
+                    }
+                    _ => {}
+                }
+            }
+            break;
+        }
+        if let Some((key, _)) = KEY_NAMES.iter().find(|(_, n)| n.eq_ignore_ascii_case(name)) {
+            return Some(Input::Key(*key, md));
+        }
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() && !md.is_empty() => {
+                let i = c.to_ascii_lowercase() as usize - 'a' as usize;
+                Some(Input::Key(LETTER_KEYS[i], md))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub(crate) enum Action {
     Move(MotionOrObj),
     Delete(MotionOrObj),
+    // Apply an operator to the text covered by a motion, with the register to use
+    Operate(Operator, MotionOrObj, Option<char>),
+    // Register and count
+    PutAfter(Option<char>, usize),
+    PutBefore(Option<char>, usize),
+    // Visual mode selection
+    StartSelection(VisualMode),
+    StopSelection,
+    OperateSelection(Operator, Option<char>),
+    // Join the selected lines. False to keep whitespace as it is
+    JoinSelection(bool),
+    // Repeat the last search count times. True to reverse its direction
+    SearchNext(bool, usize),
+    // Search for the word under the cursor count times. True to search forwards
+    SearchWord(bool, usize),
     InsertChar(char),
+    Undo(usize),
+    Redo(usize),
     UpdateCursorStyle(CursorStyle),
     StartCmdPrompt(String),
     StopCmdPrompt,
     GetCmd,
     Completion(ComplAction),
+    SetMark(char),
+    // Go to the alternate buffer, or the buffer with the given number
+    AlternateBuffer(Option<usize>),
+    Window(Window),
+    // gt and gT. Go to the next tab page or the one with the given number, or back count tabs
+    NextTab(Option<usize>),
+    PrevTab(usize),
+    // Go through the jumplist or changelist count times. True for older entries
+    Jumplist(bool, usize),
+    Changelist(bool, usize),
+    // Scroll the view, with the count of lines or pages
+    Scroll(Scroll, Option<usize>),
+    // Scroll the view to put the cursor line at the top, middle or bottom
+    ScrollCursor(ScreenPos),
+    Fold(Fold),
+    // Macros. None runs the last macro run again
+    RecordMacro(char),
+    StopMacro,
+    RunMacro(Option<char>, usize),
+    // Multiple cursors. Add count cursors on the lines below (true) or above the cursors, add
+    // cursors at the next count occurrences of the word under the cursor, turn the selection into
+    // one cursor per line, or go back to a single cursor
+    AddCursor(bool, usize),
+    AddCursorAtWord(usize),
+    SplitSelection,
+    ClearCursors,
+    // Replace count characters under the cursor with a character
+    ReplaceChar(char, usize),
+    // Replace mode. Backspace restores the text which was overwritten
+    StartReplace,
+    ReplaceModeChar(char),
+    ReplaceModeBackspace,
+    // Join count lines, at least two. False to keep whitespace as it is
+    Join(bool, usize),
+    // Toggle the case of count characters and move past them
+    ToggleCase(usize),
+    // Add to the number under or after the cursor
+    AddToNumber(i64),
+    // Open an indented line below the cursor's line, or above it if true
+    OpenLine(bool),
+    // Add or change a surrounding pair, or delete one
+    Surround(SurroundEdit, Surround),
+    DeleteSurround(SurroundTarget),
+    // Surround with the tag typed in to the prompt
+    SurroundTag(SurroundEdit),
+}
+
+impl Action {
+    // Whether the action changes the buffer on its own
+    fn is_change(&self) -> bool {
+        match self {
+            Action::Operate(op, _, _) => *op != Operator::Yank && *op != Operator::Fold,
+            Action::Delete(_)
+            | Action::PutAfter(..)
+            | Action::PutBefore(..)
+            | Action::ReplaceChar(..)
+            | Action::Join(..)
+            | Action::ToggleCase(_)
+            | Action::AddToNumber(_)
+            | Action::OpenLine(_)
+            | Action::Surround(..)
+            | Action::DeleteSurround(_) => true,
+            _ => false,
+        }
+    }
+
+    // Replace the count of an operator or put
+    fn set_count(&mut self, count: usize) -> bool {
+        match self {
+            Action::Operate(_, mo, _) => mo.set_count(count),
+            Action::PutAfter(_, n)
+            | Action::PutBefore(_, n)
+            | Action::ReplaceChar(_, n)
+            | Action::Join(_, n)
+            | Action::ToggleCase(n) => {
+                *n = count;
+                true
+            }
+            Action::AddToNumber(delta) => {
+                *delta = delta.signum() * count as i64;
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 macro_rules! thing {
@@ -64,6 +586,12 @@ macro_rules! thing {
     (DOWN, $n:expr) => {
         $crate::input::MotionOrObj::Motion($crate::input::Motion::Down($n))
     };
+    (ROW_UP, $n:expr) => {
+        $crate::input::MotionOrObj::Motion($crate::input::Motion::RowUp($n))
+    };
+    (ROW_DOWN, $n:expr) => {
+        $crate::input::MotionOrObj::Motion($crate::input::Motion::RowDown($n))
+    };
     (LEFT, $n:expr) => {
         $crate::input::MotionOrObj::Motion($crate::input::Motion::Left($n))
     };
@@ -79,6 +607,15 @@ macro_rules! thing {
     (LINE_END) => {
         $crate::input::MotionOrObj::Motion($crate::input::Motion::LineEnd)
     };
+    (FIRST_NON_BLANK) => {
+        $crate::input::MotionOrObj::Motion($crate::input::Motion::FirstNonBlank)
+    };
+    (SCREEN_LINE, $pos:ident, $n:expr) => {
+        $crate::input::MotionOrObj::Motion($crate::input::Motion::ScreenLine(
+            $crate::input::ScreenPos::$pos,
+            $n,
+        ))
+    };
     (LINE, $n:expr) => {
         $crate::input::MotionOrObj::Object($crate::input::Object::Lines($n))
     };
@@ -94,6 +631,36 @@ macro_rules! thing {
     (BACK_WORDS_EXT, $n:expr) => {
         $crate::input::MotionOrObj::Object($crate::input::Object::BackWordsExt($n))
     };
+    (WORD_ENDS, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::WordEnds($n))
+    };
+    (WORD_ENDS_EXT, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::WordEndsExt($n))
+    };
+    (BACK_WORD_ENDS, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::BackWordEnds($n))
+    };
+    (BACK_WORD_ENDS_EXT, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::BackWordEndsExt($n))
+    };
+    (PARAGRAPHS, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::Paragraphs($n))
+    };
+    (BACK_PARAGRAPHS, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::BackParagraphs($n))
+    };
+    (SENTENCES, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::Sentences($n))
+    };
+    (BACK_SENTENCES, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::BackSentences($n))
+    };
+    (FUNCTIONS, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::Functions($n))
+    };
+    (BACK_FUNCTIONS, $n:expr) => {
+        $crate::input::MotionOrObj::Object($crate::input::Object::BackFunctions($n))
+    };
 }
 
 macro_rules! act {
@@ -120,6 +687,17 @@ macro_rules! act {
 pub(crate) struct State {
     verb_count: String,
     mode: Mode,
+    register: Option<char>,
+    visual: Option<VisualMode>,
+    // Insert session being recorded, and the last complete change, for '.'
+    recording: Option<Change>,
+    last_change: Change,
+    repeating: bool,
+    recording_macro: bool,
+    // Last character found with f, t, F or T, for ';' and ','
+    last_find: Option<CharFind>,
+    // Surround waiting for the tag being typed in to the prompt
+    tag_surround: Option<SurroundEdit>,
 }
 
 impl State {
@@ -127,17 +705,66 @@ impl State {
         State {
             verb_count: String::new(),
             mode: Mode::Normal,
+            register: None,
+            visual: None,
+            recording: None,
+            last_change: Change::default(),
+            repeating: false,
+            recording_macro: false,
+            last_find: None,
+            tag_surround: None,
         }
     }
 
     pub(crate) fn set_normal_mode(&mut self) {
         self.mode = Mode::Normal;
+        self.visual = None;
+        if let Some(mut recording) = self.recording.take() {
+            recording.end = vec![Action::UpdateCursorStyle(CursorStyle::Block)];
+            self.last_change = recording;
+        }
+    }
+
+    pub(crate) fn is_input_mode(&self) -> bool {
+        self.mode == Mode::Input || self.mode == Mode::Replace
+    }
+
+    pub(crate) fn handle_input(&mut self, input: Input, actions: &mut Vec<Action>) {
+        match input {
+            Input::Key(key, md) => self.handle_key(key, md, actions),
+            Input::Char(c) => self.handle_char(c, actions),
+        }
+    }
+
+    fn handle_key(&mut self, key: Key, md: Modifiers, actions: &mut Vec<Action>) {
+        let start = actions.len();
+        let was_visual = self.visual.is_some();
+        self.key(key, md, actions);
+        self.record_change(&actions[start..], was_visual);
     }
 
-    pub(crate) fn handle_key(&mut self, key: Key, md: Modifiers, actions: &mut Vec<Action>) {
-        let _verb_count = self.verb_count.parse().unwrap_or(1);
+    fn handle_char(&mut self, c: char, actions: &mut Vec<Action>) {
+        let start = actions.len();
+        let was_visual = self.visual.is_some();
+        self.char(c, actions);
+        self.record_change(&actions[start..], was_visual);
+    }
+
+    fn key(&mut self, key: Key, md: Modifiers, actions: &mut Vec<Action>) {
+        let verb_count = self.verb_count.parse().unwrap_or(1);
         match self.mode {
             Mode::Normal => match key {
+                // Multiple cursors
+                Key::Up if md.contains(Modifiers::Control) => {
+                    actions.push(Action::AddCursor(false, verb_count))
+                }
+                Key::Down if md.contains(Modifiers::Control) => {
+                    actions.push(Action::AddCursor(true, verb_count))
+                }
+                Key::N if md.contains(Modifiers::Control) => {
+                    actions.push(Action::AddCursorAtWord(verb_count))
+                }
+                Key::Escape => actions.push(Action::ClearCursors),
                 // Basic movement
                 Key::Up => actions.push(act!(MOV, UP, 1)),
                 Key::Down => actions.push(act!(MOV, DOWN, 1)),
@@ -148,7 +775,102 @@ impl State {
                 Key::Home => actions.push(act!(MOV, LINE_START)),
                 Key::End => actions.push(act!(MOV, LINE_END)),
                 // Delete
-                Key::Delete => actions.push(act!(DEL, RIGHT, 1)),
+                Key::Delete => actions.push(Action::Operate(
+                    Operator::Delete,
+                    thing!(RIGHT, 1),
+                    self.register,
+                )),
+                // Redo
+                Key::R if md.contains(Modifiers::Control) => actions.push(Action::Redo(verb_count)),
+                // Jumplist
+                Key::O if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Jumplist(true, verb_count))
+                }
+                Key::I if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Jumplist(false, verb_count))
+                }
+                Key::Tab => actions.push(Action::Jumplist(false, verb_count)),
+                // Ctrl-^, which is Ctrl-6 on most keyboards
+                Key::Num6 if md.contains(Modifiers::Control) => {
+                    actions.push(Action::AlternateBuffer(self.count()))
+                }
+                // Panes
+                Key::W if md.contains(Modifiers::Control) => {
+                    self.mode = Mode::WindowPending(self.count())
+                }
+                // Scrolling
+                Key::D if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Scroll(Scroll::HalfPageDown, self.count()))
+                }
+                Key::U if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Scroll(Scroll::HalfPageUp, self.count()))
+                }
+                Key::F if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Scroll(Scroll::PageDown, self.count()))
+                }
+                Key::B if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Scroll(Scroll::PageUp, self.count()))
+                }
+                Key::PageDown => actions.push(Action::Scroll(Scroll::PageDown, self.count())),
+                Key::PageUp => actions.push(Action::Scroll(Scroll::PageUp, self.count())),
+                // Visual block mode
+                Key::V if md.contains(Modifiers::Control) => {
+                    self.toggle_visual(VisualMode::Block, actions)
+                }
+                // Numbers
+                Key::A if md.contains(Modifiers::Control) => {
+                    actions.push(Action::AddToNumber(verb_count as i64))
+                }
+                Key::X if md.contains(Modifiers::Control) => {
+                    actions.push(Action::AddToNumber(-(verb_count as i64)))
+                }
+                _ => return,
+            },
+            Mode::Visual => match key {
+                // Multiple cursors
+                Key::Up if md.contains(Modifiers::Control) => {
+                    actions.push(Action::AddCursor(false, verb_count))
+                }
+                Key::Down if md.contains(Modifiers::Control) => {
+                    actions.push(Action::AddCursor(true, verb_count))
+                }
+                Key::L if md.contains(Modifiers::Control) => {
+                    self.visual = None;
+                    self.mode = Mode::Normal;
+                    actions.push(Action::SplitSelection);
+                }
+                // Basic movement
+                Key::Up => actions.push(act!(MOV, UP, 1)),
+                Key::Down => actions.push(act!(MOV, DOWN, 1)),
+                Key::Left => actions.push(act!(MOV, LEFT, 1)),
+                Key::Right => actions.push(act!(MOV, RIGHT, 1)),
+                Key::Home => actions.push(act!(MOV, LINE_START)),
+                Key::End => actions.push(act!(MOV, LINE_END)),
+                // Delete
+                Key::Delete => self.operate_selection(Operator::Delete, actions),
+                // Scrolling
+                Key::D if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Scroll(Scroll::HalfPageDown, self.count()))
+                }
+                Key::U if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Scroll(Scroll::HalfPageUp, self.count()))
+                }
+                Key::F if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Scroll(Scroll::PageDown, self.count()))
+                }
+                Key::B if md.contains(Modifiers::Control) => {
+                    actions.push(Action::Scroll(Scroll::PageUp, self.count()))
+                }
+                Key::PageDown => actions.push(Action::Scroll(Scroll::PageDown, self.count())),
+                Key::PageUp => actions.push(Action::Scroll(Scroll::PageUp, self.count())),
+                Key::V if md.contains(Modifiers::Control) => {
+                    self.toggle_visual(VisualMode::Block, actions)
+                }
+                Key::Escape => {
+                    self.visual = None;
+                    self.mode = Mode::Normal;
+                    actions.push(Action::StopSelection);
+                }
                 _ => return,
             },
             Mode::Input => match key {
@@ -175,16 +897,86 @@ impl State {
                 }
                 _ => return,
             },
+            Mode::Replace => match key {
+                // Basic movement
+                Key::Up => actions.push(act!(MOV, UP, 1)),
+                Key::Down => actions.push(act!(MOV, DOWN, 1)),
+                Key::Left => actions.push(act!(MOV, LEFT, 1)),
+                Key::Right => actions.push(act!(MOV, RIGHT, 1)),
+                Key::Home => actions.push(act!(MOV, LINE_START)),
+                Key::End => actions.push(act!(MOV, LINE_END)),
+                // Line breaks are inserted rather than replacing anything
+                Key::Enter => actions.push(Action::ReplaceModeChar('\n')),
+                Key::Tab => actions.push(Action::ReplaceModeChar('\t')),
+                Key::Backspace => actions.push(Action::ReplaceModeBackspace),
+                // Exit replace mode
+                Key::Escape => {
+                    self.mode = Mode::Normal;
+                    actions.push(act!(MOV, LEFT, 1));
+                    actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
+                }
+                _ => return,
+            },
             Mode::GPressed(_) => match key {
                 Key::G => return,
                 _ => {
-                    self.mode = Mode::Normal;
+                    self.mode = self.idle_mode();
                     actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
                 }
             },
-            Mode::DPressed(_) => match key {
-                Key::Escape => {
+            Mode::ZPressed => match key {
+                Key::Enter => {
+                    self.mode = self.idle_mode();
+                    actions.push(act!(MOV, FIRST_NON_BLANK));
+                    actions.push(Action::ScrollCursor(ScreenPos::Top));
+                }
+                Key::Escape => self.mode = self.idle_mode(),
+                _ => return,
+            },
+            Mode::WindowPending(count) => {
+                let n = count.unwrap_or(1);
+                let window = match key {
+                    Key::W if md.contains(Modifiers::Control) => Window::Cycle(true, count),
+                    Key::P if md.contains(Modifiers::Control) => Window::Previous,
+                    Key::H if md.contains(Modifiers::Control) => Window::Focus(Direction::Left, n),
+                    Key::J if md.contains(Modifiers::Control) => Window::Focus(Direction::Down, n),
+                    Key::K if md.contains(Modifiers::Control) => Window::Focus(Direction::Up, n),
+                    Key::L if md.contains(Modifiers::Control) => Window::Focus(Direction::Right, n),
+                    Key::Left => Window::Focus(Direction::Left, n),
+                    Key::Down => Window::Focus(Direction::Down, n),
+                    Key::Up => Window::Focus(Direction::Up, n),
+                    Key::Right => Window::Focus(Direction::Right, n),
+                    Key::Escape => {
+                        self.mode = Mode::Normal;
+                        return;
+                    }
+                    _ => return,
+                };
+                self.mode = Mode::Normal;
+                actions.push(Action::Window(window));
+            }
+            Mode::ReplacePending(n) => match key {
+                Key::Enter => {
                     self.mode = Mode::Normal;
+                    actions.push(Action::ReplaceChar('\n', n));
+                }
+                Key::Escape => self.mode = Mode::Normal,
+                _ => return,
+            },
+            Mode::OperatorPending(..)
+            | Mode::OperatorGPressed(..)
+            | Mode::ObjectPending(..)
+            | Mode::BracketPressed(..)
+            | Mode::QuotePressed
+            | Mode::MarkPending
+            | Mode::MarkJumpPending(..)
+            | Mode::RecordPending
+            | Mode::MacroPending(_)
+            | Mode::FindPending(..)
+            | Mode::SurroundTargetPending(..)
+            | Mode::SurroundPending(_) => match key {
+                Key::Escape => {
+                    self.mode = self.idle_mode();
                     actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
                 }
                 _ => return,
@@ -202,42 +994,90 @@ impl State {
                 Key::Delete => actions.push(act!(DEL, RIGHT, 1)),
                 // Exit command
                 Key::Enter => {
-                    self.mode = Mode::Normal;
-                    actions.push(Action::GetCmd);
+                    self.mode = self.idle_mode();
+                    match self.tag_surround.take() {
+                        Some(edit) => actions.push(Action::SurroundTag(edit)),
+                        None => actions.push(Action::GetCmd),
+                    }
                     actions.push(Action::StopCmdPrompt);
                 }
                 Key::Escape => {
-                    self.mode = Mode::Normal;
+                    self.mode = self.idle_mode();
+                    self.tag_surround = None;
                     actions.push(Action::StopCmdPrompt);
                 }
                 _ => return,
             },
         }
         self.verb_count.clear();
+        if self.mode == Mode::Normal || self.mode == Mode::Visual {
+            self.register = None;
+        }
     }
 
-    pub(crate) fn handle_char(&mut self, c: char, actions: &mut Vec<Action>) {
+    fn char(&mut self, c: char, actions: &mut Vec<Action>) {
         let verb_count = self.verb_count.parse().unwrap_or(1);
+        let motion = self.motion(c, self.count());
         match self.mode {
+            // Basic movement and text object movement
+            Mode::Normal | Mode::Visual if motion.is_some() => {
+                actions.push(Action::Move(motion.unwrap()))
+            }
             Mode::Normal => match c {
-                // Basic movement
-                'h' => actions.push(act!(MOV, LEFT, verb_count)),
-                'j' => actions.push(act!(MOV, DOWN, verb_count)),
-                'k' => actions.push(act!(MOV, UP, verb_count)),
-                'l' => actions.push(act!(MOV, RIGHT, verb_count)),
-                '0' if self.verb_count.len() == 0 => actions.push(act!(MOV, LINE_START)),
-                '$' => actions.push(act!(MOV, LINE_END)),
-                'G' => actions.push(act!(MOV, TO_LINE, std::usize::MAX)),
-                // Text object movement
-                'w' => actions.push(act!(MOV, WORDS, verb_count)),
-                'W' => actions.push(act!(MOV, WORDS_EXT, verb_count)),
-                'b' => actions.push(act!(MOV, BACK_WORDS, verb_count)),
-                'B' => actions.push(act!(MOV, BACK_WORDS_EXT, verb_count)),
+                // Undo
+                'u' => actions.push(Action::Undo(verb_count)),
+                // Macros
+                'q' if self.recording_macro => {
+                    self.recording_macro = false;
+                    actions.push(Action::StopMacro);
+                }
+                'q' => {
+                    self.mode = Mode::RecordPending;
+                    return;
+                }
+                '@' => {
+                    self.mode = Mode::MacroPending(verb_count);
+                    return;
+                }
+                // Repeat the last change
+                '.' => {
+                    let count = self.verb_count.parse().ok();
+                    self.last_change.repeat(count, actions);
+                    self.repeating = true;
+                }
+                // Put
+                'p' => actions.push(Action::PutAfter(self.register, verb_count)),
+                'P' => actions.push(Action::PutBefore(self.register, verb_count)),
+                // Small edits
+                'x' => self.operate(Operator::Delete, Some(thing!(RIGHT, verb_count)), actions),
+                'X' => self.operate(Operator::Delete, Some(thing!(LEFT, verb_count)), actions),
+                's' => self.operate(Operator::Change, Some(thing!(RIGHT, verb_count)), actions),
+                'S' => self.operate(Operator::Change, Some(thing!(LINE, verb_count)), actions),
+                'C' => self.operate(Operator::Change, Some(thing!(LINE_END)), actions),
+                'D' => self.operate(Operator::Delete, Some(thing!(LINE_END)), actions),
+                'Y' => self.operate(Operator::Yank, Some(thing!(LINE, verb_count)), actions),
+                'J' => actions.push(Action::Join(true, verb_count)),
+                '~' => actions.push(Action::ToggleCase(verb_count)),
+                'r' => self.mode = Mode::ReplacePending(verb_count),
+                'R' => {
+                    self.mode = Mode::Replace;
+                    actions.push(Action::UpdateCursorStyle(CursorStyle::Line));
+                    actions.push(Action::StartReplace);
+                }
                 // Counts
                 c if c.is_ascii_digit() => {
                     self.verb_count.push(c);
                     return;
                 }
+                // Search
+                '/' | '?' => {
+                    self.mode = Mode::Command;
+                    actions.push(Action::StartCmdPrompt(c.to_string()));
+                }
+                'n' => actions.push(Action::SearchNext(false, verb_count)),
+                'N' => actions.push(Action::SearchNext(true, verb_count)),
+                '*' => actions.push(Action::SearchWord(true, verb_count)),
+                '#' => actions.push(Action::SearchWord(false, verb_count)),
                 // Enter insert mode. TODO: Proper handling of count
                 'i' => {
                     self.mode = Mode::Input;
@@ -261,90 +1101,738 @@ impl State {
                 'o' => {
                     self.mode = Mode::Input;
                     actions.push(Action::UpdateCursorStyle(CursorStyle::Line));
-                    actions.push(act!(MOV, LINE_END));
-                    actions.push(Action::InsertChar('\n'));
+                    actions.push(Action::OpenLine(false));
                 }
                 'O' => {
                     self.mode = Mode::Input;
                     actions.push(Action::UpdateCursorStyle(CursorStyle::Line));
-                    actions.push(act!(MOV, LINE_START));
-                    actions.push(Action::InsertChar('\n'));
-                    actions.push(act!(MOV, UP, 1));
+                    actions.push(Action::OpenLine(true));
                 }
                 // Go into other states
                 'g' => {
-                    self.mode = Mode::GPressed(verb_count);
+                    self.mode = Mode::GPressed(self.count());
                     actions.push(Action::UpdateCursorStyle(CursorStyle::Underline));
                 }
-                'd' => {
-                    self.mode = Mode::DPressed(verb_count);
-                    actions.push(Action::UpdateCursorStyle(CursorStyle::Underline));
+                // Operators
+                'd' => self.start_operator(Operator::Delete, self.count(), actions),
+                'c' => self.start_operator(Operator::Change, self.count(), actions),
+                'y' => self.start_operator(Operator::Yank, self.count(), actions),
+                '>' => self.start_operator(Operator::Indent, self.count(), actions),
+                '<' => self.start_operator(Operator::Outdent, self.count(), actions),
+                '=' => self.start_operator(Operator::Reindent, self.count(), actions),
+                '"' => {
+                    self.mode = Mode::QuotePressed;
+                    return;
                 }
+                ']' | '[' => self.mode = Mode::BracketPressed(None, verb_count, c == ']'),
+                'f' | 't' | 'F' | 'T' => self.find_char(None, verb_count, c),
+                'z' => self.mode = Mode::ZPressed,
+                // Marks
+                'm' => self.mode = Mode::MarkPending,
+                '\'' | '`' => self.mode = Mode::MarkJumpPending(None, c == '\''),
+                'v' => self.toggle_visual(VisualMode::Char, actions),
+                'V' => self.toggle_visual(VisualMode::Line, actions),
                 ':' => {
                     self.mode = Mode::Command;
                     actions.push(Action::StartCmdPrompt(":".to_owned()));
                 }
                 _ => return,
             },
-            Mode::Input => actions.push(Action::InsertChar(c)),
-            Mode::GPressed(n) => match c {
-                'g' => {
-                    self.mode = Mode::Normal;
-                    actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
-                    actions.push(act!(MOV, TO_LINE, n - 1));
+            Mode::Visual => match c {
+                // Counts
+                c if c.is_ascii_digit() => {
+                    self.verb_count.push(c);
+                    return;
                 }
-                _ => {
+                'v' => self.toggle_visual(VisualMode::Char, actions),
+                'V' => self.toggle_visual(VisualMode::Line, actions),
+                // Operate on selection
+                'd' | 'x' => self.operate_selection(Operator::Delete, actions),
+                'y' => self.operate_selection(Operator::Yank, actions),
+                '>' => self.operate_selection(Operator::Indent, actions),
+                '<' => self.operate_selection(Operator::Outdent, actions),
+                '=' => self.operate_selection(Operator::Reindent, actions),
+                'u' => self.operate_selection(Operator::Lowercase, actions),
+                'U' => self.operate_selection(Operator::Uppercase, actions),
+                '~' => self.operate_selection(Operator::ToggleCase, actions),
+                'c' | 's' => {
+                    // Switch cursor style first, so that the cursor can stay past the end of line
+                    actions.push(Action::UpdateCursorStyle(CursorStyle::Line));
+                    self.operate_selection(Operator::Change, actions);
+                    self.mode = Mode::Input;
+                }
+                // Search
+                '/' | '?' => {
+                    self.mode = Mode::Command;
+                    actions.push(Action::StartCmdPrompt(c.to_string()));
+                }
+                'n' => actions.push(Action::SearchNext(false, verb_count)),
+                'N' => actions.push(Action::SearchNext(true, verb_count)),
+                '*' => actions.push(Action::SearchWord(true, verb_count)),
+                '#' => actions.push(Action::SearchWord(false, verb_count)),
+                // Commands on the selected lines
+                ':' => {
+                    self.visual = None;
+                    self.mode = Mode::Command;
+                    actions.push(Action::StopSelection);
+                    actions.push(Action::StartCmdPrompt(":".to_owned()));
+                    actions.extend("'<,'>".chars().map(Action::InsertChar));
+                }
+                // Select text objects
+                'i' => self.mode = Mode::ObjectPending(None, verb_count, true),
+                'a' => self.mode = Mode::ObjectPending(None, verb_count, false),
+                ']' | '[' => self.mode = Mode::BracketPressed(None, verb_count, c == ']'),
+                'f' | 't' | 'F' | 'T' => self.find_char(None, verb_count, c),
+                'z' => self.mode = Mode::ZPressed,
+                '\'' | '`' => self.mode = Mode::MarkJumpPending(None, c == '\''),
+                'J' => {
+                    self.visual = None;
                     self.mode = Mode::Normal;
-                    actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
+                    actions.push(Action::JoinSelection(true));
+                }
+                // Go into other states
+                'g' => {
+                    self.mode = Mode::GPressed(self.count());
+                    actions.push(Action::UpdateCursorStyle(CursorStyle::Underline));
                 }
+                '"' => {
+                    self.mode = Mode::QuotePressed;
+                    return;
+                }
+                _ => return,
             },
-            Mode::DPressed(n) => {
-                match c {
-                    // Basic movement deletion
-                    'h' => actions.push(act!(DEL, LEFT, n * verb_count)),
-                    'j' => actions.push(act!(DEL, DOWN, n * verb_count)),
-                    'k' => actions.push(act!(DEL, UP, n * verb_count)),
-                    'l' => actions.push(act!(DEL, RIGHT, n * verb_count)),
-                    '0' if self.verb_count.len() == 0 => actions.push(act!(DEL, LINE_START)),
-                    '$' => actions.push(act!(DEL, LINE_END)),
-                    // Text object deletion
-                    'd' => {
+            Mode::Input => actions.push(Action::InsertChar(c)),
+            Mode::Replace => actions.push(Action::ReplaceModeChar(c)),
+            Mode::GPressed(count) => {
+                let n = count.unwrap_or(1);
+                let op = match c {
+                    'u' => Some(Operator::Lowercase),
+                    'U' => Some(Operator::Uppercase),
+                    '~' => Some(Operator::ToggleCase),
+                    'c' => Some(Operator::Comment),
+                    _ => None,
+                };
+                self.mode = self.idle_mode();
+                actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
+                match op {
+                    Some(op) if self.visual.is_some() => self.operate_selection(op, actions),
+                    Some(op) => self.start_operator(op, count, actions),
+                    None if c == 'g' => actions.push(act!(MOV, TO_LINE, n - 1)),
+                    None if c == 'J' && self.visual.is_some() => {
+                        self.visual = None;
                         self.mode = Mode::Normal;
-                        actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
-                        actions.push(act!(DEL, LINE, n * verb_count));
+                        actions.push(Action::JoinSelection(false));
                     }
-                    'w' => {
-                        self.mode = Mode::Normal;
-                        actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
-                        actions.push(act!(DEL, WORDS, n * verb_count));
+                    None if c == 'J' => actions.push(Action::Join(false, n)),
+                    None if c == 'j' => actions.push(act!(MOV, ROW_DOWN, n)),
+                    None if c == 'k' => actions.push(act!(MOV, ROW_UP, n)),
+                    None if c == 'e' => actions.push(act!(MOV, BACK_WORD_ENDS, n)),
+                    None if c == 'E' => actions.push(act!(MOV, BACK_WORD_ENDS_EXT, n)),
+                    None if c == ';' => actions.push(Action::Changelist(true, n)),
+                    None if c == ',' => actions.push(Action::Changelist(false, n)),
+                    None if c == 't' && self.visual.is_none() => {
+                        actions.push(Action::NextTab(count))
                     }
-                    'W' => {
-                        self.mode = Mode::Normal;
-                        actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
-                        actions.push(act!(DEL, WORDS_EXT, n * verb_count));
+                    None if c == 'T' && self.visual.is_none() => actions.push(Action::PrevTab(n)),
+                    None => {}
+                }
+            }
+            Mode::OperatorPending(op, count) => {
+                // G and % do something else without any count
+                let count = match (count, self.count()) {
+                    (None, None) => None,
+                    (a, b) => Some(a.unwrap_or(1) * b.unwrap_or(1)),
+                };
+                let n = count.unwrap_or(1);
+                let mo = match c {
+                    // Counts after the operator multiply the one before it
+                    c if c.is_ascii_digit() && (c != '0' || !self.verb_count.is_empty()) => {
+                        self.verb_count.push(c);
+                        return;
                     }
-                    'b' => {
-                        self.mode = Mode::Normal;
-                        actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
-                        actions.push(act!(DEL, BACK_WORDS, n * verb_count));
+                    'g' => {
+                        self.mode = Mode::OperatorGPressed(op, n);
+                        self.verb_count.clear();
+                        return;
                     }
-                    'B' => {
-                        self.mode = Mode::Normal;
-                        actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
-                        actions.push(act!(DEL, BACK_WORDS_EXT, n * verb_count));
+                    'i' | 'a' => {
+                        self.mode = Mode::ObjectPending(Some(op), n, c == 'i');
+                        self.verb_count.clear();
+                        return;
                     }
-                    // Counts
-                    c if c.is_ascii_digit() => {
-                        self.verb_count.push(c);
+                    ']' | '[' => {
+                        self.mode = Mode::BracketPressed(Some(op), n, c == ']');
+                        self.verb_count.clear();
                         return;
                     }
-                    _ => {}
+                    '\'' | '`' => {
+                        self.mode = Mode::MarkJumpPending(Some(op), c == '\'');
+                        self.verb_count.clear();
+                        return;
+                    }
+                    'f' | 't' | 'F' | 'T' => {
+                        self.find_char(Some(op), n, c);
+                        self.verb_count.clear();
+                        return;
+                    }
+                    // Surround
+                    's' if op == Operator::Yank => {
+                        self.mode = Mode::OperatorPending(Operator::Surround, count);
+                        self.verb_count.clear();
+                        return;
+                    }
+                    's' if op == Operator::Change || op == Operator::Delete => {
+                        self.mode = Mode::SurroundTargetPending(n, op == Operator::Change);
+                        self.verb_count.clear();
+                        return;
+                    }
+                    c if c == op.line_key() => Some(thing!(LINE, n)),
+                    c => self.motion(c, count),
+                };
+                self.operate(op, mo, actions);
+            }
+            Mode::OperatorGPressed(op, n) => {
+                let mo = match c {
+                    'g' => Some(thing!(TO_LINE, n - 1)),
+                    'j' => Some(thing!(ROW_DOWN, n)),
+                    'k' => Some(thing!(ROW_UP, n)),
+                    'e' => Some(thing!(BACK_WORD_ENDS, n)),
+                    'E' => Some(thing!(BACK_WORD_ENDS_EXT, n)),
+                    c if c == op.line_key() => Some(thing!(LINE, n)),
+                    _ => None,
+                };
+                self.operate(op, mo, actions);
+            }
+            Mode::ObjectPending(op, n, inner) => {
+                let mo = Self::text_object(c).map(|obj| {
+                    if inner {
+                        MotionOrObj::Object(Object::Inner(obj, n))
+                    } else {
+                        MotionOrObj::Object(Object::Around(obj, n))
+                    }
+                });
+                match op {
+                    Some(op) => self.operate(op, mo, actions),
+                    None => {
+                        // Extend the selection to cover the object
+                        self.mode = Mode::Visual;
+                        if let Some(mo) = mo {
+                            actions.push(Action::Move(mo));
+                        }
+                    }
+                }
+            }
+            Mode::BracketPressed(op, n, forward) => {
+                let mo = match c {
+                    'm' if forward => Some(thing!(FUNCTIONS, n)),
+                    'm' => Some(thing!(BACK_FUNCTIONS, n)),
+                    _ => None,
+                };
+                match op {
+                    Some(op) => self.operate(op, mo, actions),
+                    None => {
+                        self.mode = self.idle_mode();
+                        if let Some(mo) = mo {
+                            actions.push(Action::Move(mo));
+                        }
+                    }
                 }
+            }
+            Mode::QuotePressed => {
+                self.mode = self.idle_mode();
+                if Registers::is_valid(c) {
+                    // Keep the count, so that both 3"ayy and "a3yy work
+                    self.register = Some(c);
+                    return;
+                }
+            }
+            Mode::MarkPending => {
+                self.mode = Mode::Normal;
+                actions.push(Action::SetMark(c));
+            }
+            Mode::MarkJumpPending(op, linewise) => {
+                let mo = MotionOrObj::Motion(Motion::Mark(c, linewise));
+                match op {
+                    Some(op) => self.operate(op, Some(mo), actions),
+                    None => {
+                        self.mode = self.idle_mode();
+                        actions.push(Action::Move(mo));
+                    }
+                }
+            }
+            Mode::FindPending(op, n, forward, till) => {
+                let find = CharFind { c, forward, till };
+                self.last_find = Some(find);
+                let mo = MotionOrObj::Motion(Motion::FindChar(find, n, false));
+                match op {
+                    Some(op) => self.operate(op, Some(mo), actions),
+                    None => {
+                        self.mode = self.idle_mode();
+                        actions.push(Action::Move(mo));
+                    }
+                }
+            }
+            Mode::ZPressed => {
+                self.mode = self.idle_mode();
+                let fold = match c {
+                    'o' => Some(Fold::Open),
+                    'c' => Some(Fold::Close),
+                    'a' => Some(Fold::Toggle),
+                    'R' => Some(Fold::OpenAll),
+                    'M' => Some(Fold::CloseAll),
+                    _ => None,
+                };
+                let pos = match c {
+                    't' => Some(ScreenPos::Top),
+                    'z' | '.' => Some(ScreenPos::Middle),
+                    'b' | '-' => Some(ScreenPos::Bottom),
+                    _ => None,
+                };
+                if c == 'f' && self.visual.is_some() {
+                    self.operate_selection(Operator::Fold, actions);
+                } else if c == 'f' {
+                    self.start_operator(Operator::Fold, None, actions);
+                } else if let Some(fold) = fold {
+                    actions.push(Action::Fold(fold));
+                } else if let Some(pos) = pos {
+                    if c == '.' || c == '-' {
+                        actions.push(act!(MOV, FIRST_NON_BLANK));
+                    }
+                    actions.push(Action::ScrollCursor(pos));
+                }
+            }
+            Mode::ReplacePending(n) => {
+                self.mode = Mode::Normal;
+                actions.push(Action::ReplaceChar(c, n));
+            }
+            Mode::WindowPending(count) => {
+                self.mode = Mode::Normal;
+                let n = count.unwrap_or(1);
+                let window = match c {
+                    'h' => Window::Focus(Direction::Left, n),
+                    'j' => Window::Focus(Direction::Down, n),
+                    'k' => Window::Focus(Direction::Up, n),
+                    'l' => Window::Focus(Direction::Right, n),
+                    'w' => Window::Cycle(true, count),
+                    'W' => Window::Cycle(false, count),
+                    'p' => Window::Previous,
+                    'x' => Window::Exchange(count),
+                    'r' => Window::Rotate(true, n),
+                    'R' => Window::Rotate(false, n),
+                    '=' => Window::Equalize,
+                    '+' => Window::Resize(true, n as i32),
+                    '-' => Window::Resize(true, -(n as i32)),
+                    '>' => Window::Resize(false, n as i32),
+                    '<' => Window::Resize(false, -(n as i32)),
+                    'H' => Window::MoveToEdge(Direction::Left),
+                    'J' => Window::MoveToEdge(Direction::Down),
+                    'K' => Window::MoveToEdge(Direction::Up),
+                    'L' => Window::MoveToEdge(Direction::Right),
+                    's' | 'S' => Window::Split(false),
+                    'v' => Window::Split(true),
+                    'c' => Window::Close,
+                    'o' => Window::Only,
+                    _ => return,
+                };
+                actions.push(Action::Window(window));
+            }
+            Mode::SurroundTargetPending(count, change) => {
                 self.mode = Mode::Normal;
                 actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
+                let obj = match Self::text_object(c) {
+                    Some(obj @ TextObject::Bracket(..))
+                    | Some(obj @ TextObject::Quote(_))
+                    | Some(obj @ TextObject::Tag) => obj,
+                    _ => return,
+                };
+                let trim = "([{<".contains(c);
+                let target = SurroundTarget { obj, count, trim };
+                if change {
+                    self.mode = Mode::SurroundPending(SurroundEdit::Change(target));
+                    actions.push(Action::UpdateCursorStyle(CursorStyle::Underline));
+                } else {
+                    actions.push(Action::DeleteSurround(target));
+                }
+            }
+            Mode::SurroundPending(edit) => {
+                self.mode = Mode::Normal;
+                actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
+                if c == 't' || c == '<' {
+                    self.tag_surround = Some(edit);
+                    self.mode = Mode::Command;
+                    actions.push(Action::StartCmdPrompt("<".to_owned()));
+                } else if let Some(surround) = Self::surround_pair(c) {
+                    actions.push(Action::Surround(edit, surround));
+                }
+            }
+            Mode::RecordPending => {
+                self.mode = Mode::Normal;
+                if c.is_ascii_alphabetic() {
+                    self.recording_macro = true;
+                    actions.push(Action::RecordMacro(c));
+                }
+            }
+            Mode::MacroPending(n) => {
+                self.mode = Mode::Normal;
+                match c {
+                    '@' => actions.push(Action::RunMacro(None, n)),
+                    c if c.is_ascii_alphabetic() => actions.push(Action::RunMacro(Some(c), n)),
+                    _ => {}
+                }
             }
             Mode::Command => actions.push(Action::InsertChar(c)),
         }
         self.verb_count.clear();
+        if self.mode == Mode::Normal || self.mode == Mode::Visual {
+            self.register = None;
+        }
+    }
+
+    // Remember the actions of a change made from normal mode, so that it can be repeated. An
+    // insert session is recorded until it ends, along with the command which started it
+    fn record_change(&mut self, actions: &[Action], was_visual: bool) {
+        if self.repeating {
+            self.repeating = false;
+            return;
+        }
+        let actions = actions
+            .iter()
+            .filter(|a| !matches!(a, Action::Completion(_)))
+            .cloned();
+        let input_mode = self.is_input_mode();
+        if let Some(recording) = &mut self.recording {
+            if input_mode {
+                recording.inserted.extend(actions);
+            } else {
+                recording.end.extend(actions);
+                self.last_change = self.recording.take().unwrap();
+            }
+        } else if !was_visual {
+            let start: Vec<_> = actions.collect();
+            if input_mode {
+                self.recording = Some(Change {
+                    start,
+                    ..Change::default()
+                });
+            } else if start.iter().any(Action::is_change) {
+                self.last_change = Change {
+                    start,
+                    ..Change::default()
+                };
+            }
+        }
+    }
+
+    // Motions which work the same in normal mode, visual mode and after an operator
+    fn motion(&self, c: char, count: Option<usize>) -> Option<MotionOrObj> {
+        let n = count.unwrap_or(1);
+        match c {
+            // Basic movement
+            'h' => Some(thing!(LEFT, n)),
+            'j' => Some(thing!(DOWN, n)),
+            'k' => Some(thing!(UP, n)),
+            'l' => Some(thing!(RIGHT, n)),
+            '0' if self.verb_count.is_empty() => Some(thing!(LINE_START)),
+            '$' => Some(thing!(LINE_END)),
+            '^' => Some(thing!(FIRST_NON_BLANK)),
+            'G' if count.is_none() => Some(thing!(TO_LINE, usize::MAX)),
+            'G' => Some(thing!(TO_LINE, n - 1)),
+            // Text object movement
+            'w' => Some(thing!(WORDS, n)),
+            'W' => Some(thing!(WORDS_EXT, n)),
+            'b' => Some(thing!(BACK_WORDS, n)),
+            'B' => Some(thing!(BACK_WORDS_EXT, n)),
+            'e' => Some(thing!(WORD_ENDS, n)),
+            'E' => Some(thing!(WORD_ENDS_EXT, n)),
+            '}' => Some(thing!(PARAGRAPHS, n)),
+            '{' => Some(thing!(BACK_PARAGRAPHS, n)),
+            ')' => Some(thing!(SENTENCES, n)),
+            '(' => Some(thing!(BACK_SENTENCES, n)),
+            // Brackets, or with a count up to 100, a percentage of the buffer
+            '%' if count.is_none() => Some(MotionOrObj::Motion(Motion::MatchBracket)),
+            '%' if n <= 100 => Some(MotionOrObj::Motion(Motion::Percent(n))),
+            // Lines of the view
+            'H' => Some(thing!(SCREEN_LINE, Top, n)),
+            'M' => Some(thing!(SCREEN_LINE, Middle, n)),
+            'L' => Some(thing!(SCREEN_LINE, Bottom, n)),
+            // Repeat the last f, t, F or T, the other way for ','
+            ';' | ',' => self.last_find.map(|find| {
+                let forward = find.forward == (c == ';');
+                MotionOrObj::Motion(Motion::FindChar(CharFind { forward, ..find }, n, true))
+            }),
+            _ => None,
+        }
+    }
+
+    fn text_object(c: char) -> Option<TextObject> {
+        match c {
+            'w' => Some(TextObject::Word),
+            'W' => Some(TextObject::WordExt),
+            's' => Some(TextObject::Sentence),
+            'p' => Some(TextObject::Paragraph),
+            '(' | ')' | 'b' => Some(TextObject::Bracket('(', ')')),
+            '[' | ']' => Some(TextObject::Bracket('[', ']')),
+            '{' | '}' | 'B' => Some(TextObject::Bracket('{', '}')),
+            '<' | '>' => Some(TextObject::Bracket('<', '>')),
+            '"' | '\'' | '`' => Some(TextObject::Quote(c)),
+            't' => Some(TextObject::Tag),
+            'f' => Some(TextObject::Function),
+            'c' => Some(TextObject::Class),
+            'a' => Some(TextObject::Parameter),
+            'o' => Some(TextObject::Block),
+            _ => None,
+        }
+    }
+
+    // Pair to surround text with. Opening brackets put spaces inside the pair, and other
+    // punctuation goes on both sides
+    fn surround_pair(c: char) -> Option<Surround> {
+        match c {
+            '(' => Some(Surround::Pair('(', ')', true)),
+            ')' | 'b' => Some(Surround::Pair('(', ')', false)),
+            '[' => Some(Surround::Pair('[', ']', true)),
+            ']' => Some(Surround::Pair('[', ']', false)),
+            '{' => Some(Surround::Pair('{', '}', true)),
+            '}' | 'B' => Some(Surround::Pair('{', '}', false)),
+            '>' => Some(Surround::Pair('<', '>', false)),
+            c if c.is_ascii_punctuation() => Some(Surround::Pair(c, c, false)),
+            _ => None,
+        }
+    }
+
+    // Mode to return to once a multi-key command is done
+    fn idle_mode(&self) -> Mode {
+        if self.visual.is_some() {
+            Mode::Visual
+        } else {
+            Mode::Normal
+        }
+    }
+
+    // Enter visual mode, switch to another kind of visual mode, or leave it if we're already in
+    // the given kind
+    fn toggle_visual(&mut self, vmode: VisualMode, actions: &mut Vec<Action>) {
+        if self.visual == Some(vmode) {
+            self.visual = None;
+            self.mode = Mode::Normal;
+            actions.push(Action::StopSelection);
+        } else {
+            self.visual = Some(vmode);
+            self.mode = Mode::Visual;
+            actions.push(Action::StartSelection(vmode));
+        }
+    }
+
+    fn find_char(&mut self, op: Option<Operator>, count: usize, c: char) {
+        let forward = c == 'f' || c == 't';
+        let till = c == 't' || c == 'T';
+        self.mode = Mode::FindPending(op, count, forward, till);
+    }
+
+    // Count typed before a command which has a default other than 1
+    fn count(&self) -> Option<usize> {
+        self.verb_count.parse().ok()
+    }
+
+    fn start_operator(&mut self, op: Operator, count: Option<usize>, actions: &mut Vec<Action>) {
+        self.mode = Mode::OperatorPending(op, count);
+        actions.push(Action::UpdateCursorStyle(CursorStyle::Underline));
+    }
+
+    // Finish an operator. Nothing happens if the motion was invalid
+    fn operate(&mut self, op: Operator, mo: Option<MotionOrObj>, actions: &mut Vec<Action>) {
+        match mo {
+            Some(mo) if op == Operator::Surround => {
+                self.mode = Mode::SurroundPending(SurroundEdit::Add(mo));
+            }
+            Some(mo) if op == Operator::Change => {
+                // Switch cursor style first, so that the cursor can stay past the end of line
+                actions.push(Action::UpdateCursorStyle(CursorStyle::Line));
+                actions.push(Action::Operate(op, mo, self.register));
+                self.mode = Mode::Input;
+            }
+            Some(mo) => {
+                actions.push(Action::Operate(op, mo, self.register));
+                actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
+                self.mode = Mode::Normal;
+            }
+            None => {
+                actions.push(Action::UpdateCursorStyle(CursorStyle::Block));
+                self.mode = Mode::Normal;
+            }
+        }
+    }
+
+    fn operate_selection(&mut self, op: Operator, actions: &mut Vec<Action>) {
+        self.visual = None;
+        self.mode = Mode::Normal;
+        actions.push(Action::OperateSelection(op, self.register));
+    }
+}
+
+// A change which can be repeated with '.'. For insert sessions, the actions of the command which
+// started the session and of leaving it are kept apart from those typed in insert mode
+#[derive(Default)]
+struct Change {
+    start: Vec<Action>,
+    inserted: Vec<Action>,
+    end: Vec<Action>,
+}
+
+impl Change {
+    // A count replaces the count of the operator or put, also for later repeats. Otherwise the
+    // inserted text is repeated
+    fn repeat(&mut self, count: Option<usize>, actions: &mut Vec<Action>) {
+        let mut times = 1;
+        if let Some(count) = count {
+            if !self.start.iter_mut().any(|a| a.set_count(count)) {
+                times = count;
+            }
+        }
+        actions.extend(self.start.iter().cloned());
+        for _ in 0..times {
+            actions.extend(self.inserted.iter().cloned());
+        }
+        actions.extend(self.end.iter().cloned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(state: &mut State, text: &str) -> Vec<Action> {
+        let mut actions = Vec::new();
+        for input in Input::parse_all(text) {
+            state.handle_input(input, &mut actions);
+        }
+        actions
+    }
+
+    fn operated(actions: &[Action]) -> Vec<(Operator, MotionOrObj)> {
+        actions
+            .iter()
+            .filter_map(|a| match a {
+                Action::Operate(op, mo, _) => Some((*op, *mo)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn inserted(actions: &[Action]) -> String {
+        actions
+            .iter()
+            .filter_map(|a| match a {
+                Action::InsertChar(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_repeat_change() {
+        let mut state = State::new();
+        let delete = |mo| vec![(Operator::Delete, mo)];
+        let change = |mo| vec![(Operator::Change, mo)];
+        feed(&mut state, "dw");
+        assert!(operated(&feed(&mut state, ".")) == delete(thing!(WORDS, 1)));
+        // The count replaces the operator's count, also for later repeats
+        assert!(operated(&feed(&mut state, "3.")) == delete(thing!(WORDS, 3)));
+        assert!(operated(&feed(&mut state, ".")) == delete(thing!(WORDS, 3)));
+        // Moving and yanking aren't changes
+        feed(&mut state, "jyy");
+        assert!(operated(&feed(&mut state, ".")) == delete(thing!(WORDS, 3)));
+        // Insert sessions are repeated with the text typed in them
+        feed(&mut state, "cwab<Esc>");
+        let actions = feed(&mut state, ".");
+        assert!(operated(&actions) == change(thing!(WORDS, 1)));
+        assert_eq!(inserted(&actions), "ab");
+        assert!(matches!(
+            actions.last(),
+            Some(Action::UpdateCursorStyle(CursorStyle::Block))
+        ));
+        let actions = feed(&mut state, "2.");
+        assert!(operated(&actions) == change(thing!(WORDS, 2)));
+        assert_eq!(inserted(&actions), "ab");
+        // Without a count in the change, the inserted text is repeated
+        feed(&mut state, "ixy<Esc>");
+        let actions = feed(&mut state, "3.");
+        assert!(operated(&actions).is_empty());
+        assert_eq!(inserted(&actions), "xyxyxy");
+        // Changes in visual mode aren't recorded
+        feed(&mut state, "vjd");
+        assert_eq!(inserted(&feed(&mut state, ".")), "xy");
+    }
+
+    #[test]
+    fn test_repeat_after_repeat() {
+        let mut state = State::new();
+        let delete = |mo| vec![(Operator::Delete, mo)];
+        let change = |mo| vec![(Operator::Change, mo)];
+        let actions = feed(&mut state, "x.");
+        assert!(
+            operated(&actions) == [delete(thing!(RIGHT, 1)), delete(thing!(RIGHT, 1))].concat()
+        );
+        assert!(operated(&feed(&mut state, ".")) == delete(thing!(RIGHT, 1)));
+        // The change after a repeat is recorded
+        feed(&mut state, "dd");
+        assert!(operated(&feed(&mut state, ".")) == delete(thing!(LINE, 1)));
+        // A change with an insert session, followed by a repeat
+        let actions = feed(&mut state, "cwab<Esc>j.");
+        assert!(
+            operated(&actions) == [change(thing!(WORDS, 1)), change(thing!(WORDS, 1))].concat()
+        );
+        assert_eq!(inserted(&actions), "abab");
+        let actions = feed(&mut state, ".");
+        assert!(operated(&actions) == change(thing!(WORDS, 1)));
+        assert_eq!(inserted(&actions), "ab");
+    }
+
+    #[test]
+    fn test_operator_count() {
+        let mut state = State::new();
+        let delete = |mo| vec![(Operator::Delete, mo)];
+        assert!(operated(&feed(&mut state, "dG")) == delete(thing!(TO_LINE, usize::MAX)));
+        assert!(operated(&feed(&mut state, "2dG")) == delete(thing!(TO_LINE, 1)));
+        assert!(operated(&feed(&mut state, "d2G")) == delete(thing!(TO_LINE, 1)));
+        assert!(
+            operated(&feed(&mut state, "2d%")) == delete(MotionOrObj::Motion(Motion::Percent(2)))
+        );
+        assert!(
+            operated(&feed(&mut state, "d%")) == delete(MotionOrObj::Motion(Motion::MatchBracket))
+        );
+        assert!(operated(&feed(&mut state, "2d3w")) == delete(thing!(WORDS, 6)));
+        // Percentages above 100 aren't motions
+        assert!(operated(&feed(&mut state, "d101%")).is_empty());
+        assert!(feed(&mut state, "99999999999999%").is_empty());
+    }
+
+    #[test]
+    fn test_macro_text() {
+        let inputs = vec![
+            Input::Char('i'),
+            Input::Char('<'),
+            Input::Key(Key::Escape, Modifiers::empty()),
+            Input::Key(Key::R, Modifiers::Control),
+            Input::Key(Key::Enter, Modifiers::Shift),
+        ];
+        let mut text = String::new();
+        for input in &inputs {
+            input.write(&mut text);
+        }
+        // Letter keys without modifiers also come as characters
+        Input::Key(Key::J, Modifiers::empty()).write(&mut text);
+        assert_eq!(text, "i<lt><Esc><C-r><S-CR>");
+        assert_eq!(Input::parse_all(&text), inputs);
+        assert_eq!(
+            Input::parse_all("a<b>\n"),
+            vec![
+                Input::Char('a'),
+                Input::Char('<'),
+                Input::Char('b'),
+                Input::Char('>'),
+                Input::Key(Key::Enter, Modifiers::empty()),
+            ]
+        );
     }
 }
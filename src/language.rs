@@ -28,6 +28,49 @@ impl Language {
             Language::Rust => "rust",
         }
     }
+
+    pub(crate) fn comment_tokens(&self) -> CommentTokens {
+        let (line, block) = match self {
+            Language::C | Language::Cpp | Language::JavaScript | Language::Rust => {
+                (Some("//"), Some(("/*", "*/")))
+            }
+            Language::CSS => (None, Some(("/*", "*/"))),
+            Language::HTML => (None, Some(("<!--", "-->"))),
+            Language::Python => (Some("#"), None),
+        };
+        CommentTokens {
+            line: line.map(|s| s.to_owned()),
+            block: block.map(|(start, end)| (start.to_owned(), end.to_owned())),
+        }
+    }
+
+    // Language of the text in a syntax node of this language, for languages embedded in others
+    pub(crate) fn embedded_language(&self, kind: &str) -> Option<Language> {
+        match (self, kind) {
+            (Language::HTML, "script_element") => Some(Language::JavaScript),
+            (Language::HTML, "style_element") => Some(Language::CSS),
+            _ => None,
+        }
+    }
+
+    // Characters which re-indent the line they are typed on. The first set does so when typed
+    // at the start of the line. The second set only moves a line which doesn't parse where it
+    // is back to where it does, like Python's else:
+    pub(crate) fn indent_triggers(&self) -> (&'static str, &'static str) {
+        match self {
+            Language::HTML => ("", ""),
+            Language::Python => (")]}", ":"),
+            _ => (")]}", ""),
+        }
+    }
+}
+
+// Tokens for commenting out lines. Languages without line comments have each line wrapped in a
+// block comment instead
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct CommentTokens {
+    pub(crate) line: Option<String>,
+    pub(crate) block: Option<(String, String)>,
 }
 
 impl fmt::Display for Language {
@@ -1,9 +1,10 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
 use super::jsonrpc::Id;
-use super::types::{Hover, PublishDiagnosticParams};
+use super::types::{FoldingRange, Hover, PublishDiagnosticParams};
 
 pub(crate) enum LanguageServerResponse {
     Diagnostic(PublishDiagnosticParams),
     Hover(Id, String, Hover),
+    FoldingRange(Id, String, Vec<FoldingRange>),
 }
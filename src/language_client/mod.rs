@@ -27,7 +27,8 @@ pub(crate) use api::LanguageServerResponse;
 pub(crate) use jsonrpc::Id;
 pub(crate) use types::{
     Diagnostic, DiagnosticCode, DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag,
-    Hover, HoverContents, MarkedString, MarkupKind, Position, PublishDiagnosticParams, Range,
+    FoldingRange, Hover, HoverContents, MarkedString, MarkupKind, Position,
+    PublishDiagnosticParams, Range,
 };
 
 use jsonrpc::{Message, MessageContent};
@@ -227,7 +228,7 @@ impl LanguageClient {
             .unwrap();
     }
 
-    pub(crate) fn change(&mut self, path: &str, version: usize, range: Range, text: String) {
+    pub(crate) fn change(&mut self, path: &str, version: usize, changes: Vec<(Range, String)>) {
         let inner = &mut *self.inner.borrow_mut();
         let uri = uri::Uri::from_path(path).expect("failed to parse path URI");
         let version = Some(version);
@@ -239,10 +240,13 @@ impl LanguageClient {
                     params: Some(
                         serde_json::to_value(DidChangeTextDocumentParams {
                             textDocument: VersionedTextDocumentIdentifier { uri, version },
-                            contentChanges: vec![TextDocumentContentChangeEvent::Ranged {
-                                range,
-                                text,
-                            }],
+                            contentChanges: changes
+                                .into_iter()
+                                .map(|(range, text)| TextDocumentContentChangeEvent::Ranged {
+                                    range,
+                                    text,
+                                })
+                                .collect(),
                         })
                         .unwrap(),
                     ),
@@ -321,6 +325,42 @@ impl LanguageClient {
             .unwrap();
         Some(id)
     }
+
+    pub(crate) fn folding_range(&mut self, path: &str) -> Option<Id> {
+        let inner = &mut *self.inner.borrow_mut();
+        {
+            let mut sync_state = inner.sync_state.lock().unwrap();
+            // Until the server says it has folding ranges, folds come from tree-sitter
+            match &sync_state.server_capabilities {
+                Some(cap) if cap.folding_range_provider() => {}
+                _ => return None,
+            }
+            sync_state.id_method_map.insert(
+                Id::Num(inner.next_id),
+                "textDocument/foldingRange".to_owned(),
+            );
+            sync_state
+                .id_path_map
+                .insert(Id::Num(inner.next_id), path.to_owned());
+        };
+        let id = Id::Num(inner.next_id);
+        inner.next_id += 1;
+        let uri = uri::Uri::from_path(path).expect("failed to parse path URI");
+        inner
+            .wmsg_tx
+            .send(WriterMessage::Message(Message::new(MessageContent::Call {
+                id: id.clone(),
+                method: "textDocument/foldingRange".to_owned(),
+                params: Some(
+                    serde_json::to_value(FoldingRangeParams {
+                        textDocument: TextDocumentIdentifier { uri },
+                    })
+                    .unwrap(),
+                ),
+            })))
+            .unwrap();
+        Some(id)
+    }
 }
 
 struct LanguageClientInner {
@@ -429,6 +469,10 @@ impl LanguageClientInner {
                                     }),
                                     versionSupport: Some(true),
                                 }),
+                                foldingRange: Some(FoldingRangeClientCapabilities {
+                                    dynamicRegistration: Some(false),
+                                    lineFoldingOnly: Some(true),
+                                }),
                             }),
                         },
                     })
@@ -566,6 +610,21 @@ fn language_client_reader(
                                         }
                                     }
                                 }
+                                "textDocument/foldingRange" => {
+                                    let path = locked_state
+                                        .id_path_map
+                                        .remove(&id)
+                                        .expect("textDocument/foldingRange without path");
+                                    if let Ok(ranges) =
+                                        serde_json::from_value::<Vec<FoldingRange>>(result)
+                                    {
+                                        api_tx
+                                            .send(LanguageServerResponse::FoldingRange(
+                                                id, path, ranges,
+                                            ))
+                                            .unwrap();
+                                    }
+                                }
                                 _ => {
                                     debug!(
                                         "raw_message: {}",
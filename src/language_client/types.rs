@@ -160,6 +160,8 @@ pub(super) struct TextDocumentClientCapabilities {
     pub(super) hover: Option<HoverClientCapabilities>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(super) publishDiagnostics: Option<PublishDiagnosticsClientCapabilities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) foldingRange: Option<FoldingRangeClientCapabilities>,
 }
 
 #[derive(Debug, Serialize)]
@@ -184,6 +186,15 @@ pub(super) struct HoverClientCapabilities {
     pub(super) contentFormat: Option<Vec<MarkupKind>>,
 }
 
+#[derive(Debug, Serialize)]
+#[allow(non_snake_case)]
+pub(super) struct FoldingRangeClientCapabilities {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) dynamicRegistration: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) lineFoldingOnly: Option<bool>,
+}
+
 #[derive(Debug, Serialize)]
 #[allow(non_snake_case)]
 pub(super) struct ClientCapabilities {
@@ -204,6 +215,8 @@ pub(super) struct ServerCapabilities {
     pub(super) textDocumentSync: ServerTextDocumentSync,
     #[serde(default)]
     pub(super) hoverProvider: ServerHoverProvider,
+    #[serde(default)]
+    pub(super) foldingRangeProvider: ServerFoldingRangeProvider,
 }
 
 impl ServerCapabilities {
@@ -245,6 +258,13 @@ impl ServerCapabilities {
             _ => true,
         }
     }
+
+    pub(super) fn folding_range_provider(&self) -> bool {
+        match self.foldingRangeProvider {
+            ServerFoldingRangeProvider::Bool(b) => b,
+            _ => true,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -264,6 +284,20 @@ impl Default for ServerHoverProvider {
     }
 }
 
+// The options don't have anything which is used, other than there being folding ranges
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(super) enum ServerFoldingRangeProvider {
+    Bool(bool),
+    Options {},
+}
+
+impl Default for ServerFoldingRangeProvider {
+    fn default() -> ServerFoldingRangeProvider {
+        ServerFoldingRangeProvider::Bool(false)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
 #[serde(try_from = "u8")]
 pub(super) enum TextDocumentSyncKind {
@@ -407,6 +441,21 @@ pub(crate) struct HoverParams {
     pub(crate) position: Position,
 }
 
+#[derive(Debug, Serialize)]
+#[allow(non_snake_case)]
+pub(crate) struct FoldingRangeParams {
+    pub(crate) textDocument: TextDocumentIdentifier,
+}
+
+// Lines are zero-based, and the end line is part of the range. Folds are whole lines, so the
+// characters and kind are left out
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub(crate) struct FoldingRange {
+    pub(crate) startLine: usize,
+    pub(crate) endLine: usize,
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct Hover {
     pub(crate) contents: HoverContents,
@@ -1,7 +1,9 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::env;
+use std::path::Path;
 use std::rc::Rc;
 use std::{thread, time};
 
@@ -29,7 +31,9 @@ mod language_client;
 mod opengl;
 mod painter;
 mod project;
+mod registers;
 mod style;
+mod tabs;
 mod text;
 mod textview;
 mod theme;
@@ -38,21 +42,36 @@ mod window;
 
 use buffer::{BufferViewCreateParams, CursorStyle};
 use common::{abspath, PixelSize};
-use input::{Action as BedAction, MotionOrObj as BedMotionOrObj};
+use input::{Action as BedAction, Input, Motion, MotionOrObj as BedMotionOrObj, Surround, Window};
 use language_client::{LanguageClientManager, LanguageServerResponse};
+use registers::Registers;
 
 static CURSOR_LINE_WIDTH: i32 = 2;
 static CURSOR_BLOCK_WIDTH: i32 = 10;
 static DEFAULT_THEME: &str = "default";
+// Macros can't be interrupted, so stop recursive ones which never fail after this many keys
+static MAX_MACRO_INPUTS: usize = 1_000_000;
 
 pub struct Bed {
-    textview_tree: textview::TextTree,
+    tabs: tabs::TabPages,
     painter: painter::Painter,
     input_state: input::State,
     buffer_mgr: buffer::BufferMgr,
     cmd_prompt: cmdprompt::CmdPrompt,
     window: window::Window,
     in_cmd_mode: bool,
+    // Direction of the last search, for n and N
+    search_forward: bool,
+    registers: Rc<RefCell<Registers>>,
+    // Text typed in the current insert session, for the '.' register
+    inserted_text: String,
+    // Register and keys of the macro being recorded
+    macro_recording: Option<(char, String)>,
+    last_macro: Option<char>,
+    // Keys queued up by running macros
+    pending_input: VecDeque<Input>,
+    // Window title, with the active buffer's name and whether it is modified
+    title: String,
 }
 
 impl Bed {
@@ -68,7 +87,6 @@ impl Bed {
         let language_client_manager = LanguageClientManager::new(lsp_tx);
 
         let input_state = input::State::new();
-        let mut input_actions = Vec::new();
 
         let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).expect("failed to initialize GLFW");
         let (mut window, dpi, events) = window::Window::new(&mut glfw, size, "bed");
@@ -84,11 +102,13 @@ impl Bed {
             .unwrap_or_else(|| theme_set.0.get(DEFAULT_THEME).unwrap())
             .clone();
 
+        let registers = Rc::new(RefCell::new(Registers::new()));
         let mut buffer_mgr = buffer::BufferMgr::new(
             ts_core,
             projects,
             config.clone(),
             theme.clone(),
+            registers.clone(),
             language_client_manager,
         );
         let buf = match args.value_of("FILE") {
@@ -121,7 +141,7 @@ impl Bed {
             text_shaper,
             rect: textview_rect,
         };
-        let textview_tree = textview::TextTree::new(view_params, buf, view_id, theme);
+        let tabs = tabs::TabPages::new(view_params, buf, view_id, theme);
 
         window.show();
 
@@ -131,8 +151,15 @@ impl Bed {
             input_state,
             buffer_mgr,
             cmd_prompt,
-            textview_tree,
+            tabs,
             in_cmd_mode: false,
+            search_forward: true,
+            registers,
+            inserted_text: String::new(),
+            macro_recording: None,
+            last_macro: None,
+            pending_input: VecDeque::new(),
+            title: String::new(),
         };
 
         let mut start_time = time::Instant::now();
@@ -146,6 +173,7 @@ impl Bed {
 
         bed.draw();
         let mut mouse_pressed = false;
+        let mut dragged_border = None;
         let mut hovering = false;
         let mut last_event_time = time::Instant::now();
 
@@ -160,7 +188,6 @@ impl Bed {
             }
 
             for (_, event) in glfw::flush_messages(&events) {
-                input_actions.clear();
                 had_event = true;
 
                 match event {
@@ -168,26 +195,52 @@ impl Bed {
                         let viewable_rect = bed.window.viewable_rect();
                         bed.painter.resize(size2(w, h).cast(), viewable_rect);
                         let textview_rect = bed.cmd_prompt.resize(viewable_rect);
-                        bed.textview_tree.set_rect(textview_rect);
+                        bed.tabs.set_rect(textview_rect);
                     }
                     WindowEvent::Key(k, _, Action::Press, md)
                     | WindowEvent::Key(k, _, Action::Repeat, md) => {
-                        bed.input_state.handle_key(k, md, &mut input_actions);
-                        bed.process_input_actions(&input_actions);
+                        bed.handle_input(Input::Key(k, md));
                     }
                     WindowEvent::Char(c) => {
-                        bed.input_state.handle_char(c, &mut input_actions);
-                        bed.process_input_actions(&input_actions);
+                        bed.handle_input(Input::Char(c));
                     }
                     WindowEvent::MouseButton(MouseButtonLeft, Action::Press, _) => {
                         mouse_pressed = true;
+                        let pos = bed.window.cursor_pos();
+                        if let Some(i) = bed.tabs.tab_at(pos) {
+                            bed.tabs.go_to(i);
+                            continue;
+                        }
+                        // Borders between panes can be dragged to resize them
+                        if let Some(border) = bed.tabs.tree().border_at(pos) {
+                            let shape = if border.is_vertical() {
+                                glfw::StandardCursor::HResize
+                            } else {
+                                glfw::StandardCursor::VResize
+                            };
+                            bed.window.set_cursor(Some(glfw::Cursor::standard(shape)));
+                            dragged_border = Some(border);
+                            continue;
+                        }
                         bed.input_state.set_normal_mode();
-                        bed.textview_tree.active_mut().stop_completion();
+                        bed.tabs.tree_mut().active_mut().commit_undo_group();
+                        bed.tabs.tree_mut().active_mut().stop_completion();
+                        bed.tabs.tree_mut().active_mut().stop_selection();
                         bed.move_cursor_to_mouse();
                         bed.set_cursor_style(CursorStyle::Block);
                     }
                     WindowEvent::MouseButton(MouseButtonLeft, Action::Release, _) => {
                         mouse_pressed = false;
+                        if dragged_border.take().is_some() {
+                            let cursor = glfw::Cursor::standard(glfw::StandardCursor::IBeam);
+                            bed.window.set_cursor(Some(cursor));
+                        }
+                    }
+                    WindowEvent::CursorPos(..) => {
+                        if let Some(border) = &dragged_border {
+                            let pos = bed.window.cursor_pos();
+                            bed.tabs.tree_mut().drag_border(border, pos);
+                        }
                     }
                     WindowEvent::Scroll(xsc, ysc) => {
                         scroll_amt.0 += xsc;
@@ -199,12 +252,12 @@ impl Bed {
 
             redraw |= had_event;
             if had_event {
-                bed.textview_tree.set_hover(None);
+                bed.tabs.tree_mut().set_hover(None);
                 hovering = false;
                 last_event_time = time::Instant::now();
             }
             if !hovering && !mouse_pressed && last_event_time.elapsed() >= hover_duraton {
-                bed.textview_tree.set_hover(Some(bed.window.cursor_pos()));
+                bed.tabs.tree_mut().set_hover(Some(bed.window.cursor_pos()));
                 hovering = true;
             }
 
@@ -236,38 +289,251 @@ impl Bed {
         }
     }
 
-    fn process_input_actions(&mut self, actions: &[BedAction]) {
+    // Handle a key, and then any keys queued up by macros it ran. A failed motion or search drops
+    // the rest of the queue, which is what stops recursive macros
+    fn handle_input(&mut self, input: Input) {
+        let was_recording = self.macro_recording.is_some();
+        self.process_input(input);
+        // The key which started or stopped recording isn't part of the macro
+        if was_recording {
+            if let Some((_, keys)) = &mut self.macro_recording {
+                input.write(keys);
+            }
+        }
+        let mut count = 0;
+        while let Some(input) = self.pending_input.pop_front() {
+            count += 1;
+            if !self.process_input(input) || count >= MAX_MACRO_INPUTS {
+                self.pending_input.clear();
+            }
+        }
+    }
+
+    fn process_input(&mut self, input: Input) -> bool {
+        let mut actions = Vec::new();
+        let was_input_mode = self.input_state.is_input_mode();
+        self.input_state.handle_input(input, &mut actions);
+        // The '^' mark is where insert mode was left, before the cursor moves back
+        if was_input_mode && !self.input_state.is_input_mode() {
+            self.tabs.tree_mut().active_mut().mark_insert_stop();
+        }
+        self.process_input_actions(&actions)
+    }
+
+    // Returns false if a motion or search failed
+    fn process_input_actions(&mut self, actions: &[BedAction]) -> bool {
+        let mut ok = true;
         for action in actions {
             if self.in_cmd_mode {
                 match action {
                     BedAction::GetCmd => {
                         let command = self.cmd_prompt.get_command();
+                        if let Some(forward) = self.search_prompt_direction() {
+                            ok &= self.jump(|bed| bed.search(&command, forward));
+                            continue;
+                        }
+                        if !command.is_empty() {
+                            self.registers.borrow_mut().set_last_command(&command);
+                        }
                         self.handle_command(&command)
                     }
+                    BedAction::SurroundTag(edit) => {
+                        let tag = self.cmd_prompt.get_command();
+                        if !tag.is_empty() {
+                            let surround = Surround::Tag(tag);
+                            ok &= self.tabs.tree_mut().active_mut().surround(*edit, &surround);
+                        }
+                    }
                     BedAction::StopCmdPrompt => {
+                        if self.search_prompt_direction().is_some() {
+                            self.stop_search_preview();
+                        }
                         self.cmd_prompt.clear();
                         self.in_cmd_mode = false;
                     }
-                    _ => self.cmd_prompt.handle_action(action),
+                    _ => {
+                        self.cmd_prompt.handle_action(action);
+                        if let Some(forward) = self.search_prompt_direction() {
+                            let pattern = self.cmd_prompt.get_command();
+                            self.tabs
+                                .tree_mut()
+                                .active_mut()
+                                .search_preview(&pattern, forward);
+                        }
+                    }
                 }
             } else {
                 match action {
-                    BedAction::Move(mo) => self.move_cursor(*mo),
-                    BedAction::InsertChar(c) => self.insert_char(*c),
-                    BedAction::Delete(mo) => self.delete(*mo),
+                    BedAction::Move(mo) => {
+                        ok &= match mo {
+                            BedMotionOrObj::Motion(Motion::Mark(name, _))
+                                if name.is_ascii_uppercase() =>
+                            {
+                                self.go_to_global_mark(*mo)
+                            }
+                            mo if mo.is_jump() => self.jump(|bed| bed.move_cursor(*mo)),
+                            _ => self.move_cursor(*mo),
+                        }
+                    }
+                    BedAction::InsertChar(c) => {
+                        if self.input_state.is_input_mode() {
+                            self.inserted_text.push(*c);
+                        }
+                        self.insert_char(*c)
+                    }
+                    BedAction::Delete(mo) => {
+                        if let BedMotionOrObj::Motion(Motion::Left(n)) = mo {
+                            for _ in 0..*n {
+                                self.inserted_text.pop();
+                            }
+                        }
+                        self.delete(*mo)
+                    }
+                    BedAction::Operate(op, mo, reg) => {
+                        self.tabs.tree_mut().active_mut().operate(*op, *mo, *reg)
+                    }
+                    BedAction::PutAfter(reg, n) => {
+                        self.tabs.tree_mut().active_mut().put(*reg, *n, false)
+                    }
+                    BedAction::PutBefore(reg, n) => {
+                        self.tabs.tree_mut().active_mut().put(*reg, *n, true)
+                    }
+                    BedAction::StartSelection(mode) => {
+                        self.tabs.tree_mut().active_mut().start_selection(*mode)
+                    }
+                    BedAction::StopSelection => self.tabs.tree_mut().active_mut().stop_selection(),
+                    BedAction::OperateSelection(op, reg) => self
+                        .tabs
+                        .tree_mut()
+                        .active_mut()
+                        .operate_selection(*op, *reg),
+                    BedAction::JoinSelection(spaces) => {
+                        self.tabs.tree_mut().active_mut().join_selection(*spaces)
+                    }
+                    BedAction::ReplaceChar(c, n) => {
+                        ok &= self.tabs.tree_mut().active_mut().replace_char(*c, *n)
+                    }
+                    BedAction::Join(spaces, n) => {
+                        ok &= self.tabs.tree_mut().active_mut().join(*spaces, *n)
+                    }
+                    BedAction::ToggleCase(n) => self.tabs.tree_mut().active_mut().toggle_case(*n),
+                    BedAction::OpenLine(above) => {
+                        self.tabs.tree_mut().active_mut().open_line(*above)
+                    }
+                    BedAction::AddToNumber(delta) => {
+                        ok &= self.tabs.tree_mut().active_mut().add_to_number(*delta)
+                    }
+                    BedAction::Surround(edit, surround) => {
+                        ok &= self.tabs.tree_mut().active_mut().surround(*edit, surround)
+                    }
+                    BedAction::DeleteSurround(target) => {
+                        ok &= self.tabs.tree_mut().active_mut().delete_surround(*target)
+                    }
+                    BedAction::StartReplace => self.tabs.tree_mut().active_mut().start_replace(),
+                    BedAction::ReplaceModeChar(c) => {
+                        self.inserted_text.push(*c);
+                        self.tabs.tree_mut().active_mut().replace_mode_char(*c)
+                    }
+                    BedAction::ReplaceModeBackspace => {
+                        self.inserted_text.pop();
+                        self.tabs.tree_mut().active_mut().replace_mode_backspace()
+                    }
+                    BedAction::SearchNext(reverse, n) => {
+                        ok &= self.jump(|bed| bed.search_next(*reverse, *n))
+                    }
+                    BedAction::SearchWord(forward, n) => {
+                        ok &= self.jump(|bed| bed.search_word(*forward, *n));
+                    }
+                    BedAction::Undo(n) => self.tabs.tree_mut().active_mut().undo(*n),
+                    BedAction::Redo(n) => self.tabs.tree_mut().active_mut().redo(*n),
                     BedAction::UpdateCursorStyle(style) => self.set_cursor_style(*style),
                     BedAction::StartCmdPrompt(s) => {
                         self.cmd_prompt.set_prompt(s);
                         self.in_cmd_mode = true;
                     }
-                    BedAction::GetCmd => unreachable!(),
+                    BedAction::GetCmd | BedAction::SurroundTag(_) => unreachable!(),
                     BedAction::StopCmdPrompt => unreachable!(),
                     BedAction::Completion(c) => {
-                        self.textview_tree.active_mut().completion_action(*c)
+                        self.tabs.tree_mut().active_mut().completion_action(*c)
+                    }
+                    BedAction::SetMark(name) => {
+                        let pane = self.tabs.tree_mut().active_mut();
+                        if pane.set_mark(*name) && name.is_ascii_uppercase() {
+                            self.buffer_mgr.set_global_mark(*name, pane.buffer_id());
+                        }
+                    }
+                    BedAction::AlternateBuffer(n) => ok &= self.alternate_buffer(*n),
+                    BedAction::Window(window) => ok &= self.window_command(*window),
+                    BedAction::NextTab(number) => ok &= self.tabs.next(*number),
+                    BedAction::PrevTab(n) => ok &= self.tabs.prev(*n),
+                    BedAction::Jumplist(older, n) => {
+                        let pane = self.tabs.tree_mut().active_mut();
+                        ok &= if *older {
+                            pane.jump_older(*n)
+                        } else {
+                            pane.jump_newer(*n)
+                        };
+                    }
+                    BedAction::Changelist(older, n) => {
+                        ok &= self.tabs.tree_mut().active_mut().changelist(*older, *n)
+                    }
+                    BedAction::Scroll(scroll, n) => {
+                        ok &= self.tabs.tree_mut().active_mut().scroll_page(*scroll, *n)
+                    }
+                    BedAction::ScrollCursor(pos) => {
+                        self.tabs.tree_mut().active_mut().scroll_cursor(*pos)
+                    }
+                    BedAction::Fold(fold) => ok &= self.tabs.tree_mut().active_mut().fold(*fold),
+                    BedAction::AddCursor(below, n) => {
+                        ok &= self.tabs.tree_mut().active_mut().add_cursor(*below, *n)
+                    }
+                    BedAction::AddCursorAtWord(n) => {
+                        ok &= self.tabs.tree_mut().active_mut().add_cursor_at_word(*n)
+                    }
+                    BedAction::SplitSelection => {
+                        self.tabs.tree_mut().active_mut().split_selection()
+                    }
+                    BedAction::ClearCursors => self.tabs.tree_mut().active_mut().clear_cursors(),
+                    BedAction::RecordMacro(name) => {
+                        self.macro_recording = Some((*name, String::new()))
                     }
+                    BedAction::StopMacro => {
+                        if let Some((name, keys)) = self.macro_recording.take() {
+                            self.registers.borrow_mut().set_macro(name, keys);
+                        }
+                    }
+                    BedAction::RunMacro(name, n) => ok &= self.run_macro(*name, *n),
                 }
             }
         }
+        // An insert session is one undo step, everything else is committed right away
+        if !self.input_state.is_input_mode() {
+            self.tabs.tree_mut().active_mut().commit_undo_group();
+            if !self.inserted_text.is_empty() {
+                let text = std::mem::take(&mut self.inserted_text);
+                self.registers.borrow_mut().set_last_insert(text);
+            }
+        }
+        ok
+    }
+
+    // Queue up the keys of a macro, ahead of whatever is left of the macro running it
+    fn run_macro(&mut self, name: Option<char>, count: usize) -> bool {
+        let name = match name.or(self.last_macro) {
+            Some(name) => name.to_ascii_lowercase(),
+            None => return false,
+        };
+        self.last_macro = Some(name);
+        let keys = match self.registers.borrow().get(Some(name)) {
+            Some(reg) => Input::parse_all(&reg.text),
+            None => return false,
+        };
+        for _ in 0..count {
+            for input in keys.iter().rev() {
+                self.pending_input.push_front(*input);
+            }
+        }
+        true
     }
 
     fn handle_language_server_response(&mut self, message: LanguageServerResponse) -> bool {
@@ -283,46 +549,165 @@ impl Bed {
                     buffer.update_hover(id, hover);
                 }
             }
+            LanguageServerResponse::FoldingRange(id, path, ranges) => {
+                if let Some(buffer) = self.buffer_mgr.buffer_for_path(&path) {
+                    let buffer = &mut *buffer.borrow_mut();
+                    buffer.set_fold_ranges(id, ranges);
+                    redraw = true;
+                }
+            }
         }
         redraw
     }
 
     fn check_redraw(&mut self) -> bool {
-        self.textview_tree.map(|pane| pane.check_redraw())
+        self.tabs.tree_mut().map(|pane| pane.check_redraw())
     }
 
     fn draw(&mut self) {
+        self.update_title();
         self.painter.clear(style::Color::new(0, 0, 0, 0xff));
 
-        self.textview_tree.draw(&mut self.painter);
+        self.tabs.draw(&mut self.painter);
         self.cmd_prompt.draw(&mut self.painter);
 
         self.window.swap_buffers();
     }
 
+    fn update_title(&mut self) {
+        let pane = self.tabs.tree().active();
+        let modified = if pane.is_modified() { " [+]" } else { "" };
+        let title = format!("{}{} - bed", pane.buffer_name(), modified);
+        if title != self.title {
+            self.window.set_title(&title);
+            self.title = title;
+        }
+    }
+
     fn insert_char(&mut self, c: char) {
-        self.textview_tree.active_mut().insert_char(c);
+        self.tabs.tree_mut().active_mut().insert_char(c);
     }
 
     fn delete(&mut self, mo: BedMotionOrObj) {
-        self.textview_tree.active_mut().delete(mo);
+        self.tabs.tree_mut().active_mut().delete(mo);
+    }
+
+    fn move_cursor(&mut self, mo: BedMotionOrObj) -> bool {
+        self.tabs.tree_mut().active_mut().move_cursor(mo)
+    }
+
+    // Some(true) for a forward search prompt, Some(false) for a backward one
+    fn search_prompt_direction(&self) -> Option<bool> {
+        match self.cmd_prompt.prompt() {
+            "/" => Some(true),
+            "?" => Some(false),
+            _ => None,
+        }
+    }
+
+    // Search typed in to the prompt. An empty pattern repeats the last search
+    fn search(&mut self, pattern: &str, forward: bool) -> bool {
+        let pattern = if pattern.is_empty() {
+            match self.registers.borrow().last_search() {
+                Some(pattern) => pattern.to_owned(),
+                None => return false,
+            }
+        } else {
+            pattern.to_owned()
+        };
+        self.registers.borrow_mut().set_last_search(&pattern);
+        self.search_forward = forward;
+        let pane = self.tabs.tree_mut().active_mut();
+        pane.stop_search_preview();
+        let found = pane.search(&pattern, forward, 1);
+        self.buffer_mgr.set_search_pattern(Some(&pattern));
+        found
     }
 
-    fn move_cursor(&mut self, mo: BedMotionOrObj) {
-        self.textview_tree.active_mut().move_cursor(mo);
+    // Go back to where the search started, and to highlighting the last search
+    fn stop_search_preview(&mut self) {
+        self.tabs.tree_mut().active_mut().stop_search_preview();
+        let registers = self.registers.borrow();
+        self.buffer_mgr.set_search_pattern(registers.last_search());
+    }
+
+    fn search_next(&mut self, reverse: bool, count: usize) -> bool {
+        let registers = self.registers.borrow();
+        match registers.last_search() {
+            Some(pattern) => self.tabs.tree_mut().active_mut().search(
+                pattern,
+                self.search_forward != reverse,
+                count,
+            ),
+            None => false,
+        }
+    }
+
+    fn search_word(&mut self, forward: bool, count: usize) -> bool {
+        match self
+            .tabs
+            .tree_mut()
+            .active_mut()
+            .search_word(forward, count)
+        {
+            Some(pattern) => {
+                self.registers.borrow_mut().set_last_search(&pattern);
+                self.search_forward = forward;
+                self.buffer_mgr.set_search_pattern(Some(&pattern));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Run a motion or search, and add where it started to the jumplist if it succeeds
+    fn jump<F: FnOnce(&mut Bed) -> bool>(&mut self, f: F) -> bool {
+        let origin = self.tabs.tree().active().jump_origin();
+        let ok = f(self);
+        if ok {
+            self.tabs.tree_mut().active_mut().add_jump(origin);
+        }
+        ok
+    }
+
+    // Global marks can be in another buffer, which the pane switches to
+    fn go_to_global_mark(&mut self, mo: BedMotionOrObj) -> bool {
+        let name = match mo {
+            BedMotionOrObj::Motion(Motion::Mark(name, _)) => name,
+            _ => unreachable!(),
+        };
+        let buf = match self.buffer_mgr.global_mark_buffer(name) {
+            Some(Ok(buf)) => buf,
+            Some(Err(e)) => {
+                self.cmd_prompt
+                    .set_error(&format!("error loading buffer: {}", e));
+                return false;
+            }
+            None => return false,
+        };
+        let pane = self.tabs.tree_mut().active_mut();
+        if buf.borrow().buffer_id() == pane.buffer_id() {
+            return self.jump(|bed| bed.move_cursor(mo));
+        }
+        let bufmgr = &mut self.buffer_mgr;
+        pane.new_buffer(buf, || bufmgr.next_view_id());
+        pane.move_cursor(mo)
     }
 
     fn set_cursor_visible(&mut self, visible: bool) {
-        self.textview_tree.active_mut().set_cursor_visible(visible)
+        self.tabs
+            .tree_mut()
+            .active_mut()
+            .set_cursor_visible(visible)
     }
 
     fn set_cursor_style(&mut self, style: CursorStyle) {
-        self.textview_tree.active_mut().set_cursor_style(style)
+        self.tabs.tree_mut().active_mut().set_cursor_style(style)
     }
 
     fn move_cursor_to_mouse(&mut self) {
         let pos = self.window.cursor_pos();
-        if let Some(view) = self.textview_tree.set_active_and_get_from_pos(pos) {
+        if let Some(view) = self.tabs.tree_mut().set_active_and_get_from_pos(pos) {
             view.move_cursor_to_point(pos);
         }
     }
@@ -330,7 +715,7 @@ impl Bed {
     fn scroll(&mut self, amt: (f64, f64), duration: time::Duration) -> bool {
         let pos = self.window.cursor_pos();
         let vec = vec2(amt.0, -amt.1);
-        let redraw = self.textview_tree.map(|pane| {
+        let redraw = self.tabs.tree_mut().map(|pane| {
             if pane.rect().contains(pos) {
                 pane.scroll(vec, duration)
             } else {
@@ -340,63 +725,159 @@ impl Bed {
         redraw
     }
 
-    fn write_buffer(&mut self, optpath: Option<&str>) {
+    // Writing to another file which exists needs the ! modifier
+    fn write_buffer(&mut self, optpath: Option<&str>, overwrite: bool) -> Result<(), String> {
         let optpath = optpath.map(|path| abspath(path));
-        let bufid = self.textview_tree.active().buffer_id();
+        let bufid = self.tabs.tree().active().buffer_id();
+        if let Some(path) = &optpath {
+            let is_own_path = self
+                .buffer_mgr
+                .buffer_for_path(path)
+                .map(|buf| buf.borrow().buffer_id())
+                == Some(bufid);
+            if !overwrite && !is_own_path && Path::new(path).exists() {
+                return Err(format!("file exists (add ! to override): {}", path));
+            }
+        }
         match self.buffer_mgr.write_buffer(bufid, optpath) {
-            Some(Ok(nbytes)) => debug!("wrote {} bytes", nbytes),
-            Some(Err(e)) => error!("error writing buffer: {}", e),
-            None => debug!("buffer does not have path"),
+            Some(Ok(nbytes)) => {
+                debug!("wrote {} bytes", nbytes);
+                Ok(())
+            }
+            Some(Err(e)) => Err(format!("error writing buffer: {}", e)),
+            None => Err("buffer does not have path".to_owned()),
         }
     }
 
-    fn load_buffer(&mut self, optpath: Option<&str>) {
+    // Write every buffer with changes. Buffers which fail to be written don't stop the others
+    fn write_all(&mut self) -> Result<(), String> {
+        let mut ret = Ok(());
+        for buf in self.buffer_mgr.modified_buffers() {
+            let bufid = buf.borrow().buffer_id();
+            let res = match self.buffer_mgr.write_buffer(bufid, None) {
+                Some(Ok(nbytes)) => {
+                    debug!("wrote {} bytes", nbytes);
+                    Ok(())
+                }
+                Some(Err(e)) => Err(format!("error writing buffer: {}", e)),
+                None => Err("buffer does not have path".to_owned()),
+            };
+            if ret.is_ok() {
+                ret = res;
+            }
+        }
+        ret
+    }
+
+    // Load a file, or the active buffer's file again. Changes to the buffer which gets reloaded
+    // are only thrown away if forced
+    fn load_buffer(&mut self, optpath: Option<&str>, force: bool) -> Result<(), String> {
         let optpath = optpath.map(|path| abspath(path));
-        let bufid = self.textview_tree.active().buffer_id();
+        let modified = match &optpath {
+            Some(path) => self
+                .buffer_mgr
+                .buffer_for_path(path)
+                .map_or(false, |buf| buf.borrow().is_modified()),
+            None => self.tabs.tree().active().is_modified(),
+        };
+        if modified && !force {
+            return Err("no write since last change (add ! to override)".to_owned());
+        }
+        let bufid = self.tabs.tree().active().buffer_id();
         match self.buffer_mgr.load_buffer(bufid, optpath) {
             Some(Ok(buf)) => {
                 let bufmgr = &mut self.buffer_mgr;
-                self.textview_tree
+                self.tabs
+                    .tree_mut()
                     .active_mut()
                     .new_buffer(buf, || bufmgr.next_view_id());
                 debug!("loaded buffer");
+                Ok(())
             }
-            Some(Err(e)) => error!("error loading buffer: {}", e),
-            None => error!("buffer does not have path"),
+            Some(Err(e)) => Err(format!("error loading buffer: {}", e)),
+            None => Err("buffer does not have path".to_owned()),
         }
     }
 
-    fn change_directory(&mut self, optpath: Option<&str>) {
+    fn change_directory(&mut self, optpath: Option<&str>) -> Result<(), String> {
         let path = optpath.unwrap_or("~");
         let abspath = abspath(path);
-        if let Err(e) = env::set_current_dir(&abspath) {
-            error!("failed to change directory to '{}': {}", path, e);
-        }
+        env::set_current_dir(&abspath)
+            .map_err(|e| format!("failed to change directory to '{}': {}", path, e))
     }
 
-    fn horizontal_split(&mut self, optpath: Option<&str>) {
+    fn horizontal_split(&mut self, optpath: Option<&str>) -> Result<(), String> {
         let view_id = self.buffer_mgr.next_view_id();
         if let Some(path) = optpath {
             let abspath = abspath(path);
             match self.buffer_mgr.from_file(&abspath) {
-                Ok(buf) => self.textview_tree.split_h(Some(buf), view_id),
-                Err(e) => error!("erorr loading buffer: {}", e),
+                Ok(buf) => self.tabs.tree_mut().split_h(Some(buf), view_id),
+                Err(e) => return Err(format!("error loading buffer: {}", e)),
             }
         } else {
-            self.textview_tree.split_h(None, view_id);
+            self.tabs.tree_mut().split_h(None, view_id);
         }
+        Ok(())
     }
 
-    fn vertical_split(&mut self, optpath: Option<&str>) {
+    fn vertical_split(&mut self, optpath: Option<&str>) -> Result<(), String> {
         let view_id = self.buffer_mgr.next_view_id();
         if let Some(path) = optpath {
             let abspath = abspath(path);
             match self.buffer_mgr.from_file(&abspath) {
-                Ok(buf) => self.textview_tree.split_v(Some(buf), view_id),
-                Err(e) => error!("erorr loading buffer: {}", e),
+                Ok(buf) => self.tabs.tree_mut().split_v(Some(buf), view_id),
+                Err(e) => return Err(format!("error loading buffer: {}", e)),
             }
         } else {
-            self.textview_tree.split_v(None, view_id);
+            self.tabs.tree_mut().split_v(None, view_id);
+        }
+        Ok(())
+    }
+
+    // Open a file, or an empty buffer, in a new tab page. A file which is already open isn't
+    // loaded again, so that changes to it are kept
+    fn new_tab(&mut self, optpath: Option<&str>) -> Result<(), String> {
+        let buf = match optpath.map(|path| abspath(path)) {
+            Some(path) => match self.buffer_mgr.buffer_for_path(&path) {
+                Some(buf) => buf,
+                None => self
+                    .buffer_mgr
+                    .from_file(&path)
+                    .map_err(|e| format!("error loading buffer: {}", e))?,
+            },
+            None => self.buffer_mgr.empty(),
+        };
+        let view_id = self.buffer_mgr.next_view_id();
+        self.tabs.new_tab(buf, view_id);
+        Ok(())
+    }
+
+    // Ctrl-W commands
+    fn window_command(&mut self, window: Window) -> bool {
+        let tree = self.tabs.tree_mut();
+        let result = match window {
+            Window::Focus(dir, n) => Ok(tree.focus(dir, n)),
+            Window::Cycle(forward, number) => Ok(tree.cycle(forward, number)),
+            Window::Previous => Ok(tree.go_to_previous()),
+            Window::Exchange(number) => Ok(tree.exchange(number)),
+            Window::Rotate(forward, n) => Ok(tree.rotate(forward, n)),
+            Window::Equalize => {
+                tree.equalize();
+                Ok(true)
+            }
+            Window::Resize(vertical, n) => Ok(tree.resize(vertical, n)),
+            Window::MoveToEdge(dir) => Ok(tree.move_to_edge(dir)),
+            Window::Split(true) => self.vertical_split(None).map(|_| true),
+            Window::Split(false) => self.horizontal_split(None).map(|_| true),
+            Window::Close => tree.close(false).map(|_| true),
+            Window::Only => tree.only(false).map(|_| true),
+        };
+        match result {
+            Ok(ok) => ok,
+            Err(e) => {
+                self.cmd_prompt.set_error(&e);
+                false
+            }
         }
     }
 }
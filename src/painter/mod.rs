@@ -22,6 +22,17 @@ mod shapes;
 use glyphrender::GlyphRenderer;
 use shapes::{ColorQuad, ColorTriangle, RoundColorRect, TexColorQuad};
 
+// How text which doesn't fit in the width it is drawn in is laid out
+#[derive(Clone, Copy)]
+pub(crate) enum TextWrap<'a> {
+    // Cut off at the width
+    None,
+    // Move words which don't fit on to the next line
+    Fold,
+    // Start a new line at each of the given columns, indented by the given amount
+    At(&'a [usize], i32),
+}
+
 // Struct which handles drawing UI elements
 pub(crate) struct Painter {
     projection: Mat4,
@@ -178,7 +189,7 @@ impl<'a> WidgetPainter<'a> {
         cursor: Option<(usize, Color, CursorStyle)>,
         width: u32,
         line_height: u32,
-        fold: bool,
+        wrap: TextWrap,
     ) {
         let collected = line.styled_iter().collect::<Vec<_>>();
         let mut i = 0;
@@ -197,13 +208,13 @@ impl<'a> WidgetPainter<'a> {
             cursor,
             width,
             line_height as i32,
-            fold,
+            wrap,
         );
 
         // Draw right-aligned text
         if pos.x <= width as i32 && i < collected.len() {
-            // TODO: How do we support right-aligned and fold?
-            if fold {
+            // TODO: How do we support right-aligned and wrapping?
+            if !matches!(wrap, TextWrap::None) {
                 unimplemented!();
             }
             let space_remaining = width as i32 - pos.x;
@@ -226,7 +237,7 @@ impl<'a> WidgetPainter<'a> {
                 cursor,
                 width,
                 line_height as i32,
-                fold,
+                wrap,
             );
             pos = pos_here;
             gidx = gidx_here;
@@ -276,12 +287,12 @@ impl<'a> WidgetPainter<'a> {
         cursor: Option<(usize, Color, CursorStyle)>,
         width: u32,
         line_height: i32,
-        fold: bool,
+        wrap: TextWrap,
     ) -> (Point2D<i32, PixelSize>, usize) {
         for (clusters, face, style, size, color, opt_under, _) in line {
             let (clusters, face, style, size, color, opt_under) =
                 (*clusters, *face, *style, *size, *color, *opt_under);
-            if fold {
+            if let TextWrap::Fold = wrap {
                 let chunk_width = clusters.width() as i32;
                 if pos.x + chunk_width > width as i32 {
                     pos.x = basex;
@@ -289,7 +300,12 @@ impl<'a> WidgetPainter<'a> {
                 }
             }
             for cluster in clusters {
-                if pos.x >= width as i32 {
+                if let TextWrap::At(wraps, indent) = wrap {
+                    if wraps.contains(&gidx) {
+                        pos.x = basex + indent;
+                        pos.y += line_height;
+                    }
+                } else if pos.x >= width as i32 {
                     break;
                 }
                 let raster = shaper.get_raster(face, style).unwrap();
@@ -18,6 +18,8 @@ struct ProjectInner {
     vcs: Option<VersionControl>,
     indent_tabs: Option<bool>,
     tab_width: Option<usize>,
+    soft_wrap: Option<bool>,
+    wrap_indent: Option<usize>,
 }
 
 pub(crate) struct Project {
@@ -25,6 +27,8 @@ pub(crate) struct Project {
     pub(crate) vcs: Option<VersionControl>,
     pub(crate) indent_tabs: Option<bool>,
     pub(crate) tab_width: Option<usize>,
+    pub(crate) soft_wrap: Option<bool>,
+    pub(crate) wrap_indent: Option<usize>,
 }
 
 impl Project {
@@ -34,6 +38,8 @@ impl Project {
             vcs: inner.vcs,
             indent_tabs: inner.indent_tabs,
             tab_width: inner.tab_width,
+            soft_wrap: inner.soft_wrap,
+            wrap_indent: inner.wrap_indent,
         }
     }
 }
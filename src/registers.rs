@@ -0,0 +1,269 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+// Vim-style registers.
+//
+// '"'        unnamed register, holds whatever was last yanked or deleted
+// 'a'-'z'    named registers. Writing to 'A'-'Z' appends to the corresponding named register
+// '0'        last yank
+// '1'-'9'    delete history. Line-wise or multi-line deletes are pushed on to '1'
+// '-'        last small (within one line) delete
+// '.'        last inserted text (read-only)
+// ':'        last command line (read-only)
+// '/'        last search pattern (read-only)
+// '%'        current file name (read-only, resolved by the buffer)
+// '_'        black hole. Writes are discarded, reads return nothing
+
+pub(crate) const BLACK_HOLE: char = '_';
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum RegisterKind {
+    Chars,
+    Lines,
+    // Rectangular selection. Each line of the text is put on a separate line
+    Block,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Register {
+    pub(crate) kind: RegisterKind,
+    pub(crate) text: String,
+}
+
+impl Register {
+    pub(crate) fn new(kind: RegisterKind, text: String) -> Register {
+        Register { kind, text }
+    }
+
+    fn append(&mut self, other: &Register) {
+        if self.kind == RegisterKind::Lines || other.kind == RegisterKind::Lines {
+            if self.kind != RegisterKind::Lines && !self.text.ends_with('\n') {
+                self.text.push('\n');
+            }
+            self.kind = RegisterKind::Lines;
+        }
+        self.text.push_str(&other.text);
+        if self.kind == RegisterKind::Lines && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Registers {
+    unnamed: Option<Register>,
+    named: [Option<Register>; 26],
+    numbered: [Option<Register>; 10],
+    small_delete: Option<Register>,
+    last_insert: String,
+    last_command: String,
+    last_search: String,
+}
+
+impl Registers {
+    pub(crate) fn new() -> Registers {
+        Registers::default()
+    }
+
+    pub(crate) fn is_valid(name: char) -> bool {
+        match name {
+            '"' | '-' | '.' | ':' | '/' | '%' | BLACK_HOLE => true,
+            c => c.is_ascii_alphanumeric(),
+        }
+    }
+
+    // Store yanked text. Returns false if the register cannot be written to
+    pub(crate) fn yank(&mut self, name: Option<char>, reg: Register) -> bool {
+        match name {
+            None | Some('"') => {
+                self.numbered[0] = Some(reg.clone());
+                self.unnamed = Some(reg);
+                true
+            }
+            Some(c) => self.write_named(c, reg),
+        }
+    }
+
+    // Store deleted text. Returns false if the register cannot be written to
+    pub(crate) fn delete(&mut self, name: Option<char>, reg: Register) -> bool {
+        match name {
+            None | Some('"') => {
+                if reg.kind == RegisterKind::Lines || reg.text.contains('\n') {
+                    for i in (1..9).rev() {
+                        self.numbered[i + 1] = self.numbered[i].take();
+                    }
+                    self.numbered[1] = Some(reg.clone());
+                } else {
+                    self.small_delete = Some(reg.clone());
+                }
+                self.unnamed = Some(reg);
+                true
+            }
+            Some(c) => self.write_named(c, reg),
+        }
+    }
+
+    // Store a recorded macro. Unlike yanks, this leaves the unnamed register alone
+    pub(crate) fn set_macro(&mut self, name: char, text: String) {
+        let reg = Register::new(RegisterKind::Chars, text);
+        if name.is_ascii_lowercase() {
+            self.named[name as usize - 'a' as usize] = Some(reg);
+        } else if name.is_ascii_uppercase() {
+            match &mut self.named[name as usize - 'A' as usize] {
+                Some(named) => named.append(&reg),
+                named => *named = Some(reg),
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, name: Option<char>) -> Option<Register> {
+        match name {
+            None | Some('"') => self.unnamed.clone(),
+            Some('-') => self.small_delete.clone(),
+            Some('.') => Some(Register::new(RegisterKind::Chars, self.last_insert.clone())),
+            Some(':') => Some(Register::new(
+                RegisterKind::Chars,
+                self.last_command.clone(),
+            )),
+            Some('/') => Some(Register::new(RegisterKind::Chars, self.last_search.clone())),
+            Some(c) if c.is_ascii_digit() => self.numbered[c as usize - '0' as usize].clone(),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let i = c.to_ascii_lowercase() as usize - 'a' as usize;
+                self.named[i].clone()
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_last_insert(&mut self, text: String) {
+        self.last_insert = text;
+    }
+
+    pub(crate) fn set_last_command(&mut self, text: &str) {
+        self.last_command.clear();
+        self.last_command.push_str(text);
+    }
+
+    pub(crate) fn set_last_search(&mut self, text: &str) {
+        self.last_search.clear();
+        self.last_search.push_str(text);
+    }
+
+    pub(crate) fn last_search(&self) -> Option<&str> {
+        if self.last_search.is_empty() {
+            None
+        } else {
+            Some(&self.last_search)
+        }
+    }
+
+    fn write_named(&mut self, name: char, reg: Register) -> bool {
+        match name {
+            BLACK_HOLE => true,
+            '-' => {
+                self.small_delete = Some(reg.clone());
+                self.unnamed = Some(reg);
+                true
+            }
+            c if c.is_ascii_digit() => {
+                self.numbered[c as usize - '0' as usize] = Some(reg.clone());
+                self.unnamed = Some(reg);
+                true
+            }
+            c if c.is_ascii_lowercase() => {
+                self.named[c as usize - 'a' as usize] = Some(reg.clone());
+                self.unnamed = Some(reg);
+                true
+            }
+            c if c.is_ascii_uppercase() => {
+                let i = c as usize - 'A' as usize;
+                match &mut self.named[i] {
+                    Some(named) => named.append(&reg),
+                    named => *named = Some(reg),
+                }
+                self.unnamed = self.named[i].clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Register {
+        Register::new(RegisterKind::Chars, s.to_owned())
+    }
+
+    fn lines(s: &str) -> Register {
+        Register::new(RegisterKind::Lines, s.to_owned())
+    }
+
+    #[test]
+    fn test_yank() {
+        let mut regs = Registers::new();
+        assert!(regs.yank(None, chars("abc")));
+        assert_eq!(regs.get(None), Some(chars("abc")));
+        assert_eq!(regs.get(Some('0')), Some(chars("abc")));
+        assert!(regs.yank(Some('a'), lines("x\n")));
+        assert_eq!(regs.get(Some('a')), Some(lines("x\n")));
+        assert_eq!(regs.get(Some('"')), Some(lines("x\n")));
+        assert_eq!(regs.get(Some('0')), Some(chars("abc")));
+        assert!(regs.yank(Some(BLACK_HOLE), chars("gone")));
+        assert_eq!(regs.get(Some(BLACK_HOLE)), None);
+        assert_eq!(regs.get(None), Some(lines("x\n")));
+    }
+
+    #[test]
+    fn test_append() {
+        let mut regs = Registers::new();
+        regs.yank(Some('A'), chars("ab"));
+        assert_eq!(regs.get(Some('a')), Some(chars("ab")));
+        regs.yank(Some('A'), chars("cd"));
+        assert_eq!(regs.get(Some('a')), Some(chars("abcd")));
+        regs.yank(Some('A'), lines("ef\n"));
+        assert_eq!(regs.get(Some('a')), Some(lines("abcd\nef\n")));
+        regs.yank(Some('A'), chars("gh"));
+        assert_eq!(regs.get(Some('A')), Some(lines("abcd\nef\ngh\n")));
+    }
+
+    #[test]
+    fn test_delete_history() {
+        let mut regs = Registers::new();
+        regs.delete(None, chars("x"));
+        assert_eq!(regs.get(Some('-')), Some(chars("x")));
+        assert_eq!(regs.get(Some('1')), None);
+        for i in 0..10 {
+            regs.delete(None, lines(&format!("{}\n", i)));
+        }
+        assert_eq!(regs.get(Some('1')), Some(lines("9\n")));
+        assert_eq!(regs.get(Some('9')), Some(lines("1\n")));
+        assert_eq!(regs.get(None), Some(lines("9\n")));
+    }
+
+    #[test]
+    fn test_macro() {
+        let mut regs = Registers::new();
+        regs.yank(None, chars("x"));
+        regs.set_macro('q', "dd".to_owned());
+        regs.set_macro('Q', "j".to_owned());
+        assert_eq!(regs.get(Some('q')), Some(chars("ddj")));
+        assert_eq!(regs.get(None), Some(chars("x")));
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut regs = Registers::new();
+        assert!(!regs.yank(Some('.'), chars("a")));
+        assert!(!regs.delete(Some(':'), chars("a")));
+        assert!(!regs.yank(Some('%'), chars("a")));
+        regs.set_last_insert("ins".to_owned());
+        regs.set_last_command("w");
+        regs.set_last_search("foo");
+        assert!(!regs.yank(Some('/'), chars("a")));
+        assert_eq!(regs.get(Some('.')), Some(chars("ins")));
+        assert_eq!(regs.get(Some(':')), Some(chars("w")));
+        assert_eq!(regs.get(Some('/')), Some(chars("foo")));
+    }
+}
@@ -0,0 +1,361 @@
+// (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use euclid::{point2, size2, Point2D, Rect};
+
+use crate::buffer::{Buffer, BufferID, BufferViewCreateParams, BufferViewID};
+use crate::common::PixelSize;
+use crate::painter::{Painter, TextWrap};
+use crate::style::TextStyle;
+use crate::text::{RopeOrStr, ShapedText, TextAlignment};
+use crate::textview::TextTree;
+use crate::theme::Theme;
+
+const TAB_WIDTH: usize = 8;
+
+// Tab pages, each with its own layout of panes. A bar with a label for each tab is shown above
+// the panes while there is more than one. The labels use the prompt's font and padding
+pub(crate) struct TabPages {
+    trees: Vec<TextTree>,
+    active: usize,
+    rect: Rect<u32, PixelSize>,
+    // Label text, whether it is the active tab, and the shaped text
+    labels: Vec<(String, bool, ShapedText)>,
+    ascender: i32,
+    descender: i32,
+    // New tabs are made with these
+    view_params: BufferViewCreateParams,
+    theme: Rc<Theme>,
+}
+
+impl TabPages {
+    pub(crate) fn new(
+        view_params: BufferViewCreateParams,
+        buf: Rc<RefCell<Buffer>>,
+        view_id: BufferViewID,
+        theme: Rc<Theme>,
+    ) -> TabPages {
+        let (ascender, descender) = {
+            let config = &view_params.config;
+            let shaper = &mut *view_params.text_shaper.borrow_mut();
+            let raster = shaper
+                .get_raster(config.prompt_face, TextStyle::default())
+                .unwrap();
+            let metrics = raster.get_metrics(config.prompt_font_size, view_params.dpi);
+            (metrics.ascender, metrics.descender)
+        };
+        TabPages {
+            trees: vec![TextTree::new(
+                view_params.clone(),
+                buf,
+                view_id,
+                theme.clone(),
+            )],
+            active: 0,
+            rect: view_params.rect,
+            labels: Vec::new(),
+            ascender,
+            descender,
+            view_params,
+            theme,
+        }
+    }
+
+    pub(crate) fn tree(&self) -> &TextTree {
+        &self.trees[self.active]
+    }
+
+    pub(crate) fn tree_mut(&mut self) -> &mut TextTree {
+        &mut self.trees[self.active]
+    }
+
+    pub(crate) fn set_rect(&mut self, rect: Rect<u32, PixelSize>) {
+        self.rect = rect;
+        self.relayout();
+    }
+
+    pub(crate) fn draw(&mut self, painter: &mut Painter) {
+        if self.trees.len() > 1 {
+            self.update_labels();
+            self.draw_bar(painter);
+        }
+        self.trees[self.active].draw(painter);
+    }
+
+    // Take a buffer out of the panes of every tab
+    pub(crate) fn remove_buffer<F>(
+        &mut self,
+        id: BufferID,
+        replacement: &Rc<RefCell<Buffer>>,
+        f: &mut F,
+    ) where
+        F: FnMut() -> BufferViewID,
+    {
+        for tree in &mut self.trees {
+            tree.remove_buffer(id, replacement, f);
+        }
+    }
+
+    // -------- Opening and closing tabs ----------------
+
+    // :tabnew. The new tab goes after the active one
+    pub(crate) fn new_tab(&mut self, buf: Rc<RefCell<Buffer>>, view_id: BufferViewID) {
+        let tree = TextTree::new(self.view_params.clone(), buf, view_id, self.theme.clone());
+        self.active += 1;
+        self.trees.insert(self.active, tree);
+        self.relayout();
+    }
+
+    // :tabclose. Close the active tab, or the tab with the given number. The tab after it
+    // becomes active, or the one before it if it was the last
+    pub(crate) fn close_tab(&mut self, number: Option<usize>, force: bool) -> Result<(), String> {
+        let i = match number {
+            Some(n) => self.index_for_number(n)?,
+            None => self.active,
+        };
+        if self.trees.len() == 1 {
+            return Err("cannot close the last tab page".to_owned());
+        }
+        if !force {
+            self.trees[i].check_close()?;
+        }
+        self.trees.remove(i);
+        if self.active > i || self.active == self.trees.len() {
+            self.active -= 1;
+        }
+        self.relayout();
+        Ok(())
+    }
+
+    // -------- Moving between tabs ----------------
+
+    // gt. Go to the next tab, or the tab with the given number
+    pub(crate) fn next(&mut self, number: Option<usize>) -> bool {
+        let i = match number {
+            Some(n) => match self.index_for_number(n) {
+                Ok(i) => i,
+                Err(_) => return false,
+            },
+            None => (self.active + 1) % self.trees.len(),
+        };
+        self.go_to(i)
+    }
+
+    // gT. Go back count tabs, going round to the last one after the first
+    pub(crate) fn prev(&mut self, count: usize) -> bool {
+        let len = self.trees.len();
+        self.go_to((self.active + len - count % len) % len)
+    }
+
+    // Index of the tab whose label is at a point in the tab bar
+    pub(crate) fn tab_at(&self, pos: Point2D<u32, PixelSize>) -> Option<usize> {
+        if self.trees.len() < 2 || !self.bar_rect().contains(pos) {
+            return None;
+        }
+        let mut x = self.rect.origin.x;
+        for (i, (_, _, shaped)) in self.labels.iter().enumerate() {
+            x += self.label_width(shaped);
+            if pos.x < x {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn go_to(&mut self, i: usize) -> bool {
+        if i == self.active || i >= self.trees.len() {
+            return false;
+        }
+        self.active = i;
+        true
+    }
+
+    // :tabmove. Move the active tab to after the tab with the given number, or by a number of
+    // tabs with + or -. It goes to the end without an argument
+    pub(crate) fn move_tab(&mut self, arg: Option<&str>) -> Result<(), String> {
+        let target = tab_move_target(arg, self.active, self.trees.len())?;
+        let tree = self.trees.remove(self.active);
+        self.trees.insert(target, tree);
+        self.active = target;
+        Ok(())
+    }
+
+    fn index_for_number(&self, number: usize) -> Result<usize, String> {
+        if number == 0 || number > self.trees.len() {
+            Err(format!("tab page {} does not exist", number))
+        } else {
+            Ok(number - 1)
+        }
+    }
+
+    // -------- Tab bar ----------------
+
+    fn bar_height(&self) -> u32 {
+        (self.ascender - self.descender) as u32
+            + self.view_params.config.prompt_padding_vertical * 2
+    }
+
+    fn bar_rect(&self) -> Rect<u32, PixelSize> {
+        Rect::new(
+            self.rect.origin,
+            size2(self.rect.size.width, self.bar_height()),
+        )
+    }
+
+    fn relayout(&mut self) {
+        let mut rect = self.rect;
+        if self.trees.len() > 1 {
+            let height = self.bar_height();
+            rect.origin.y += height;
+            rect.size.height = rect.size.height.saturating_sub(height);
+        }
+        for tree in &mut self.trees {
+            tree.set_rect(rect);
+        }
+    }
+
+    fn label_width(&self, shaped: &ShapedText) -> u32 {
+        shaped.width() as u32 + self.view_params.config.prompt_padding_horizontal * 2
+    }
+
+    // Labels have the tab's number, and the name of the active pane's buffer, with a + if it has
+    // changes which haven't been written
+    fn update_labels(&mut self) {
+        self.labels.truncate(self.trees.len());
+        for i in 0..self.trees.len() {
+            let pane = self.trees[i].active();
+            let name = pane.buffer_name();
+            let name = Path::new(&name)
+                .file_name()
+                .map_or(name.clone(), |name| name.to_string_lossy().into_owned());
+            let modified = if pane.is_modified() { " +" } else { "" };
+            let text = format!("{} {}{}", i + 1, name, modified);
+            let is_active = i == self.active;
+            match self.labels.get(i) {
+                Some((t, a, _)) if *t == text && *a == is_active => continue,
+                _ => {}
+            }
+            let shaped = self.shape_label(&text, is_active);
+            if i < self.labels.len() {
+                self.labels[i] = (text, is_active, shaped);
+            } else {
+                self.labels.push((text, is_active, shaped));
+            }
+        }
+    }
+
+    fn shape_label(&self, text: &str, is_active: bool) -> ShapedText {
+        let config = &self.view_params.config;
+        let color = if is_active {
+            self.theme.tab_bar.active_foreground
+        } else {
+            self.theme.tab_bar.foreground
+        };
+        let lc = text.chars().count();
+        let shaper = &mut *self.view_params.text_shaper.borrow_mut();
+        shaper.shape_line(
+            RopeOrStr::from(text),
+            self.view_params.dpi,
+            TAB_WIDTH,
+            &[(lc, config.prompt_face)],
+            &[(lc, TextStyle::default())],
+            &[(lc, config.prompt_font_size)],
+            &[(lc, color)],
+            &[(lc, None)],
+            &[(lc, TextAlignment::Left)],
+        )
+    }
+
+    fn draw_bar(&self, painter: &mut Painter) {
+        let config = &self.view_params.config;
+        let rect = self.bar_rect();
+        let shaper = &mut *self.view_params.text_shaper.borrow_mut();
+        let mut painter = painter.widget_ctx(rect.cast(), self.theme.tab_bar.background, false);
+        let mut x = 0;
+        for (i, (_, _, shaped)) in self.labels.iter().enumerate() {
+            let width = self.label_width(shaped);
+            if i == self.active {
+                let label_rect = Rect::new(point2(x, 0), size2(width, rect.size.height));
+                painter.color_quad(
+                    label_rect.cast(),
+                    self.theme.tab_bar.active_background,
+                    false,
+                );
+            }
+            let pos = point2(
+                (x + config.prompt_padding_horizontal) as i32,
+                config.prompt_padding_vertical as i32 + self.ascender,
+            );
+            painter.draw_shaped_text(
+                shaper,
+                pos,
+                shaped,
+                None,
+                width,
+                (self.ascender - self.descender) as u32,
+                TextWrap::None,
+            );
+            x += width;
+        }
+    }
+}
+
+// Index which :tabmove puts the active tab at, once it is taken out. Numbers count the tabs
+// before the move, so that "after tab N" is the same place for every tab
+fn tab_move_target(arg: Option<&str>, active: usize, len: usize) -> Result<usize, String> {
+    let invalid = || Err(format!("invalid tab page: {}", arg.unwrap_or("")));
+    let arg = match arg {
+        Some(arg) => arg,
+        None => return Ok(len - 1),
+    };
+    let (sign, digits) = match arg.chars().next() {
+        Some(c @ '+') | Some(c @ '-') => (Some(c), &arg[1..]),
+        _ => (None, arg),
+    };
+    let n = if digits.is_empty() && sign.is_some() {
+        1
+    } else {
+        match digits.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => return invalid(),
+        }
+    };
+    let target = match sign {
+        Some('+') => active + n,
+        Some(_) if n > active => return invalid(),
+        Some(_) => active - n,
+        None if n > len => return invalid(),
+        None if active < n => n - 1,
+        None => n,
+    };
+    if target >= len {
+        return invalid();
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tab_move_target() {
+        assert_eq!(tab_move_target(None, 1, 4), Ok(3));
+        assert_eq!(tab_move_target(Some("0"), 2, 4), Ok(0));
+        // Moving the second tab after the first or second tab leaves it where it is
+        assert_eq!(tab_move_target(Some("1"), 1, 4), Ok(1));
+        assert_eq!(tab_move_target(Some("2"), 1, 4), Ok(1));
+        assert_eq!(tab_move_target(Some("4"), 1, 4), Ok(3));
+        assert_eq!(tab_move_target(Some("1"), 3, 4), Ok(1));
+        assert_eq!(tab_move_target(Some("+1"), 1, 4), Ok(2));
+        assert_eq!(tab_move_target(Some("+"), 1, 4), Ok(2));
+        assert_eq!(tab_move_target(Some("-1"), 1, 4), Ok(0));
+        assert!(tab_move_target(Some("-2"), 1, 4).is_err());
+        assert!(tab_move_target(Some("+3"), 1, 4).is_err());
+        assert!(tab_move_target(Some("5"), 1, 4).is_err());
+        assert!(tab_move_target(Some("x"), 1, 4).is_err());
+    }
+}
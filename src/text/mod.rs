@@ -51,6 +51,38 @@ impl ShapedText {
         width
     }
 
+    // Columns at which to break the text into rows no wider than the given width. Rows break
+    // between words, and only break up a word which is too wide for a row by itself. Rows after
+    // the first start at the given indent
+    pub(crate) fn wrap_points(&self, width: i32, indent: i32) -> Vec<usize> {
+        let mut ret = Vec::new();
+        let (mut gidx, mut x, mut row_start) = (0, 0, 0);
+        for (clusters, _, _, _, _, _, _) in self.styled_iter() {
+            let word_width = clusters.width();
+            if x > row_start && x + word_width > width {
+                ret.push(gidx);
+                x = indent;
+                row_start = indent;
+            }
+            if x + word_width <= width {
+                x += word_width;
+                gidx += clusters.map(|clus| clus.num_graphemes).sum::<usize>();
+                continue;
+            }
+            for clus in clusters {
+                let clus_width = clus.glyph_infos.iter().fold(0, |a, x| a + x.advance.width);
+                if x > row_start && x + clus_width > width {
+                    ret.push(gidx);
+                    x = indent;
+                    row_start = indent;
+                }
+                x += clus_width;
+                gidx += clus.num_graphemes;
+            }
+        }
+        ret
+    }
+
     fn default() -> ShapedText {
         ShapedText {
             metrics: ShapedTextMetrics::default(),
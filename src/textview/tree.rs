@@ -1,21 +1,45 @@
 // (C) 2020 Srimanta Barua <srimanta.barua1@gmail.com>
 
 use std::cell::RefCell;
+use std::cmp::min;
+use std::mem;
 use std::rc::Rc;
 
-use euclid::{size2, Point2D, Rect};
+use euclid::{point2, size2, Point2D, Rect};
 
-use crate::buffer::{Buffer, BufferViewCreateParams, BufferViewID};
+use crate::buffer::{Buffer, BufferID, BufferViewCreateParams, BufferViewID};
 use crate::common::PixelSize;
+use crate::input::Direction;
 use crate::painter::Painter;
 use crate::theme::Theme;
 
 use super::TextPane;
 
+// Distance from a border between panes at which the mouse can still grab it
+const BORDER_GRAB_WIDTH: u32 = 4;
+
 pub(crate) struct TextTree {
     theme: Rc<Theme>,
     rect: Rect<u32, PixelSize>,
     root: Node,
+    // Pane which was active before the current one, for Ctrl-W p
+    previous: Option<usize>,
+    next_pane_id: usize,
+}
+
+// Border between two children of a node, which is being dragged with the mouse. The node is found
+// by going down through the children at each index in the path
+pub(crate) struct PaneBorder {
+    path: Vec<usize>,
+    index: usize,
+    vertical: bool,
+}
+
+impl PaneBorder {
+    // Whether the border is a vertical line, between panes on the left and right
+    pub(crate) fn is_vertical(&self) -> bool {
+        self.vertical
+    }
 }
 
 impl TextTree {
@@ -29,6 +53,8 @@ impl TextTree {
             rect: view_params.rect,
             root: Node::new_leaf(view_params, buf, view_id),
             theme,
+            previous: None,
+            next_pane_id: 1,
         }
     }
 
@@ -47,9 +73,7 @@ impl TextTree {
         optbuffer: Option<Rc<RefCell<Buffer>>>,
         view_id: BufferViewID,
     ) {
-        self.root.split_v(optbuffer, view_id);
-        self.root
-            .set_rect(self.rect, self.theme.textview.border_width);
+        self.split(Split::Vertical, optbuffer, view_id);
     }
 
     pub(crate) fn split_h(
@@ -57,9 +81,21 @@ impl TextTree {
         optbuffer: Option<Rc<RefCell<Buffer>>>,
         view_id: BufferViewID,
     ) {
-        self.root.split_h(optbuffer, view_id);
-        self.root
-            .set_rect(self.rect, self.theme.textview.border_width);
+        self.split(Split::Horizontal, optbuffer, view_id);
+    }
+
+    fn split(
+        &mut self,
+        split: Split,
+        optbuffer: Option<Rc<RefCell<Buffer>>>,
+        view_id: BufferViewID,
+    ) {
+        let old = self.active_id();
+        let pane_id = self.next_pane_id;
+        self.next_pane_id += 1;
+        self.root.split(split, optbuffer, view_id, pane_id);
+        self.note_switch(old);
+        self.relayout();
     }
 
     pub(crate) fn active(&self) -> &TextPane {
@@ -77,7 +113,9 @@ impl TextTree {
         if !self.rect.contains(pos) {
             return None;
         }
-        self.root.set_active_and_get_from_pos(pos)
+        let id = self.root.leaf_at(pos)?;
+        self.activate(id);
+        Some(self.root.active_mut())
     }
 
     pub(crate) fn set_hover(&mut self, optpoint: Option<Point2D<u32, PixelSize>>) {
@@ -90,9 +128,380 @@ impl TextTree {
     {
         self.root.map(f)
     }
+
+    // Take a buffer out of every pane. Panes with no other buffers show the replacement
+    pub(crate) fn remove_buffer<F>(
+        &mut self,
+        id: BufferID,
+        replacement: &Rc<RefCell<Buffer>>,
+        f: &mut F,
+    ) where
+        F: FnMut() -> BufferViewID,
+    {
+        self.root.remove_buffer(id, replacement, f);
+    }
+
+    // -------- Moving between panes ----------------
+
+    // Ctrl-W h/j/k/l. Go count panes in a direction, starting from the top or left edge of the
+    // active pane
+    pub(crate) fn focus(&mut self, dir: Direction, count: usize) -> bool {
+        let border_width = self.theme.textview.border_width;
+        let old = self.active_id();
+        for _ in 0..count {
+            let rect = self.active().rect();
+            let pos = match dir {
+                Direction::Left if rect.min_x() > border_width => {
+                    point2(rect.min_x() - border_width - 1, rect.min_y())
+                }
+                Direction::Up if rect.min_y() > border_width => {
+                    point2(rect.min_x(), rect.min_y() - border_width - 1)
+                }
+                Direction::Right => point2(rect.max_x() + border_width, rect.min_y()),
+                Direction::Down => point2(rect.min_x(), rect.max_y() + border_width),
+                _ => break,
+            };
+            if !self.rect.contains(pos) {
+                break;
+            }
+            match self.root.leaf_at(pos) {
+                Some(id) => self.root.activate(id),
+                None => break,
+            };
+        }
+        self.note_switch(old)
+    }
+
+    // Ctrl-W w and W. Go to the next or previous pane, or to the pane with the given number
+    pub(crate) fn cycle(&mut self, forward: bool, number: Option<usize>) -> bool {
+        let mut ids = Vec::new();
+        self.root.pane_ids(&mut ids);
+        let cur = ids.iter().position(|id| *id == self.active_id()).unwrap();
+        let i = match number {
+            Some(n) => min(n.max(1), ids.len()) - 1,
+            None if forward => (cur + 1) % ids.len(),
+            None => (cur + ids.len() - 1) % ids.len(),
+        };
+        self.activate(ids[i])
+    }
+
+    // Ctrl-W p
+    pub(crate) fn go_to_previous(&mut self) -> bool {
+        match self.previous {
+            Some(id) => self.activate(id),
+            None => false,
+        }
+    }
+
+    fn active_id(&self) -> usize {
+        self.root.active_leaf().pane_id
+    }
+
+    fn activate(&mut self, id: usize) -> bool {
+        let old = self.active_id();
+        self.root.activate(id);
+        self.note_switch(old)
+    }
+
+    // Remember the pane which was active before, if the active pane changed
+    fn note_switch(&mut self, old: usize) -> bool {
+        if self.active_id() == old {
+            return false;
+        }
+        self.previous = Some(old);
+        true
+    }
+
+    // -------- Moving panes ----------------
+
+    // Ctrl-W x. Swap the active pane with the next one in its row or column, or the one with the
+    // given number. The cursor stays where it is on the screen, in the other pane
+    pub(crate) fn exchange(&mut self, number: Option<usize>) -> bool {
+        let node = match self.root.active_parent_mut() {
+            Some(node) => node,
+            None => return false,
+        };
+        let i = node.active;
+        let j = match number {
+            Some(n) => n.saturating_sub(1),
+            None if i + 1 < node.children.len() => i + 1,
+            None => i - 1,
+        };
+        if i == j || j >= node.children.len() {
+            return false;
+        }
+        node.children.swap(i, j);
+        self.relayout();
+        true
+    }
+
+    // Ctrl-W r and R. Rotate the panes in the active pane's row or column count times, downwards
+    // or rightwards if forward. The active pane moves with them
+    pub(crate) fn rotate(&mut self, forward: bool, count: usize) -> bool {
+        let node = match self.root.active_parent_mut() {
+            Some(node) => node,
+            None => return false,
+        };
+        let len = node.children.len();
+        let n = count % len;
+        if forward {
+            node.children.rotate_right(n);
+            node.active = (node.active + n) % len;
+        } else {
+            node.children.rotate_left(n);
+            node.active = (node.active + len - n) % len;
+        }
+        self.relayout();
+        true
+    }
+
+    // Ctrl-W H/J/K/L. Move the active pane to an edge, taking up the full height or width
+    pub(crate) fn move_to_edge(&mut self, dir: Direction) -> bool {
+        if self.root.is_leaf() {
+            return false;
+        }
+        let leaf = self.root.take_active();
+        self.root.tidy();
+        let split = match dir {
+            Direction::Left | Direction::Right => Split::Vertical,
+            Direction::Up | Direction::Down => Split::Horizontal,
+        };
+        let at_start = dir == Direction::Left || dir == Direction::Up;
+        if !self.root.is_leaf() && self.root.split == split {
+            let root = &mut self.root;
+            let ratio = root.ratios.iter().sum::<f32>() / root.ratios.len() as f32;
+            let i = if at_start { 0 } else { root.children.len() };
+            root.children.insert(i, leaf);
+            root.ratios.insert(i, ratio);
+            root.active = i;
+        } else {
+            let rest = mem::replace(&mut self.root, Node::empty());
+            let ratio = rest.span(split) as f32;
+            let (children, ratios, active) = if at_start {
+                (vec![leaf, rest], vec![1.0, ratio], 0)
+            } else {
+                (vec![rest, leaf], vec![ratio, 1.0], 1)
+            };
+            self.root = Node {
+                rect: self.rect,
+                split,
+                children,
+                ratios,
+                active,
+                opt_view: None,
+                pane_id: 0,
+            };
+        }
+        self.relayout();
+        true
+    }
+
+    // -------- Closing panes ----------------
+
+    // :close and Ctrl-W c. The last pane can't be closed. Buffers which have changes, and aren't
+    // shown in any other pane, would be lost, so that needs to be forced
+    pub(crate) fn close(&mut self, force: bool) -> Result<(), String> {
+        if self.root.is_leaf() {
+            return Err("cannot close the last pane".to_owned());
+        }
+        if !force {
+            check_unsaved(&[self.root.active_leaf()])?;
+        }
+        let closed = self.active_id();
+        self.root.take_active();
+        self.root.tidy();
+        if let Some(id) = self.previous.take() {
+            if id != closed {
+                self.root.activate(id);
+            }
+        }
+        self.relayout();
+        Ok(())
+    }
+
+    // :only and Ctrl-W o. Close every pane but the active one
+    pub(crate) fn only(&mut self, force: bool) -> Result<(), String> {
+        let active = self.active_id();
+        if !force {
+            let mut leaves = Vec::new();
+            self.root.leaves(&mut leaves);
+            leaves.retain(|leaf| leaf.pane_id != active);
+            check_unsaved(&leaves)?;
+        }
+        let mut node = mem::replace(&mut self.root, Node::empty());
+        while !node.is_leaf() {
+            let i = node.active;
+            node = node.children.swap_remove(i);
+        }
+        self.root = node;
+        self.previous = None;
+        self.relayout();
+        Ok(())
+    }
+
+    // Error for changes which would be lost by closing every pane, when closing the tab page
+    pub(crate) fn check_close(&self) -> Result<(), String> {
+        let mut leaves = Vec::new();
+        self.root.leaves(&mut leaves);
+        check_unsaved(&leaves)
+    }
+
+    // -------- Resizing panes ----------------
+
+    // Ctrl-W =. Give all panes the same width and height
+    pub(crate) fn equalize(&mut self) {
+        self.root.equalize();
+        self.relayout();
+    }
+
+    // Ctrl-W +/- and >/<. Grow or shrink the active pane by count rows (if vertical) or columns.
+    // The space comes from the panes after it first, and then from the ones before it
+    pub(crate) fn resize(&mut self, vertical: bool, count: i32) -> bool {
+        let cell = self.active().cell_size();
+        let (split, step) = if vertical {
+            (Split::Horizontal, cell.height)
+        } else {
+            (Split::Vertical, cell.width)
+        };
+        let border_width = self.theme.textview.border_width;
+        let delta = count as i64 * step as i64;
+        if self.root.resize_active(split, delta, step, border_width) {
+            self.relayout();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Border near a point, which the mouse can drag
+    pub(crate) fn border_at(&self, pos: Point2D<u32, PixelSize>) -> Option<PaneBorder> {
+        if !self.rect.contains(pos) {
+            return None;
+        }
+        let mut path = Vec::new();
+        self.root
+            .border_at(pos, self.theme.textview.border_width, &mut path)
+    }
+
+    // Move a border to the mouse. Panes stay at least a row or column across
+    pub(crate) fn drag_border(
+        &mut self,
+        border: &PaneBorder,
+        pos: Point2D<u32, PixelSize>,
+    ) -> bool {
+        let cell = self.active().cell_size();
+        let border_width = self.theme.textview.border_width;
+        let mut node = &mut self.root;
+        for i in &border.path {
+            node = match node.children.get_mut(*i) {
+                Some(node) => node,
+                None => return false,
+            };
+        }
+        if border.index + 1 >= node.children.len() {
+            return false;
+        }
+        let (pos, origin, min_size) = if border.vertical {
+            (pos.x, node.rect.min_x(), cell.width)
+        } else {
+            (pos.y, node.rect.min_y(), cell.height)
+        };
+        let mut sizes = node.sizes(border_width);
+        let end = origin
+            + sizes[..=border.index].iter().sum::<u32>()
+            + border.index as u32 * border_width;
+        let delta = pos as i64 - end as i64;
+        if !move_border(&mut sizes, border.index, delta, min_size) {
+            return false;
+        }
+        node.ratios = sizes.iter().map(|size| *size as f32).collect();
+        self.relayout();
+        true
+    }
+
+    fn relayout(&mut self) {
+        self.root
+            .set_rect(self.rect, self.theme.textview.border_width);
+    }
+}
+
+// Error for the first buffer with changes which is only shown in the given panes
+fn check_unsaved(leaves: &[&Node]) -> Result<(), String> {
+    let buffers = leaves
+        .iter()
+        .copied()
+        .flat_map(|leaf| leaf.opt_view.as_ref().unwrap().buffers())
+        .collect::<Vec<_>>();
+    for buf in &buffers {
+        let count = buffers.iter().filter(|b| Rc::ptr_eq(b, buf)).count();
+        let buf_ref = buf.borrow();
+        if buf_ref.is_modified() && Rc::strong_count(buf) == count {
+            return Err(format!(
+                "no write since last change for buffer \"{}\" (add ! to override)",
+                buf_ref.name()
+            ));
+        }
+    }
+    Ok(())
 }
 
-#[derive(PartialEq, Eq)]
+// Split a length between children with the given ratios
+fn split_sizes(ratios: &[f32], avail: u32) -> Vec<u32> {
+    let total = ratios.iter().map(|r| *r as f64).sum::<f64>();
+    let (mut acc, mut prev) = (0.0, 0);
+    let mut ret = ratios
+        .iter()
+        .map(|ratio| {
+            acc += *ratio as f64;
+            let end = min((avail as f64 * acc / total).round() as u32, avail);
+            let size = end - prev;
+            prev = end;
+            size
+        })
+        .collect::<Vec<_>>();
+    if let Some(last) = ret.last_mut() {
+        *last += avail - prev;
+    }
+    ret
+}
+
+// Grow (for a positive delta) or shrink child i. It grows into the children after it first, and
+// shrinks into the one after it, or the one before it if it is the last
+fn resize_sizes(sizes: &mut [u32], i: usize, delta: i64, min_size: u32) -> bool {
+    if sizes.len() < 2 {
+        return false;
+    }
+    let old = sizes[i];
+    if delta >= 0 {
+        let mut want = delta as u32;
+        for j in (i + 1..sizes.len()).chain((0..i).rev()) {
+            let take = min(want, sizes[j].saturating_sub(min_size));
+            sizes[j] -= take;
+            sizes[i] += take;
+            want -= take;
+        }
+    } else {
+        let give = min((-delta) as u32, sizes[i].saturating_sub(min_size));
+        let j = if i + 1 < sizes.len() { i + 1 } else { i - 1 };
+        sizes[i] -= give;
+        sizes[j] += give;
+    }
+    sizes[i] != old
+}
+
+// Move the border after child i, only changing the children on either side of it
+fn move_border(sizes: &mut [u32], i: usize, delta: i64, min_size: u32) -> bool {
+    let delta = if delta >= 0 {
+        min(delta as u32, sizes[i + 1].saturating_sub(min_size)) as i64
+    } else {
+        -(min((-delta) as u32, sizes[i].saturating_sub(min_size)) as i64)
+    };
+    sizes[i] = (sizes[i] as i64 + delta) as u32;
+    sizes[i + 1] = (sizes[i + 1] as i64 - delta) as u32;
+    delta != 0
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum Split {
     None,
     Horizontal, // horizontal line between two panes, top and bottom
@@ -103,8 +512,12 @@ struct Node {
     rect: Rect<u32, PixelSize>,
     split: Split,
     children: Vec<Node>,
+    // Share of the node's width or height which each child gets
+    ratios: Vec<f32>,
     active: usize,
     opt_view: Option<TextPane>,
+    // Identifies leaves, so that the previous pane can be found again
+    pane_id: usize,
 }
 
 impl Node {
@@ -113,22 +526,31 @@ impl Node {
         buf: Rc<RefCell<Buffer>>,
         id: BufferViewID,
     ) -> Node {
+        Node::leaf_with(TextPane::new(view_params, buf, id), 0)
+    }
+
+    fn leaf_with(view: TextPane, pane_id: usize) -> Node {
         Node {
-            rect: view_params.rect,
+            rect: view.rect(),
             split: Split::None,
             children: Vec::new(),
+            ratios: Vec::new(),
             active: 0,
-            opt_view: Some(TextPane::new(view_params, buf, id)),
+            opt_view: Some(view),
+            pane_id,
         }
     }
 
-    fn leaf_with(view: TextPane) -> Node {
+    // Stands in for a node which is being moved
+    fn empty() -> Node {
         Node {
-            rect: view.rect(),
+            rect: Rect::zero(),
             split: Split::None,
             children: Vec::new(),
+            ratios: Vec::new(),
             active: 0,
-            opt_view: Some(view),
+            opt_view: None,
+            pane_id: 0,
         }
     }
 
@@ -151,6 +573,22 @@ impl Node {
         }
     }
 
+    fn remove_buffer<F>(&mut self, id: BufferID, replacement: &Rc<RefCell<Buffer>>, f: &mut F)
+    where
+        F: FnMut() -> BufferViewID,
+    {
+        if self.is_leaf() {
+            self.opt_view
+                .as_mut()
+                .unwrap()
+                .remove_buffer(id, replacement, f);
+        } else {
+            for c in &mut self.children {
+                c.remove_buffer(id, replacement, f);
+            }
+        }
+    }
+
     fn set_hover(&mut self, optpoint: Option<Point2D<u32, PixelSize>>) {
         if self.is_leaf() {
             self.opt_view.as_mut().unwrap().set_hover(optpoint);
@@ -184,52 +622,50 @@ impl Node {
         }
     }
 
-    fn split_h(&mut self, optbuffer: Option<Rc<RefCell<Buffer>>>, view_id: BufferViewID) {
+    // The new pane goes above or to the left of the active one, and the two share its space
+    fn split(
+        &mut self,
+        split: Split,
+        optbuffer: Option<Rc<RefCell<Buffer>>>,
+        view_id: BufferViewID,
+        pane_id: usize,
+    ) {
         if self.is_leaf() {
             let view = self.opt_view.take().unwrap();
             self.active = 0;
             self.children
-                .push(Node::leaf_with(view.clone(optbuffer, view_id)));
-            self.children.push(Node::leaf_with(view));
-            self.split = Split::Horizontal;
-        } else if self.split == Split::Horizontal {
-            if self.children[self.active].is_leaf() {
-                let view = self.children[self.active]
-                    .opt_view
-                    .as_ref()
-                    .unwrap()
-                    .clone(optbuffer, view_id);
-                self.children.insert(self.active, Node::leaf_with(view));
-            } else {
-                self.children[self.active].split_h(optbuffer, view_id);
-            }
+                .push(Node::leaf_with(view.clone(optbuffer, view_id), pane_id));
+            self.children.push(Node::leaf_with(view, self.pane_id));
+            self.ratios = vec![1.0, 1.0];
+            self.split = split;
+        } else if self.split == split && self.children[self.active].is_leaf() {
+            let view = self.children[self.active]
+                .opt_view
+                .as_ref()
+                .unwrap()
+                .clone(optbuffer, view_id);
+            let ratio = self.ratios[self.active] / 2.0;
+            self.ratios[self.active] = ratio;
+            self.children
+                .insert(self.active, Node::leaf_with(view, pane_id));
+            self.ratios.insert(self.active, ratio);
         } else {
-            self.children[self.active].split_h(optbuffer, view_id);
+            self.children[self.active].split(split, optbuffer, view_id, pane_id);
         }
     }
 
-    fn split_v(&mut self, optbuffer: Option<Rc<RefCell<Buffer>>>, view_id: BufferViewID) {
-        if self.is_leaf() {
-            let view = self.opt_view.take().unwrap();
-            self.active = 0;
-            self.children
-                .push(Node::leaf_with(view.clone(optbuffer, view_id)));
-            self.children.push(Node::leaf_with(view));
-            self.split = Split::Vertical;
-        } else if self.split == Split::Vertical {
-            if self.children[self.active].is_leaf() {
-                let view = self.children[self.active]
-                    .opt_view
-                    .as_ref()
-                    .unwrap()
-                    .clone(optbuffer, view_id);
-                self.children.insert(self.active, Node::leaf_with(view));
-            } else {
-                self.children[self.active].split_v(optbuffer, view_id);
-            }
+    // Sizes of the children along the direction of the split
+    fn sizes(&self, border_width: u32) -> Vec<u32> {
+        let num_c = self.children.len() as u32;
+        let total = if self.split == Split::Vertical {
+            self.rect.size.width
         } else {
-            self.children[self.active].split_v(optbuffer, view_id);
-        }
+            self.rect.size.height
+        };
+        split_sizes(
+            &self.ratios,
+            total.saturating_sub(border_width * (num_c - 1)),
+        )
     }
 
     fn set_rect(&mut self, rect: Rect<u32, PixelSize>, border_width: u32) {
@@ -237,34 +673,16 @@ impl Node {
         if self.is_leaf() {
             self.opt_view.as_mut().unwrap().set_rect(rect);
         } else {
-            let num_c = self.children.len() as u32;
+            let sizes = self.sizes(border_width);
             let mut origin = rect.origin;
-            if self.split == Split::Vertical {
-                let rem_w = rect.size.width - border_width * (num_c - 1);
-                let num_p1 = (rem_w % num_c) as usize;
-                let w_per_c = rem_w / num_c;
-                let mut size = size2(0, rect.size.height);
-                for i in 0..self.children.len() {
-                    if i < num_p1 {
-                        size.width = w_per_c + 1;
-                    } else {
-                        size.width = w_per_c;
-                    }
-                    self.children[i].set_rect(Rect::new(origin, size), border_width);
+            for (child, size) in self.children.iter_mut().zip(sizes) {
+                if self.split == Split::Vertical {
+                    let size = size2(size, rect.size.height);
+                    child.set_rect(Rect::new(origin, size), border_width);
                     origin.x += size.width + border_width;
-                }
-            } else {
-                let rem_h = rect.size.height - border_width * (num_c - 1);
-                let num_p1 = (rem_h % num_c) as usize;
-                let h_per_c = rem_h / num_c;
-                let mut size = size2(rect.size.width, 0);
-                for i in 0..self.children.len() {
-                    if i < num_p1 {
-                        size.height = h_per_c + 1;
-                    } else {
-                        size.height = h_per_c;
-                    }
-                    self.children[i].set_rect(Rect::new(origin, size), border_width);
+                } else {
+                    let size = size2(rect.size.width, size);
+                    child.set_rect(Rect::new(origin, size), border_width);
                     origin.y += size.height + border_width;
                 }
             }
@@ -287,20 +705,230 @@ impl Node {
         }
     }
 
-    fn set_active_and_get_from_pos(
+    fn active_leaf(&self) -> &Node {
+        if self.is_leaf() {
+            self
+        } else {
+            self.children[self.active].active_leaf()
+        }
+    }
+
+    // Node which the active pane is a child of
+    fn active_parent_mut(&mut self) -> Option<&mut Node> {
+        if self.is_leaf() {
+            return None;
+        }
+        if self.children[self.active].is_leaf() {
+            return Some(self);
+        }
+        self.children[self.active].active_parent_mut()
+    }
+
+    fn leaves<'a>(&'a self, ret: &mut Vec<&'a Node>) {
+        if self.is_leaf() {
+            ret.push(self);
+        } else {
+            for c in &self.children {
+                c.leaves(ret);
+            }
+        }
+    }
+
+    fn pane_ids(&self, ret: &mut Vec<usize>) {
+        let mut leaves = Vec::new();
+        self.leaves(&mut leaves);
+        ret.extend(leaves.iter().map(|leaf| leaf.pane_id));
+    }
+
+    fn leaf_at(&self, pos: Point2D<u32, PixelSize>) -> Option<usize> {
+        if self.is_leaf() {
+            return Some(self.pane_id);
+        }
+        self.children
+            .iter()
+            .find(|c| c.rect.contains(pos))
+            .and_then(|c| c.leaf_at(pos))
+    }
+
+    // Make the pane with the given ID active. Returns false if it isn't in this node
+    fn activate(&mut self, id: usize) -> bool {
+        if self.is_leaf() {
+            return self.pane_id == id;
+        }
+        for i in 0..self.children.len() {
+            if self.children[i].activate(id) {
+                self.active = i;
+                return true;
+            }
+        }
+        false
+    }
+
+    // Take the active pane out of the node, which can't be a leaf. The pane after it, or the one
+    // before it if it was the last, becomes active. Use tidy() after this
+    fn take_active(&mut self) -> Node {
+        let i = self.active;
+        if !self.children[i].is_leaf() {
+            return self.children[i].take_active();
+        }
+        self.ratios.remove(i);
+        let ret = self.children.remove(i);
+        if self.active == self.children.len() {
+            self.active -= 1;
+        }
+        ret
+    }
+
+    // Nodes with only one child are replaced by the child, and children split the same way as
+    // their parent are merged into it
+    fn tidy(&mut self) {
+        if self.is_leaf() {
+            return;
+        }
+        for c in &mut self.children {
+            c.tidy();
+        }
+        let mut i = 0;
+        while i < self.children.len() {
+            if self.children[i].is_leaf() || self.children[i].split != self.split {
+                i += 1;
+                continue;
+            }
+            let child = self.children.remove(i);
+            let ratio = self.ratios.remove(i);
+            let total = child.ratios.iter().sum::<f32>();
+            let num_c = child.children.len();
+            if self.active == i {
+                self.active += child.active;
+            } else if self.active > i {
+                self.active += num_c - 1;
+            }
+            for (j, (c, r)) in child.children.into_iter().zip(child.ratios).enumerate() {
+                self.children.insert(i + j, c);
+                self.ratios.insert(i + j, ratio * r / total);
+            }
+            i += num_c;
+        }
+        if self.children.len() == 1 {
+            *self = self.children.pop().unwrap();
+        }
+    }
+
+    // Number of panes across the node, in the direction of the split
+    fn span(&self, split: Split) -> usize {
+        if self.is_leaf() {
+            1
+        } else if self.split == split {
+            self.children.iter().map(|c| c.span(split)).sum()
+        } else {
+            self.children.iter().map(|c| c.span(split)).max().unwrap()
+        }
+    }
+
+    fn equalize(&mut self) {
+        if self.is_leaf() {
+            return;
+        }
+        let split = self.split;
+        self.ratios = self.children.iter().map(|c| c.span(split) as f32).collect();
+        for c in &mut self.children {
+            c.equalize();
+        }
+    }
+
+    // Resize the active pane in the closest node split the given way
+    fn resize_active(
         &mut self,
+        split: Split,
+        delta: i64,
+        min_size: u32,
+        border_width: u32,
+    ) -> bool {
+        if self.is_leaf() {
+            return false;
+        }
+        if self.children[self.active].resize_active(split, delta, min_size, border_width) {
+            return true;
+        }
+        if self.split != split {
+            return false;
+        }
+        let mut sizes = self.sizes(border_width);
+        if !resize_sizes(&mut sizes, self.active, delta, min_size) {
+            return false;
+        }
+        self.ratios = sizes.iter().map(|size| *size as f32).collect();
+        true
+    }
+
+    fn border_at(
+        &self,
         pos: Point2D<u32, PixelSize>,
-    ) -> Option<&mut TextPane> {
+        border_width: u32,
+        path: &mut Vec<usize>,
+    ) -> Option<PaneBorder> {
         if self.is_leaf() {
-            self.opt_view.as_mut()
+            return None;
+        }
+        let vertical = self.split == Split::Vertical;
+        let (pos_main, origin) = if vertical {
+            (pos.x, self.rect.min_x())
         } else {
-            for i in 0..self.children.len() {
-                if self.children[i].rect.contains(pos) {
-                    self.active = i;
-                    return self.children[i].set_active_and_get_from_pos(pos);
-                }
+            (pos.y, self.rect.min_y())
+        };
+        let mut end = origin;
+        let sizes = self.sizes(border_width);
+        for (i, size) in sizes.iter().enumerate().take(sizes.len() - 1) {
+            end += size;
+            let start = end.saturating_sub(BORDER_GRAB_WIDTH);
+            if pos_main >= start && pos_main < end + border_width + BORDER_GRAB_WIDTH {
+                return Some(PaneBorder {
+                    path: path.clone(),
+                    index: i,
+                    vertical,
+                });
             }
-            None
+            end += border_width;
         }
+        let i = self.children.iter().position(|c| c.rect.contains(pos))?;
+        path.push(i);
+        self.children[i].border_at(pos, border_width, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_sizes() {
+        assert_eq!(split_sizes(&[1.0, 1.0], 11), vec![6, 5]);
+        assert_eq!(split_sizes(&[1.0, 1.0, 1.0], 100), vec![33, 34, 33]);
+        assert_eq!(split_sizes(&[30.0, 70.0], 200), vec![60, 140]);
+        // Sizes which are used as ratios come back out the same
+        assert_eq!(split_sizes(&[17.0, 40.0, 43.0], 100), vec![17, 40, 43]);
+    }
+
+    #[test]
+    fn test_resize_sizes() {
+        let mut sizes = [40, 30, 30];
+        assert!(resize_sizes(&mut sizes, 1, 25, 10));
+        assert_eq!(sizes, [35, 55, 10]);
+        // Nothing is left to take
+        assert!(!resize_sizes(&mut sizes, 1, 50, 35));
+        assert!(resize_sizes(&mut sizes, 2, -20, 5));
+        assert_eq!(sizes, [35, 60, 5]);
+        let mut sizes = [50];
+        assert!(!resize_sizes(&mut sizes, 0, 10, 1));
+    }
+
+    #[test]
+    fn test_move_border() {
+        let mut sizes = [40, 30, 30];
+        assert!(move_border(&mut sizes, 0, 10, 5));
+        assert_eq!(sizes, [50, 20, 30]);
+        assert!(move_border(&mut sizes, 1, -100, 5));
+        assert_eq!(sizes, [50, 5, 45]);
+        assert!(!move_border(&mut sizes, 0, 20, 5));
     }
 }
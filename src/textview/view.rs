@@ -5,11 +5,14 @@ use std::ops::Drop;
 use std::rc::Rc;
 use std::time::Duration;
 
-use euclid::{vec2, Point2D, Rect, Vector2D};
+use euclid::{vec2, Point2D, Rect, Size2D, Vector2D};
 
 use crate::buffer::{Buffer, BufferID, BufferViewCreateParams, BufferViewID, CursorStyle};
 use crate::common::PixelSize;
-use crate::input::{ComplAction, MotionOrObj};
+use crate::input::{
+    ComplAction, Fold, MotionOrObj, Operator, ScreenPos, Scroll, Surround, SurroundEdit,
+    SurroundTarget, VisualMode,
+};
 use crate::painter::Painter;
 
 struct TextView {
@@ -44,10 +47,10 @@ impl TextView {
         }
     }
 
-    fn move_cursor(&mut self, mo: MotionOrObj) {
+    fn move_cursor(&mut self, mo: MotionOrObj) -> bool {
         {
             let buffer = &mut *self.buffer.borrow_mut();
-            buffer.move_view_cursor(&self.id, mo);
+            buffer.move_view_cursor(&self.id, mo)
         }
     }
 
@@ -65,6 +68,265 @@ impl TextView {
         }
     }
 
+    fn operate(&mut self, op: Operator, mo: MotionOrObj, register: Option<char>) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_operate(&self.id, op, mo, register);
+        }
+    }
+
+    fn put(&mut self, register: Option<char>, count: usize, before: bool) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_put(&self.id, register, count, before);
+        }
+    }
+
+    fn start_selection(&mut self, mode: VisualMode) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_start_selection(&self.id, mode);
+        }
+    }
+
+    fn stop_selection(&mut self) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_stop_selection(&self.id);
+        }
+    }
+
+    fn operate_selection(&mut self, op: Operator, register: Option<char>) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_operate_selection(&self.id, op, register);
+        }
+    }
+
+    fn join_selection(&mut self, spaces: bool) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_join_selection(&self.id, spaces);
+        }
+    }
+
+    fn replace_char(&mut self, c: char, count: usize) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_replace_char(&self.id, c, count)
+        }
+    }
+
+    fn join(&mut self, spaces: bool, count: usize) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_join(&self.id, spaces, count)
+        }
+    }
+
+    fn open_line(&mut self, above: bool) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_open_line(&self.id, above)
+        }
+    }
+
+    fn toggle_case(&mut self, count: usize) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_toggle_case(&self.id, count);
+        }
+    }
+
+    fn add_to_number(&mut self, delta: i64) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_add_to_number(&self.id, delta)
+        }
+    }
+
+    fn surround(&mut self, edit: SurroundEdit, surround: &Surround) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_surround(&self.id, edit, surround)
+        }
+    }
+
+    fn delete_surround(&mut self, target: SurroundTarget) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_delete_surround(&self.id, target)
+        }
+    }
+
+    fn start_replace(&mut self) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_start_replace(&self.id);
+        }
+    }
+
+    fn replace_mode_char(&mut self, c: char) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_replace_mode_char(&self.id, c);
+        }
+    }
+
+    fn replace_mode_backspace(&mut self) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_replace_mode_backspace(&self.id);
+        }
+    }
+
+    fn search(&mut self, pattern: &str, forward: bool, count: usize) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_search(&self.id, pattern, forward, count)
+        }
+    }
+
+    fn search_word(&mut self, forward: bool, count: usize) -> Option<String> {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_search_word(&self.id, forward, count)
+        }
+    }
+
+    fn search_line(&self, pattern: &str, forward: bool) -> Result<usize, String> {
+        {
+            let buffer = &*self.buffer.borrow();
+            buffer.view_search_line(&self.id, pattern, forward)
+        }
+    }
+
+    fn search_preview(&mut self, pattern: &str, forward: bool) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_search_preview(&self.id, pattern, forward);
+        }
+    }
+
+    fn stop_search_preview(&mut self) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_stop_search_preview(&self.id);
+        }
+    }
+
+    fn substitute(
+        &mut self,
+        lines: (usize, usize),
+        pattern: &str,
+        replacement: &str,
+        global: bool,
+        ignore_case: bool,
+    ) -> Result<usize, String> {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_substitute(&self.id, lines, pattern, replacement, global, ignore_case)
+        }
+    }
+
+    fn cursor_line(&self) -> usize {
+        {
+            let buffer = &*self.buffer.borrow();
+            buffer.view_cursor_line(&self.id)
+        }
+    }
+
+    fn set_mark(&mut self, name: char) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_set_mark(&self.id, name)
+        }
+    }
+
+    fn mark_line(&self, name: char) -> Option<usize> {
+        {
+            let buffer = &*self.buffer.borrow();
+            buffer.view_mark_line(&self.id, name)
+        }
+    }
+
+    fn mark_insert_stop(&mut self) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_mark_insert_stop(&self.id);
+        }
+    }
+
+    fn changelist(&mut self, older: bool, n: usize) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_changelist(&self.id, older, n)
+        }
+    }
+
+    fn cursor_cidx(&self) -> usize {
+        {
+            let buffer = &*self.buffer.borrow();
+            buffer.view_cursor_cidx(&self.id)
+        }
+    }
+
+    fn add_jump(&mut self, jump_id: usize, cidx: usize) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_add_jump(&self.id, jump_id, cidx);
+        }
+    }
+
+    fn remove_jump(&mut self, jump_id: usize) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_remove_jump(&self.id, jump_id);
+        }
+    }
+
+    fn jump_line(&self, jump_id: usize) -> Option<usize> {
+        {
+            let buffer = &*self.buffer.borrow();
+            buffer.view_jump_line(&self.id, jump_id)
+        }
+    }
+
+    fn go_to_jump(&mut self, jump_id: usize) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_go_to_jump(&self.id, jump_id)
+        }
+    }
+
+    fn len_lines(&self) -> usize {
+        {
+            let buffer = &*self.buffer.borrow();
+            buffer.len_lines()
+        }
+    }
+
+    fn undo(&mut self, n: usize) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_undo(&self.id, n);
+        }
+    }
+
+    fn redo(&mut self, n: usize) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_redo(&self.id, n);
+        }
+    }
+
+    fn commit_undo_group(&mut self) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.commit_undo_group();
+        }
+    }
+
     fn move_cursor_to_point(&mut self, point: Point2D<u32, PixelSize>) {
         {
             let buffer = &mut *self.buffer.borrow_mut();
@@ -79,6 +341,55 @@ impl TextView {
         }
     }
 
+    fn scroll_page(&mut self, scroll: Scroll, count: Option<usize>) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_scroll_page(&self.id, scroll, count)
+        }
+    }
+
+    fn scroll_cursor(&mut self, pos: ScreenPos) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_scroll_cursor(&self.id, pos);
+        }
+    }
+
+    fn fold(&mut self, fold: Fold) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_fold(&self.id, fold)
+        }
+    }
+
+    fn add_cursor(&mut self, below: bool, count: usize) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_add_cursor_line(&self.id, below, count)
+        }
+    }
+
+    fn add_cursor_at_word(&mut self, count: usize) -> bool {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_add_cursor_at_word(&self.id, count)
+        }
+    }
+
+    fn split_selection(&mut self) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_split_selection(&self.id);
+        }
+    }
+
+    fn clear_cursors(&mut self) {
+        {
+            let buffer = &mut *self.buffer.borrow_mut();
+            buffer.view_clear_cursors(&self.id);
+        }
+    }
+
     fn set_cursor_visible(&mut self, visible: bool) {
         {
             let buffer = &mut *self.buffer.borrow_mut();
@@ -100,6 +411,13 @@ impl TextView {
         }
     }
 
+    fn cell_size(&self) -> Size2D<u32, PixelSize> {
+        {
+            let buffer = &*self.buffer.borrow();
+            buffer.view_cell_size(&self.id)
+        }
+    }
+
     fn buffer_id(&self) -> BufferID {
         {
             let buffer = &*self.buffer.borrow();
@@ -107,6 +425,20 @@ impl TextView {
         }
     }
 
+    fn buffer_name(&self) -> String {
+        {
+            let buffer = &*self.buffer.borrow();
+            buffer.name().to_owned()
+        }
+    }
+
+    fn is_modified(&self) -> bool {
+        {
+            let buffer = &*self.buffer.borrow();
+            buffer.is_modified()
+        }
+    }
+
     fn check_redraw(&mut self) -> bool {
         {
             let buffer = &mut *self.buffer.borrow_mut();
@@ -150,22 +482,66 @@ impl Drop for TextView {
     }
 }
 
+const MAX_JUMPS: usize = 100;
+
+// Where a jump started, to add to the jumplist if the jump succeeds
+#[derive(Clone, Copy)]
+pub(crate) struct JumpOrigin {
+    view: usize,
+    cidx: usize,
+}
+
 pub(crate) struct TextPane {
     scroll_vel: Vector2D<f64, PixelSize>,
     params: BufferViewCreateParams,
     views: Vec<TextView>,
     active: usize,
+    // Jumplist, oldest first, as view indices and jump IDs. The positions are kept by the views,
+    // so that they move with edits
+    jumps: Vec<(usize, usize)>,
+    jump_idx: usize,
+    next_jump_id: usize,
+    // Buffer which was active before the current one, for Ctrl-^
+    alternate: Option<BufferID>,
 }
 
 impl TextPane {
-    pub(crate) fn move_cursor(&mut self, mo: MotionOrObj) {
-        self.views[self.active].move_cursor(mo);
+    pub(crate) fn move_cursor(&mut self, mo: MotionOrObj) -> bool {
+        self.views[self.active].move_cursor(mo)
     }
 
     pub(crate) fn move_cursor_to_point(&mut self, point: Point2D<u32, PixelSize>) {
         self.views[self.active].move_cursor_to_point(point);
     }
 
+    pub(crate) fn scroll_page(&mut self, scroll: Scroll, count: Option<usize>) -> bool {
+        self.views[self.active].scroll_page(scroll, count)
+    }
+
+    pub(crate) fn scroll_cursor(&mut self, pos: ScreenPos) {
+        self.views[self.active].scroll_cursor(pos);
+    }
+
+    pub(crate) fn fold(&mut self, fold: Fold) -> bool {
+        self.views[self.active].fold(fold)
+    }
+
+    pub(crate) fn add_cursor(&mut self, below: bool, count: usize) -> bool {
+        self.views[self.active].add_cursor(below, count)
+    }
+
+    pub(crate) fn add_cursor_at_word(&mut self, count: usize) -> bool {
+        self.views[self.active].add_cursor_at_word(count)
+    }
+
+    pub(crate) fn split_selection(&mut self) {
+        self.views[self.active].split_selection();
+    }
+
+    pub(crate) fn clear_cursors(&mut self) {
+        self.views[self.active].clear_cursors();
+    }
+
     pub(crate) fn insert_char(&mut self, c: char) {
         self.views[self.active].insert_char(c);
     }
@@ -174,6 +550,202 @@ impl TextPane {
         self.views[self.active].delete(mo);
     }
 
+    pub(crate) fn operate(&mut self, op: Operator, mo: MotionOrObj, register: Option<char>) {
+        self.views[self.active].operate(op, mo, register);
+    }
+
+    pub(crate) fn put(&mut self, register: Option<char>, count: usize, before: bool) {
+        self.views[self.active].put(register, count, before);
+    }
+
+    pub(crate) fn start_selection(&mut self, mode: VisualMode) {
+        self.views[self.active].start_selection(mode);
+    }
+
+    pub(crate) fn stop_selection(&mut self) {
+        self.views[self.active].stop_selection();
+    }
+
+    pub(crate) fn operate_selection(&mut self, op: Operator, register: Option<char>) {
+        self.views[self.active].operate_selection(op, register);
+    }
+
+    pub(crate) fn join_selection(&mut self, spaces: bool) {
+        self.views[self.active].join_selection(spaces);
+    }
+
+    pub(crate) fn replace_char(&mut self, c: char, count: usize) -> bool {
+        self.views[self.active].replace_char(c, count)
+    }
+
+    pub(crate) fn join(&mut self, spaces: bool, count: usize) -> bool {
+        self.views[self.active].join(spaces, count)
+    }
+
+    pub(crate) fn toggle_case(&mut self, count: usize) {
+        self.views[self.active].toggle_case(count);
+    }
+
+    pub(crate) fn open_line(&mut self, above: bool) {
+        self.views[self.active].open_line(above);
+    }
+
+    pub(crate) fn add_to_number(&mut self, delta: i64) -> bool {
+        self.views[self.active].add_to_number(delta)
+    }
+
+    pub(crate) fn surround(&mut self, edit: SurroundEdit, surround: &Surround) -> bool {
+        self.views[self.active].surround(edit, surround)
+    }
+
+    pub(crate) fn delete_surround(&mut self, target: SurroundTarget) -> bool {
+        self.views[self.active].delete_surround(target)
+    }
+
+    pub(crate) fn start_replace(&mut self) {
+        self.views[self.active].start_replace();
+    }
+
+    pub(crate) fn replace_mode_char(&mut self, c: char) {
+        self.views[self.active].replace_mode_char(c);
+    }
+
+    pub(crate) fn replace_mode_backspace(&mut self) {
+        self.views[self.active].replace_mode_backspace();
+    }
+
+    pub(crate) fn search(&mut self, pattern: &str, forward: bool, count: usize) -> bool {
+        self.views[self.active].search(pattern, forward, count)
+    }
+
+    pub(crate) fn search_word(&mut self, forward: bool, count: usize) -> Option<String> {
+        self.views[self.active].search_word(forward, count)
+    }
+
+    pub(crate) fn search_line(&self, pattern: &str, forward: bool) -> Result<usize, String> {
+        self.views[self.active].search_line(pattern, forward)
+    }
+
+    pub(crate) fn search_preview(&mut self, pattern: &str, forward: bool) {
+        self.views[self.active].search_preview(pattern, forward);
+    }
+
+    pub(crate) fn stop_search_preview(&mut self) {
+        self.views[self.active].stop_search_preview();
+    }
+
+    pub(crate) fn substitute(
+        &mut self,
+        lines: (usize, usize),
+        pattern: &str,
+        replacement: &str,
+        global: bool,
+        ignore_case: bool,
+    ) -> Result<usize, String> {
+        self.views[self.active].substitute(lines, pattern, replacement, global, ignore_case)
+    }
+
+    pub(crate) fn cursor_line(&self) -> usize {
+        self.views[self.active].cursor_line()
+    }
+
+    pub(crate) fn set_mark(&mut self, name: char) -> bool {
+        self.views[self.active].set_mark(name)
+    }
+
+    pub(crate) fn mark_line(&self, name: char) -> Option<usize> {
+        self.views[self.active].mark_line(name)
+    }
+
+    pub(crate) fn mark_insert_stop(&mut self) {
+        self.views[self.active].mark_insert_stop();
+    }
+
+    pub(crate) fn changelist(&mut self, older: bool, n: usize) -> bool {
+        self.views[self.active].changelist(older, n)
+    }
+
+    // -------- Jumplist ----------------
+
+    pub(crate) fn jump_origin(&self) -> JumpOrigin {
+        JumpOrigin {
+            view: self.active,
+            cidx: self.views[self.active].cursor_cidx(),
+        }
+    }
+
+    // Add where a jump started to the end of the jumplist. An older entry on the same line is
+    // dropped
+    pub(crate) fn add_jump(&mut self, origin: JumpOrigin) {
+        let view = &mut self.views[origin.view];
+        let jump_id = self.next_jump_id;
+        self.next_jump_id += 1;
+        view.add_jump(jump_id, origin.cidx);
+        let linum = view.jump_line(jump_id);
+        let views = &mut self.views;
+        self.jumps.retain(|(i, id)| {
+            if *i == origin.view && views[*i].jump_line(*id) == linum {
+                views[*i].remove_jump(*id);
+                false
+            } else {
+                true
+            }
+        });
+        self.jumps.push((origin.view, jump_id));
+        if self.jumps.len() > MAX_JUMPS {
+            let (i, id) = self.jumps.remove(0);
+            self.views[i].remove_jump(id);
+        }
+        self.jump_idx = self.jumps.len();
+    }
+
+    // Go n entries back in the jumplist. Leaving the end of the list adds the cursor position to
+    // it, so that Ctrl-I can come back
+    pub(crate) fn jump_older(&mut self, n: usize) -> bool {
+        if self.jump_idx == self.jumps.len() {
+            if self.jump_idx < n {
+                return false;
+            }
+            self.add_jump(self.jump_origin());
+            self.jump_idx = self.jumps.len() - 1;
+        }
+        match self.jump_idx.checked_sub(n) {
+            Some(idx) => self.go_to_jump(idx),
+            None => false,
+        }
+    }
+
+    pub(crate) fn jump_newer(&mut self, n: usize) -> bool {
+        let idx = self.jump_idx + n;
+        if idx >= self.jumps.len() {
+            return false;
+        }
+        self.go_to_jump(idx)
+    }
+
+    fn go_to_jump(&mut self, idx: usize) -> bool {
+        let (i, jump_id) = self.jumps[idx];
+        self.jump_idx = idx;
+        self.switch_view(i);
+        self.views[i].go_to_jump(jump_id)
+    }
+
+    pub(crate) fn len_lines(&self) -> usize {
+        self.views[self.active].len_lines()
+    }
+
+    pub(crate) fn undo(&mut self, n: usize) {
+        self.views[self.active].undo(n);
+    }
+
+    pub(crate) fn redo(&mut self, n: usize) {
+        self.views[self.active].redo(n);
+    }
+
+    pub(crate) fn commit_undo_group(&mut self) {
+        self.views[self.active].commit_undo_group();
+    }
+
     pub(crate) fn check_redraw(&mut self) -> bool {
         self.views[self.active].check_redraw()
     }
@@ -213,24 +785,43 @@ impl TextPane {
         self.views[self.active].buffer_id()
     }
 
+    pub(crate) fn buffer_name(&self) -> String {
+        self.views[self.active].buffer_name()
+    }
+
+    pub(crate) fn is_modified(&self) -> bool {
+        self.views[self.active].is_modified()
+    }
+
+    // Buffers shown in the pane
+    pub(super) fn buffers(&self) -> impl Iterator<Item = &Rc<RefCell<Buffer>>> {
+        self.views.iter().map(|view| &view.buffer)
+    }
+
     pub(crate) fn rect(&self) -> Rect<u32, PixelSize> {
         self.params.rect
     }
 
+    pub(crate) fn cell_size(&self) -> Size2D<u32, PixelSize> {
+        self.views[self.active].cell_size()
+    }
+
     pub(crate) fn new_buffer<F>(&mut self, buf: Rc<RefCell<Buffer>>, mut f: F)
     where
         F: FnMut() -> BufferViewID,
     {
+        let origin = self.jump_origin();
         for i in 0..self.views.len() {
             if Rc::ptr_eq(&self.views[i].buffer, &buf) {
                 if i != self.active {
-                    self.views[self.active].deactivate();
-                    self.views[i].activate();
-                    self.active = i;
+                    self.add_jump(origin);
+                    self.switch_view(i);
                 }
                 return;
             }
         }
+        self.add_jump(origin);
+        self.alternate = Some(self.buffer_id());
         self.views[self.active].deactivate();
         let view_id = f();
         let view = TextView::new(self.params.clone(), buf, view_id);
@@ -240,21 +831,69 @@ impl TextPane {
 
     pub(crate) fn next_buffer(&mut self) {
         if self.views.len() > 1 {
-            self.views[self.active].deactivate();
-            self.active = (self.active + 1) % self.views.len();
-            self.views[self.active].activate();
+            self.switch_view((self.active + 1) % self.views.len());
         }
     }
 
     pub(crate) fn prev_buffer(&mut self) {
         if self.views.len() > 1 {
-            self.views[self.active].deactivate();
-            if self.active == 0 {
-                self.active = self.views.len() - 1;
-            } else {
-                self.active -= 1;
+            self.switch_view((self.active + self.views.len() - 1) % self.views.len());
+        }
+    }
+
+    pub(crate) fn alternate_buffer(&self) -> Option<BufferID> {
+        self.alternate
+    }
+
+    // Take a buffer out of the pane. The pane goes to its alternate buffer if it was showing this
+    // one, and to the replacement if it has no other buffers
+    pub(crate) fn remove_buffer<F>(&mut self, id: BufferID, replacement: &Rc<RefCell<Buffer>>, f: F)
+    where
+        F: FnOnce() -> BufferViewID,
+    {
+        let i = match self.views.iter().position(|view| view.buffer_id() == id) {
+            Some(i) => i,
+            None => return,
+        };
+        if self.views.len() == 1 {
+            let view = TextView::new(self.params.clone(), replacement.clone(), f());
+            self.views.push(view);
+        }
+        if i == self.active {
+            let next = self
+                .alternate
+                .and_then(|alt| self.views.iter().position(|view| view.buffer_id() == alt))
+                .filter(|j| *j != i)
+                .unwrap_or(if i == 0 { 1 } else { i - 1 });
+            self.switch_view(next);
+        }
+        if self.alternate == Some(id) {
+            self.alternate = None;
+        }
+        self.views.remove(i);
+        if self.active > i {
+            self.active -= 1;
+        }
+        // Jumps into the buffer go with it
+        let removed = self.jumps[..self.jump_idx]
+            .iter()
+            .filter(|(view, _)| *view == i)
+            .count();
+        self.jump_idx -= removed;
+        self.jumps.retain(|(view, _)| *view != i);
+        for (view, _) in &mut self.jumps {
+            if *view > i {
+                *view -= 1;
             }
-            self.views[self.active].activate();
+        }
+    }
+
+    fn switch_view(&mut self, i: usize) {
+        if i != self.active {
+            self.alternate = Some(self.buffer_id());
+            self.views[self.active].deactivate();
+            self.views[i].activate();
+            self.active = i;
         }
     }
 
@@ -273,6 +912,10 @@ impl TextPane {
             scroll_vel: vec2(0.0, 0.0),
             active: 0,
             params: view_params,
+            jumps: Vec::new(),
+            jump_idx: 0,
+            next_jump_id: 0,
+            alternate: None,
         }
     }
 
@@ -291,6 +934,10 @@ impl TextPane {
             active: 0,
             scroll_vel: vec2(0.0, 0.0),
             params: self.params.clone(),
+            jumps: Vec::new(),
+            jump_idx: 0,
+            next_jump_id: 0,
+            alternate: None,
         }
     }
 
@@ -17,9 +17,13 @@ pub(crate) struct ThemeTextview {
     pub(crate) foreground: Color,
     pub(crate) cursor_line: Color,
     pub(crate) cursor: Color,
+    pub(crate) selection: Color,
+    pub(crate) search_match: Color,
     pub(crate) border_width: u32,
     pub(crate) border_color: Color,
     pub(crate) indent_guide: Color,
+    // Background of the line closed folds are drawn as
+    pub(crate) fold: Color,
     pub(crate) lint_warnings: Option<Color>,
     pub(crate) lint_errors: Option<Color>,
 }
@@ -31,9 +35,12 @@ impl Default for ThemeTextview {
             foreground: Color::new(0, 0, 0, 0xff),
             cursor_line: Color::new(0xee, 0xee, 0xee, 0xff),
             cursor: Color::new(0xff, 0x88, 0x22, 0xff),
+            selection: Color::new(0xcc, 0xdd, 0xff, 0xff),
+            search_match: Color::new(0xff, 0xee, 0x88, 0xff),
             border_width: 1,
             border_color: Color::new(0, 0, 0, 0xff),
             indent_guide: Color::new(0xee, 0xee, 0xee, 0x88),
+            fold: Color::new(0xee, 0xee, 0xf4, 0xff),
             lint_warnings: Some(Color::new(0x88, 0x88, 0x22, 0xff)),
             lint_errors: Some(Color::new(0xff, 0x22, 0x22, 0xff)),
         }
@@ -60,6 +67,7 @@ pub(crate) struct ThemePrompt {
     pub(crate) background: Color,
     pub(crate) foreground: Color,
     pub(crate) cursor: Color,
+    pub(crate) error: Color,
 }
 
 impl Default for ThemePrompt {
@@ -68,6 +76,26 @@ impl Default for ThemePrompt {
             background: Color::new(0xff, 0xff, 0xff, 0xff),
             foreground: Color::new(0, 0, 0, 0xff),
             cursor: Color::new(0xff, 0x88, 0x22, 0xff),
+            error: Color::new(0xdd, 0x22, 0x22, 0xff),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ThemeTabBar {
+    pub(crate) background: Color,
+    pub(crate) foreground: Color,
+    pub(crate) active_background: Color,
+    pub(crate) active_foreground: Color,
+}
+
+impl Default for ThemeTabBar {
+    fn default() -> ThemeTabBar {
+        ThemeTabBar {
+            background: Color::new(0xee, 0xee, 0xee, 0xff),
+            foreground: Color::new(0, 0, 0, 0x80),
+            active_background: Color::new(0xff, 0xff, 0xff, 0xff),
+            active_foreground: Color::new(0, 0, 0, 0xff),
         }
     }
 }
@@ -126,6 +154,7 @@ pub(crate) struct Theme {
     pub(crate) hover: ThemeHover,
     pub(crate) completion: ThemeCompletion,
     pub(crate) prompt: ThemePrompt,
+    pub(crate) tab_bar: ThemeTabBar,
     pub(crate) syntax: FnvHashMap<String, ThemeSyntaxElem>,
 }
 
@@ -137,6 +166,7 @@ impl Default for Theme {
             hover: ThemeHover::default(),
             completion: ThemeCompletion::default(),
             prompt: ThemePrompt::default(),
+            tab_bar: ThemeTabBar::default(),
             syntax: FnvHashMap::default(),
         }
     }
@@ -26,9 +26,63 @@ static JS_HIGHLIGHTS: &str = include_str!("../res/tree-sitter/javascript/highlig
 static PYTHON_HIGHLIGHTS: &str = include_str!("../res/tree-sitter/python/highlights.scm");
 static RUST_HIGHLIGHTS: &str = include_str!("../res/tree-sitter/rust/highlights.scm");
 
+static C_TEXTOBJECTS: &str = include_str!("../res/tree-sitter/c/textobjects.scm");
+static CPP_TEXTOBJECTS: &str = include_str!("../res/tree-sitter/cpp/textobjects.scm");
+static CSS_TEXTOBJECTS: &str = include_str!("../res/tree-sitter/css/textobjects.scm");
+static HTML_TEXTOBJECTS: &str = include_str!("../res/tree-sitter/html/textobjects.scm");
+static JS_TEXTOBJECTS: &str = include_str!("../res/tree-sitter/javascript/textobjects.scm");
+static PYTHON_TEXTOBJECTS: &str = include_str!("../res/tree-sitter/python/textobjects.scm");
+static RUST_TEXTOBJECTS: &str = include_str!("../res/tree-sitter/rust/textobjects.scm");
+
+static C_INDENTS: &str = include_str!("../res/tree-sitter/c/indents.scm");
+static CPP_INDENTS: &str = include_str!("../res/tree-sitter/cpp/indents.scm");
+static CSS_INDENTS: &str = include_str!("../res/tree-sitter/css/indents.scm");
+static HTML_INDENTS: &str = include_str!("../res/tree-sitter/html/indents.scm");
+static JS_INDENTS: &str = include_str!("../res/tree-sitter/javascript/indents.scm");
+static PYTHON_INDENTS: &str = include_str!("../res/tree-sitter/python/indents.scm");
+static RUST_INDENTS: &str = include_str!("../res/tree-sitter/rust/indents.scm");
+
+static C_FOLDS: &str = include_str!("../res/tree-sitter/c/folds.scm");
+static CPP_FOLDS: &str = include_str!("../res/tree-sitter/cpp/folds.scm");
+static CSS_FOLDS: &str = include_str!("../res/tree-sitter/css/folds.scm");
+static HTML_FOLDS: &str = include_str!("../res/tree-sitter/html/folds.scm");
+static JS_FOLDS: &str = include_str!("../res/tree-sitter/javascript/folds.scm");
+static PYTHON_FOLDS: &str = include_str!("../res/tree-sitter/python/folds.scm");
+static RUST_FOLDS: &str = include_str!("../res/tree-sitter/rust/folds.scm");
+
+// Queries for one language
+pub(crate) struct TsQueries {
+    pub(crate) highlights: Query,
+    pub(crate) textobjects: Query,
+    pub(crate) indents: Query,
+    pub(crate) folds: Query,
+}
+
+impl TsQueries {
+    fn new(
+        language: TSLanguage,
+        name: &str,
+        highlights: &str,
+        textobjects: &str,
+        indents: &str,
+        folds: &str,
+    ) -> TsQueries {
+        TsQueries {
+            highlights: Query::new(language, highlights)
+                .unwrap_or_else(|_| panic!("failed to load highlight queries for {}", name)),
+            textobjects: Query::new(language, textobjects)
+                .unwrap_or_else(|_| panic!("failed to load text object queries for {}", name)),
+            indents: Query::new(language, indents)
+                .unwrap_or_else(|_| panic!("failed to load indent queries for {}", name)),
+            folds: Query::new(language, folds)
+                .unwrap_or_else(|_| panic!("failed to load fold queries for {}", name)),
+        }
+    }
+}
+
 pub(crate) struct TsCore {
     languages: Vec<TSLanguage>,
-    hl_queries: Vec<Rc<Query>>,
+    queries: Vec<Rc<TsQueries>>,
     exts: FnvHashMap<String, (Language, usize)>,
 }
 
@@ -43,36 +97,67 @@ impl TsCore {
             unsafe { tree_sitter_python() },
             unsafe { tree_sitter_rust() },
         ];
-        let hl_queries = vec![
-            Rc::new(
-                Query::new(languages[0], C_HIGHLIGHTS)
-                    .expect("failed to load highlight queries for C"),
+        let queries = vec![
+            TsQueries::new(
+                languages[0],
+                "C",
+                C_HIGHLIGHTS,
+                C_TEXTOBJECTS,
+                C_INDENTS,
+                C_FOLDS,
             ),
-            Rc::new(
-                Query::new(languages[1], &(CPP_HIGHLIGHTS.to_owned() + C_HIGHLIGHTS))
-                    .expect("failed to load highlight queries for C++"),
+            TsQueries::new(
+                languages[1],
+                "C++",
+                &(CPP_HIGHLIGHTS.to_owned() + C_HIGHLIGHTS),
+                &(CPP_TEXTOBJECTS.to_owned() + C_TEXTOBJECTS),
+                &(CPP_INDENTS.to_owned() + C_INDENTS),
+                &(CPP_FOLDS.to_owned() + C_FOLDS),
             ),
-            Rc::new(
-                Query::new(languages[2], CSS_HIGHLIGHTS)
-                    .expect("failed to load highlight queries for CSS"),
+            TsQueries::new(
+                languages[2],
+                "CSS",
+                CSS_HIGHLIGHTS,
+                CSS_TEXTOBJECTS,
+                CSS_INDENTS,
+                CSS_FOLDS,
             ),
-            Rc::new(
-                Query::new(languages[3], HTML_HIGHLIGHTS)
-                    .expect("failed to load highlight queries for HTML"),
+            TsQueries::new(
+                languages[3],
+                "HTML",
+                HTML_HIGHLIGHTS,
+                HTML_TEXTOBJECTS,
+                HTML_INDENTS,
+                HTML_FOLDS,
             ),
-            Rc::new(
-                Query::new(languages[4], JS_HIGHLIGHTS)
-                    .expect("failed to load highlight queries for JavaScript"),
+            TsQueries::new(
+                languages[4],
+                "JavaScript",
+                JS_HIGHLIGHTS,
+                JS_TEXTOBJECTS,
+                JS_INDENTS,
+                JS_FOLDS,
             ),
-            Rc::new(
-                Query::new(languages[5], PYTHON_HIGHLIGHTS)
-                    .expect("failed to load highlight queries for Python"),
+            TsQueries::new(
+                languages[5],
+                "Python",
+                PYTHON_HIGHLIGHTS,
+                PYTHON_TEXTOBJECTS,
+                PYTHON_INDENTS,
+                PYTHON_FOLDS,
             ),
-            Rc::new(
-                Query::new(languages[6], RUST_HIGHLIGHTS)
-                    .expect("failed to load highlight queries for Rust"),
+            TsQueries::new(
+                languages[6],
+                "Rust",
+                RUST_HIGHLIGHTS,
+                RUST_TEXTOBJECTS,
+                RUST_INDENTS,
+                RUST_FOLDS,
             ),
-        ];
+        ]
+        .into_iter()
+        .map(Rc::new)
+        .collect();
         let mut exts = FnvHashMap::default();
         exts.insert("c".to_owned(), (Language::C, 0));
         exts.insert("h".to_owned(), (Language::C, 0));
@@ -85,18 +170,21 @@ impl TsCore {
         exts.insert("rs".to_owned(), (Language::Rust, 6));
         TsCore {
             languages,
-            hl_queries,
+            queries,
             exts,
         }
     }
 
-    pub(crate) fn parser_from_extension(&self, ext: &str) -> Option<(Language, Parser, Rc<Query>)> {
+    pub(crate) fn parser_from_extension(
+        &self,
+        ext: &str,
+    ) -> Option<(Language, Parser, Rc<TsQueries>)> {
         self.exts.get(ext).map(|(ft, i)| {
             let mut parser = Parser::new();
             parser
                 .set_language(self.languages[*i])
                 .expect("failed to set parser language");
-            (ft.to_owned(), parser, self.hl_queries[*i].clone())
+            (ft.to_owned(), parser, self.queries[*i].clone())
         })
     }
 }
@@ -89,6 +89,10 @@ impl Window {
         self.window.swap_buffers();
     }
 
+    pub(crate) fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
     // Set the window to be visible
     pub(crate) fn show(&mut self) {
         self.window.show();